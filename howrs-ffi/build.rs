@@ -0,0 +1,13 @@
+//! Regenerate `include/howrs.h` from the exported functions
+
+use std::path::PathBuf;
+
+fn main() {
+    let crate_dir = PathBuf::from(std::env::var("CARGO_MANIFEST_DIR").unwrap());
+    println!("cargo:rerun-if-changed=src/lib.rs");
+    println!("cargo:rerun-if-changed=cbindgen.toml");
+
+    cbindgen::generate(&crate_dir)
+        .expect("generating C header")
+        .write_to_file(crate_dir.join("include/howrs.h"));
+}
@@ -0,0 +1,86 @@
+#ifndef HOWRS_H
+#define HOWRS_H
+
+/* Generated by cbindgen from howrs-ffi/src/lib.rs, do not edit */
+
+#include <stdarg.h>
+#include <stdbool.h>
+#include <stddef.h>
+#include <stdint.h>
+#include <stdlib.h>
+
+// Result of every `howrs_*` call
+typedef enum HowrsStatus {
+  // Done; for authentication, the face matched
+  HOWRS_STATUS_OK = 0,
+  // The face didn't match, or no usable face was seen in time
+  HOWRS_STATUS_NO_MATCH = 1,
+  // No stage could scan, e.g. no daemon running and no camera access
+  HOWRS_STATUS_UNAVAILABLE = 2,
+  // A null pointer or a string that isn't UTF-8
+  HOWRS_STATUS_INVALID_ARGUMENT = -1,
+  // Anything else; see [`howrs_last_error`]
+  HOWRS_STATUS_ERROR = -2,
+} HowrsStatus;
+
+// One enrolled face, see [`howrs_list`]
+typedef struct HowrsFace {
+  // Record id, a UUID
+  char *id;
+  // Free-form label, e.g. the pose of a guided enrollment, or null
+  char *label;
+  // Unix timestamp of enrollment, 0 if unknown
+  uint64_t created_at;
+} HowrsFace;
+
+// Faces returned by [`howrs_list`], released with [`howrs_face_list_free`]
+typedef struct HowrsFaceList {
+  struct HowrsFace *faces;
+  size_t len;
+} HowrsFaceList;
+
+#ifdef __cplusplus
+extern "C" {
+#endif // __cplusplus
+
+// Message of the last failed call on this thread, or null.
+//
+// The string stays valid until the next `howrs_*` call on this thread.
+const char *howrs_last_error(void);
+
+// Check whether the face in front of the camera belongs to `user`.
+//
+// `timeout_ms` bounds the scan; 0 uses the configured timeouts.
+//
+// # Safety
+// `user` must be null or a valid C string.
+enum HowrsStatus howrs_authenticate(const char *user, uint32_t timeout_ms);
+
+// Capture the face in front of the camera and enroll it for `user`.
+//
+// `timeout_ms` bounds the capture; 0 uses `scan_durnation`. Without root,
+// the request goes to `howrs daemon`, and a polkit prompt may be shown
+// before the capture starts.
+//
+// # Safety
+// `user` must be null or a valid C string.
+enum HowrsStatus howrs_enroll(const char *user, uint32_t timeout_ms);
+
+// List the faces enrolled for `user` into `out`, which must later be passed
+// to [`howrs_face_list_free`]. An unknown user has no faces.
+//
+// # Safety
+// `user` must be null or a valid C string, and `out` null or valid for writes.
+enum HowrsStatus howrs_list(const char *user, struct HowrsFaceList *out);
+
+// Release the faces of a list filled by [`howrs_list`] and empty it
+//
+// # Safety
+// `list` must be null or filled by [`howrs_list`] and not freed yet.
+void howrs_face_list_free(struct HowrsFaceList *list);
+
+#ifdef __cplusplus
+}  // extern "C"
+#endif  // __cplusplus
+
+#endif  /* HOWRS_H */
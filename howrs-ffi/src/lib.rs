@@ -0,0 +1,305 @@
+//! C API for embedding howrs without going through PAM, e.g. in a screen
+//! locker or a settings panel. The header is `include/howrs.h`, regenerated
+//! by cbindgen on every build.
+//!
+//! Every call blocks until it has an answer and returns a [`HowrsStatus`].
+//! On `HOWRS_STATUS_ERROR` the message is available from
+//! [`howrs_last_error`] on the same thread. Nothing is logged: the library
+//! runs inside someone else's process.
+//!
+//! Authentication walks the same `[pam.fallback]` ladder as the PAM module.
+//! Enrollment writes to the system face store, so unless the caller is root
+//! it goes through `howrs daemon`, which asks polkit first.
+
+use anyhow::{bail, Result};
+use howrs::{auth, config, daemon, identity, storage, AuthFailure, AuthResult};
+use std::cell::RefCell;
+use std::ffi::{CStr, CString};
+use std::os::raw::c_char;
+use std::panic::{catch_unwind, AssertUnwindSafe};
+use std::time::{Duration, Instant};
+
+/// Result of every `howrs_*` call
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HowrsStatus {
+    /// Done; for authentication, the face matched
+    Ok = 0,
+    /// The face didn't match, or no usable face was seen in time
+    NoMatch = 1,
+    /// No stage could scan, e.g. no daemon running and no camera access
+    Unavailable = 2,
+    /// A null pointer or a string that isn't UTF-8
+    InvalidArgument = -1,
+    /// Anything else; see [`howrs_last_error`]
+    Error = -2,
+}
+
+/// One enrolled face, see [`howrs_list`]
+#[repr(C)]
+#[derive(Debug)]
+pub struct HowrsFace {
+    /// Record id, a UUID
+    pub id: *mut c_char,
+    /// Free-form label, e.g. the pose of a guided enrollment, or null
+    pub label: *mut c_char,
+    /// Unix timestamp of enrollment, 0 if unknown
+    pub created_at: u64,
+}
+
+/// Faces returned by [`howrs_list`], released with [`howrs_face_list_free`]
+#[repr(C)]
+#[derive(Debug)]
+pub struct HowrsFaceList {
+    pub faces: *mut HowrsFace,
+    pub len: usize,
+}
+
+thread_local! {
+    static LAST_ERROR: RefCell<Option<CString>> = const { RefCell::new(None) };
+}
+
+/// Message of the last failed call on this thread, or null.
+///
+/// The string stays valid until the next `howrs_*` call on this thread.
+#[no_mangle]
+pub extern "C" fn howrs_last_error() -> *const c_char {
+    LAST_ERROR.with(|last| {
+        last.borrow()
+            .as_ref()
+            .map_or(std::ptr::null(), |msg| msg.as_ptr())
+    })
+}
+
+/// Check whether the face in front of the camera belongs to `user`.
+///
+/// `timeout_ms` bounds the scan; 0 uses the configured timeouts.
+///
+/// # Safety
+/// `user` must be null or a valid C string.
+#[no_mangle]
+pub unsafe extern "C" fn howrs_authenticate(user: *const c_char, timeout_ms: u32) -> HowrsStatus {
+    call(|| {
+        let user = arg(user)?;
+        let timeout = match timeout_ms {
+            0 => {
+                let config = config::load_config(None)?;
+                config.pam.fallback.timeout(config.scan_durnation)
+            }
+            ms => Duration::from_millis(ms.into()),
+        };
+        match howrs::authenticate(user, timeout) {
+            AuthResult::Matched => Ok(HowrsStatus::Ok),
+            AuthResult::Failed(AuthFailure::NoCamera) | AuthResult::Unavailable => {
+                Ok(HowrsStatus::Unavailable)
+            }
+            AuthResult::Failed(_) => Ok(HowrsStatus::NoMatch),
+            AuthResult::Error(e) => Err(e.into()),
+        }
+    })
+}
+
+/// Capture the face in front of the camera and enroll it for `user`.
+///
+/// `timeout_ms` bounds the capture; 0 uses `scan_durnation`. Without root,
+/// the request goes to `howrs daemon`, and a polkit prompt may be shown
+/// before the capture starts.
+///
+/// # Safety
+/// `user` must be null or a valid C string.
+#[no_mangle]
+pub unsafe extern "C" fn howrs_enroll(user: *const c_char, timeout_ms: u32) -> HowrsStatus {
+    call(|| {
+        let user = arg(user)?;
+        let config = config::load_config(None)?;
+        let timeout = match timeout_ms {
+            0 => Duration::from_secs(config.scan_durnation as u64),
+            ms => Duration::from_millis(ms.into()),
+        };
+
+        let enrolled = if identity::is_root() {
+            // A real account, so the name can't point outside the store
+            identity::lookup(user)?;
+            let mut pipeline = auth::load_pipeline(&config)?;
+            auth::enroll(&mut pipeline, &config, user, Instant::now() + timeout)?
+        } else {
+            daemon::request_enroll(&config.pam.fallback.daemon_socket, user, timeout)?
+        };
+        Ok(match enrolled {
+            true => HowrsStatus::Ok,
+            false => HowrsStatus::NoMatch,
+        })
+    })
+}
+
+/// List the faces enrolled for `user` into `out`, which must later be passed
+/// to [`howrs_face_list_free`]. An unknown user has no faces.
+///
+/// # Safety
+/// `user` must be null or a valid C string, and `out` null or valid for writes.
+#[no_mangle]
+pub unsafe extern "C" fn howrs_list(user: *const c_char, out: *mut HowrsFaceList) -> HowrsStatus {
+    call(|| {
+        let user = arg(user)?;
+        if out.is_null() {
+            bail!(InvalidArgument);
+        }
+        let records = storage::load_records(user)?;
+        out.write(face_list(&records));
+        Ok(HowrsStatus::Ok)
+    })
+}
+
+/// Release the faces of a list filled by [`howrs_list`] and empty it
+///
+/// # Safety
+/// `list` must be null or filled by [`howrs_list`] and not freed yet.
+#[no_mangle]
+pub unsafe extern "C" fn howrs_face_list_free(list: *mut HowrsFaceList) {
+    let Some(list) = list.as_mut() else {
+        return;
+    };
+    if list.faces.is_null() {
+        return;
+    }
+    let faces = Box::from_raw(std::ptr::slice_from_raw_parts_mut(list.faces, list.len));
+    for face in faces.iter() {
+        drop(CString::from_raw(face.id));
+        if !face.label.is_null() {
+            drop(CString::from_raw(face.label));
+        }
+    }
+    list.faces = std::ptr::null_mut();
+    list.len = 0;
+}
+
+fn face_list(records: &[storage::FaceRecord]) -> HowrsFaceList {
+    let faces: Box<[HowrsFace]> = records
+        .iter()
+        .map(|record| HowrsFace {
+            id: c_string(&record.id),
+            label: record
+                .meta
+                .label
+                .as_deref()
+                .map_or(std::ptr::null_mut(), c_string),
+            created_at: record.meta.created_at,
+        })
+        .collect();
+    let len = faces.len();
+    HowrsFaceList {
+        faces: Box::into_raw(faces).cast(),
+        len,
+    }
+}
+
+/// Owned C copy of `s`, dropping any interior NUL
+fn c_string(s: &str) -> *mut c_char {
+    CString::new(s.replace('\0', ""))
+        .unwrap_or_default()
+        .into_raw()
+}
+
+/// Marker error mapped to [`HowrsStatus::InvalidArgument`]
+#[derive(Debug)]
+struct InvalidArgument;
+
+impl std::fmt::Display for InvalidArgument {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("invalid argument")
+    }
+}
+
+impl std::error::Error for InvalidArgument {}
+
+/// Borrow a C string argument
+///
+/// # Safety
+/// `s` must be null or a valid C string that outlives the call.
+unsafe fn arg<'a>(s: *const c_char) -> Result<&'a str> {
+    if s.is_null() {
+        bail!(InvalidArgument);
+    }
+    CStr::from_ptr(s)
+        .to_str()
+        .map_err(|_| anyhow::Error::new(InvalidArgument))
+}
+
+/// Run `f`, turning errors and panics into a status and the thread's last error
+fn call(f: impl FnOnce() -> Result<HowrsStatus>) -> HowrsStatus {
+    LAST_ERROR.with(|last| last.borrow_mut().take());
+    let result = catch_unwind(AssertUnwindSafe(f))
+        .unwrap_or_else(|_| Err(anyhow::anyhow!("panic inside howrs")));
+    match result {
+        Ok(status) => status,
+        Err(e) => {
+            let status = if e.is::<InvalidArgument>() {
+                HowrsStatus::InvalidArgument
+            } else if is_camera(&e) {
+                HowrsStatus::Unavailable
+            } else {
+                HowrsStatus::Error
+            };
+            let msg = CString::new(format!("{:#}", e).replace('\0', "")).unwrap_or_default();
+            LAST_ERROR.with(|last| *last.borrow_mut() = Some(msg));
+            status
+        }
+    }
+}
+
+/// A camera that can't be opened or read is reported like no camera
+fn is_camera(e: &anyhow::Error) -> bool {
+    match e.downcast_ref::<howrs::Error>() {
+        Some(e) => e.is_camera(),
+        None => matches!(
+            e.downcast_ref::<howrs_vision::Error>(),
+            Some(howrs_vision::Error::Camera { .. })
+        ),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_invalid_arguments() {
+        unsafe {
+            assert_eq!(
+                howrs_authenticate(std::ptr::null(), 0),
+                HowrsStatus::InvalidArgument
+            );
+            let msg = CStr::from_ptr(howrs_last_error()).to_str().unwrap();
+            assert_eq!(msg, "invalid argument");
+
+            let user = CString::new("alice").unwrap();
+            assert_eq!(
+                howrs_list(user.as_ptr(), std::ptr::null_mut()),
+                HowrsStatus::InvalidArgument
+            );
+            // Freeing nothing is fine
+            howrs_face_list_free(std::ptr::null_mut());
+        }
+    }
+
+    #[test]
+    fn test_face_list_roundtrip() {
+        let mut labelled = storage::FaceRecord::new(vec![vec![0.0; 4]], Some("left".into()));
+        labelled.meta.created_at = 42;
+        let records = [storage::FaceRecord::new(vec![vec![0.0; 4]], None), labelled];
+
+        let mut list = face_list(&records);
+        assert_eq!(list.len, 2);
+        unsafe {
+            let faces = std::slice::from_raw_parts(list.faces, list.len);
+            assert_eq!(CStr::from_ptr(faces[0].id).to_str().unwrap(), records[0].id);
+            assert!(faces[0].label.is_null());
+            assert_eq!(CStr::from_ptr(faces[1].label).to_str().unwrap(), "left");
+            assert_eq!(faces[1].created_at, 42);
+
+            howrs_face_list_free(&mut list);
+        }
+        assert!(list.faces.is_null());
+        assert_eq!(list.len, 0);
+    }
+}
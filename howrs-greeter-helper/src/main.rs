@@ -0,0 +1,204 @@
+//! `howrs-greeter-helper`: spawned by a display manager next to its greeter.
+//!
+//! It warms up face authentication before the user gets to it, by starting
+//! the daemon and reading the models, and publishes signals on D-Bus so the
+//! greeter can show "Looking for your face…" as soon as someone sits down:
+//!
+//! ```text
+//! org.howrs.Greeter1.FaceDetected(d score)
+//! org.howrs.Greeter1.FaceLost()
+//! ```
+//!
+//! emitted from `/org/howrs/Greeter` by the owner of `org.howrs.Greeter`.
+//!
+//! Only detection runs here, nothing is matched. As soon as an
+//! authentication request queues for the camera the helper lets go of it,
+//! and picks it up again once the request is done.
+
+use anyhow::{Context, Result};
+use clap::Parser;
+use howrs::config::{Config, FallbackStage};
+use howrs::error::Report;
+use howrs::stream::{Flow, SkipReason, StreamEvent, StreamOptions};
+use howrs::video::{self, Camera, CameraLock};
+use howrs::{auth, config, daemon, Pipeline};
+use std::time::{Duration, Instant};
+use zbus::blocking::{connection, Connection};
+
+/// Well-known name owned on the bus
+const BUS_NAME: &str = "org.howrs.Greeter";
+const OBJECT_PATH: &str = "/org/howrs/Greeter";
+const INTERFACE: &str = "org.howrs.Greeter1";
+
+/// Consecutive frames without a face before `FaceLost` is sent
+const LOST_AFTER: u32 = 5;
+/// How long to leave the camera alone after handing it over
+const YIELD_BACKOFF: Duration = Duration::from_secs(2);
+/// How long to wait for a socket-activated daemon to load its models
+const WARM_TIMEOUT: Duration = Duration::from_secs(30);
+
+#[derive(Parser)]
+#[command(
+    name = "howrs-greeter-helper",
+    about = "Warm up face authentication and publish face events for a login screen"
+)]
+struct Args {
+    /// Only warm up, then exit without watching the camera
+    #[arg(long)]
+    warm_only: bool,
+    /// Publish on the system bus instead of the session bus
+    #[arg(long)]
+    system: bool,
+}
+
+fn main() -> Result<()> {
+    howrs::logging::init();
+    let args = Args::parse();
+    let config = config::load_config(None)?;
+
+    warm_daemon(&config);
+    // Also reads the model files into the page cache for the in-process stage
+    let mut pipeline = auth::load_auth_pipeline(&config)?;
+    if args.warm_only {
+        return Ok(());
+    }
+
+    let builder = if args.system {
+        connection::Builder::system()?
+    } else {
+        connection::Builder::session()?
+    };
+    let bus = builder
+        .name(BUS_NAME)?
+        .build()
+        .context("connecting to D-Bus")?;
+
+    tracing::info!("watching {} for faces", config.camera);
+    let mut presence = Presence::default();
+    loop {
+        watch(&mut pipeline, &config, &bus, &mut presence)?;
+        std::thread::sleep(YIELD_BACKOFF);
+    }
+}
+
+/// Start the daemon when authentication goes through it; it answers the ping
+/// once its models are loaded
+fn warm_daemon(config: &Config) {
+    let fallback = &config.pam.fallback;
+    if !fallback.stages.contains(&FallbackStage::Daemon) {
+        return;
+    }
+    let start = Instant::now();
+    match daemon::request_ping(&fallback.daemon_socket, WARM_TIMEOUT) {
+        Ok(()) => tracing::info!("daemon ready after {:?}", start.elapsed()),
+        Err(e) => tracing::warn!("daemon not available: {}", Report(&e)),
+    }
+}
+
+/// Publish face events from the camera until an authentication request wants
+/// it, or return right away if it is busy
+fn watch(
+    pipeline: &mut Pipeline,
+    config: &Config,
+    bus: &Connection,
+    presence: &mut Presence,
+) -> Result<()> {
+    let mut camera = match Camera::open(&config.camera, &config.capture.settings()) {
+        Ok(camera) => camera,
+        Err(e) => {
+            tracing::debug!("camera unavailable: {}", Report(&e));
+            return Ok(());
+        }
+    };
+
+    // `auto` is resolved once rather than on every frame
+    let device = video::resolve_device(&config.camera).unwrap_or_else(|_| config.camera.clone());
+    pipeline.run_stream(&mut camera, &StreamOptions::default(), |event| {
+        let found = match event {
+            StreamEvent::FrameCaptured { .. } if CameraLock::contended(&device) => {
+                return Ok(Flow::Stop);
+            }
+            StreamEvent::FaceDetected { detection, .. } => Some(detection.score as f64),
+            StreamEvent::FrameSkipped {
+                reason: SkipReason::NoFace,
+                ..
+            } => None,
+            StreamEvent::FrameSkipped {
+                reason: SkipReason::Failed(e),
+                ..
+            } => return Err(e.into()),
+            _ => return Ok(Flow::Continue),
+        };
+
+        match presence.update(found.is_some()) {
+            Some(true) => {
+                bus.emit_signal(
+                    None::<&str>,
+                    OBJECT_PATH,
+                    INTERFACE,
+                    "FaceDetected",
+                    &(found.unwrap_or(0.0),),
+                )?;
+            }
+            Some(false) => {
+                bus.emit_signal(None::<&str>, OBJECT_PATH, INTERFACE, "FaceLost", &())?;
+            }
+            None => {}
+        }
+        Ok::<_, anyhow::Error>(Flow::Continue)
+    })?;
+    tracing::debug!("camera wanted for authentication, handing it over");
+    Ok(())
+}
+
+/// Whether a face is in view, ignoring a few frames where detection misses
+#[derive(Debug, Default)]
+struct Presence {
+    present: bool,
+    missed: u32,
+}
+
+impl Presence {
+    /// Record one frame; the new state if it changed
+    fn update(&mut self, found: bool) -> Option<bool> {
+        if found {
+            self.missed = 0;
+            if !self.present {
+                self.present = true;
+                return Some(true);
+            }
+        } else if self.present {
+            self.missed += 1;
+            if self.missed >= LOST_AFTER {
+                self.present = false;
+                return Some(false);
+            }
+        }
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_presence() {
+        let mut presence = Presence::default();
+        assert_eq!(presence.update(false), None);
+        assert_eq!(presence.update(true), Some(true));
+        assert_eq!(presence.update(true), None);
+
+        // A few missed detections don't count as leaving
+        for _ in 1..LOST_AFTER {
+            assert_eq!(presence.update(false), None);
+        }
+        assert_eq!(presence.update(true), None);
+
+        for _ in 1..LOST_AFTER {
+            presence.update(false);
+        }
+        assert_eq!(presence.update(false), Some(false));
+        assert_eq!(presence.update(false), None);
+    }
+}
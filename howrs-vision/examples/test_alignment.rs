@@ -77,7 +77,7 @@ fn main() -> Result<()> {
         let embedding = face::encode_face(&mut recognizer, &aligned)?;
         println!(
             "✓ Generated embedding with {} dimensions",
-            embedding.vector.len()
+            embedding.vector().len()
         );
     }
 
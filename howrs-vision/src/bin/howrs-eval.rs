@@ -0,0 +1,120 @@
+//! Evaluate the detector + encoder over a labelled folder.
+//!
+//! Usage: `howrs-eval <dir> [--roc] [--flip] [--equalize|--clahe]
+//! [--far <rate>] [--export-impostors <file>]` where `<dir>` contains one
+//! sub-directory of images per person (plus optionally `_strangers`, whose
+//! faces are all impostors), `--flip` enables flip augmentation when
+//! encoding and `--equalize`/`--clahe` normalize the contrast of grayscale
+//! images. `--far` picks the threshold for that false accept rate, and
+//! `--export-impostors` saves every embedding as an impostor set for
+//! `howrs threshold`.
+
+use anyhow::{Context, Result};
+use howrs_vision::eval::{self, Distribution};
+use howrs_vision::normalize::Normalization;
+use std::path::PathBuf;
+
+fn main() -> Result<()> {
+    let mut dir = None;
+    let mut show_roc = false;
+    let mut flip_augment = false;
+    let mut normalization = Normalization::None;
+    let mut far = None;
+    let mut export = None;
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--roc" => show_roc = true,
+            "--flip" => flip_augment = true,
+            "--equalize" => normalization = Normalization::Equalize,
+            "--clahe" => normalization = Normalization::Clahe,
+            "--far" => {
+                let rate = args.next().context("--far needs a rate")?;
+                far = Some(rate.parse::<f32>().context("invalid --far")?);
+            }
+            "--export-impostors" => {
+                export = Some(PathBuf::from(
+                    args.next().context("--export-impostors needs a file")?,
+                ));
+            }
+            _ if dir.is_none() => dir = Some(PathBuf::from(arg)),
+            _ => anyhow::bail!("unexpected argument: {}", arg),
+        }
+    }
+    let Some(dir) = dir else {
+        anyhow::bail!(
+            "usage: howrs-eval <dir> [--roc] [--flip] [--equalize|--clahe] [--far <rate>] [--export-impostors <file>]"
+        );
+    };
+
+    let report = eval::run(&dir, flip_augment, normalization)?;
+
+    println!(
+        "{} people, {} images ({} without a face)",
+        report.people,
+        report.images,
+        report.skipped.len()
+    );
+    if report.strangers > 0 {
+        println!("{} stranger faces", report.strangers);
+    }
+    for path in &report.skipped {
+        println!("  no face: {}", path.display());
+    }
+    for (name, scores) in [("same", &report.same), ("different", &report.different)] {
+        match Distribution::from_scores(scores) {
+            Some(d) => println!(
+                "{:<10} pairs={:<6} mean={:.3} std={:.3} min={:.3} max={:.3}",
+                name, d.count, d.mean, d.std_dev, d.min, d.max
+            ),
+            None => println!("{:<10} pairs=0", name),
+        }
+    }
+
+    if show_roc {
+        println!("threshold,true_accept_rate,false_accept_rate");
+        for p in &report.roc {
+            println!(
+                "{:.3},{:.4},{:.4}",
+                p.threshold, p.true_accept_rate, p.false_accept_rate
+            );
+        }
+    }
+
+    match report.suggested_threshold {
+        Some(t) => println!(
+            "suggested threshold: {:.3} (false accept rate <= {})",
+            t,
+            eval::MAX_FALSE_ACCEPT_RATE
+        ),
+        None => println!("suggested threshold: not enough pairs"),
+    }
+
+    if let Some(far) = far {
+        match eval::threshold_for_far(&report.same, &report.different, far) {
+            Some(t) => println!(
+                "threshold for false accept rate {}: {:.3} (false accept rate {:.5}, true accept rate {:.3}, {} impostor pairs)",
+                far, t.threshold, t.false_accept_rate, t.true_accept_rate, t.impostor_pairs
+            ),
+            None => println!("threshold for false accept rate {}: no impostor pairs", far),
+        }
+    }
+
+    if let Some(c) = report.calibration {
+        println!(
+            "calibration (for config.toml):\n[calibration]\nslope = {:.3}\nintercept = {:.3}",
+            c.slope, c.intercept
+        );
+    }
+
+    if let Some(path) = export {
+        eval::write_embeddings(&path, &report.embeddings)?;
+        println!(
+            "saved {} embeddings to {}",
+            report.embeddings.len(),
+            path.display()
+        );
+    }
+
+    Ok(())
+}
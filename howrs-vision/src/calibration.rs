@@ -0,0 +1,113 @@
+//! Mapping raw cosine similarity to an estimated genuine-match probability
+//!
+//! A logistic curve `p = 1 / (1 + exp(-(slope * score + intercept)))` is
+//! fitted to same-person and different-person scores. Both classes are
+//! weighted equally, so the probability assumes even odds before the scan
+//! rather than whatever mix of pairs the evaluation set happened to contain.
+
+/// Newton iterations before giving up on convergence
+const MAX_ITERATIONS: usize = 100;
+/// Ridge penalty on the slope, keeping the fit finite on separable data
+const RIDGE: f64 = 1e-4;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Calibration {
+    pub slope: f32,
+    pub intercept: f32,
+}
+
+impl Calibration {
+    /// Shipped curve for SFace: 50% at OpenCV's recommended cosine threshold
+    /// of 0.363, rising to ~99% by 0.6
+    pub const SFACE: Calibration = Calibration {
+        slope: 20.0,
+        intercept: -7.26,
+    };
+
+    /// Estimated probability that a pair scoring `score` is the same person
+    pub fn probability(&self, score: f32) -> f32 {
+        1.0 / (1.0 + (-(self.slope * score + self.intercept)).exp())
+    }
+
+    /// Fit a curve to labelled scores; `None` unless both kinds are present
+    pub fn fit(same: &[f32], different: &[f32]) -> Option<Self> {
+        if same.is_empty() || different.is_empty() {
+            return None;
+        }
+        let samples = same
+            .iter()
+            .map(|&s| (s as f64, 1.0, 0.5 / same.len() as f64))
+            .chain(
+                different
+                    .iter()
+                    .map(|&s| (s as f64, 0.0, 0.5 / different.len() as f64)),
+            );
+
+        let (mut a, mut b) = (1.0f64, 0.0f64);
+        for _ in 0..MAX_ITERATIONS {
+            // Gradient and Hessian of the weighted log loss
+            let (mut ga, mut gb) = (RIDGE * a, 0.0);
+            let (mut haa, mut hab, mut hbb) = (RIDGE, 0.0, 0.0);
+            for (x, y, w) in samples.clone() {
+                let p = 1.0 / (1.0 + (-(a * x + b)).exp());
+                let r = w * (p - y);
+                let v = w * p * (1.0 - p);
+                ga += r * x;
+                gb += r;
+                haa += v * x * x;
+                hab += v * x;
+                hbb += v;
+            }
+
+            let det = haa * hbb - hab * hab;
+            if det.abs() < 1e-12 {
+                break;
+            }
+            let da = (hbb * ga - hab * gb) / det;
+            let db = (haa * gb - hab * ga) / det;
+            a -= da;
+            b -= db;
+            if da.abs() < 1e-9 && db.abs() < 1e-9 {
+                break;
+            }
+        }
+
+        Some(Calibration {
+            slope: a as f32,
+            intercept: b as f32,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sface_midpoint() {
+        let p = Calibration::SFACE.probability(0.363);
+        assert!((p - 0.5).abs() < 0.01);
+        assert!(Calibration::SFACE.probability(0.6) > 0.98);
+        assert!(Calibration::SFACE.probability(0.1) < 0.01);
+    }
+
+    #[test]
+    fn test_fit_overlapping() {
+        let same: Vec<f32> = (0..50).map(|i| 0.4 + i as f32 * 0.01).collect();
+        // Many more impostor pairs than genuine ones, as in any real set
+        let different: Vec<f32> = (0..500).map(|i| -0.1 + i as f32 * 0.001).collect();
+
+        let c = Calibration::fit(&same, &different).unwrap();
+        assert!(c.slope > 0.0);
+        assert!(c.probability(0.8) > 0.95);
+        assert!(c.probability(0.0) < 0.05);
+        // The overlap sits around 0.4, so that's where the curve crosses 50%
+        assert!((0.3..0.5).contains(&(-c.intercept / c.slope)));
+    }
+
+    #[test]
+    fn test_fit_needs_both_classes() {
+        assert!(Calibration::fit(&[0.5], &[]).is_none());
+        assert!(Calibration::fit(&[], &[0.1]).is_none());
+    }
+}
@@ -0,0 +1,321 @@
+//! Depth cameras (Intel RealSense, time-of-flight sensors) read through V4L2
+//! as 16-bit `Z16` frames, and a liveness check on the face's relief.
+//!
+//! A face has a nose, eye sockets and cheeks; a printed photo or a screen is
+//! flat, however it is tilted. [`DepthGate`] fits a plane to the depth under
+//! the face and checks how far the face departs from it.
+//!
+//! The face box comes from the camera that detected it and is scaled to the
+//! depth frame, so both must see roughly the same view, as the IR and depth
+//! streams of one RealSense module do.
+
+use crate::error::{Error, Result, ResultExt};
+use crate::video::{self, CameraLock};
+use std::fmt;
+use std::time::Instant;
+use v4l::buffer::Type;
+use v4l::io::mmap::Stream;
+use v4l::io::traits::CaptureStream;
+use v4l::video::Capture;
+use v4l::{Device, Format, FourCC};
+
+/// Readings further than this from the face's median depth are background
+/// (or the sensor's flying pixels at its edges) and left out of the fit
+const MAX_FACE_DEPTH: f32 = 150.0;
+
+/// One depth frame, in millimetres; 0 where the sensor had no reading
+#[derive(Debug, Clone)]
+pub struct DepthFrame {
+    pub width: u32,
+    pub height: u32,
+    pub depth: Vec<u16>,
+}
+
+pub struct DepthCamera {
+    stream: Stream<'static>,
+    width: u32,
+    height: u32,
+    /// Millimetres per depth unit
+    scale: f32,
+    _lock: CameraLock,
+}
+
+impl DepthCamera {
+    /// Open `device` for `Z16` depth, queuing behind other users until
+    /// `deadline`. `scale` is the depth unit in millimetres, 1 for RealSense.
+    pub fn open_until(device: &str, deadline: Instant, scale: f32) -> Result<Self> {
+        let lock = CameraLock::acquire(device, deadline)?;
+        let dev = Device::with_path(video::resolve_device(device)?).camera("open depth camera")?;
+        let fmt = dev.format().camera("get format")?;
+        let z16 = FourCC::new(b"Z16 ");
+        let fmt = dev
+            .set_format(&Format::new(fmt.width, fmt.height, z16))
+            .unwrap_or(fmt);
+        if fmt.fourcc != z16 {
+            return Err(Error::camera(format!(
+                "{} delivers {} rather than Z16 depth",
+                device, fmt.fourcc
+            )));
+        }
+        let stream = Stream::with_buffers(&dev, Type::VideoCapture, 4).camera("stream")?;
+        Ok(Self {
+            stream,
+            width: fmt.width,
+            height: fmt.height,
+            scale,
+            _lock: lock,
+        })
+    }
+
+    #[tracing::instrument(name = "capture_depth", level = "debug", skip_all)]
+    pub fn frame(&mut self) -> Result<DepthFrame> {
+        let (data, _) = self.stream.next().camera("capture depth frame")?;
+        let pixels = (self.width * self.height) as usize;
+        if data.len() < pixels * 2 {
+            return Err(Error::camera("short Z16 buffer"));
+        }
+        let depth = data[..pixels * 2]
+            .chunks_exact(2)
+            .map(|px| {
+                let raw = u16::from_le_bytes([px[0], px[1]]) as f32;
+                (raw * self.scale).min(u16::MAX as f32) as u16
+            })
+            .collect();
+        Ok(DepthFrame {
+            width: self.width,
+            height: self.height,
+            depth,
+        })
+    }
+}
+
+/// Shape of the face surface in a depth frame
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Relief {
+    /// Fraction of the face region with a usable reading
+    pub coverage: f32,
+    /// Median distance to the face, mm
+    pub distance: f32,
+    /// 90th percentile of the distance to the best-fitting plane, mm
+    pub relief: f32,
+}
+
+impl Relief {
+    /// Measure the central part of `bbox`, a face box in a `source`
+    /// (width, height) frame. `None` if the box misses the depth frame.
+    pub fn measure(frame: &DepthFrame, bbox: &[f32; 4], source: (u32, u32)) -> Option<Self> {
+        let sx = frame.width as f32 / source.0.max(1) as f32;
+        let sy = frame.height as f32 / source.1.max(1) as f32;
+        // The corners of a face box are hair and background
+        let [x, y, w, h] = *bbox;
+        let x0 = ((x + w * 0.2) * sx).max(0.0) as u32;
+        let y0 = ((y + h * 0.2) * sy).max(0.0) as u32;
+        let x1 = (((x + w * 0.8) * sx) as u32).min(frame.width);
+        let y1 = (((y + h * 0.8) * sy) as u32).min(frame.height);
+        if x1 <= x0 || y1 <= y0 {
+            return None;
+        }
+
+        let mut samples: Vec<(f32, f32, f32)> = Vec::new();
+        for py in y0..y1 {
+            for px in x0..x1 {
+                let z = frame.depth[(py * frame.width + px) as usize];
+                if z > 0 {
+                    samples.push((px as f32, py as f32, z as f32));
+                }
+            }
+        }
+        let total = ((x1 - x0) * (y1 - y0)) as f32;
+        if samples.is_empty() {
+            return Some(Self {
+                coverage: 0.0,
+                distance: 0.0,
+                relief: 0.0,
+            });
+        }
+
+        let distance = median(samples.iter().map(|s| s.2).collect());
+        samples.retain(|s| (s.2 - distance).abs() <= MAX_FACE_DEPTH);
+        let coverage = samples.len() as f32 / total;
+        let Some([a, b, c]) = fit_plane(&samples) else {
+            return Some(Self {
+                coverage,
+                distance,
+                relief: 0.0,
+            });
+        };
+        let mut residuals: Vec<f32> = samples
+            .iter()
+            .map(|&(x, y, z)| (z - (a * x + b * y + c)).abs())
+            .collect();
+        residuals.sort_unstable_by(f32::total_cmp);
+        let relief = residuals[(residuals.len() - 1) * 9 / 10];
+        Some(Self {
+            coverage,
+            distance,
+            relief,
+        })
+    }
+}
+
+/// Least-squares plane `z = a x + b y + c`; `None` when the points don't
+/// span one, e.g. a single row
+fn fit_plane(samples: &[(f32, f32, f32)]) -> Option<[f32; 3]> {
+    let n = samples.len() as f64;
+    // Centered on the mean, or the normal equations lose precision
+    let (mx, my, mz) = samples.iter().fold((0.0, 0.0, 0.0), |(x, y, z), s| {
+        (x + s.0 as f64 / n, y + s.1 as f64 / n, z + s.2 as f64 / n)
+    });
+    let (mut xx, mut xy, mut yy, mut xz, mut yz) = (0.0, 0.0, 0.0, 0.0, 0.0);
+    for &(x, y, z) in samples {
+        let (x, y, z) = (x as f64 - mx, y as f64 - my, z as f64 - mz);
+        xx += x * x;
+        xy += x * y;
+        yy += y * y;
+        xz += x * z;
+        yz += y * z;
+    }
+    let det = xx * yy - xy * xy;
+    if det.abs() < 1e-9 {
+        return None;
+    }
+    let a = (xz * yy - yz * xy) / det;
+    let b = (yz * xx - xz * xy) / det;
+    let c = mz - a * mx - b * my;
+    Some([a as f32, b as f32, c as f32])
+}
+
+fn median(mut values: Vec<f32>) -> f32 {
+    values.sort_unstable_by(f32::total_cmp);
+    values[values.len() / 2]
+}
+
+/// Limits a face's [`Relief`] must fall within to count as live
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct DepthGate {
+    /// Least fraction of the face with a depth reading
+    pub min_coverage: f32,
+    /// Farthest a face may be, mm
+    pub max_distance: f32,
+    /// Least departure from a plane, mm; photos and screens stay under it
+    pub min_relief: f32,
+    /// Most departure from a plane, mm; beyond it the box isn't one surface
+    pub max_relief: f32,
+}
+
+impl Default for DepthGate {
+    fn default() -> Self {
+        Self {
+            min_coverage: 0.5,
+            max_distance: 1200.0,
+            min_relief: 8.0,
+            max_relief: 80.0,
+        }
+    }
+}
+
+/// Why [`DepthGate::check`] rejected a face
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum NotLive {
+    /// Too little of the face had a depth reading
+    NoDepth(f32),
+    TooFar(f32),
+    Flat(f32),
+    Uneven(f32),
+}
+
+impl fmt::Display for NotLive {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            NotLive::NoDepth(coverage) => {
+                write!(
+                    f,
+                    "no depth for most of the face ({:.0}%)",
+                    coverage * 100.0
+                )
+            }
+            NotLive::TooFar(mm) => write!(f, "face too far for depth ({:.0} mm)", mm),
+            NotLive::Flat(mm) => write!(f, "face is flat ({:.1} mm relief)", mm),
+            NotLive::Uneven(mm) => write!(f, "face region uneven ({:.1} mm relief)", mm),
+        }
+    }
+}
+
+impl std::error::Error for NotLive {}
+
+impl DepthGate {
+    pub fn check(&self, relief: &Relief) -> Result<(), NotLive> {
+        if relief.coverage < self.min_coverage {
+            return Err(NotLive::NoDepth(relief.coverage));
+        }
+        if relief.distance > self.max_distance {
+            return Err(NotLive::TooFar(relief.distance));
+        }
+        if relief.relief < self.min_relief {
+            return Err(NotLive::Flat(relief.relief));
+        }
+        if relief.relief > self.max_relief {
+            return Err(NotLive::Uneven(relief.relief));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// 100x100 frame of a tilted plane at ~500 mm, plus `bump(x, y)`
+    fn frame(bump: impl Fn(f32, f32) -> f32) -> DepthFrame {
+        let depth = (0..100 * 100)
+            .map(|i| {
+                let (x, y) = ((i % 100) as f32, (i / 100) as f32);
+                (500.0 + 0.8 * x - 0.3 * y + bump(x, y)) as u16
+            })
+            .collect();
+        DepthFrame {
+            width: 100,
+            height: 100,
+            depth,
+        }
+    }
+
+    #[test]
+    fn test_relief() {
+        let bbox = [20.0, 20.0, 60.0, 60.0];
+        let gate = DepthGate::default();
+
+        let photo = Relief::measure(&frame(|_, _| 0.0), &bbox, (100, 100)).unwrap();
+        assert!(photo.coverage > 0.99);
+        assert!(photo.relief < 1.0);
+        assert!(matches!(gate.check(&photo), Err(NotLive::Flat(_))));
+
+        // A 30 mm "nose" in the middle of the face
+        let face = frame(|x, y| {
+            let d2 = (x - 50.0).powi(2) + (y - 50.0).powi(2);
+            -30.0 * (-d2 / 200.0).exp()
+        });
+        let live = Relief::measure(&face, &bbox, (100, 100)).unwrap();
+        assert!(live.relief > gate.min_relief, "{:?}", live);
+        assert_eq!(gate.check(&live), Ok(()));
+
+        // Same box on a frame twice the depth camera's resolution
+        let scaled = [40.0, 40.0, 120.0, 120.0];
+        assert_eq!(Relief::measure(&face, &scaled, (200, 200)), Some(live));
+    }
+
+    #[test]
+    fn test_no_depth() {
+        let empty = DepthFrame {
+            width: 10,
+            height: 10,
+            depth: vec![0; 100],
+        };
+        let relief = Relief::measure(&empty, &[0.0, 0.0, 10.0, 10.0], (10, 10)).unwrap();
+        assert!(matches!(
+            DepthGate::default().check(&relief),
+            Err(NotLive::NoDepth(_))
+        ));
+        assert!(Relief::measure(&empty, &[50.0, 50.0, 10.0, 10.0], (10, 10)).is_none());
+    }
+}
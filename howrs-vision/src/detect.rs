@@ -0,0 +1,119 @@
+//! Face detection on its own
+//!
+//! For programs that only need to find faces, such as a door camera that
+//! crops visitors or a photo tool: [`detect`] loads the detector on first use
+//! and keeps it for later calls, so there is no [`ort::session::Session`] to
+//! manage and no recognition model is loaded.
+//!
+//! ```no_run
+//! use howrs_vision::{detect, DetectOptions};
+//!
+//! let img = image::open("visitor.jpg")?;
+//! let opts = DetectOptions {
+//!     score_threshold: 0.8,
+//!     ..DetectOptions::default()
+//! };
+//! for face in detect(&img, &opts)? {
+//!     println!("{:?} {:.2}", face.bbox, face.score);
+//! }
+//! # Ok::<(), Box<dyn std::error::Error>>(())
+//! ```
+
+use crate::detector::{self, Backend, Detector};
+use crate::error::Result;
+use crate::face::{Detection, SizeFilter};
+use crate::model::Provider;
+use crate::normalize::Normalization;
+use crate::pipeline::{Search, DEFAULT_NMS_THRESHOLD, DEFAULT_SCORE_THRESHOLD};
+use image::DynamicImage;
+use std::path::PathBuf;
+use std::sync::{Mutex, PoisonError};
+
+/// Which detector [`detect`] uses and how it filters faces
+#[derive(Debug, Clone, PartialEq)]
+pub struct DetectOptions {
+    pub backend: Backend,
+    /// ONNX file of the detector; unset, the YuNet model embedded in the
+    /// binary. SCRFD needs one.
+    pub model: Option<PathBuf>,
+    pub provider: Provider,
+    /// Minimum detector confidence
+    pub score_threshold: f32,
+    /// Overlap above which the weaker of two detections is dropped
+    pub nms_threshold: f32,
+    /// Images are shrunk so their longer side is at most this many pixels
+    /// before detection; detections are still in full image coordinates
+    pub detect_size: Option<u32>,
+    /// Only look for faces in this centered fraction of the image
+    pub roi: Option<f32>,
+    /// Faces outside these bounds are dropped
+    pub size_filter: SizeFilter,
+    /// Applied to grayscale (IR) images before detection
+    pub normalization: Normalization,
+}
+
+impl Default for DetectOptions {
+    fn default() -> Self {
+        Self {
+            backend: Backend::default(),
+            model: None,
+            provider: Provider::default(),
+            score_threshold: DEFAULT_SCORE_THRESHOLD,
+            nms_threshold: DEFAULT_NMS_THRESHOLD,
+            detect_size: None,
+            roi: None,
+            size_filter: SizeFilter::default(),
+            normalization: Normalization::default(),
+        }
+    }
+}
+
+/// Detector kept by [`detect`], with the options that chose it
+type Loaded = ((Backend, Option<PathBuf>, Provider), Box<dyn Detector>);
+
+static DETECTOR: Mutex<Option<Loaded>> = Mutex::new(None);
+
+/// Faces in `img`, highest scoring first, in `img`'s pixel coordinates.
+///
+/// The detector is loaded on the first call and reused while `backend`,
+/// `model` and `provider` stay the same. Calls from several threads take
+/// turns; give each thread a [`crate::Pipeline`] to detect in parallel.
+pub fn detect(img: &DynamicImage, opts: &DetectOptions) -> Result<Vec<Detection>> {
+    let key = (opts.backend, opts.model.clone(), opts.provider);
+    // A panic mid-detection leaves nothing half-updated worth discarding
+    let mut loaded = DETECTOR.lock().unwrap_or_else(PoisonError::into_inner);
+    if loaded.as_ref().map(|(k, _)| k) != Some(&key) {
+        let detector = detector::load_on(opts.backend, opts.model.as_deref(), opts.provider)?;
+        *loaded = Some((key, detector));
+    }
+    let (_, detector) = loaded.as_mut().expect("loaded above");
+
+    Search {
+        roi: opts.roi,
+        detect_size: opts.detect_size,
+        score_threshold: opts.score_threshold,
+        nms_threshold: opts.nms_threshold,
+        normalization: opts.normalization,
+        size_filter: opts.size_filter,
+    }
+    .run(detector.as_mut(), img)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_detect_options() {
+        let opts = DetectOptions::default();
+        assert_eq!(opts.backend, Backend::YuNet);
+        assert_eq!(opts.score_threshold, DEFAULT_SCORE_THRESHOLD);
+
+        // SCRFD isn't embedded, so there is nothing to load without a file
+        let opts = DetectOptions {
+            backend: Backend::Scrfd,
+            ..opts
+        };
+        assert!(detect(&DynamicImage::new_rgb8(64, 64), &opts).is_err());
+    }
+}
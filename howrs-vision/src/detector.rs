@@ -0,0 +1,305 @@
+//! Face detector backends behind one trait
+//!
+//! YuNet is embedded and the default. SCRFD (from InsightFace) copes better
+//! with strongly off-angle faces, e.g. from IR cameras mounted below the
+//! screen, but is not redistributable here and is loaded from a file.
+
+use crate::error::{Error, Result};
+use crate::face::{self, Detection, RawOutputs};
+use crate::model::{self, Provider};
+use crate::pool;
+use image::{DynamicImage, GenericImageView};
+use ort::session::Session;
+use std::path::Path;
+
+/// SCRFD input resolution
+const SCRFD_SIZE: u32 = 640;
+/// Feature map strides of the SCRFD heads, in output order
+const SCRFD_STRIDES: [u32; 3] = [8, 16, 32];
+/// Anchors per feature map location
+const SCRFD_ANCHORS: usize = 2;
+
+/// Finds faces in an image
+pub trait Detector: Send {
+    /// Detect faces, in the image's own pixel coordinates
+    fn detect(
+        &mut self,
+        img: &DynamicImage,
+        score_threshold: f32,
+        nms_threshold: f32,
+    ) -> Result<Vec<Detection>>;
+
+    /// Detect faces in several images, returning one list per image
+    fn detect_batch(
+        &mut self,
+        imgs: &[DynamicImage],
+        score_threshold: f32,
+        nms_threshold: f32,
+    ) -> Result<Vec<Vec<Detection>>> {
+        imgs.iter()
+            .map(|img| self.detect(img, score_threshold, nms_threshold))
+            .collect()
+    }
+
+    /// Detect faces within the centered `fraction` of `img` (0.6 keeps the
+    /// middle 60% of each side), reporting coordinates in the whole image.
+    ///
+    /// Detectors that size their input to the image run the smaller region
+    /// faster.
+    fn detect_roi(
+        &mut self,
+        img: &DynamicImage,
+        fraction: f32,
+        score_threshold: f32,
+        nms_threshold: f32,
+    ) -> Result<Vec<Detection>> {
+        let (x, y, w, h) = roi(img.dimensions(), fraction);
+        let crop = image::imageops::crop_imm(&*face::rgb_view(img), x, y, w, h).to_image();
+        let mut detections = self.detect(
+            &DynamicImage::ImageRgb8(crop),
+            score_threshold,
+            nms_threshold,
+        )?;
+
+        for d in &mut detections {
+            d.translate((x as f32, y as f32));
+        }
+        Ok(detections)
+    }
+}
+
+/// Available detector implementations
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Backend {
+    #[default]
+    YuNet,
+    Scrfd,
+}
+
+/// Load `backend`, from `model_path` if given, otherwise from the embedded
+/// model when there is one
+pub fn load(backend: Backend, model_path: Option<&Path>) -> Result<Box<dyn Detector>> {
+    load_on(backend, model_path, Provider::Auto)
+}
+
+/// [`load`] running on `provider`
+pub fn load_on(
+    backend: Backend,
+    model_path: Option<&Path>,
+    provider: Provider,
+) -> Result<Box<dyn Detector>> {
+    let session = |path| model::file_session_on(path, provider);
+    Ok(match (backend, model_path) {
+        (Backend::YuNet, None) => Box::new(YuNet::new(model::detector_session_on(provider)?)),
+        (Backend::YuNet, Some(path)) => Box::new(YuNet::new(session(path)?)),
+        (Backend::Scrfd, Some(path)) => Box::new(Scrfd::new(session(path)?)),
+        (Backend::Scrfd, None) => {
+            return Err(Error::model("the SCRFD detector needs a model file path"))
+        }
+    })
+}
+
+/// Centered `(x, y, w, h)` covering `fraction` of each side of `frame`
+fn roi(frame: (u32, u32), fraction: f32) -> (u32, u32, u32, u32) {
+    let fraction = fraction.clamp(0.1, 1.0);
+    let (width, height) = frame;
+    let w = ((width as f32 * fraction) as u32).max(1);
+    let h = ((height as f32 * fraction) as u32).max(1);
+    ((width - w) / 2, (height - h) / 2, w, h)
+}
+
+/// OpenCV's YuNet, see [`face::detect_faces`]
+pub struct YuNet {
+    session: Session,
+}
+
+impl YuNet {
+    pub fn new(session: Session) -> Self {
+        Self { session }
+    }
+}
+
+impl Detector for YuNet {
+    fn detect(
+        &mut self,
+        img: &DynamicImage,
+        score_threshold: f32,
+        nms_threshold: f32,
+    ) -> Result<Vec<Detection>> {
+        face::detect_faces(&mut self.session, img, score_threshold, nms_threshold)
+    }
+
+    fn detect_batch(
+        &mut self,
+        imgs: &[DynamicImage],
+        score_threshold: f32,
+        nms_threshold: f32,
+    ) -> Result<Vec<Vec<Detection>>> {
+        face::detect_faces_batch(&mut self.session, imgs, score_threshold, nms_threshold)
+    }
+}
+
+/// InsightFace SCRFD with keypoints (the `_bnkps`/`_kps` exports)
+pub struct Scrfd {
+    session: Session,
+}
+
+impl Scrfd {
+    pub fn new(session: Session) -> Self {
+        Self { session }
+    }
+}
+
+impl Detector for Scrfd {
+    #[tracing::instrument(name = "detect", level = "debug", skip_all, fields(backend = "scrfd"))]
+    fn detect(
+        &mut self,
+        img: &DynamicImage,
+        score_threshold: f32,
+        nms_threshold: f32,
+    ) -> Result<Vec<Detection>> {
+        let size = SCRFD_SIZE;
+        let (width, height) = img.dimensions();
+        let scale = size as f32 / width.max(height) as f32;
+        let new_width = (width as f32 * scale) as u32;
+        let new_height = (height as f32 * scale) as u32;
+        let offset_x = (size - new_width) / 2;
+        let offset_y = (size - new_height) / 2;
+
+        let plane = (size * size) as usize;
+        let mut input_data = pool::tensors().take(3 * plane);
+        face::letterbox_bgr(
+            &face::rgb_view(img),
+            (offset_x, offset_y, new_width, new_height),
+            size,
+            &mut input_data,
+        );
+        // SCRFD takes RGB scaled to about [-1, 1]; padding stays black
+        let (b_channel, rest) = input_data.split_at_mut(plane);
+        b_channel.swap_with_slice(&mut rest[plane..]);
+        input_data
+            .iter_mut()
+            .for_each(|v| *v = (*v - 127.5) / 128.0);
+
+        let output_data = face::run_detector(&mut self.session, &input_data, 1, size);
+        pool::tensors().recycle(input_data);
+
+        let mut detections = decode_scrfd(&output_data?, size, score_threshold)?;
+        for d in &mut detections {
+            d.translate((-(offset_x as f32), -(offset_y as f32)));
+            d.rescale((1.0 / scale, 1.0 / scale));
+        }
+
+        if nms_threshold < 1.0 {
+            detections = face::nms(&detections, nms_threshold);
+        }
+        Ok(detections)
+    }
+}
+
+/// Decode SCRFD outputs (scores, then boxes, then keypoints, per stride)
+/// into detections in canvas pixels
+fn decode_scrfd(outputs: &RawOutputs, size: u32, score_threshold: f32) -> Result<Vec<Detection>> {
+    let heads = SCRFD_STRIDES.len();
+    if outputs.len() != 3 * heads {
+        return Err(Error::inference(format!(
+            "expected {} SCRFD outputs (scores, boxes and keypoints per stride), got {}; \
+             is this a keypoint export?",
+            3 * heads,
+            outputs.len()
+        )));
+    }
+
+    let mut detections = Vec::new();
+    for (i, &stride) in SCRFD_STRIDES.iter().enumerate() {
+        let scores = &outputs[i].1;
+        let boxes = &outputs[i + heads].1;
+        let keypoints = &outputs[i + 2 * heads].1;
+
+        let grid = (size / stride) as usize;
+        let count = grid * grid * SCRFD_ANCHORS;
+        if scores.len() != count || boxes.len() != count * 4 || keypoints.len() != count * 10 {
+            return Err(Error::inference(format!(
+                "unexpected SCRFD output sizes for stride {}",
+                stride
+            )));
+        }
+
+        let s = stride as f32;
+        for (a, &score) in scores.iter().enumerate() {
+            if score < score_threshold {
+                continue;
+            }
+            // Anchors sit on the top-left corner of each cell, row-major
+            let cell = a / SCRFD_ANCHORS;
+            let cx = (cell % grid) as f32 * s;
+            let cy = (cell / grid) as f32 * s;
+
+            // Boxes are distances from the anchor to each side, in strides
+            let d = &boxes[a * 4..a * 4 + 4];
+            let (x1, y1) = (cx - d[0] * s, cy - d[1] * s);
+            let (x2, y2) = (cx + d[2] * s, cy + d[3] * s);
+
+            let mut landmarks = [0.0f32; 10];
+            for (p, k) in landmarks
+                .chunks_exact_mut(2)
+                .zip(keypoints[a * 10..a * 10 + 10].chunks_exact(2))
+            {
+                p[0] = cx + k[0] * s;
+                p[1] = cy + k[1] * s;
+            }
+
+            detections.push(Detection {
+                bbox: [x1, y1, x2 - x1, y2 - y1],
+                score,
+                landmarks,
+            });
+        }
+    }
+    Ok(detections)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_roi() {
+        assert_eq!(roi((640, 480), 0.5), (160, 120, 320, 240));
+        assert_eq!(roi((640, 480), 1.0), (0, 0, 640, 480));
+        // Out of range fractions are clamped rather than rejected
+        assert_eq!(roi((640, 480), 2.0), (0, 0, 640, 480));
+    }
+
+    #[test]
+    fn test_decode_scrfd() {
+        // 64px canvas: grids of 8x8, 4x4 and 2x2 cells
+        let size = 64;
+        let mut outputs: RawOutputs = Vec::new();
+        let counts: Vec<usize> = SCRFD_STRIDES
+            .iter()
+            .map(|&s| ((size / s) * (size / s)) as usize * SCRFD_ANCHORS)
+            .collect();
+        for &n in &counts {
+            outputs.push((vec![n as i64, 1], vec![0.0; n]));
+        }
+        for &n in &counts {
+            outputs.push((vec![n as i64, 4], vec![1.0; n * 4]));
+        }
+        for &n in &counts {
+            outputs.push((vec![n as i64, 10], vec![0.5; n * 10]));
+        }
+        // Second anchor of cell (x=1, y=2) at stride 16
+        let anchor = (2 * 4 + 1) * SCRFD_ANCHORS + 1;
+        outputs[1].1[anchor] = 0.9;
+
+        let detections = decode_scrfd(&outputs, size, 0.5).unwrap();
+        assert_eq!(detections.len(), 1);
+        let d = &detections[0];
+        assert_eq!(d.bbox, [0.0, 16.0, 32.0, 32.0]);
+        assert_eq!(&d.landmarks[..2], &[24.0, 40.0]);
+
+        outputs.pop();
+        assert!(decode_scrfd(&outputs, size, 0.5).is_err());
+    }
+}
@@ -0,0 +1,124 @@
+//! Drawing on frames for debug output and visualizations
+//!
+//! Plain pixel plotting, clipped to the image, and a built-in 3x5 font
+//! covering what scores need (`0-9`, `.` and `-`), so nothing has to be
+//! loaded. See [`crate::face::annotate`] for detections.
+
+use image::{Rgb, RgbImage};
+
+pub const GREEN: [u8; 3] = [0, 255, 0];
+pub const RED: [u8; 3] = [255, 0, 0];
+pub const YELLOW: [u8; 3] = [255, 255, 0];
+
+/// Pixels per glyph dot
+pub const SCALE: i64 = 3;
+
+/// Height of a line of [`text`], in pixels
+pub const TEXT_HEIGHT: i64 = 5 * SCALE;
+
+/// Set one pixel, ignoring coordinates outside the image
+pub fn point(img: &mut RgbImage, x: i64, y: i64, color: [u8; 3]) {
+    let (w, h) = img.dimensions();
+    if x >= 0 && y >= 0 && (x as u32) < w && (y as u32) < h {
+        img.put_pixel(x as u32, y as u32, Rgb(color));
+    }
+}
+
+/// Outline of an `[x, y, w, h]` box
+pub fn rect(img: &mut RgbImage, bbox: [f32; 4], color: [u8; 3]) {
+    let x0 = bbox[0].round() as i64;
+    let y0 = bbox[1].round() as i64;
+    let x1 = (bbox[0] + bbox[2]).round() as i64;
+    let y1 = (bbox[1] + bbox[3]).round() as i64;
+    for x in x0..=x1 {
+        point(img, x, y0, color);
+        point(img, x, y1, color);
+    }
+    for y in y0..=y1 {
+        point(img, x0, y, color);
+        point(img, x1, y, color);
+    }
+}
+
+/// `+` centered on `(x, y)`, `radius` pixels to each side
+pub fn cross(img: &mut RgbImage, x: f32, y: f32, radius: i64, color: [u8; 3]) {
+    let (x, y) = (x.round() as i64, y.round() as i64);
+    for o in -radius..=radius {
+        point(img, x + o, y, color);
+        point(img, x, y + o, color);
+    }
+}
+
+/// 3x5 bitmaps for `0-9`, `.` and `-`, one row per entry, MSB on the left
+fn glyph(c: char) -> Option<[u8; 5]> {
+    Some(match c {
+        '0' => [0b111, 0b101, 0b101, 0b101, 0b111],
+        '1' => [0b010, 0b110, 0b010, 0b010, 0b111],
+        '2' => [0b111, 0b001, 0b111, 0b100, 0b111],
+        '3' => [0b111, 0b001, 0b111, 0b001, 0b111],
+        '4' => [0b101, 0b101, 0b111, 0b001, 0b001],
+        '5' => [0b111, 0b100, 0b111, 0b001, 0b111],
+        '6' => [0b111, 0b100, 0b111, 0b101, 0b111],
+        '7' => [0b111, 0b001, 0b010, 0b010, 0b010],
+        '8' => [0b111, 0b101, 0b111, 0b101, 0b111],
+        '9' => [0b111, 0b101, 0b111, 0b001, 0b111],
+        '.' => [0b000, 0b000, 0b000, 0b000, 0b010],
+        '-' => [0b000, 0b000, 0b111, 0b000, 0b000],
+        _ => return None,
+    })
+}
+
+/// Draw `text` with its top left corner at `(x, y)`; unknown characters are
+/// left blank
+pub fn text(img: &mut RgbImage, text: &str, x: i64, y: i64, color: [u8; 3]) {
+    for (i, c) in text.chars().enumerate() {
+        let Some(rows) = glyph(c) else { continue };
+        let left = x + i as i64 * 4 * SCALE;
+        for (row, bits) in rows.iter().enumerate() {
+            for col in 0..3 {
+                if bits & (0b100 >> col) == 0 {
+                    continue;
+                }
+                for dy in 0..SCALE {
+                    for dx in 0..SCALE {
+                        let py = y + row as i64 * SCALE + dy;
+                        point(img, left + col * SCALE + dx, py, color);
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_text() {
+        let mut img = RgbImage::new(40, 20);
+        text(&mut img, "1.0", 0, 0, GREEN);
+        let lit = |x: u32, y: u32| img.get_pixel(x, y).0 == GREEN;
+
+        // '1': top middle dot lit, top left dot dark
+        assert!(lit(SCALE as u32, 0));
+        assert!(!lit(0, 0));
+        // '.': only the bottom middle dot of the second glyph
+        let dot = 4 * SCALE as u32;
+        assert!(lit(dot + SCALE as u32, 4 * SCALE as u32));
+        assert!(!lit(dot + SCALE as u32, 0));
+        // Off-image text is clipped rather than panicking
+        text(&mut img, "888", 35, 15, RED);
+    }
+
+    #[test]
+    fn test_shapes_clip() {
+        let mut img = RgbImage::new(10, 10);
+        rect(&mut img, [2.0, 2.0, 4.0, 4.0], GREEN);
+        assert_eq!(img.get_pixel(2, 4).0, GREEN);
+        assert_eq!(img.get_pixel(4, 4).0, [0, 0, 0]);
+        cross(&mut img, 0.0, 9.0, 3, RED);
+        assert_eq!(img.get_pixel(3, 9).0, RED);
+        assert_eq!(img.get_pixel(0, 6).0, RED);
+    }
+}
@@ -0,0 +1,194 @@
+//! Errors of the vision pipeline
+//!
+//! Every failure is classified by where it happened so callers (PAM, the
+//! daemon, the C API) can tell a missing camera from a broken model without
+//! matching on messages. An error displays only its own context; the causes
+//! are reachable through [`std::error::Error::source`] and shown by
+//! [`Report`].
+
+use crate::cancel::Cancelled;
+use crate::quality::LowQuality;
+use std::fmt::{self, Display};
+
+/// Any error that can be the cause of an [`Error`]
+pub type BoxError = Box<dyn std::error::Error + Send + Sync + 'static>;
+
+pub type Result<T, E = Error> = std::result::Result<T, E>;
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    /// Opening, configuring or reading a camera, video or image
+    #[error("{context}")]
+    Camera {
+        context: String,
+        #[source]
+        source: Option<BoxError>,
+    },
+    /// Finding, fetching, verifying or loading a model
+    #[error("{context}")]
+    Model {
+        context: String,
+        #[source]
+        source: Option<BoxError>,
+    },
+    /// Running a model or making sense of what it returned
+    #[error("{context}")]
+    Inference {
+        context: String,
+        #[source]
+        source: Option<BoxError>,
+    },
+    /// Malformed input, such as an embedding of the wrong size
+    #[error("{context}")]
+    Invalid {
+        context: String,
+        #[source]
+        source: Option<BoxError>,
+    },
+    /// Reading or writing a file other than a model
+    #[error("{context}")]
+    Io {
+        context: String,
+        #[source]
+        source: Option<BoxError>,
+    },
+    /// There was no face in the image
+    #[error("no face detected in image")]
+    NoFace,
+    /// The face failed the pipeline's quality gate
+    #[error(transparent)]
+    LowQuality(#[from] LowQuality),
+    /// Stopped through a [`crate::cancel::CancelToken`]
+    #[error(transparent)]
+    Cancelled(#[from] Cancelled),
+}
+
+macro_rules! kinds {
+    ($($name:ident => $variant:ident),*) => {
+        impl Error {
+            $(
+                pub fn $name(context: impl Display) -> Self {
+                    Error::$variant {
+                        context: context.to_string(),
+                        source: None,
+                    }
+                }
+            )*
+
+            /// Add `context` in front of the message, keeping the kind of
+            /// failure
+            pub fn context(self, context: impl Display) -> Self {
+                match self {
+                    $(
+                        Error::$variant { .. } => Error::$variant {
+                            context: context.to_string(),
+                            source: Some(Box::new(self)),
+                        },
+                    )*
+                    Error::NoFace | Error::LowQuality(_) | Error::Cancelled(_) => self,
+                }
+            }
+        }
+
+        /// Classify a foreign error, e.g. `file.read(..).camera("read frame")?`;
+        /// on an `Option`, `None` becomes an error with only the context
+        pub trait ResultExt<T> {
+            $(fn $name(self, context: impl Display) -> Result<T>;)*
+        }
+
+        impl<T, E: Into<BoxError>> ResultExt<T> for std::result::Result<T, E> {
+            $(
+                fn $name(self, context: impl Display) -> Result<T> {
+                    self.map_err(|e| Error::$variant {
+                        context: context.to_string(),
+                        source: Some(e.into()),
+                    })
+                }
+            )*
+        }
+
+        impl<T> ResultExt<T> for Option<T> {
+            $(
+                fn $name(self, context: impl Display) -> Result<T> {
+                    self.ok_or_else(|| Error::$name(context))
+                }
+            )*
+        }
+    };
+}
+
+kinds!(
+    camera => Camera,
+    model => Model,
+    inference => Inference,
+    invalid => Invalid,
+    io => Io
+);
+
+impl Error {
+    /// Whether the work was stopped through a [`crate::cancel::CancelToken`]
+    pub fn is_cancelled(&self) -> bool {
+        matches!(self, Error::Cancelled(_))
+    }
+}
+
+/// ONNX Runtime failures not classified at the call site happen while
+/// running a session
+impl From<ort::Error> for Error {
+    fn from(e: ort::Error) -> Self {
+        Error::Inference {
+            context: "onnx runtime".to_string(),
+            source: Some(e.into()),
+        }
+    }
+}
+
+/// Shows an error followed by its causes, `outer: inner: innermost`, like
+/// `{:#}` does for an `anyhow::Error`
+pub struct Report<'a>(pub &'a (dyn std::error::Error + 'static));
+
+impl Display for Report<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0)?;
+        let mut source = self.0.source();
+        while let Some(e) = source {
+            write!(f, ": {}", e)?;
+            source = e.source();
+        }
+        Ok(())
+    }
+}
+
+/// Add context to an [`Error`] without changing its kind
+pub trait Context<T> {
+    fn context(self, context: impl Display) -> Result<T>;
+}
+
+impl<T> Context<T> for Result<T> {
+    fn context(self, context: impl Display) -> Result<T> {
+        self.map_err(|e| e.context(context))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_context_keeps_kind() {
+        let io = std::io::Error::new(std::io::ErrorKind::NotFound, "no such device");
+        let err = Err::<(), _>(io)
+            .camera("open camera")
+            .context("reopening camera /dev/video0")
+            .unwrap_err();
+        assert!(matches!(err, Error::Camera { .. }));
+        assert_eq!(
+            Report(&err).to_string(),
+            "reopening camera /dev/video0: open camera: no such device"
+        );
+        assert_eq!(err.to_string(), "reopening camera /dev/video0");
+
+        let err = Error::from(Cancelled).context("detecting faces");
+        assert!(err.is_cancelled());
+    }
+}
@@ -0,0 +1,444 @@
+//! Offline evaluation over a labelled image folder
+//!
+//! The folder is laid out as `<dir>/<person>/<images>`. Every image is
+//! embedded once, then all pairs are compared to build the same-person and
+//! different-person similarity distributions, an ROC curve, a suggested
+//! match threshold and a fitted [`Calibration`].
+//!
+//! Images in [`STRANGERS_DIR`], such as group photos of people outside the
+//! set, add every face in them as an impostor for all labelled faces.
+//!
+//! The embeddings can be saved as an impostor set (see [`write_embeddings`])
+//! that `howrs threshold` compares a user's own faces against, to pick a
+//! threshold for a target false accept rate with [`threshold_for_far`].
+
+use crate::calibration::Calibration;
+use crate::error::{Context, Error, Result, ResultExt};
+use crate::face::{self, Embedding};
+use crate::normalize::Normalization;
+use crate::pipeline::Pipeline;
+use std::path::{Path, PathBuf};
+
+/// Highest false accept rate tolerated by [`Report::suggested_threshold`]
+pub const MAX_FALSE_ACCEPT_RATE: f32 = 0.001;
+/// Number of thresholds swept when building the ROC curve
+pub const ROC_STEPS: usize = 200;
+/// Folder whose faces belong to nobody in the set
+pub const STRANGERS_DIR: &str = "_strangers";
+/// Impostor set looked up in the model directory
+pub const IMPOSTORS_FILE: &str = "impostors.bin";
+/// Leads an embedding file, followed by the dimension and count as
+/// little-endian `u32`s, then the values as little-endian `f32`s
+const EMBEDDINGS_MAGIC: &[u8; 4] = b"HWEM";
+
+/// Summary statistics of one similarity distribution
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Distribution {
+    pub count: usize,
+    pub mean: f32,
+    pub std_dev: f32,
+    pub min: f32,
+    pub max: f32,
+}
+
+impl Distribution {
+    pub fn from_scores(scores: &[f32]) -> Option<Self> {
+        if scores.is_empty() {
+            return None;
+        }
+        let n = scores.len() as f32;
+        let mean = scores.iter().sum::<f32>() / n;
+        let var = scores.iter().map(|s| (s - mean).powi(2)).sum::<f32>() / n;
+        Some(Self {
+            count: scores.len(),
+            mean,
+            std_dev: var.sqrt(),
+            min: scores.iter().copied().fold(f32::INFINITY, f32::min),
+            max: scores.iter().copied().fold(f32::NEG_INFINITY, f32::max),
+        })
+    }
+}
+
+/// One point of the ROC curve: pairs scoring `>= threshold` are accepted
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RocPoint {
+    pub threshold: f32,
+    /// Fraction of same-person pairs accepted
+    pub true_accept_rate: f32,
+    /// Fraction of different-person pairs accepted
+    pub false_accept_rate: f32,
+}
+
+#[derive(Debug, Clone)]
+pub struct Report {
+    pub people: usize,
+    pub images: usize,
+    /// Faces found in [`STRANGERS_DIR`]
+    pub strangers: usize,
+    /// Images where no face was detected
+    pub skipped: Vec<PathBuf>,
+    pub same: Vec<f32>,
+    pub different: Vec<f32>,
+    pub roc: Vec<RocPoint>,
+    /// Lowest threshold keeping the false accept rate within
+    /// [`MAX_FALSE_ACCEPT_RATE`], if both kinds of pairs were compared
+    pub suggested_threshold: Option<f32>,
+    /// Similarity-to-probability curve fitted to these pairs
+    pub calibration: Option<Calibration>,
+    /// Every labelled and stranger embedding, filled in by [`run`]
+    pub embeddings: Vec<Embedding>,
+}
+
+/// A threshold picked for a target false accept rate
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct FarThreshold {
+    pub threshold: f32,
+    /// Fraction of the impostor pairs accepted at `threshold`
+    pub false_accept_rate: f32,
+    /// Fraction of the genuine pairs accepted at `threshold`
+    pub true_accept_rate: f32,
+    /// Impostor pairs compared; below `1 / far` the target can't be
+    /// resolved and `threshold` rejects every one of them
+    pub impostor_pairs: usize,
+}
+
+/// Embed every image under `dir` and evaluate all pairs
+pub fn run(dir: &Path, flip_augment: bool, normalization: Normalization) -> Result<Report> {
+    let mut pipeline = Pipeline::new()?
+        .with_flip_augment(flip_augment)
+        .with_normalization(normalization);
+    let mut labelled = Vec::new();
+    let mut strangers = Vec::new();
+    let mut skipped = Vec::new();
+    let mut images = 0;
+
+    for (person, paths) in scan_dir(dir)? {
+        if person == STRANGERS_DIR {
+            for path in paths {
+                let img = image::open(&path).camera(format!("opening {}", path.display()))?;
+                images += 1;
+                let faces = pipeline.process_all(&img)?;
+                if faces.is_empty() {
+                    tracing::warn!("no face detected in {}", path.display());
+                    skipped.push(path);
+                }
+                strangers.extend(faces.into_iter().map(|(_, embedding)| embedding));
+            }
+            continue;
+        }
+
+        for chunk in paths.chunks(face::MAX_BATCH) {
+            let imgs = chunk
+                .iter()
+                .map(|path| image::open(path).camera(format!("opening {}", path.display())))
+                .collect::<Result<Vec<_>>>()?;
+            images += imgs.len();
+
+            let detections = pipeline.detect_best_batch(&imgs)?;
+            for ((path, img), detection) in chunk.iter().zip(&imgs).zip(detections) {
+                match detection {
+                    Some(detection) => {
+                        let embedding = pipeline.encode_detection(img, &detection)?;
+                        labelled.push((person.clone(), embedding));
+                    }
+                    None => {
+                        tracing::warn!("no face detected in {}", path.display());
+                        skipped.push(path.clone());
+                    }
+                }
+            }
+        }
+    }
+
+    let mut report = evaluate_with_strangers(&labelled, &strangers);
+    report.images = images;
+    report.skipped = skipped;
+    report.embeddings = labelled.into_iter().map(|(_, e)| e).collect();
+    report.embeddings.extend(strangers);
+    Ok(report)
+}
+
+/// Compare every pair of labelled embeddings
+pub fn evaluate(labelled: &[(String, Embedding)]) -> Report {
+    evaluate_with_strangers(labelled, &[])
+}
+
+/// [`evaluate`], also comparing each of `strangers` against every labelled
+/// embedding as a different-person pair
+pub fn evaluate_with_strangers(
+    labelled: &[(String, Embedding)],
+    strangers: &[Embedding],
+) -> Report {
+    let mut same = Vec::new();
+    let mut different = Vec::new();
+    for (i, (person_a, a)) in labelled.iter().enumerate() {
+        for (person_b, b) in &labelled[i + 1..] {
+            let score = face::match_embedding(a, b);
+            if person_a == person_b {
+                same.push(score);
+            } else {
+                different.push(score);
+            }
+        }
+        different.extend(strangers.iter().map(|s| face::match_embedding(a, s)));
+    }
+
+    let mut people: Vec<&str> = labelled.iter().map(|(p, _)| p.as_str()).collect();
+    people.sort_unstable();
+    people.dedup();
+
+    let roc = roc_curve(&same, &different, ROC_STEPS);
+    // Without impostor pairs every threshold trivially has a zero false accept rate
+    let suggested_threshold = if different.is_empty() || same.is_empty() {
+        None
+    } else {
+        suggest_threshold(&roc, MAX_FALSE_ACCEPT_RATE)
+    };
+    let calibration = Calibration::fit(&same, &different);
+
+    Report {
+        people: people.len(),
+        images: labelled.len(),
+        strangers: strangers.len(),
+        skipped: Vec::new(),
+        same,
+        different,
+        roc,
+        suggested_threshold,
+        calibration,
+        embeddings: Vec::new(),
+    }
+}
+
+/// Sweep `steps + 1` thresholds evenly over the cosine range `[-1, 1]`
+pub fn roc_curve(same: &[f32], different: &[f32], steps: usize) -> Vec<RocPoint> {
+    let rate = |scores: &[f32], t: f32| {
+        if scores.is_empty() {
+            0.0
+        } else {
+            scores.iter().filter(|&&s| s >= t).count() as f32 / scores.len() as f32
+        }
+    };
+
+    (0..=steps)
+        .map(|i| {
+            let threshold = -1.0 + 2.0 * i as f32 / steps as f32;
+            RocPoint {
+                threshold,
+                true_accept_rate: rate(same, threshold),
+                false_accept_rate: rate(different, threshold),
+            }
+        })
+        .collect()
+}
+
+/// Lowest ROC threshold whose false accept rate is at most `max_far`
+pub fn suggest_threshold(roc: &[RocPoint], max_far: f32) -> Option<f32> {
+    roc.iter()
+        .find(|p| p.false_accept_rate <= max_far)
+        .map(|p| p.threshold)
+}
+
+/// Lowest threshold accepting at most a `far` fraction of the `different`
+/// (impostor) scores, computed exactly rather than over the ROC steps.
+/// `None` without impostor scores.
+pub fn threshold_for_far(same: &[f32], different: &[f32], far: f32) -> Option<FarThreshold> {
+    if different.is_empty() {
+        return None;
+    }
+    let mut sorted = different.to_vec();
+    sorted.sort_unstable_by(|a, b| b.total_cmp(a));
+    let allowed = (far.max(0.0) * sorted.len() as f32).floor() as usize;
+    // Just above the first impostor score that must be rejected
+    let threshold = sorted.get(allowed).map_or(-1.0, |&score| score.next_up());
+    let rate = |scores: &[f32]| {
+        scores.iter().filter(|&&s| s >= threshold).count() as f32 / scores.len().max(1) as f32
+    };
+    Some(FarThreshold {
+        threshold,
+        false_accept_rate: rate(different),
+        true_accept_rate: rate(same),
+        impostor_pairs: different.len(),
+    })
+}
+
+/// Save `embeddings`, which must share one dimension, as an impostor set
+pub fn write_embeddings(path: &Path, embeddings: &[Embedding]) -> Result<()> {
+    let dim = embeddings.first().map_or(0, Embedding::dim);
+    if embeddings.iter().any(|e| e.dim() != dim) {
+        return Err(Error::invalid("embeddings of different dimensions"));
+    }
+    let mut data = Vec::with_capacity(12 + embeddings.len() * dim * 4);
+    data.extend_from_slice(EMBEDDINGS_MAGIC);
+    data.extend_from_slice(&(dim as u32).to_le_bytes());
+    data.extend_from_slice(&(embeddings.len() as u32).to_le_bytes());
+    for value in embeddings.iter().flat_map(Embedding::as_slice) {
+        data.extend_from_slice(&value.to_le_bytes());
+    }
+    std::fs::write(path, data).io(format!("writing {}", path.display()))
+}
+
+/// Load an impostor set written by [`write_embeddings`]
+pub fn read_embeddings(path: &Path) -> Result<Vec<Embedding>> {
+    let data = std::fs::read(path).io(format!("reading {}", path.display()))?;
+    parse_embeddings(&data).context(format!("parsing {}", path.display()))
+}
+
+fn parse_embeddings(data: &[u8]) -> Result<Vec<Embedding>> {
+    let Some((header, values)) = data.split_at_checked(12) else {
+        return Err(Error::invalid("truncated header"));
+    };
+    if &header[..4] != EMBEDDINGS_MAGIC {
+        return Err(Error::invalid("not an embedding file"));
+    }
+    let word = |i: usize| u32::from_le_bytes(header[i..i + 4].try_into().unwrap()) as usize;
+    let (dim, count) = (word(4), word(8));
+    let expected = dim.checked_mul(count).and_then(|n| n.checked_mul(4));
+    if dim == 0 || expected != Some(values.len()) {
+        return Err(Error::invalid(format!(
+            "{} bytes of values for {} embeddings of {}",
+            values.len(),
+            count,
+            dim
+        )));
+    }
+    values
+        .chunks_exact(dim * 4)
+        .map(|row| {
+            let row = row
+                .chunks_exact(4)
+                .map(|v| f32::from_le_bytes(v.try_into().unwrap()))
+                .collect();
+            Embedding::with_dim(row, dim)
+        })
+        .collect()
+}
+
+/// List `<person>/<file>` entries, sorted for reproducible output
+fn scan_dir(dir: &Path) -> Result<Vec<(String, Vec<PathBuf>)>> {
+    let mut people = Vec::new();
+    let context = || format!("reading {}", dir.display());
+    for entry in std::fs::read_dir(dir).io(context())? {
+        let entry = entry.io(context())?;
+        if !entry.file_type().io(context())?.is_dir() {
+            continue;
+        }
+        let path = entry.path();
+        let mut paths: Vec<PathBuf> = std::fs::read_dir(&path)
+            .io(format!("reading {}", path.display()))?
+            .filter_map(|e| e.ok())
+            .map(|e| e.path())
+            .filter(|p| image::ImageFormat::from_path(p).is_ok())
+            .collect();
+        paths.sort();
+        if !paths.is_empty() {
+            people.push((entry.file_name().to_string_lossy().into_owned(), paths));
+        }
+    }
+    people.sort();
+    Ok(people)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn unit(angle: f32) -> Embedding {
+        let mut v = vec![0.0; 128];
+        v[0] = angle.cos();
+        v[1] = angle.sin();
+        Embedding::new(v).unwrap()
+    }
+
+    #[test]
+    fn test_evaluate_splits_pairs() {
+        let labelled = vec![
+            ("a".to_string(), unit(0.0)),
+            ("a".to_string(), unit(0.1)),
+            ("b".to_string(), unit(1.5)),
+        ];
+        let report = evaluate(&labelled);
+        assert_eq!(report.people, 2);
+        assert_eq!(report.same.len(), 1);
+        assert_eq!(report.different.len(), 2);
+        assert!(report.same[0] > 0.99);
+
+        // Threshold must reject both impostor pairs but keep the genuine one
+        let t = report.suggested_threshold.unwrap();
+        assert!(report.different.iter().all(|&s| s < t));
+        assert!(report.same[0] >= t);
+    }
+
+    #[test]
+    fn test_strangers_are_impostors() {
+        let labelled = vec![("a".to_string(), unit(0.0)), ("a".to_string(), unit(0.1))];
+        let report = evaluate_with_strangers(&labelled, &[unit(1.5), unit(2.0)]);
+        assert_eq!(report.people, 1);
+        assert_eq!(report.strangers, 2);
+        assert_eq!(report.same.len(), 1);
+        // Each stranger against each labelled face, never against each other
+        assert_eq!(report.different.len(), 4);
+        assert!(report.suggested_threshold.is_some());
+    }
+
+    #[test]
+    fn test_threshold_for_far() {
+        let same = [0.9, 0.8, 0.495];
+        let different: Vec<f32> = (0..1000).map(|i| i as f32 / 1000.0 - 0.5).collect();
+        let t = threshold_for_far(&same, &different, 0.01).unwrap();
+        // The ten highest impostor scores are let through, the eleventh isn't
+        assert!(t.threshold > different[989] && t.threshold <= different[990]);
+        assert_eq!(t.false_accept_rate, 0.01);
+        assert_eq!(t.true_accept_rate, 1.0);
+
+        // Too few impostors for the target: all of them are rejected
+        let t = threshold_for_far(&same, &different, 1e-4).unwrap();
+        assert_eq!(t.false_accept_rate, 0.0);
+        assert!(t.threshold > different[999]);
+        assert!((t.true_accept_rate - 2.0 / 3.0).abs() < 1e-6);
+
+        assert_eq!(threshold_for_far(&same, &[], 0.01), None);
+        assert_eq!(
+            threshold_for_far(&same, &[0.1], 1.0).unwrap().threshold,
+            -1.0
+        );
+    }
+
+    #[test]
+    fn test_embeddings_file() {
+        let dir = std::env::temp_dir().join(format!("howrs-impostors-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join(IMPOSTORS_FILE);
+        write_embeddings(&path, &[unit(0.0), unit(1.0)]).unwrap();
+        let read = read_embeddings(&path).unwrap();
+        assert_eq!(read.len(), 2);
+        assert_eq!(read[1].as_slice(), unit(1.0).as_slice());
+
+        let data = std::fs::read(&path).unwrap();
+        assert!(parse_embeddings(&data[..data.len() - 4]).is_err());
+        assert!(parse_embeddings(b"HWEM").is_err());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_roc_monotonic() {
+        let roc = roc_curve(&[0.9, 0.7, 0.5], &[0.1, 0.3, 0.6], 20);
+        assert_eq!(roc.len(), 21);
+        assert_eq!(roc[0].true_accept_rate, 1.0);
+        assert_eq!(roc[0].false_accept_rate, 1.0);
+        for w in roc.windows(2) {
+            assert!(w[1].true_accept_rate <= w[0].true_accept_rate);
+            assert!(w[1].false_accept_rate <= w[0].false_accept_rate);
+        }
+    }
+
+    #[test]
+    fn test_distribution() {
+        let d = Distribution::from_scores(&[0.2, 0.4, 0.6]).unwrap();
+        assert_eq!(d.count, 3);
+        assert!((d.mean - 0.4).abs() < 1e-6);
+        assert_eq!(d.min, 0.2);
+        assert_eq!(d.max, 0.6);
+        assert!(Distribution::from_scores(&[]).is_none());
+    }
+}
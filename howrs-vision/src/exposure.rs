@@ -0,0 +1,274 @@
+//! Exposure bracketing: when the first frames of a scan are too dark or
+//! washed out to find a face, step the camera's manual exposure (and gain,
+//! once exposure is at its limit) until one is found, then keep that setting
+//! for the rest of the scan.
+//!
+//! The controls are set through a second handle on the device, so this works
+//! alongside a [`Camera`](crate::Camera) that is streaming. The device's own
+//! settings are put back when the [`Bracketing`] is dropped.
+
+use crate::error::{Result, ResultExt};
+use crate::quality::{MAX_BRIGHTNESS, MIN_BRIGHTNESS};
+use v4l::control::{Control, Value};
+use v4l::Device;
+
+const CID_EXPOSURE_AUTO: u32 = 0x009a_0901;
+const CID_EXPOSURE_ABSOLUTE: u32 = 0x009a_0902;
+const CID_GAIN: u32 = 0x0098_0913;
+/// `V4L2_EXPOSURE_MANUAL`
+const EXPOSURE_MANUAL: i64 = 1;
+/// Frames skipped after a change, while the sensor applies it
+const SETTLE_FRAMES: usize = 2;
+/// Gain steps between its minimum and maximum
+const GAIN_STEPS: i64 = 4;
+
+/// Limits of an integer control
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Range {
+    pub min: i64,
+    pub max: i64,
+}
+
+impl Range {
+    fn clamp(&self, value: i64) -> i64 {
+        value.clamp(self.min, self.max)
+    }
+}
+
+/// Exposure time and gain, in the device's units
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Setting {
+    pub exposure: i64,
+    pub gain: Option<i64>,
+}
+
+/// Steps the exposure of one device, see the module docs
+pub struct Bracketing {
+    device: Device,
+    exposure: Range,
+    gain: Option<Range>,
+    current: Setting,
+    /// Controls to put back on drop, once anything was changed
+    original: Vec<(u32, i64)>,
+    changed: bool,
+    settle: usize,
+    /// A face was found: the setting stays
+    locked: bool,
+}
+
+impl Bracketing {
+    /// Open the controls of the device at `node`; `None` if it has no manual
+    /// exposure
+    pub fn open(node: &str) -> Result<Option<Self>> {
+        let device = Device::with_path(node).camera("open camera controls")?;
+        let controls = device.query_controls().camera("query camera controls")?;
+        let range = |id| {
+            controls.iter().find(|c| c.id == id).map(|c| Range {
+                min: c.minimum,
+                max: c.maximum,
+            })
+        };
+        let (Some(exposure), Some(_)) = (range(CID_EXPOSURE_ABSOLUTE), range(CID_EXPOSURE_AUTO))
+        else {
+            return Ok(None);
+        };
+        let gain = range(CID_GAIN);
+
+        let read = |id| match device.control(id) {
+            Ok(Control {
+                value: Value::Integer(value),
+                ..
+            }) => Some(value),
+            _ => None,
+        };
+        let mut original = Vec::new();
+        for id in [CID_EXPOSURE_AUTO, CID_EXPOSURE_ABSOLUTE, CID_GAIN] {
+            if let Some(value) = read(id) {
+                original.push((id, value));
+            }
+        }
+        let current = Setting {
+            exposure: exposure.clamp(read(CID_EXPOSURE_ABSOLUTE).unwrap_or(exposure.min)),
+            gain: gain.map(|gain| gain.clamp(read(CID_GAIN).unwrap_or(gain.min))),
+        };
+        Ok(Some(Self {
+            device,
+            exposure,
+            gain,
+            current,
+            original,
+            changed: false,
+            settle: 0,
+            locked: false,
+        }))
+    }
+
+    /// The setting in use
+    pub fn setting(&self) -> Setting {
+        self.current
+    }
+
+    /// Take in a frame of mean luma `brightness`, in which a face was found
+    /// or not, stepping the exposure if it's too dark or bright
+    pub fn observe(&mut self, brightness: f32, face: bool) -> Result<()> {
+        if self.locked {
+            return Ok(());
+        }
+        if face {
+            self.locked = true;
+            if self.changed {
+                tracing::info!(
+                    exposure = self.current.exposure,
+                    gain = self.current.gain,
+                    "face found after bracketing, keeping the exposure"
+                );
+            }
+            return Ok(());
+        }
+        if self.settle > 0 {
+            self.settle -= 1;
+            return Ok(());
+        }
+        let brighter = if brightness < MIN_BRIGHTNESS {
+            true
+        } else if brightness > MAX_BRIGHTNESS {
+            false
+        } else {
+            return Ok(());
+        };
+        let Some(next) = next_setting(self.current, self.exposure, self.gain, brighter) else {
+            return Ok(());
+        };
+        self.apply(next)
+    }
+
+    fn apply(&mut self, setting: Setting) -> Result<()> {
+        let mut controls = Vec::new();
+        if !self.changed {
+            controls.push((CID_EXPOSURE_AUTO, EXPOSURE_MANUAL));
+        }
+        controls.push((CID_EXPOSURE_ABSOLUTE, setting.exposure));
+        if let Some(gain) = setting.gain {
+            controls.push((CID_GAIN, gain));
+        }
+        tracing::debug!(
+            exposure = setting.exposure,
+            gain = setting.gain,
+            "bracketing exposure"
+        );
+        self.changed = true;
+        self.set(&controls).camera("set camera exposure")?;
+        self.current = setting;
+        self.settle = SETTLE_FRAMES;
+        Ok(())
+    }
+
+    fn set(&self, controls: &[(u32, i64)]) -> std::io::Result<()> {
+        // One at a time: the exposure can only be set once auto is off
+        for &(id, value) in controls {
+            self.device.set_control(Control {
+                id,
+                value: Value::Integer(value),
+            })?;
+        }
+        Ok(())
+    }
+}
+
+impl Drop for Bracketing {
+    fn drop(&mut self) {
+        if !self.changed {
+            return;
+        }
+        // Manual values first, so auto mode is what's left on
+        let original: Vec<_> = self.original.iter().rev().copied().collect();
+        if let Err(e) = self.set(&original) {
+            tracing::warn!("failed to restore camera exposure: {}", e);
+        }
+    }
+}
+
+/// The setting one step brighter or darker than `current`: exposure doubled
+/// or halved, gain raised once exposure is at its maximum and lowered before
+/// exposure is. `None` at the limit.
+pub fn next_setting(
+    current: Setting,
+    exposure: Range,
+    gain: Option<Range>,
+    brighter: bool,
+) -> Option<Setting> {
+    let gain_step = |gain: Range| ((gain.max - gain.min) / GAIN_STEPS).max(1);
+    let next = match (brighter, current.gain.zip(gain)) {
+        (true, Some((value, range))) if current.exposure >= exposure.max => Setting {
+            gain: Some(range.clamp(value + gain_step(range))),
+            ..current
+        },
+        (true, _) => Setting {
+            exposure: exposure.clamp(current.exposure.max(1) * 2),
+            ..current
+        },
+        (false, Some((value, range))) if value > range.min => Setting {
+            gain: Some(range.clamp(value - gain_step(range))),
+            ..current
+        },
+        (false, _) => Setting {
+            exposure: exposure.clamp(current.exposure / 2),
+            ..current
+        },
+    };
+    (next != current).then_some(next)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_next_setting() {
+        let exposure = Range { min: 3, max: 2047 };
+        let gain = Some(Range { min: 0, max: 100 });
+        let start = Setting {
+            exposure: 156,
+            gain: Some(0),
+        };
+
+        let up = next_setting(start, exposure, gain, true).unwrap();
+        assert_eq!(up.exposure, 312);
+        // Exposure maxed out: gain goes up
+        let maxed = Setting {
+            exposure: 2047,
+            gain: Some(0),
+        };
+        assert_eq!(
+            next_setting(maxed, exposure, gain, true).unwrap().gain,
+            Some(25)
+        );
+        let limit = Setting {
+            exposure: 2047,
+            gain: Some(100),
+        };
+        assert_eq!(next_setting(limit, exposure, gain, true), None);
+
+        // Darker: gain comes down before exposure
+        let down = next_setting(
+            Setting {
+                exposure: 2047,
+                gain: Some(50),
+            },
+            exposure,
+            gain,
+            false,
+        )
+        .unwrap();
+        assert_eq!((down.exposure, down.gain), (2047, Some(25)));
+        assert_eq!(
+            next_setting(start, exposure, gain, false).unwrap().exposure,
+            78
+        );
+        let floor = Setting {
+            exposure: 3,
+            gain: None,
+        };
+        assert_eq!(next_setting(floor, exposure, None, false), None);
+    }
+}
@@ -1,8 +1,10 @@
-use crate::yunet;
-use anyhow::Result;
-use image::{DynamicImage, GenericImageView};
-use ndarray::{Array2, Array4};
-use ort::{session::Session, value::Value};
+use crate::error::{Error, Result, ResultExt};
+use crate::{draw, pool, simd, yunet};
+use image::{DynamicImage, GenericImageView, RgbImage};
+use ndarray::Array2;
+use ort::{session::Session, value::TensorRef};
+use std::borrow::Cow;
+use zeroize::Zeroize;
 
 /// Detection result from YuNet
 #[derive(Debug, Clone)]
@@ -12,80 +14,423 @@ pub struct Detection {
     pub landmarks: [f32; 10], // 5 points: x1,y1,x2,y2,...,x5,y5
 }
 
-/// Face embedding (SFace output)
+impl Detection {
+    /// Map the box and landmarks from a resized frame back to the original,
+    /// `scale` being original over resized size along x and y
+    pub fn rescale(&mut self, (sx, sy): (f32, f32)) {
+        let [x, y, w, h] = &mut self.bbox;
+        *x *= sx;
+        *y *= sy;
+        *w *= sx;
+        *h *= sy;
+        for point in self.landmarks.chunks_exact_mut(2) {
+            point[0] *= sx;
+            point[1] *= sy;
+        }
+    }
+
+    /// Shift the box and landmarks by `(dx, dy)` pixels
+    pub fn translate(&mut self, (dx, dy): (f32, f32)) {
+        self.bbox[0] += dx;
+        self.bbox[1] += dy;
+        for point in self.landmarks.chunks_exact_mut(2) {
+            point[0] += dx;
+            point[1] += dy;
+        }
+    }
+
+    /// This detection as fractions of an image of `(width, height)` pixels,
+    /// the space [`yunet::RawDetection`]s are decoded in
+    pub fn to_normalized(&self, (width, height): (u32, u32)) -> yunet::RawDetection {
+        let mut d = self.clone();
+        d.rescale((1.0 / width.max(1) as f32, 1.0 / height.max(1) as f32));
+        yunet::RawDetection {
+            bbox: d.bbox,
+            score: d.score,
+            landmarks: d.landmarks,
+        }
+    }
+}
+
+/// Face embedding (SFace output): one row of finite values with unit
+/// length, wiped from memory when dropped
 #[derive(Debug, Clone)]
 pub struct Embedding {
-    pub vector: Array2<f32>,
+    vector: Array2<f32>,
 }
 
+impl Embedding {
+    /// L2-normalize `values` into an embedding; empty, non-finite and
+    /// all-zero values are rejected
+    pub fn new(mut values: Vec<f32>) -> Result<Self> {
+        let norm = simd::dot(&values, &values).sqrt();
+        if values.is_empty() || !norm.is_finite() || norm == 0.0 {
+            let len = values.len();
+            values.zeroize();
+            return Err(Error::invalid(format!(
+                "invalid embedding: {} values of norm {}",
+                len, norm
+            )));
+        }
+        // In place, so no unnormalized copy lingers
+        values.iter_mut().for_each(|x| *x /= norm);
+        let dim = values.len();
+        Ok(Self {
+            vector: Array2::from_shape_vec((1, dim), values).expect("one row of dim values"),
+        })
+    }
+
+    /// [`Self::new`], also requiring `dim` values
+    pub fn with_dim(values: Vec<f32>, dim: usize) -> Result<Self> {
+        if values.len() != dim {
+            let len = values.len();
+            let mut values = values;
+            values.zeroize();
+            return Err(Error::invalid(format!(
+                "embedding has {} values, expected {}",
+                len, dim
+            )));
+        }
+        Self::new(values)
+    }
+
+    /// The embedding as a `1 x dim` matrix
+    pub fn vector(&self) -> &Array2<f32> {
+        &self.vector
+    }
+
+    pub fn as_slice(&self) -> &[f32] {
+        self.vector.as_slice().expect("embeddings are contiguous")
+    }
+
+    /// Number of values
+    pub fn dim(&self) -> usize {
+        self.vector.len()
+    }
+
+    /// The values in native byte order, for hashing or storage
+    pub fn as_bytes(&self) -> &[u8] {
+        let values = self.as_slice();
+        // f32 has no padding and every byte pattern is a valid u8
+        unsafe { std::slice::from_raw_parts(values.as_ptr().cast(), std::mem::size_of_val(values)) }
+    }
+
+    /// Cosine similarity, in `[-1, 1]`. Both sides are unit length, so
+    /// this is the dot product; embeddings of another dimension are
+    /// compared on their common prefix.
+    pub fn cosine(&self, other: &Embedding) -> f32 {
+        simd::dot(self.as_slice(), other.as_slice()).clamp(-1.0, 1.0)
+    }
+
+    /// Euclidean distance, in `[0, 2]`; `sqrt(2 - 2 * cosine)` for unit vectors
+    pub fn euclidean(&self, other: &Embedding) -> f32 {
+        self.as_slice()
+            .iter()
+            .zip(other.as_slice())
+            .map(|(x, y)| (x - y) * (x - y))
+            .sum::<f32>()
+            .sqrt()
+    }
+}
+
+impl Drop for Embedding {
+    fn drop(&mut self) {
+        if let Some(values) = self.vector.as_slice_memory_order_mut() {
+            values.zeroize();
+        }
+    }
+}
+
+/// A face size, compared against the longer side of a detection box
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FaceSize {
+    Pixels(u32),
+    /// Fraction of the shorter side of the frame
+    Fraction(f32),
+}
+
+impl FaceSize {
+    pub(crate) fn pixels(&self, frame: (u32, u32)) -> f32 {
+        match *self {
+            FaceSize::Pixels(px) => px as f32,
+            FaceSize::Fraction(f) => f * frame.0.min(frame.1) as f32,
+        }
+    }
+}
+
+/// Bounds on face size applied before alignment: tiny faces are usually
+/// background people or reflections, and a face filling the whole frame
+/// suggests something pressed against the lens
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct SizeFilter {
+    pub min: Option<FaceSize>,
+    pub max: Option<FaceSize>,
+}
+
+impl SizeFilter {
+    /// Whether `detection` in a `frame` of `(width, height)` is within bounds
+    pub fn allows(&self, detection: &Detection, frame: (u32, u32)) -> bool {
+        let size = detection.bbox[2].max(detection.bbox[3]);
+        self.min.is_none_or(|min| size >= min.pixels(frame))
+            && self.max.is_none_or(|max| size <= max.pixels(frame))
+    }
+
+    /// Drop the detections outside the bounds
+    pub fn apply(&self, detections: Vec<Detection>, frame: (u32, u32)) -> Vec<Detection> {
+        let before = detections.len();
+        let kept: Vec<Detection> = detections
+            .into_iter()
+            .filter(|d| self.allows(d, frame))
+            .collect();
+        if kept.len() < before {
+            tracing::debug!(
+                "dropped {} face(s) outside size limits",
+                before - kept.len()
+            );
+        }
+        kept
+    }
+}
+
+/// YuNet input resolution: every image is letterboxed into a square canvas
+const DETECTOR_SIZE: u32 = 640;
+/// Canvas sizes must be a multiple of the detector's largest stride
+const DETECTOR_STRIDE: u32 = 32;
+/// Most images sent through the detector in one batched run
+pub const MAX_BATCH: usize = 8;
+
+/// Where an image landed inside the detector canvas
+#[derive(Debug, Clone, Copy)]
+struct Letterbox {
+    /// Side of the square canvas
+    size: u32,
+    scale: f32,
+    offset_x: u32,
+    offset_y: u32,
+}
+
+/// Model output tensors as `(shape, data)` pairs, in output order
+pub(crate) type RawOutputs = Vec<(Vec<i64>, Vec<f32>)>;
+
 /// Detect faces in an image using YuNet detector
+#[tracing::instrument(name = "detect", level = "debug", skip_all)]
 pub fn detect_faces(
     session: &mut Session,
     img: &DynamicImage,
     score_threshold: f32,
     nms_threshold: f32,
 ) -> Result<Vec<Detection>> {
-    // YuNet model expects fixed input size [1, 3, 640, 640]
-    // Pad image to square to avoid distortion
-    let target_size = 640;
+    let size = canvas_size(session, img.dimensions());
+    let (input_data, letterbox) = detector_input(img, size);
+    let output_data = run_detector(session, &input_data, 1, size);
+    pool::tensors().recycle(input_data);
+
+    decode_detector_outputs(&output_data?, letterbox, score_threshold, nms_threshold)
+}
+
+/// Canvas side for an image of `dims`: just big enough if the detector takes
+/// any input size, otherwise the fixed [`DETECTOR_SIZE`]
+fn canvas_size(session: &Session, dims: (u32, u32)) -> u32 {
+    let dynamic = session
+        .inputs()
+        .first()
+        .and_then(|input| input.dtype().tensor_shape())
+        .is_some_and(|shape| shape.len() == 4 && shape[2] < 0 && shape[3] < 0);
+    if !dynamic {
+        return DETECTOR_SIZE;
+    }
+    let side = dims.0.max(dims.1).min(DETECTOR_SIZE);
+    side.div_ceil(DETECTOR_STRIDE).max(1) * DETECTOR_STRIDE
+}
+
+/// Detect faces in several images, returning one list per image.
+///
+/// If the detector has a dynamic batch dimension, up to [`MAX_BATCH`]
+/// images go through each run. The stock YuNet export is fixed at batch 1;
+/// then images run one at a time while the next one is letterboxed on a
+/// second thread.
+#[tracing::instrument(name = "detect_batch", level = "debug", skip_all, fields(images = imgs.len()))]
+pub fn detect_faces_batch(
+    session: &mut Session,
+    imgs: &[DynamicImage],
+    score_threshold: f32,
+    nms_threshold: f32,
+) -> Result<Vec<Vec<Detection>>> {
+    if supports_batching(session) {
+        let mut results = Vec::with_capacity(imgs.len());
+        for chunk in imgs.chunks(MAX_BATCH) {
+            results.extend(detect_batched(
+                session,
+                chunk,
+                score_threshold,
+                nms_threshold,
+            )?);
+        }
+        return Ok(results);
+    }
+
+    std::thread::scope(|scope| {
+        // One prepared input in flight keeps the detector busy without
+        // holding every canvas in memory at once
+        let (tx, rx) = std::sync::mpsc::sync_channel(1);
+        scope.spawn(move || {
+            for img in imgs {
+                if tx.send(detector_input(img, DETECTOR_SIZE)).is_err() {
+                    break;
+                }
+            }
+        });
+
+        let mut results = Vec::with_capacity(imgs.len());
+        for (input_data, letterbox) in rx {
+            let output_data = run_detector(session, &input_data, 1, DETECTOR_SIZE);
+            pool::tensors().recycle(input_data);
+            results.push(decode_detector_outputs(
+                &output_data?,
+                letterbox,
+                score_threshold,
+                nms_threshold,
+            )?);
+        }
+        Ok(results)
+    })
+}
+
+/// Whether the detector's first input has a dynamic batch dimension
+fn supports_batching(session: &Session) -> bool {
+    session
+        .inputs()
+        .first()
+        .and_then(|input| input.dtype().tensor_shape())
+        .and_then(|shape| shape.first().copied())
+        .is_some_and(|batch| batch < 0)
+}
+
+fn detect_batched(
+    session: &mut Session,
+    imgs: &[DynamicImage],
+    score_threshold: f32,
+    nms_threshold: f32,
+) -> Result<Vec<Vec<Detection>>> {
+    let len = canvas_len(DETECTOR_SIZE);
+    let mut input_data = pool::tensors().take(imgs.len() * len);
+    let letterboxes: Vec<Letterbox> = imgs
+        .iter()
+        .zip(input_data.chunks_exact_mut(len))
+        .map(|(img, out)| letterbox_into(img, DETECTOR_SIZE, out))
+        .collect();
+    let output_data = run_detector(session, &input_data, imgs.len(), DETECTOR_SIZE);
+    pool::tensors().recycle(input_data);
+    let output_data = output_data?;
+
+    letterboxes
+        .into_iter()
+        .enumerate()
+        .map(|(i, letterbox)| {
+            let per_image = split_batch(&output_data, imgs.len(), i)?;
+            decode_detector_outputs(&per_image, letterbox, score_threshold, nms_threshold)
+        })
+        .collect()
+}
+
+/// Outputs of image `index` out of a batch of `batch`, reshaped to batch 1
+fn split_batch(outputs: &RawOutputs, batch: usize, index: usize) -> Result<RawOutputs> {
+    outputs
+        .iter()
+        .map(|(shape, data)| {
+            if shape.first() != Some(&(batch as i64)) || data.len() % batch != 0 {
+                return Err(Error::inference(format!(
+                    "output shape {:?} does not match batch {}",
+                    shape, batch
+                )));
+            }
+            let per_image = data.len() / batch;
+            let mut shape = shape.clone();
+            shape[0] = 1;
+            let start = index * per_image;
+            Ok((shape, data[start..start + per_image].to_vec()))
+        })
+        .collect()
+}
+
+fn canvas_len(size: u32) -> usize {
+    3 * (size * size) as usize
+}
+
+/// Letterbox `img` into a pooled `size`x`size` detector input tensor
+fn detector_input(img: &DynamicImage, size: u32) -> (Vec<f32>, Letterbox) {
+    // The tensor comes zeroed from the pool, which leaves the padding black
+    let mut input_data = pool::tensors().take(canvas_len(size));
+    let letterbox = letterbox_into(img, size, &mut input_data);
+    (input_data, letterbox)
+}
+
+/// Letterbox `img` into one zeroed `size`x`size` BGR canvas of the detector input
+fn letterbox_into(img: &DynamicImage, size: u32, out: &mut [f32]) -> Letterbox {
+    let target_size = size;
     let (orig_width, orig_height) = img.dimensions();
 
-    // Create square canvas with padding
+    // Letterbox into a square canvas to avoid distortion
     let max_dim = orig_width.max(orig_height);
     let scale = target_size as f32 / max_dim as f32;
     let new_width = (orig_width as f32 * scale) as u32;
     let new_height = (orig_height as f32 * scale) as u32;
-
-    // Resize maintaining aspect ratio
-    let resized = img.resize_exact(new_width, new_height, image::imageops::FilterType::Triangle);
-
-    // Create square canvas and paste resized image
-    let mut canvas = image::DynamicImage::new_rgb8(target_size, target_size);
     let offset_x = (target_size - new_width) / 2;
     let offset_y = (target_size - new_height) / 2;
-    image::imageops::overlay(&mut canvas, &resized, offset_x as i64, offset_y as i64);
 
-    let img_rgb = canvas.to_rgb8();
+    let src = rgb_view(img);
+    letterbox_bgr(
+        &src,
+        (offset_x, offset_y, new_width, new_height),
+        target_size,
+        out,
+    );
 
-    // YuNet expects input shape [1, 3, H, W] in BGR format
-    // SIMD-optimized RGB to BGR channel extraction with cache-friendly layout
-    let pixel_count = (target_size * target_size) as usize;
-    let mut input_data = Vec::with_capacity(3 * pixel_count);
-    
-    // Pre-allocate all channels
-    unsafe {
-        input_data.set_len(3 * pixel_count);
+    Letterbox {
+        size,
+        scale,
+        offset_x,
+        offset_y,
     }
-    
-    // Split into channel slices for better cache locality
-    let (b_channel, rest) = input_data.split_at_mut(pixel_count);
-    let (g_channel, r_channel) = rest.split_at_mut(pixel_count);
-    
-    // Convert RGB to BGR using SIMD
-    let pixels = img_rgb.as_raw();
-    for i in 0..pixel_count {
-        let idx = i * 3;
-        r_channel[i] = pixels[idx] as f32;     // R
-        g_channel[i] = pixels[idx + 1] as f32; // G
-        b_channel[i] = pixels[idx + 2] as f32; // B
-    }
-
-    let input_array = Array4::from_shape_vec(
-        (1, 3, target_size as usize, target_size as usize),
-        input_data,
-    )?;
-    let input_tensor = Value::from_array(input_array)?;
+}
+
+/// Run the detector on `batch` stacked `size`x`size` canvases, copying out
+/// every output
+pub(crate) fn run_detector(
+    session: &mut Session,
+    input_data: &[f32],
+    batch: usize,
+    size: u32,
+) -> Result<RawOutputs> {
+    // YuNet expects input shape [N, 3, H, W] in BGR format
+    let size = size as usize;
+    let shape = [batch, 3, size, size];
+    let input_tensor = TensorRef::from_array_view((shape, input_data))?;
 
     let outputs = session.run(ort::inputs![input_tensor])?;
 
     // Extract all output tensors and store the data
-    let mut output_data: Vec<(Vec<i64>, Vec<f32>)> = Vec::new();
+    let mut output_data = Vec::new();
     for (_name, output) in outputs.iter() {
         let (shape, data) = output.try_extract_tensor::<f32>()?;
-        let shape_vec: Vec<i64> = shape.iter().copied().collect();
-        let data_vec = data.to_vec();
-        output_data.push((shape_vec, data_vec));
+        output_data.push((shape.iter().copied().collect(), data.to_vec()));
     }
+    Ok(output_data)
+}
+
+/// Turn the raw outputs for one image into detections in its own coordinates
+fn decode_detector_outputs(
+    output_data: &RawOutputs,
+    letterbox: Letterbox,
+    score_threshold: f32,
+    nms_threshold: f32,
+) -> Result<Vec<Detection>> {
+    let Letterbox {
+        size: target_size,
+        scale,
+        offset_x,
+        offset_y,
+    } = letterbox;
 
     // Create references for parsing
     let output_refs: Vec<(&[i64], &[f32])> = output_data
@@ -112,33 +457,14 @@ pub fn detect_faces(
     // Scale detection coordinates back to original image size
     // Account for padding that was added
     let mut detections: Vec<Detection> = raw_detections
-        .into_iter()
+        .iter()
         .map(|d| {
-            // Coordinates are normalized (0-1) relative to 640x640 canvas
+            // Coordinates are normalized (0-1) relative to the canvas
             // Convert to pixels, remove padding offset, then rescale to original dimensions
-            let bbox_x_px = d.bbox[0] * target_size as f32;
-            let bbox_y_px = d.bbox[1] * target_size as f32;
-            let bbox_w_px = d.bbox[2] * target_size as f32;
-            let bbox_h_px = d.bbox[3] * target_size as f32;
-
-            let bbox_x = (bbox_x_px - offset_x as f32) / scale;
-            let bbox_y = (bbox_y_px - offset_y as f32) / scale;
-            let bbox_w = bbox_w_px / scale;
-            let bbox_h = bbox_h_px / scale;
-
-            let mut landmarks = [0.0f32; 10];
-            for i in 0..5 {
-                let lm_x_px = d.landmarks[i * 2] * target_size as f32;
-                let lm_y_px = d.landmarks[i * 2 + 1] * target_size as f32;
-                landmarks[i * 2] = (lm_x_px - offset_x as f32) / scale;
-                landmarks[i * 2 + 1] = (lm_y_px - offset_y as f32) / scale;
-            }
-
-            Detection {
-                bbox: [bbox_x, bbox_y, bbox_w, bbox_h],
-                score: d.score,
-                landmarks,
-            }
+            let mut d = d.to_pixels(target_size);
+            d.translate((-(offset_x as f32), -(offset_y as f32)));
+            d.rescale((1.0 / scale, 1.0 / scale));
+            d
         })
         .collect();
 
@@ -150,14 +476,101 @@ pub fn detect_faces(
     Ok(detections)
 }
 
+/// Borrow `img` as RGB8, converting only when it is stored in another format
+pub(crate) fn rgb_view(img: &DynamicImage) -> Cow<'_, RgbImage> {
+    match img.as_rgb8() {
+        Some(rgb) => Cow::Borrowed(rgb),
+        None => Cow::Owned(img.to_rgb8()),
+    }
+}
+
+/// Bilinearly resize `src` into the `(x, y, w, h)` region of a planar BGR
+/// `size`x`size` tensor, leaving the rest of `out` untouched
+pub(crate) fn letterbox_bgr(
+    src: &RgbImage,
+    region: (u32, u32, u32, u32),
+    size: u32,
+    out: &mut [f32],
+) {
+    let (x, y, w, h) = region;
+    let (src_w, src_h) = src.dimensions();
+    let plane = (size * size) as usize;
+    let (b_channel, rest) = out.split_at_mut(plane);
+    let (g_channel, r_channel) = rest.split_at_mut(plane);
+    let pixels = src.as_raw();
+    let row_len = src_w as usize * 3;
+
+    // Source offsets and weights are the same for every row (and column), so
+    // work them out once; the blend itself is integer math on the bytes
+    let columns: Vec<_> = (0..w)
+        .map(|ox| {
+            let (x0, x1, wx) = bilinear_tap(ox, w, src_w);
+            (x0 as usize * 3, x1 as usize * 3, wx)
+        })
+        .collect();
+
+    for oy in 0..h {
+        let (y0, y1, wy) = bilinear_tap(oy, h, src_h);
+        let top = &pixels[y0 as usize * row_len..][..row_len];
+        let bottom = &pixels[y1 as usize * row_len..][..row_len];
+        let start = ((oy + y) * size + x) as usize;
+        let end = start + w as usize;
+        for (((&(x0, x1, wx), r), g), b) in columns
+            .iter()
+            .zip(&mut r_channel[start..end])
+            .zip(&mut g_channel[start..end])
+            .zip(&mut b_channel[start..end])
+        {
+            let sample = |c: usize| {
+                let blend = |a: u8, b: u8, t: u32| a as u32 * (BILINEAR_ONE - t) + b as u32 * t;
+                let upper = blend(top[x0 + c], top[x1 + c], wx);
+                let lower = blend(bottom[x0 + c], bottom[x1 + c], wx);
+                let value = upper * (BILINEAR_ONE - wy) + lower * wy;
+                ((value + BILINEAR_ROUND) >> (2 * BILINEAR_SHIFT)) as f32
+            };
+            *r = sample(0);
+            *g = sample(1);
+            *b = sample(2);
+        }
+    }
+}
+
+/// Fraction bits of the bilinear weights in [`letterbox_bgr`]
+const BILINEAR_SHIFT: u32 = 8;
+const BILINEAR_ONE: u32 = 1 << BILINEAR_SHIFT;
+/// Half of the two weights' combined scale, to round rather than truncate
+const BILINEAR_ROUND: u32 = 1 << (2 * BILINEAR_SHIFT - 1);
+
+/// The two source indices and fixed-point weight of the second one, for
+/// output index `out` of `len` resampled from `src_len` (pixel centres aligned)
+fn bilinear_tap(out: u32, len: u32, src_len: u32) -> (u32, u32, u32) {
+    let scale = src_len as f32 / len.max(1) as f32;
+    let f = ((out as f32 + 0.5) * scale - 0.5).clamp(0.0, (src_len - 1) as f32);
+    let i0 = f as u32;
+    let i1 = (i0 + 1).min(src_len - 1);
+    let weight = ((f - i0 as f32) * BILINEAR_ONE as f32).round() as u32;
+    (i0, i1, weight)
+}
+
+/// Candidates [`nms`] considers, best scores first; the rest of a dense
+/// low-threshold frame would only cost quadratic time
+pub const NMS_TOP_K: usize = 500;
+/// Detections [`nms`] keeps at most, stopping once it has this many
+pub const NMS_MAX_KEEP: usize = 64;
+
 /// Apply non-maximum suppression to remove overlapping detections
 pub fn nms(detections: &[Detection], iou_threshold: f32) -> Vec<Detection> {
     if detections.is_empty() {
         return vec![];
     }
 
+    let by_score = |a: &Detection, b: &Detection| b.score.total_cmp(&a.score);
     let mut sorted = detections.to_vec();
-    sorted.sort_by(|a, b| b.score.partial_cmp(&a.score).unwrap());
+    if sorted.len() > NMS_TOP_K {
+        sorted.select_nth_unstable_by(NMS_TOP_K, by_score);
+        sorted.truncate(NMS_TOP_K);
+    }
+    sorted.sort_by(by_score);
 
     let mut keep = Vec::new();
     let mut suppressed = vec![false; sorted.len()];
@@ -167,6 +580,9 @@ pub fn nms(detections: &[Detection], iou_threshold: f32) -> Vec<Detection> {
             continue;
         }
         keep.push(sorted[i].clone());
+        if keep.len() == NMS_MAX_KEEP {
+            break;
+        }
 
         for j in (i + 1)..sorted.len() {
             if suppressed[j] {
@@ -198,12 +614,77 @@ fn compute_iou(a: &[f32; 4], b: &[f32; 4]) -> f32 {
     inter / (area_a + area_b - inter)
 }
 
-/// Align and crop face using landmarks
+/// Where the five landmarks should land in an aligned crop of
+/// `width`x`height`, in the same order as [`Detection::landmarks`]
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct AlignTemplate {
+    pub width: u32,
+    pub height: u32,
+    pub landmarks: [f32; 10],
+}
+
+impl AlignTemplate {
+    /// InsightFace ArcFace reference points for 112x112 crops (SFace, ArcFace)
+    pub const ARCFACE_112: AlignTemplate = AlignTemplate {
+        width: 112,
+        height: 112,
+        landmarks: [
+            38.2946, 51.6963, 73.5318, 51.5014, 56.0252, 71.7366, 41.5493, 92.3655, 70.7299,
+            92.2041,
+        ],
+    };
+
+    /// The same points on the narrower 96x112 crop of SphereFace/CosFace
+    pub const ARCFACE_96X112: AlignTemplate = AlignTemplate {
+        width: 96,
+        height: 112,
+        landmarks: [
+            30.2946, 51.6963, 65.5318, 51.5014, 48.0252, 71.7366, 33.5493, 92.3655, 62.7299,
+            92.2041,
+        ],
+    };
+
+    /// The same points centered in a 128x128 crop
+    pub const ARCFACE_128: AlignTemplate = AlignTemplate {
+        width: 128,
+        height: 128,
+        landmarks: [
+            46.2946, 51.6963, 81.5318, 51.5014, 64.0252, 71.7366, 49.5493, 92.3655, 78.7299,
+            92.2041,
+        ],
+    };
+
+    /// The template for a crop `factor` times as large
+    pub fn scaled(&self, factor: f32) -> AlignTemplate {
+        AlignTemplate {
+            width: (self.width as f32 * factor).round() as u32,
+            height: (self.height as f32 * factor).round() as u32,
+            landmarks: self.landmarks.map(|v| v * factor),
+        }
+    }
+}
+
+/// Align and crop face to a `size`x`size` ArcFace crop, see [`align_face_to`]
 pub fn align_face(img: &DynamicImage, detection: &Detection, size: u32) -> Result<DynamicImage> {
-    // Eye-based alignment using affine transform
-    // Reference landmarks for 112x112 SFace input (ArcFace standard)
-    let ref_left_eye = (38.3_f32, 51.7_f32);
-    let ref_right_eye = (73.5_f32, 51.5_f32);
+    align_face_to(
+        img,
+        detection,
+        &AlignTemplate::ARCFACE_112.scaled(size as f32 / 112.0),
+    )
+}
+
+/// Align and crop face using landmarks, placing the eyes where `template`
+/// puts them
+#[tracing::instrument(name = "align", level = "debug", skip_all)]
+pub fn align_face_to(
+    img: &DynamicImage,
+    detection: &Detection,
+    template: &AlignTemplate,
+) -> Result<DynamicImage> {
+    // Eye-based alignment using a similarity transform; the eyes are the
+    // most reliable landmarks, so the other three are not fitted
+    let ref_left_eye = (template.landmarks[0], template.landmarks[1]);
+    let ref_right_eye = (template.landmarks[2], template.landmarks[3]);
 
     // Extract eye coordinates from landmarks
     // landmarks: [left_eye_x, left_eye_y, right_eye_x, right_eye_y, nose_x, nose_y, ...]
@@ -222,7 +703,7 @@ pub fn align_face(img: &DynamicImage, detection: &Detection, size: u32) -> Resul
     let actual_eye_dist = (eye_dx * eye_dx + eye_dy * eye_dy).sqrt();
 
     // Calculate scale to match reference eye distance
-    let scale = (size as f32 / 112.0) * (ref_eye_dist / actual_eye_dist);
+    let scale = ref_eye_dist / actual_eye_dist;
 
     // Calculate center point between eyes
     let eye_center = (
@@ -234,12 +715,6 @@ pub fn align_face(img: &DynamicImage, detection: &Detection, size: u32) -> Resul
         (ref_left_eye.1 + ref_right_eye.1) / 2.0,
     );
 
-    // Scale reference center to output size
-    let ref_center_scaled = (
-        ref_eye_center.0 * size as f32 / 112.0,
-        ref_eye_center.1 * size as f32 / 112.0,
-    );
-
     // Create transformation matrix
     // We need: rotate around eye center, scale, then translate to reference position
     let cos_angle = eye_angle.cos();
@@ -254,65 +729,64 @@ pub fn align_face(img: &DynamicImage, detection: &Detection, size: u32) -> Resul
     let c = -scale * sin_angle;
     let d = scale * cos_angle;
 
-    // Translation: after rotation and scaling, shift so eye_center maps to ref_center_scaled
-    let tx = ref_center_scaled.0 - (a * eye_center.0 + b * eye_center.1);
-    let ty = ref_center_scaled.1 - (c * eye_center.0 + d * eye_center.1);
+    // Translation: after rotation and scaling, shift so eye_center maps to ref_eye_center
+    let tx = ref_eye_center.0 - (a * eye_center.0 + b * eye_center.1);
+    let ty = ref_eye_center.1 - (c * eye_center.0 + d * eye_center.1);
 
     // Apply transformation by creating output image and mapping pixels
-    let (img_w, img_h) = img.dimensions();
-    let mut output = image::RgbImage::new(size, size);
-
-    // For each pixel in output, find corresponding source pixel
-    for out_y in 0..size {
-        for out_x in 0..size {
-            // Invert the transformation to find source coordinates
-            // input = inv([a,b;c,d]) * (output - [tx,ty])
-            let out_x_f = out_x as f32;
-            let out_y_f = out_y as f32;
-
-            // Subtract translation
-            let tmp_x = out_x_f - tx;
-            let tmp_y = out_y_f - ty;
-
-            // Apply inverse rotation and scale
-            let det = a * d - b * c;
-            let in_x = (d * tmp_x - b * tmp_y) / det;
-            let in_y = (-c * tmp_x + a * tmp_y) / det;
-
-            // Sample from input image (with boundary check)
-            if in_x >= 0.0 && in_x < img_w as f32 && in_y >= 0.0 && in_y < img_h as f32 {
-                // Bilinear interpolation
-                let x0 = in_x.floor() as u32;
-                let y0 = in_y.floor() as u32;
-                let x1 = (x0 + 1).min(img_w - 1);
-                let y1 = (y0 + 1).min(img_h - 1);
-
-                let fx = in_x - x0 as f32;
-                let fy = in_y - y0 as f32;
-
-                let p00 = img.get_pixel(x0, y0);
-                let p10 = img.get_pixel(x1, y0);
-                let p01 = img.get_pixel(x0, y1);
-                let p11 = img.get_pixel(x1, y1);
-
-                // Bilinear interpolation optimized for LLVM auto-vectorization
-                let w00 = (1.0 - fx) * (1.0 - fy);
-                let w10 = fx * (1.0 - fy);
-                let w01 = (1.0 - fx) * fy;
-                let w11 = fx * fy;
-                
-                // Compute interpolation for each RGB channel
-                // Using simple arithmetic allows LLVM to auto-vectorize
-                let r = (p00[0] as f32 * w00 + p10[0] as f32 * w10 
-                       + p01[0] as f32 * w01 + p11[0] as f32 * w11) as u8;
-                let g = (p00[1] as f32 * w00 + p10[1] as f32 * w10 
-                       + p01[1] as f32 * w01 + p11[1] as f32 * w11) as u8;
-                let b_val = (p00[2] as f32 * w00 + p10[2] as f32 * w10 
-                           + p01[2] as f32 * w01 + p11[2] as f32 * w11) as u8;
-
-                output.put_pixel(out_x, out_y, image::Rgb([r, g, b_val]));
+    let src = rgb_view(img);
+    let (img_w, img_h) = src.dimensions();
+    let pixels = src.as_raw();
+    let src_stride = img_w as usize * 3;
+    let (width, height) = (template.width, template.height);
+    let mut output = RgbImage::from_raw(
+        width,
+        height,
+        pool::frames().take((width * height * 3) as usize),
+    )
+    .expect("pooled buffer has the requested length");
+
+    // Invert the transformation to find source coordinates:
+    // input = inv([a,b;c,d]) * (output - [tx,ty])
+    let det = a * d - b * c;
+    let (ia, ib, ic, id) = (d / det, -b / det, -c / det, a / det);
+
+    for (out_y, row) in output.chunks_exact_mut(width as usize * 3).enumerate() {
+        // Source position of the row's first pixel; each step right adds (ia, ic)
+        let tmp_y = out_y as f32 - ty;
+        let row_x = ia * -tx + ib * tmp_y;
+        let row_y = ic * -tx + id * tmp_y;
+        for (out_x, px) in row.chunks_exact_mut(3).enumerate() {
+            let in_x = row_x + ia * out_x as f32;
+            let in_y = row_y + ic * out_x as f32;
+
+            // Sample from input image (with boundary check); outside stays black
+            if !(in_x >= 0.0 && in_x < img_w as f32 && in_y >= 0.0 && in_y < img_h as f32) {
+                continue;
+            }
+            // Bilinear interpolation
+            let x0 = in_x as usize;
+            let y0 = in_y as usize;
+            let x1 = (x0 + 1).min(img_w as usize - 1);
+            let y1 = (y0 + 1).min(img_h as usize - 1);
+
+            let fx = in_x - x0 as f32;
+            let fy = in_y - y0 as f32;
+            let w00 = (1.0 - fx) * (1.0 - fy);
+            let w10 = fx * (1.0 - fy);
+            let w01 = (1.0 - fx) * fy;
+            let w11 = fx * fy;
+
+            let top = &pixels[y0 * src_stride..][..src_stride];
+            let bottom = &pixels[y1 * src_stride..][..src_stride];
+            let (p00, p10) = (&top[x0 * 3..][..3], &top[x1 * 3..][..3]);
+            let (p01, p11) = (&bottom[x0 * 3..][..3], &bottom[x1 * 3..][..3]);
+            for c in 0..3 {
+                px[c] = (p00[c] as f32 * w00
+                    + p10[c] as f32 * w10
+                    + p01[c] as f32 * w01
+                    + p11[c] as f32 * w11) as u8;
             }
-            // else: leave black (default)
         }
     }
 
@@ -320,37 +794,44 @@ pub fn align_face(img: &DynamicImage, detection: &Detection, size: u32) -> Resul
 }
 
 /// Encode face image to embedding using SFace
+#[tracing::instrument(name = "encode", level = "debug", skip_all)]
 pub fn encode_face(session: &mut Session, face_img: &DynamicImage) -> Result<Embedding> {
     // SFace expects input shape [1, 3, 112, 112] in BGR format with values in [0, 255]
-    let size = 112;
-    let face_rgb = face_img.resize_exact(size, size, image::imageops::FilterType::Triangle);
-    let face_rgb = face_rgb.to_rgb8();
+    let (width, height) = encoder_input_size(session).unwrap_or(face_img.dimensions());
+    // Aligned faces already have the right size; only resize other inputs
+    let face_rgb = if face_img.dimensions() == (width, height) {
+        rgb_view(face_img)
+    } else {
+        Cow::Owned(
+            face_img
+                .resize_exact(width, height, image::imageops::FilterType::Triangle)
+                .to_rgb8(),
+        )
+    };
 
     // Convert to CHW format in BGR order (B, G, R) with values in [0, 255]
-    // SIMD-optimized RGB to BGR channel extraction with cache-friendly layout
-    let pixel_count = (size * size) as usize;
-    let mut input_data = Vec::with_capacity(3 * pixel_count);
-    
-    // Pre-allocate all channels
-    unsafe {
-        input_data.set_len(3 * pixel_count);
-    }
-    
+    let pixel_count = (width * height) as usize;
+    let mut input_data = pool::tensors().take(3 * pixel_count);
+
     // Split into channel slices for better cache locality
     let (b_channel, rest) = input_data.split_at_mut(pixel_count);
     let (g_channel, r_channel) = rest.split_at_mut(pixel_count);
-    
+
     // Convert RGB to BGR
     let pixels = face_rgb.as_raw();
-    for i in 0..pixel_count {
-        let idx = i * 3;
-        r_channel[i] = pixels[idx] as f32;     // R
-        g_channel[i] = pixels[idx + 1] as f32; // G
-        b_channel[i] = pixels[idx + 2] as f32; // B
+    for (((px, r), g), b) in pixels
+        .chunks_exact(3)
+        .zip(r_channel)
+        .zip(g_channel)
+        .zip(b_channel)
+    {
+        *r = px[0] as f32;
+        *g = px[1] as f32;
+        *b = px[2] as f32;
     }
 
-    let input_array = Array4::from_shape_vec((1, 3, size as usize, size as usize), input_data)?;
-    let input_tensor = Value::from_array(input_array)?;
+    let shape = [1usize, 3, height as usize, width as usize];
+    let input_tensor = TensorRef::from_array_view((shape, &*input_data))?;
 
     let outputs = session.run(ort::inputs![input_tensor])?;
     let (shape, data) = outputs[0].try_extract_tensor::<f32>()?;
@@ -362,46 +843,100 @@ pub fn encode_face(session: &mut Session, face_img: &DynamicImage) -> Result<Emb
         data.len()
     };
     let embedding_vec: Vec<f32> = data[0..embedding_size].to_vec();
+    drop(outputs);
+    pool::tensors().recycle(input_data);
 
-    // Normalize the embedding (L2 normalization)
-    let norm: f32 = embedding_vec.iter().map(|x| x * x).sum::<f32>().sqrt();
-    let normalized = if norm > 0.0 {
-        embedding_vec.iter().map(|x| x / norm).collect()
-    } else {
-        embedding_vec
-    };
-
-    let embedding_array = Array2::from_shape_vec((1, embedding_size), normalized)?;
+    // Normalized in place, so no copy lingers
+    Embedding::new(embedding_vec).inference("the encoder returned an invalid embedding")
+}
 
-    Ok(Embedding {
-        vector: embedding_array,
-    })
+/// `(width, height)` of the encoder input, if the model fixes it
+pub(crate) fn encoder_input_size(session: &Session) -> Option<(u32, u32)> {
+    let input = session.inputs().first()?;
+    let shape = input.dtype().tensor_shape()?;
+    match shape[..] {
+        [_, _, height, width] if height > 0 && width > 0 => Some((width as u32, height as u32)),
+        _ => None,
+    }
 }
 
-/// Compute cosine similarity between two embeddings
+/// Compute cosine similarity between two embeddings, see [`Embedding::cosine`]
 pub fn match_embedding(a: &Embedding, b: &Embedding) -> f32 {
-    // Optimized dot product for cosine similarity with LLVM auto-vectorization
-    // Embeddings are already L2-normalized, so dot product = cosine similarity
-    let a_data = a.vector.as_slice().unwrap();
-    let b_data = b.vector.as_slice().unwrap();
-    
-    let len = a_data.len().min(b_data.len());
-    
-    // Simple loop that LLVM can auto-vectorize
-    // Using iterator zip and sum is optimal for auto-vectorization
-    let dot: f32 = a_data.iter()
-        .zip(b_data.iter())
-        .take(len)
-        .map(|(x, y)| x * y)
-        .sum();
-    
-    dot.max(-1.0).min(1.0)
+    a.cosine(b)
+}
+
+/// Encode `face_img` and its mirror image and average the two embeddings.
+///
+/// Horizontal-flip test-time augmentation: roughly twice the encode cost
+/// for an embedding that is less sensitive to lighting and pose asymmetry.
+pub fn encode_face_flip(session: &mut Session, face_img: &DynamicImage) -> Result<Embedding> {
+    let direct = encode_face(session, face_img)?;
+    let mirrored = mirror(face_img);
+    let flipped = encode_face(session, &mirrored);
+    pool::frames().recycle_image(mirrored);
+    average_embeddings(&direct, &flipped?)
+}
+
+/// Horizontally flipped copy of `img` in a pooled buffer
+fn mirror(img: &DynamicImage) -> DynamicImage {
+    let rgb = rgb_view(img);
+    let (w, h) = rgb.dimensions();
+    let src = rgb.as_raw();
+    let mut out = pool::frames().take(src.len());
+    for (src_row, out_row) in src
+        .chunks_exact(w as usize * 3)
+        .zip(out.chunks_exact_mut(w as usize * 3))
+    {
+        for (src_px, out_px) in src_row.chunks_exact(3).zip(out_row.rchunks_exact_mut(3)) {
+            out_px.copy_from_slice(src_px);
+        }
+    }
+    DynamicImage::ImageRgb8(RgbImage::from_raw(w, h, out).expect("buffer matches dimensions"))
+}
+
+/// A copy of `img` with the box (green), landmarks (red) and score of each
+/// detection drawn on it. Scores go below the box, leaving the space above
+/// it for a caller's own label.
+pub fn annotate(img: &DynamicImage, detections: &[Detection]) -> DynamicImage {
+    let mut rgb = img.to_rgb8();
+    for d in detections {
+        draw::rect(&mut rgb, d.bbox, draw::GREEN);
+        for landmark in d.landmarks.chunks_exact(2) {
+            draw::cross(&mut rgb, landmark[0], landmark[1], 3, draw::RED);
+        }
+        let x = d.bbox[0].round() as i64;
+        let y = (d.bbox[1] + d.bbox[3]).round() as i64 + 2;
+        draw::text(&mut rgb, &format!("{:.2}", d.score), x, y, draw::GREEN);
+    }
+    DynamicImage::ImageRgb8(rgb)
+}
+
+/// L2-normalized mean of two embeddings
+fn average_embeddings(a: &Embedding, b: &Embedding) -> Result<Embedding> {
+    let sum = a.as_slice().iter().zip(b.as_slice()).map(|(x, y)| x + y);
+    Embedding::new(sum.collect())
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    #[test]
+    fn test_annotate() {
+        let img = DynamicImage::new_rgb8(64, 64);
+        let detection = Detection {
+            bbox: [10.0, 10.0, 30.0, 30.0],
+            score: 0.9,
+            landmarks: [20.0, 20.0, 30.0, 20.0, 25.0, 25.0, 20.0, 32.0, 30.0, 32.0],
+        };
+        let annotated = annotate(&img, &[detection]).into_rgb8();
+        assert_eq!(annotated.get_pixel(10, 25).0, draw::GREEN);
+        assert_eq!(annotated.get_pixel(25, 25).0, draw::RED);
+        // The score's first digit, just below the box
+        assert_eq!(annotated.get_pixel(10, 42).0, draw::GREEN);
+        assert_eq!(annotated.get_pixel(5, 5).0, [0, 0, 0]);
+    }
+
     #[test]
     fn test_iou() {
         let a = [10.0, 10.0, 20.0, 20.0];
@@ -437,4 +972,221 @@ mod tests {
         let result = nms(&detections, 0.3);
         assert_eq!(result.len(), 2); // Should keep first and third
     }
+
+    #[test]
+    fn test_coordinate_spaces() {
+        let raw = yunet::RawDetection {
+            bbox: [0.25, 0.5, 0.125, 0.25],
+            score: 0.9,
+            landmarks: [0.5; 10],
+        };
+        let mut d = raw.to_pixels(640);
+        assert_eq!(d.bbox, [160.0, 320.0, 80.0, 160.0]);
+        assert_eq!(d.landmarks, [320.0; 10]);
+
+        // Back to fractions of a non-square frame
+        d.translate((0.0, -80.0));
+        let normalized = d.to_normalized((640, 480));
+        assert_eq!(normalized.bbox, [0.25, 0.5, 0.125, 1.0 / 3.0]);
+        assert_eq!(normalized.landmarks[..2], [0.5, 0.5]);
+        assert_eq!(normalized.score, 0.9);
+    }
+
+    #[test]
+    fn test_nms_dense() {
+        // A grid of disjoint low-score boxes: only the best are considered
+        // and the output is capped
+        let detections: Vec<_> = (0..2000)
+            .map(|i| Detection {
+                bbox: [(i % 50) as f32 * 10.0, (i / 50) as f32 * 10.0, 5.0, 5.0],
+                score: i as f32 / 2000.0,
+                landmarks: [0.0; 10],
+            })
+            .collect();
+        let result = nms(&detections, 0.3);
+        assert_eq!(result.len(), NMS_MAX_KEEP);
+        assert_eq!(result[0].score, 1999.0 / 2000.0);
+        assert!(result.windows(2).all(|w| w[0].score >= w[1].score));
+    }
+
+    #[test]
+    fn test_letterbox_bgr() {
+        // Uniform 4x2 image letterboxed into an 8x8 canvas: rows 2..6 filled
+        let src = RgbImage::from_pixel(4, 2, image::Rgb([10, 20, 30]));
+        let mut out = vec![0.0; 3 * 64];
+        letterbox_bgr(&src, (0, 2, 8, 4), 8, &mut out);
+
+        let (b, rest) = out.split_at(64);
+        let (g, r) = rest.split_at(64);
+        for y in 0..8 {
+            let expected = if (2..6).contains(&y) {
+                [30.0, 20.0, 10.0]
+            } else {
+                [0.0; 3]
+            };
+            for x in 0..8 {
+                let i = y * 8 + x;
+                assert_eq!([b[i], g[i], r[i]], expected, "pixel ({}, {})", x, y);
+            }
+        }
+    }
+
+    #[test]
+    fn test_letterbox_bgr_matches_float() {
+        // The float bilinear resize the fixed-point one replaced
+        let src = RgbImage::from_fn(37, 23, |x, y| {
+            image::Rgb([(x * 7) as u8, (y * 11) as u8, ((x * y) % 256) as u8])
+        });
+        let (w, h) = (50, 31);
+        let mut out = vec![0.0; 3 * 64 * 64];
+        letterbox_bgr(&src, (3, 5, w, h), 64, &mut out);
+
+        let (sx, sy) = (37.0 / w as f32, 23.0 / h as f32);
+        for oy in 0..h {
+            let fy = ((oy as f32 + 0.5) * sy - 0.5).clamp(0.0, 22.0);
+            let (y0, wy) = (fy as u32, fy.fract());
+            let y1 = (y0 + 1).min(22);
+            for ox in 0..w {
+                let fx = ((ox as f32 + 0.5) * sx - 0.5).clamp(0.0, 36.0);
+                let (x0, wx) = (fx as u32, fx.fract());
+                let x1 = (x0 + 1).min(36);
+                let at = |px, py, c| src.get_pixel(px, py)[c] as f32;
+                for c in 0..3 {
+                    let top = at(x0, y0, c) * (1.0 - wx) + at(x1, y0, c) * wx;
+                    let bottom = at(x0, y1, c) * (1.0 - wx) + at(x1, y1, c) * wx;
+                    let expected = top * (1.0 - wy) + bottom * wy;
+                    let plane = 2 - c;
+                    let got = out[plane * 64 * 64 + ((oy + 5) * 64 + ox + 3) as usize];
+                    assert!(
+                        (got - expected).abs() <= 1.0,
+                        "pixel ({}, {}) channel {}: {} vs {}",
+                        ox,
+                        oy,
+                        c,
+                        got,
+                        expected
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_mirror() {
+        let src = RgbImage::from_fn(3, 2, |x, y| image::Rgb([x as u8, y as u8, 0]));
+        let mirrored = mirror(&DynamicImage::ImageRgb8(src));
+        let mirrored = mirrored.as_rgb8().unwrap();
+        assert_eq!(mirrored.get_pixel(0, 1).0, [2, 1, 0]);
+        assert_eq!(mirrored.get_pixel(2, 0).0, [0, 0, 0]);
+    }
+
+    #[test]
+    fn test_align_template() {
+        let doubled = AlignTemplate::ARCFACE_112.scaled(2.0);
+        assert_eq!((doubled.width, doubled.height), (224, 224));
+        assert_eq!(doubled.landmarks[0], 2.0 * 38.2946);
+
+        // A face already the template's size, 50px right and 40px down
+        let src = RgbImage::from_fn(200, 200, |x, y| image::Rgb([x as u8, y as u8, 0]));
+        let template = AlignTemplate::ARCFACE_96X112;
+        let mut landmarks = template.landmarks;
+        for point in landmarks.chunks_exact_mut(2) {
+            point[0] += 50.0;
+            point[1] += 40.0;
+        }
+        let detection = Detection {
+            bbox: [50.0, 40.0, 96.0, 112.0],
+            score: 1.0,
+            landmarks,
+        };
+        let aligned = align_face_to(&DynamicImage::ImageRgb8(src), &detection, &template).unwrap();
+        assert_eq!(aligned.dimensions(), (96, 112));
+        let [r, g, _] = aligned.as_rgb8().unwrap().get_pixel(48, 60).0;
+        assert!((r as i32 - 98).abs() <= 1 && (g as i32 - 100).abs() <= 1);
+    }
+
+    #[test]
+    fn test_align_rotated_grey() {
+        // Eyes tilted 90 degrees in a grey frame: the crop comes out upright,
+        // sampled through the RGB conversion
+        let src = image::GrayImage::from_fn(200, 200, |x, _| image::Luma([x as u8]));
+        let template = AlignTemplate::ARCFACE_112;
+        let (lx, ly) = (template.landmarks[0], template.landmarks[1]);
+        let (rx, ry) = (template.landmarks[2], template.landmarks[3]);
+        let mut landmarks = [0.0; 10];
+        // Rotate the template eyes a quarter turn around (100, 100)
+        landmarks[..4].copy_from_slice(&[100.0 - ly, 100.0 + lx, 100.0 - ry, 100.0 + rx]);
+        let detection = Detection {
+            bbox: [0.0, 0.0, 200.0, 200.0],
+            score: 1.0,
+            landmarks,
+        };
+        let aligned = align_face_to(&DynamicImage::ImageLuma8(src), &detection, &template)
+            .unwrap()
+            .to_rgb8();
+        // Source x runs up the crop, so every row is flat and rows darken
+        let row = |y: u32| aligned.get_pixel(56, y)[0] as i32;
+        assert!((row(20) - aligned.get_pixel(90, 20)[0] as i32).abs() <= 1);
+        assert!(row(20) - row(90) >= 60);
+        assert_eq!(aligned.get_pixel(56, 20)[0], aligned.get_pixel(56, 20)[2]);
+    }
+
+    #[test]
+    fn test_average_embeddings() {
+        let a = Embedding::new(vec![1.0, 0.0]).unwrap();
+        let b = Embedding::new(vec![0.0, 1.0]).unwrap();
+        let avg = average_embeddings(&a, &b).unwrap();
+        let half = std::f32::consts::FRAC_1_SQRT_2;
+        assert!((avg.vector()[[0, 0]] - half).abs() < 1e-6);
+        assert!((avg.vector()[[0, 1]] - half).abs() < 1e-6);
+    }
+
+    #[test]
+    fn test_embedding() {
+        let a = Embedding::new(vec![3.0, 4.0]).unwrap();
+        assert_eq!(a.as_slice(), [0.6, 0.8]);
+        assert_eq!(a.dim(), 2);
+        assert_eq!(a.as_bytes().len(), 8);
+        assert_eq!(a.as_bytes()[..4], 0.6f32.to_ne_bytes());
+
+        let b = Embedding::new(vec![0.0, 1.0]).unwrap();
+        assert!((a.cosine(&b) - 0.8).abs() < 1e-6);
+        assert!((a.euclidean(&b) - (2.0f32 - 2.0 * 0.8).sqrt()).abs() < 1e-6);
+        assert_eq!(a.cosine(&a), 1.0);
+
+        assert!(Embedding::new(vec![]).is_err());
+        assert!(Embedding::new(vec![0.0, 0.0]).is_err());
+        assert!(Embedding::new(vec![f32::NAN, 1.0]).is_err());
+        assert!(Embedding::with_dim(vec![1.0, 0.0], 128).is_err());
+        assert!(Embedding::with_dim(vec![1.0, 0.0], 2).is_ok());
+    }
+
+    #[test]
+    fn test_split_batch() {
+        let outputs = vec![(vec![2, 3, 1], vec![1.0, 2.0, 3.0, 4.0, 5.0, 6.0])];
+        let second = split_batch(&outputs, 2, 1).unwrap();
+        assert_eq!(second, vec![(vec![1, 3, 1], vec![4.0, 5.0, 6.0])]);
+        assert!(split_batch(&outputs, 3, 0).is_err());
+    }
+
+    #[test]
+    fn test_size_filter() {
+        let face = |size: f32| Detection {
+            bbox: [0.0, 0.0, size, size * 1.2],
+            score: 0.9,
+            landmarks: [0.0; 10],
+        };
+        let filter = SizeFilter {
+            min: Some(FaceSize::Pixels(60)),
+            max: Some(FaceSize::Fraction(0.8)),
+        };
+        let frame = (640, 480);
+
+        assert!(!filter.allows(&face(40.0), frame));
+        assert!(filter.allows(&face(100.0), frame));
+        // 0.8 of the 480px side is 384px, compared against the box height
+        assert!(!filter.allows(&face(330.0), frame));
+        assert_eq!(filter.apply(vec![face(40.0), face(100.0)], frame).len(), 1);
+        assert!(SizeFilter::default().allows(&face(1.0), frame));
+    }
 }
@@ -1,10 +1,32 @@
+pub mod calibration;
+pub mod cancel;
+pub mod depth;
+pub mod detect;
+pub mod detector;
+pub mod draw;
+pub mod error;
+pub mod eval;
+pub mod exposure;
 pub mod face;
 pub mod model;
+pub mod motion;
+pub mod normalize;
+#[cfg(feature = "tokio")]
+pub mod nonblocking;
+pub mod pad;
 pub mod pipeline;
+pub mod pool;
+pub mod quality;
+pub mod ring;
+pub mod selftest;
+pub mod simd;
+pub mod stream;
 pub mod video;
 pub mod yunet;
 
 // Re-export commonly used types
+pub use detect::{detect, DetectOptions};
+pub use error::{Error, Result};
 pub use face::{Detection, Embedding};
 pub use pipeline::Pipeline;
 pub use video::Camera;
@@ -1,4 +1,5 @@
-use anyhow::{Context, Result};
+use crate::error::{Context, Error, Report, Result, ResultExt};
+use crate::face::AlignTemplate;
 use ort::{
     ep::{self, ExecutionProvider},
     session::{
@@ -6,47 +7,636 @@ use ort::{
         Session,
     },
 };
+use sha2::{Digest, Sha256};
+use std::fs::File;
+use std::os::unix::fs::PermissionsExt;
+use std::os::unix::io::AsRawFd;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::sync::atomic::{AtomicUsize, Ordering};
 
-// Placeholder: include_bytes for required models. In a real setup, these would be the actual files.
+// Without `embed-models` the models come only from the model directory, see
+// `howrs models fetch`
+#[cfg(feature = "embed-models")]
 pub static FACE_RECOGNITION_MODEL: &[u8] =
     include_bytes!("../models/face_recognition_sface_2021dec.onnx");
+#[cfg(feature = "embed-models")]
 pub static DETECTOR_MODEL: &[u8] = include_bytes!("../models/face_detection_yunet_2023mar.onnx");
 
-pub fn session_builder() -> Result<SessionBuilder> {
-    let mut builder =
-        Session::builder()?.with_optimization_level(GraphOptimizationLevel::Level3)?;
+#[cfg(feature = "embed-models")]
+const EMBEDDED_DETECTOR: Option<&[u8]> = Some(DETECTOR_MODEL);
+#[cfg(not(feature = "embed-models"))]
+const EMBEDDED_DETECTOR: Option<&[u8]> = None;
+#[cfg(feature = "embed-models")]
+const EMBEDDED_ENCODER: Option<&[u8]> = Some(FACE_RECOGNITION_MODEL);
+#[cfg(not(feature = "embed-models"))]
+const EMBEDDED_ENCODER: Option<&[u8]> = None;
 
-    #[cfg(feature = "openvino")]
-    {
-        let ep = ep::OpenVINO::default();
-        if ep.is_available()? {
-            ep.register(&mut builder)?;
-        } else {
-            log::warn!("openvino feature is enabled, onnx runtime not compiled with openvino")
+/// Role a model plays in the pipeline
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ModelKind {
+    Detector,
+    Encoder,
+}
+
+/// Numeric precision of a model's weights
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Precision {
+    #[default]
+    Fp32,
+    /// Quantized weights; about twice as fast on CPUs without a GPU, at a
+    /// small cost in accuracy
+    Int8,
+}
+
+impl Precision {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Precision::Fp32 => "fp32",
+            Precision::Int8 => "int8",
         }
     }
 
-    #[cfg(feature = "cuda")]
-    {
-        let ep = ep::CUDA::default();
-        if ep.is_available()? {
-            ep.register(&mut builder);
-        } else {
-            log::warn!("cuda feature is enabled, onnx runtime not compiled with cuda")
+    pub fn parse(s: &str) -> Option<Self> {
+        match s.trim() {
+            "fp32" => Some(Precision::Fp32),
+            "int8" => Some(Precision::Int8),
+            _ => None,
+        }
+    }
+}
+
+/// File in the model directory recording the precision picked by a benchmark
+const SELECTED_PRECISION_FILE: &str = "precision";
+
+/// Precision last picked by a benchmark for models in `dir`, if any
+pub fn selected_precision(dir: &Path) -> Option<Precision> {
+    let selected = std::fs::read_to_string(dir.join(SELECTED_PRECISION_FILE)).ok()?;
+    Precision::parse(&selected)
+}
+
+/// Record `precision` as the benchmark winner for models in `dir`
+pub fn save_selected_precision(dir: &Path, precision: Precision) -> Result<()> {
+    std::fs::create_dir_all(dir).model(format!("create {}", dir.display()))?;
+    let path = dir.join(SELECTED_PRECISION_FILE);
+    std::fs::write(&path, format!("{}\n", precision.as_str()))
+        .model(format!("write {}", path.display()))?;
+    std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o644))
+        .model(format!("chmod {}", path.display()))?;
+    Ok(())
+}
+
+/// Expected shape of a model input or output tensor
+#[derive(Debug, Clone, Copy)]
+pub struct TensorSpec {
+    pub name: &'static str,
+    pub shape: &'static [i64],
+}
+
+/// Static description of a known model
+#[derive(Debug, Clone, Copy)]
+pub struct ModelInfo {
+    pub name: &'static str,
+    pub kind: ModelKind,
+    pub precision: Precision,
+    pub file_name: &'static str,
+    pub url: &'static str,
+    /// Pinned SHA-256 of the model file, if one has been published. None of
+    /// the opencv_zoo models is pinned yet: each needs the hash of its
+    /// published file, taken from a verified download
+    pub sha256: Option<&'static str>,
+    /// Pixel layout expected by the input tensor
+    pub input_format: &'static str,
+    pub inputs: &'static [TensorSpec],
+    pub outputs: &'static [TensorSpec],
+    /// Upstream recommended detection score threshold (detectors only)
+    pub score_threshold: Option<f32>,
+    /// Upstream recommended NMS IoU threshold (detectors only)
+    pub nms_threshold: Option<f32>,
+    /// Upstream recommended cosine similarity threshold (encoders only)
+    pub match_threshold: Option<f32>,
+    /// Crop the encoder was trained on (encoders only)
+    pub alignment: Option<AlignTemplate>,
+    /// Model bytes compiled into the binary, if any
+    pub embedded: Option<&'static [u8]>,
+}
+
+impl ModelInfo {
+    /// Check `bytes` against the pinned hash. An unpinned model fails too:
+    /// nothing says its bytes are the ones published.
+    pub fn verify(&self, bytes: &[u8]) -> Result<()> {
+        let Some(expected) = self.sha256 else {
+            return Err(Error::model(format!(
+                "{} has no pinned sha256 to check against",
+                self.name
+            )));
+        };
+        let actual = sha256_hex(bytes);
+        if !actual.eq_ignore_ascii_case(expected) {
+            return Err(Error::model(format!(
+                "{} sha256 mismatch: expected {}, got {}",
+                self.name, expected, actual
+            )));
+        }
+        Ok(())
+    }
+
+    /// Path of this model in `dir`, if it has been fetched there
+    pub fn installed(&self, dir: &Path) -> Option<PathBuf> {
+        Some(dir.join(self.file_name)).filter(|path| path.is_file())
+    }
+
+    /// Check the copy at `path` against the pinned hash; its SHA-256 either way
+    pub fn verify_file(&self, path: &Path) -> Result<String> {
+        let bytes = std::fs::read(path).model(format!("read {}", path.display()))?;
+        self.verify(&bytes)?;
+        Ok(sha256_hex(&bytes))
+    }
+
+    /// Download this model into `dir` with `curl`.
+    ///
+    /// The file is verified before it replaces an installed copy, so a failed
+    /// or tampered download leaves the old one in place. An unpinned model
+    /// fails verification unless `allow_unpinned` trusts whatever arrives.
+    pub fn fetch(&self, dir: &Path, allow_unpinned: bool) -> Result<PathBuf> {
+        std::fs::create_dir_all(dir).model(format!("create {}", dir.display()))?;
+        let dest = dir.join(self.file_name);
+        let partial = dir.join(format!("{}.part", self.file_name));
+
+        let status = Command::new("curl")
+            .args(["--fail", "--silent", "--show-error", "--location"])
+            .args(["--proto", "=https", "--output"])
+            .arg(&partial)
+            .arg(self.url)
+            .status()
+            .model("run curl")?;
+        let verified = match status.success() {
+            true if self.sha256.is_none() && allow_unpinned => Ok(()),
+            true => self.verify_file(&partial).map(drop),
+            false => Err(Error::model(format!(
+                "downloading {} failed: {}",
+                self.url, status
+            ))),
+        };
+        if let Err(e) = verified {
+            let _ = std::fs::remove_file(&partial);
+            return Err(e);
+        }
+
+        // Readable by the PAM module in every login program
+        std::fs::set_permissions(&partial, std::fs::Permissions::from_mode(0o644))
+            .model(format!("chmod {}", partial.display()))?;
+        std::fs::rename(&partial, &dest).model(format!("install {}", dest.display()))?;
+        Ok(dest)
+    }
+}
+
+pub fn sha256_hex(bytes: &[u8]) -> String {
+    Sha256::digest(bytes)
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect()
+}
+
+pub const YUNET_2023MAR: ModelInfo = ModelInfo {
+    name: "yunet-2023mar",
+    kind: ModelKind::Detector,
+    precision: Precision::Fp32,
+    file_name: "face_detection_yunet_2023mar.onnx",
+    url: "https://github.com/opencv/opencv_zoo/raw/main/models/face_detection_yunet/face_detection_yunet_2023mar.onnx",
+    sha256: None,
+    input_format: "NCHW BGR f32 0-255",
+    inputs: &[TensorSpec {
+        name: "input",
+        shape: &[1, 3, 640, 640],
+    }],
+    outputs: &[
+        TensorSpec { name: "cls_8", shape: &[1, 6400, 1] },
+        TensorSpec { name: "cls_16", shape: &[1, 1600, 1] },
+        TensorSpec { name: "cls_32", shape: &[1, 400, 1] },
+        TensorSpec { name: "obj_8", shape: &[1, 6400, 1] },
+        TensorSpec { name: "obj_16", shape: &[1, 1600, 1] },
+        TensorSpec { name: "obj_32", shape: &[1, 400, 1] },
+        TensorSpec { name: "bbox_8", shape: &[1, 6400, 4] },
+        TensorSpec { name: "bbox_16", shape: &[1, 1600, 4] },
+        TensorSpec { name: "bbox_32", shape: &[1, 400, 4] },
+        TensorSpec { name: "kps_8", shape: &[1, 6400, 10] },
+        TensorSpec { name: "kps_16", shape: &[1, 1600, 10] },
+        TensorSpec { name: "kps_32", shape: &[1, 400, 10] },
+    ],
+    score_threshold: Some(0.9),
+    nms_threshold: Some(0.3),
+    match_threshold: None,
+    alignment: None,
+    embedded: EMBEDDED_DETECTOR,
+};
+
+pub const SFACE_2021DEC: ModelInfo = ModelInfo {
+    name: "sface-2021dec",
+    kind: ModelKind::Encoder,
+    precision: Precision::Fp32,
+    file_name: "face_recognition_sface_2021dec.onnx",
+    url: "https://media.githubusercontent.com/media/opencv/opencv_zoo/refs/heads/main/models/face_recognition_sface/face_recognition_sface_2021dec.onnx",
+    sha256: None,
+    input_format: "NCHW BGR f32 0-255, aligned 112x112 crop",
+    inputs: &[TensorSpec {
+        name: "data",
+        shape: &[1, 3, 112, 112],
+    }],
+    outputs: &[TensorSpec {
+        name: "fc1",
+        shape: &[1, 128],
+    }],
+    score_threshold: None,
+    nms_threshold: None,
+    match_threshold: Some(0.363),
+    alignment: Some(AlignTemplate::ARCFACE_112),
+    embedded: EMBEDDED_ENCODER,
+};
+
+/// Statically quantized YuNet. Quantize and dequantize nodes sit inside the
+/// graph, so input and outputs are the same float tensors as the original.
+pub const YUNET_2023MAR_INT8: ModelInfo = ModelInfo {
+    name: "yunet-2023mar-int8",
+    precision: Precision::Int8,
+    file_name: "face_detection_yunet_2023mar_int8.onnx",
+    url: "https://github.com/opencv/opencv_zoo/raw/main/models/face_detection_yunet/face_detection_yunet_2023mar_int8.onnx",
+    // Each file has its own hash; not inherited from the fp32 model
+    sha256: None,
+    embedded: None,
+    ..YUNET_2023MAR
+};
+
+/// Statically quantized SFace, with float input and output like the original.
+/// Its embeddings are close to but not the same as SFace's; re-enroll after
+/// switching.
+pub const SFACE_2021DEC_INT8: ModelInfo = ModelInfo {
+    name: "sface-2021dec-int8",
+    precision: Precision::Int8,
+    file_name: "face_recognition_sface_2021dec_int8.onnx",
+    url: "https://media.githubusercontent.com/media/opencv/opencv_zoo/refs/heads/main/models/face_recognition_sface/face_recognition_sface_2021dec_int8.onnx",
+    // Each file has its own hash; not inherited from the fp32 model
+    sha256: None,
+    embedded: None,
+    ..SFACE_2021DEC
+};
+
+/// Known detector and encoder models
+pub struct Registry;
+
+impl Registry {
+    pub const MODELS: &'static [ModelInfo] = &[
+        YUNET_2023MAR,
+        SFACE_2021DEC,
+        YUNET_2023MAR_INT8,
+        SFACE_2021DEC_INT8,
+    ];
+
+    pub fn all() -> &'static [ModelInfo] {
+        Self::MODELS
+    }
+
+    pub fn get(name: &str) -> Option<&'static ModelInfo> {
+        Self::MODELS.iter().find(|m| m.name == name)
+    }
+
+    pub fn of_kind(kind: ModelKind) -> impl Iterator<Item = &'static ModelInfo> {
+        Self::MODELS.iter().filter(move |m| m.kind == kind)
+    }
+
+    pub fn default_detector() -> &'static ModelInfo {
+        &Self::MODELS[0]
+    }
+
+    pub fn default_encoder() -> &'static ModelInfo {
+        &Self::MODELS[1]
+    }
+
+    /// The default model of `kind` at `precision`
+    pub fn variant(kind: ModelKind, precision: Precision) -> &'static ModelInfo {
+        Self::MODELS
+            .iter()
+            .find(|m| m.kind == kind && m.precision == precision)
+            .expect("every kind has a model at every precision")
+    }
+}
+
+/// Hardware inference runs on
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Provider {
+    /// Every accelerator compiled into this build that the runtime can use,
+    /// otherwise the CPU
+    #[default]
+    Auto,
+    Cpu,
+    /// Needs the `openvino` feature
+    OpenVino,
+    /// Needs the `cuda` feature
+    Cuda,
+}
+
+/// See [`set_intra_threads`]
+static INTRA_THREADS: AtomicUsize = AtomicUsize::new(0);
+
+/// Threads a session created from now on may use within one inference.
+/// 0, the default, leaves it to ONNX Runtime, which starts one per core;
+/// each of those threads has its own scratch memory.
+pub fn set_intra_threads(threads: usize) {
+    INTRA_THREADS.store(threads, Ordering::Relaxed);
+}
+
+pub fn session_builder() -> Result<SessionBuilder> {
+    session_builder_on(Provider::Auto)
+}
+
+/// Session builder for `provider`; an explicitly requested accelerator that
+/// can't be used is an error rather than a silent CPU fallback
+#[allow(unused_mut)]
+pub fn session_builder_on(provider: Provider) -> Result<SessionBuilder> {
+    let threads = INTRA_THREADS.load(Ordering::Relaxed);
+    let mut builder = Session::builder()
+        .and_then(|b| b.with_optimization_level(GraphOptimizationLevel::Level3))
+        .and_then(|b| match threads {
+            0 => Ok(b),
+            n => b.with_intra_threads(n),
+        })
+        .model("create onnx runtime session")?;
+
+    match provider {
+        Provider::Auto => {
+            #[cfg(feature = "openvino")]
+            {
+                let ep = ep::OpenVINO::default();
+                if ep.is_available().model("probe openvino")? {
+                    ep.register(&mut builder).model("register openvino")?;
+                } else {
+                    tracing::warn!(
+                        "openvino feature is enabled, onnx runtime not compiled with openvino"
+                    )
+                }
+            }
+
+            #[cfg(feature = "cuda")]
+            {
+                let ep = ep::CUDA::default();
+                if ep.is_available().model("probe cuda")? {
+                    ep.register(&mut builder);
+                } else {
+                    tracing::warn!("cuda feature is enabled, onnx runtime not compiled with cuda")
+                }
+            }
+        }
+        Provider::Cpu => {}
+        Provider::OpenVino => {
+            #[cfg(feature = "openvino")]
+            {
+                let ep = ep::OpenVINO::default();
+                if !ep.is_available().model("probe openvino")? {
+                    return Err(Error::model("onnx runtime not compiled with openvino"));
+                }
+                ep.register(&mut builder).model("register openvino")?;
+            }
+            #[cfg(not(feature = "openvino"))]
+            return Err(Error::model("built without the openvino feature"));
+        }
+        Provider::Cuda => {
+            #[cfg(feature = "cuda")]
+            {
+                let ep = ep::CUDA::default();
+                if !ep.is_available().model("probe cuda")? {
+                    return Err(Error::model("onnx runtime not compiled with cuda"));
+                }
+                ep.register(&mut builder).model("register cuda")?;
+            }
+            #[cfg(not(feature = "cuda"))]
+            return Err(Error::model("built without the cuda feature"));
         }
     }
 
     Ok(builder)
 }
 
+/// Execution providers compiled into this build and whether the loaded ONNX
+/// Runtime can actually use them. The CPU provider is always available.
+#[allow(unused_mut, unused_variables)]
+pub fn execution_providers() -> Vec<(&'static str, bool)> {
+    // Probing goes through the runtime; if that can't load, nothing is usable
+    let runtime = runtime_info().is_some();
+
+    let mut eps = vec![("cpu", true)];
+    #[cfg(feature = "openvino")]
+    eps.push((
+        "openvino",
+        runtime && ep::OpenVINO::default().is_available().unwrap_or(false),
+    ));
+    #[cfg(feature = "cuda")]
+    eps.push((
+        "cuda",
+        runtime && ep::CUDA::default().is_available().unwrap_or(false),
+    ));
+    eps
+}
+
+/// Build information of the loaded ONNX Runtime, or `None` if it can't be loaded
+pub fn runtime_info() -> Option<String> {
+    std::panic::catch_unwind(|| ort::info().to_string()).ok()
+}
+
 pub fn recog_session() -> Result<Session> {
-    session_builder()?
-        .commit_from_memory(FACE_RECOGNITION_MODEL)
-        .context("load recognition model")
+    recog_session_on(Provider::Auto)
+}
+
+pub fn recog_session_on(provider: Provider) -> Result<Session> {
+    embedded_session(Registry::default_encoder(), provider).context("load recognition model")
 }
 
 pub fn detector_session() -> Result<Session> {
-    session_builder()?
-        .commit_from_memory(DETECTOR_MODEL)
-        .context("load detector model")
+    detector_session_on(Provider::Auto)
+}
+
+pub fn detector_session_on(provider: Provider) -> Result<Session> {
+    embedded_session(Registry::default_detector(), provider).context("load detector model")
+}
+
+/// Load `info` from `model_dir` if it has been fetched there and matches its
+/// pinned hash, otherwise the copy embedded in the binary
+pub fn session(info: &ModelInfo, model_dir: &Path) -> Result<Session> {
+    let Some(path) = info.installed(model_dir) else {
+        return embedded_session(info, Provider::Auto);
+    };
+    match info.verify_file(&path) {
+        Ok(_) => file_session(&path),
+        Err(e) if info.embedded.is_some() => {
+            tracing::warn!("not loading {}: {}", path.display(), Report(&e));
+            embedded_session(info, Provider::Auto)
+        }
+        Err(e) => Err(e).model(format!("load model {}", path.display())),
+    }
+}
+
+/// Load a model from an ONNX file, e.g. a detector that isn't embedded.
+///
+/// The file is memory-mapped rather than read into a buffer, so it is parsed
+/// straight from the page cache.
+pub fn file_session(path: &Path) -> Result<Session> {
+    file_session_on(path, Provider::Auto)
+}
+
+/// [`file_session`] running on `provider`
+pub fn file_session_on(path: &Path, provider: Provider) -> Result<Session> {
+    let mapped = MappedFile::open(path).and_then(|file| {
+        session_builder_on(provider)?
+            .commit_from_memory(file.bytes())
+            .model("load model")
+    });
+    match mapped {
+        Ok(session) => Ok(session),
+        // Models with external weights are only found relative to their path
+        Err(e) => {
+            tracing::debug!("loading mapped {} failed: {}", path.display(), Report(&e));
+            session_builder_on(provider)?
+                .commit_from_file(path)
+                .model(format!("load model {}", path.display()))
+        }
+    }
+}
+
+/// Read-only mapping of a whole file, unmapped on drop
+struct MappedFile {
+    ptr: *mut libc::c_void,
+    len: usize,
+}
+
+impl MappedFile {
+    fn open(path: &Path) -> Result<Self> {
+        let file = File::open(path).model(format!("open {}", path.display()))?;
+        let len = file
+            .metadata()
+            .model(format!("stat {}", path.display()))?
+            .len() as usize;
+        if len == 0 {
+            return Err(Error::model(format!("{} is empty", path.display())));
+        }
+        let ptr = unsafe {
+            libc::mmap(
+                std::ptr::null_mut(),
+                len,
+                libc::PROT_READ,
+                libc::MAP_PRIVATE,
+                file.as_raw_fd(),
+                0,
+            )
+        };
+        if ptr == libc::MAP_FAILED {
+            return Err(std::io::Error::last_os_error()).model(format!("map {}", path.display()));
+        }
+        Ok(Self { ptr, len })
+    }
+
+    fn bytes(&self) -> &[u8] {
+        unsafe { std::slice::from_raw_parts(self.ptr.cast(), self.len) }
+    }
+}
+
+impl Drop for MappedFile {
+    fn drop(&mut self) {
+        unsafe { libc::munmap(self.ptr, self.len) };
+    }
+}
+
+fn embedded_session(info: &ModelInfo, provider: Provider) -> Result<Session> {
+    let bytes = info.embedded.ok_or_else(|| {
+        Error::model(format!(
+            "{} is not embedded in this build; run `howrs models fetch`",
+            info.name
+        ))
+    })?;
+    session_builder_on(provider)?
+        .commit_from_memory(bytes)
+        .model(format!("load model {}", info.name))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_registry_lookup() {
+        assert_eq!(Registry::default_detector().kind, ModelKind::Detector);
+        assert_eq!(Registry::default_encoder().kind, ModelKind::Encoder);
+        assert!(Registry::get("yunet-2023mar").is_some());
+        assert!(Registry::get("missing").is_none());
+        assert_eq!(YUNET_2023MAR.outputs.len(), 12);
+
+        let int8 = Registry::variant(ModelKind::Encoder, Precision::Int8);
+        assert_eq!(int8.name, "sface-2021dec-int8");
+        assert_eq!(int8.alignment, SFACE_2021DEC.alignment);
+        assert!(int8.embedded.is_none());
+        assert_eq!(
+            Registry::variant(ModelKind::Detector, Precision::Fp32).name,
+            Registry::default_detector().name
+        );
+
+        // Every file is pinned on its own
+        let pins: Vec<_> = Registry::all().iter().filter_map(|m| m.sha256).collect();
+        let mut unique = pins.clone();
+        unique.sort_unstable();
+        unique.dedup();
+        assert_eq!(unique.len(), pins.len());
+    }
+
+    #[test]
+    fn test_mapped_file() {
+        let dir = std::env::temp_dir().join(format!("howrs-mapped-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("model.onnx");
+        std::fs::write(&path, b"not a model").unwrap();
+        assert_eq!(MappedFile::open(&path).unwrap().bytes(), b"not a model");
+
+        std::fs::write(&path, b"").unwrap();
+        assert!(MappedFile::open(&path).is_err());
+        assert!(MappedFile::open(&dir.join("missing.onnx")).is_err());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_verify() {
+        let mut info = SFACE_2021DEC;
+        info.sha256 = Some("e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855");
+        assert!(info.verify(b"").is_ok());
+        assert!(info.verify(b"x").is_err());
+
+        // Nothing to check against is a failure, not a pass
+        info.sha256 = None;
+        assert!(info.verify(b"").is_err());
+    }
+
+    #[test]
+    fn test_installed() {
+        let dir = std::env::temp_dir().join(format!("howrs-models-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let mut info = YUNET_2023MAR;
+        info.sha256 = None;
+        assert_eq!(info.installed(&dir), None);
+
+        let path = dir.join(info.file_name);
+        std::fs::write(&path, b"").unwrap();
+        assert_eq!(info.installed(&dir), Some(path.clone()));
+        assert!(info.verify_file(&path).is_err());
+        let empty = "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855";
+        info.sha256 = Some(empty);
+        assert_eq!(info.verify_file(&path).unwrap(), empty);
+
+        // Nothing to fall back to, so a copy that doesn't verify is an error
+        let mut int8 = YUNET_2023MAR_INT8;
+        int8.sha256 = Some(empty);
+        std::fs::write(dir.join(int8.file_name), b"tampered").unwrap();
+        assert!(session(&int8, &dir).is_err());
+
+        assert_eq!(selected_precision(&dir), None);
+        save_selected_precision(&dir, Precision::Int8).unwrap();
+        assert_eq!(selected_precision(&dir), Some(Precision::Int8));
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
 }
@@ -0,0 +1,119 @@
+//! Liveness from how a face changes over the last second of a scan.
+//!
+//! A live face is never perfectly still: it blinks, breathes and sways. A
+//! photo on a stand or a still image fed through a virtual camera changes
+//! only by sensor noise. This looks at the frames a [`FrameRing`] kept, so
+//! it needs no capture of its own. It doesn't catch a replayed video or a
+//! photo waved in front of the camera; an anti-spoofing model
+//! ([`crate::pad`]) or a depth camera ([`crate::depth`]) does.
+
+use crate::ring::FrameRing;
+use std::time::Duration;
+
+/// Frames a measurement needs, the matched one included
+pub const MIN_FRAMES: usize = 3;
+
+/// Change of a face region across recent frames
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Motion {
+    /// Lower median, over successive pairs of frames, of the mean absolute
+    /// luma difference inside the face box (0-255)
+    pub change: f32,
+    /// Frames compared
+    pub frames: usize,
+    /// Time between the first and the last of them
+    pub span: Duration,
+}
+
+impl Motion {
+    /// Measure the central part of `bbox`, a face box in the newest frame
+    /// of `ring`, over the frames captured within `window` before it.
+    ///
+    /// The median ignores a single jump, such as the face entering the
+    /// frame. `None` with fewer than [`MIN_FRAMES`] frames of the newest
+    /// one's size, or if the box misses the frame.
+    pub fn measure(ring: &FrameRing, bbox: &[f32; 4], window: Duration) -> Option<Self> {
+        let latest = ring.latest()?;
+        let (width, height) = latest.frame.dimensions();
+        let frames: Vec<_> = ring
+            .iter()
+            .filter(|f| f.frame.dimensions() == (width, height))
+            .filter(|f| latest.captured.duration_since(f.captured) <= window)
+            .collect();
+        if frames.len() < MIN_FRAMES {
+            return None;
+        }
+
+        // The corners of a face box are hair and background
+        let [x, y, w, h] = *bbox;
+        let x0 = (x + w * 0.2).max(0.0) as u32;
+        let y0 = (y + h * 0.2).max(0.0) as u32;
+        let x1 = ((x + w * 0.8) as u32).min(width);
+        let y1 = ((y + h * 0.8) as u32).min(height);
+        if x1 <= x0 || y1 <= y0 {
+            return None;
+        }
+
+        let luma = |p: &image::Rgb<u8>| {
+            let [r, g, b] = p.0;
+            0.299 * r as f32 + 0.587 * g as f32 + 0.114 * b as f32
+        };
+        let mut changes: Vec<f32> = frames
+            .windows(2)
+            .map(|pair| {
+                let (a, b) = (&pair[0].frame, &pair[1].frame);
+                let mut sum = 0.0;
+                for py in y0..y1 {
+                    for px in x0..x1 {
+                        sum += (luma(a.get_pixel(px, py)) - luma(b.get_pixel(px, py))).abs();
+                    }
+                }
+                sum / ((x1 - x0) * (y1 - y0)) as f32
+            })
+            .collect();
+        changes.sort_by(f32::total_cmp);
+
+        Some(Self {
+            change: changes[(changes.len() - 1) / 2],
+            frames: frames.len(),
+            span: latest.captured.duration_since(frames[0].captured),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::{Rgb, RgbImage};
+    use std::time::Instant;
+
+    fn ring_of(values: &[u8]) -> FrameRing {
+        let start = Instant::now();
+        let mut ring = FrameRing::new(values.len());
+        for (i, &v) in values.iter().enumerate() {
+            let frame = RgbImage::from_pixel(20, 20, Rgb([v, v, v]));
+            ring.push(&frame, start + Duration::from_millis(i as u64 * 100));
+        }
+        ring
+    }
+
+    #[test]
+    fn test_motion() {
+        let bbox = [0.0, 0.0, 20.0, 20.0];
+        let window = Duration::from_secs(1);
+
+        // A still image, after one jump as it came into view
+        let still = Motion::measure(&ring_of(&[0, 100, 100, 100]), &bbox, window).unwrap();
+        assert_eq!(still.change, 0.0);
+        assert_eq!(still.frames, 4);
+        assert_eq!(still.span, Duration::from_millis(300));
+
+        let moving = Motion::measure(&ring_of(&[100, 110, 100, 110]), &bbox, window).unwrap();
+        assert!((moving.change - 10.0).abs() < 0.01);
+
+        // Frames outside the window don't count
+        let short = Duration::from_millis(150);
+        assert!(Motion::measure(&ring_of(&[0, 10, 20, 30]), &bbox, short).is_none());
+        assert!(Motion::measure(&ring_of(&[0, 10, 20]), &[40.0, 40.0, 5.0, 5.0], window).is_none());
+    }
+}
@@ -0,0 +1,262 @@
+//! Capture and inference for async programs, with the `tokio` feature
+//!
+//! Camera reads and ONNX Runtime calls block for tens of milliseconds, too
+//! long for an async task. [`Frames`] reads a camera on a thread of its own
+//! and [`AsyncPipeline`] runs the models through
+//! [`tokio::task::spawn_blocking`], so a D-Bus service or a greeter can wait
+//! on frames, IPC and timeouts together in one `select!`.
+//!
+//! ```no_run
+//! # async fn run() -> howrs_vision::Result<()> {
+//! use howrs_vision::nonblocking::AsyncPipeline;
+//! use howrs_vision::video::{Camera, CaptureSettings};
+//! use howrs_vision::Pipeline;
+//!
+//! let pipeline = AsyncPipeline::new(Pipeline::new()?);
+//! let mut frames = Camera::open("/dev/video0", &CaptureSettings::default())?.frames();
+//! while let Some(frame) = frames.next().await {
+//!     let faces = pipeline.detect_all(frame?.into()).await?;
+//!     println!("{} faces", faces.len());
+//! }
+//! # Ok(())
+//! # }
+//! ```
+
+use crate::cancel::{CancelToken, Cancelled};
+use crate::error::Result;
+use crate::face::{Detection, Embedding};
+use crate::pipeline::{Pipeline, PipelineTimings};
+use crate::video::{Camera, CameraManager, FrameSource};
+use futures_core::Stream;
+use image::{DynamicImage, RgbImage};
+use std::pin::Pin;
+use std::sync::{mpsc, Arc, Mutex, PoisonError};
+use std::task::{Context, Poll};
+use std::time::Duration;
+use tokio::sync::mpsc as async_mpsc;
+
+/// Frames of a camera as a [`Stream`], see [`Camera::frames`].
+///
+/// A frame is only read once the stream is polled for it, so like
+/// [`FrameSource::frame`] on a camera it is the newest one rather than one
+/// queued up while the caller was busy. Read errors are yielded and the
+/// stream goes on; a [`CameraManager`] recovers from them by itself. The
+/// stream ends only when the reader thread is gone, and dropping it closes
+/// the camera.
+pub struct Frames {
+    /// One message per frame wanted; closing it stops the reader thread
+    demand: mpsc::Sender<()>,
+    frames: async_mpsc::Receiver<Result<RgbImage>>,
+    /// A frame was asked for and hasn't arrived yet
+    requested: bool,
+}
+
+impl Frames {
+    /// Read `source` on a thread of its own. It is a plain thread rather
+    /// than one from tokio's blocking pool since it lives as long as the
+    /// stream, and it doesn't need a runtime.
+    pub fn new<S: FrameSource + Send + 'static>(mut source: S) -> Self {
+        let (demand, wanted) = mpsc::channel();
+        let (sender, frames) = async_mpsc::channel(1);
+        std::thread::spawn(move || {
+            for () in wanted {
+                if sender.blocking_send(source.frame()).is_err() {
+                    break;
+                }
+            }
+        });
+        Self {
+            demand,
+            frames,
+            requested: false,
+        }
+    }
+
+    /// The next frame, `None` once the stream has ended
+    pub async fn next(&mut self) -> Option<Result<RgbImage>> {
+        std::future::poll_fn(|cx| Pin::new(&mut *self).poll_next(cx)).await
+    }
+}
+
+impl Stream for Frames {
+    type Item = Result<RgbImage>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        if !self.requested {
+            if self.demand.send(()).is_err() {
+                return Poll::Ready(None);
+            }
+            self.requested = true;
+        }
+        let frame = self.frames.poll_recv(cx);
+        if frame.is_ready() {
+            self.requested = false;
+        }
+        frame
+    }
+}
+
+impl Camera {
+    /// The camera's newest frames as an async [`Stream`]; see [`Frames`]
+    pub fn frames(self) -> Frames {
+        Frames::new(self)
+    }
+}
+
+impl CameraManager {
+    /// Like [`Camera::frames`], reopening the device when it stops
+    /// delivering frames
+    pub fn frames(self) -> Frames {
+        Frames::new(self)
+    }
+}
+
+/// [`Pipeline`] with `async` methods, each run on tokio's blocking pool.
+///
+/// Cheap to clone; clones share the pipeline and take turns on it. Dropping
+/// a returned future doesn't stop work already handed to the pool, so a
+/// call abandoned in a `select!` still finishes in the background; cancel it
+/// through [`Self::cancel_token`] to free the pipeline sooner.
+#[derive(Clone)]
+pub struct AsyncPipeline {
+    inner: Arc<Mutex<Pipeline>>,
+    cancel: CancelToken,
+}
+
+impl AsyncPipeline {
+    pub fn new(pipeline: Pipeline) -> Self {
+        Self {
+            cancel: pipeline.cancel.clone(),
+            inner: Arc::new(Mutex::new(pipeline)),
+        }
+    }
+
+    /// The pipeline's [`Pipeline::cancel`] token
+    pub fn cancel_token(&self) -> CancelToken {
+        self.cancel.clone()
+    }
+
+    /// Run `f` on the pipeline on the blocking pool, for anything without a
+    /// method here, such as [`Pipeline::run_stream`]. Fails with
+    /// [`Cancelled`] if the runtime shuts down first; a panic in `f` is
+    /// resumed in the caller.
+    pub async fn with<T, F>(&self, f: F) -> Result<T>
+    where
+        T: Send + 'static,
+        F: FnOnce(&mut Pipeline) -> T + Send + 'static,
+    {
+        let inner = Arc::clone(&self.inner);
+        let task = tokio::task::spawn_blocking(move || {
+            // A panic mid-inference leaves the sessions as usable as before
+            let mut pipeline = inner.lock().unwrap_or_else(PoisonError::into_inner);
+            f(&mut pipeline)
+        });
+        match task.await {
+            Ok(out) => Ok(out),
+            Err(e) => match e.try_into_panic() {
+                Ok(panic) => std::panic::resume_unwind(panic),
+                Err(_) => Err(Cancelled.into()),
+            },
+        }
+    }
+
+    /// [`Pipeline::warm_up`]
+    pub async fn warm_up(&self) -> Result<Duration> {
+        self.with(|p| p.warm_up()).await?
+    }
+
+    /// [`Pipeline::process_image`]
+    pub async fn process_image(
+        &self,
+        img: DynamicImage,
+    ) -> Result<(Detection, Embedding, PipelineTimings)> {
+        self.with(move |p| p.process_image(&img)).await?
+    }
+
+    /// [`Pipeline::process_all`]
+    pub async fn process_all(&self, img: DynamicImage) -> Result<Vec<(Detection, Embedding)>> {
+        self.with(move |p| p.process_all(&img)).await?
+    }
+
+    /// [`Pipeline::detect_best`]
+    pub async fn detect_best(&self, img: DynamicImage) -> Result<Option<Detection>> {
+        self.with(move |p| p.detect_best(&img)).await?
+    }
+
+    /// [`Pipeline::detect_all`]
+    pub async fn detect_all(&self, img: DynamicImage) -> Result<Vec<Detection>> {
+        self.with(move |p| p.detect_all(&img)).await?
+    }
+
+    /// [`Pipeline::encode_detection`]
+    pub async fn encode_detection(
+        &self,
+        img: DynamicImage,
+        detection: Detection,
+    ) -> Result<Embedding> {
+        self.with(move |p| p.encode_detection(&img, &detection))
+            .await?
+    }
+
+    /// [`Pipeline::extract_embedding`]
+    pub async fn extract_embedding(&self, img: DynamicImage) -> Result<Embedding> {
+        self.with(move |p| p.extract_embedding(&img)).await?
+    }
+}
+
+impl From<Pipeline> for AsyncPipeline {
+    fn from(pipeline: Pipeline) -> Self {
+        Self::new(pipeline)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicU32, Ordering};
+
+    /// Numbers its frames in the first pixel
+    struct Counter(Arc<AtomicU32>);
+
+    impl FrameSource for Counter {
+        fn frame(&mut self) -> Result<RgbImage> {
+            let n = self.0.fetch_add(1, Ordering::SeqCst) + 1;
+            Ok(RgbImage::from_pixel(1, 1, image::Rgb([n as u8, 0, 0])))
+        }
+    }
+
+    impl Drop for Counter {
+        fn drop(&mut self) {
+            self.0.store(u32::MAX, Ordering::SeqCst);
+        }
+    }
+
+    #[test]
+    fn test_frames_on_demand() {
+        let reads = Arc::new(AtomicU32::new(0));
+        let mut frames = Frames::new(Counter(Arc::clone(&reads)));
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .build()
+            .unwrap();
+
+        runtime.block_on(async {
+            for n in 1..=3 {
+                let frame = frames.next().await.unwrap().unwrap();
+                assert_eq!(frame.get_pixel(0, 0).0[0], n);
+            }
+        });
+        // Nothing is read ahead of the caller
+        std::thread::sleep(Duration::from_millis(50));
+        assert_eq!(reads.load(Ordering::SeqCst), 3);
+
+        // Dropping the stream releases the source
+        drop(frames);
+        for _ in 0..100 {
+            if reads.load(Ordering::SeqCst) == u32::MAX {
+                return;
+            }
+            std::thread::sleep(Duration::from_millis(10));
+        }
+        panic!("frame source not dropped");
+    }
+}
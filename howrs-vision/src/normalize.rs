@@ -0,0 +1,223 @@
+//! Contrast normalization for IR and other grayscale frames
+//!
+//! IR cameras light the face with their own emitter, so brightness swings a
+//! lot with distance and ambient light. Equalizing the histogram before
+//! detection and again on the aligned face keeps embeddings of the same
+//! person closer together. Color frames are left alone.
+
+use crate::pool;
+use image::{DynamicImage, GenericImageView, RgbImage};
+
+/// Contrast limit of [`Normalization::Clahe`], as a multiple of the mean bin count
+pub const CLAHE_CLIP_LIMIT: f32 = 2.0;
+/// Tiles per side of [`Normalization::Clahe`]
+pub const CLAHE_TILES: u32 = 8;
+
+/// Largest channel difference for a pixel to still count as gray; YUYV
+/// conversion of a gray image leaves small rounding differences
+const GRAY_TOLERANCE: u8 = 2;
+/// Pixels sampled by [`is_grayscale`]
+const GRAY_SAMPLES: usize = 1024;
+
+/// Preprocessing applied to grayscale images
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Normalization {
+    #[default]
+    None,
+    /// Global histogram equalization
+    Equalize,
+    /// Contrast limited adaptive histogram equalization; evens out faces lit
+    /// from one side better than [`Normalization::Equalize`]
+    Clahe,
+}
+
+impl Normalization {
+    /// Normalized RGB copy of `img`, or `None` if disabled or `img` has color
+    pub fn apply(self, img: &DynamicImage) -> Option<DynamicImage> {
+        if self == Normalization::None {
+            return None;
+        }
+        let (width, height) = img.dimensions();
+        let mut luma: Vec<u8> = match img {
+            DynamicImage::ImageLuma8(gray) => gray.as_raw().clone(),
+            DynamicImage::ImageRgb8(rgb) if is_grayscale(rgb) => {
+                rgb.pixels().map(|p| p.0[0]).collect()
+            }
+            _ => return None,
+        };
+        match self {
+            Normalization::None => unreachable!(),
+            Normalization::Equalize => equalize(&mut luma),
+            Normalization::Clahe => clahe(&mut luma, width, height),
+        }
+
+        let mut buf = pool::frames().take(luma.len() * 3);
+        for (&y, px) in luma.iter().zip(buf.chunks_exact_mut(3)) {
+            px.fill(y);
+        }
+        RgbImage::from_raw(width, height, buf).map(DynamicImage::ImageRgb8)
+    }
+}
+
+/// Whether every sampled pixel has (nearly) equal channels
+pub fn is_grayscale(img: &RgbImage) -> bool {
+    let pixels = img.as_raw().chunks_exact(3);
+    let step = (pixels.len() / GRAY_SAMPLES).max(1);
+    pixels.step_by(step).all(|p| {
+        let (min, max) = (p[0].min(p[1]).min(p[2]), p[0].max(p[1]).max(p[2]));
+        max - min <= GRAY_TOLERANCE
+    })
+}
+
+fn histogram(values: impl Iterator<Item = u8>) -> [u32; 256] {
+    let mut hist = [0u32; 256];
+    for v in values {
+        hist[v as usize] += 1;
+    }
+    hist
+}
+
+/// Spread the cumulative histogram of `luma` evenly over 0-255
+fn equalize(luma: &mut [u8]) {
+    let hist = histogram(luma.iter().copied());
+    let total = luma.len() as u32;
+    let first = hist.iter().copied().find(|&n| n > 0).unwrap_or(0);
+    if total == first {
+        // Flat image, nothing to spread
+        return;
+    }
+
+    let mut lut = [0u8; 256];
+    let mut cdf = 0;
+    for (v, &n) in hist.iter().enumerate() {
+        cdf += n;
+        lut[v] = ((cdf.saturating_sub(first)) as u64 * 255 / (total - first) as u64) as u8;
+    }
+    for v in luma {
+        *v = lut[*v as usize];
+    }
+}
+
+/// CLAHE: equalize each of [`CLAHE_TILES`]² tiles with its histogram clipped
+/// at [`CLAHE_CLIP_LIMIT`], then blend the four nearest tile mappings per
+/// pixel so tile borders don't show
+fn clahe(luma: &mut [u8], width: u32, height: u32) {
+    // Tiles smaller than 8 pixels have too few samples for a histogram
+    let tiles_x = CLAHE_TILES.min(width / 8).max(1) as usize;
+    let tiles_y = CLAHE_TILES.min(height / 8).max(1) as usize;
+    let (width, height) = (width as usize, height as usize);
+    let span =
+        |tile: usize, tiles: usize, len: usize| (tile * len / tiles, (tile + 1) * len / tiles);
+
+    let mut luts = vec![[0u8; 256]; tiles_x * tiles_y];
+    for ty in 0..tiles_y {
+        let (y0, y1) = span(ty, tiles_y, height);
+        for tx in 0..tiles_x {
+            let (x0, x1) = span(tx, tiles_x, width);
+            let tile = (y0..y1).flat_map(|y| luma[y * width + x0..y * width + x1].iter().copied());
+            let mut hist = histogram(tile);
+            let pixels = ((y1 - y0) * (x1 - x0)) as u32;
+
+            // Clip and hand the excess back out evenly
+            let limit = ((CLAHE_CLIP_LIMIT * pixels as f32 / 256.0) as u32).max(1);
+            let mut excess = 0;
+            for n in hist.iter_mut() {
+                excess += n.saturating_sub(limit);
+                *n = (*n).min(limit);
+            }
+            let (share, rest) = (excess / 256, excess % 256);
+            for (v, n) in hist.iter_mut().enumerate() {
+                *n += share + u32::from((v as u32) < rest);
+            }
+
+            let lut = &mut luts[ty * tiles_x + tx];
+            let mut cdf = 0;
+            for (v, &n) in hist.iter().enumerate() {
+                cdf += n;
+                lut[v] = (cdf as u64 * 255 / pixels.max(1) as u64) as u8;
+            }
+        }
+    }
+
+    // Position of a pixel relative to the tile centers: the lower tile index
+    // and the weight of the upper one
+    let neighbours = |pos: usize, tiles: usize, len: usize| {
+        let f = (pos as f32 + 0.5) * tiles as f32 / len as f32 - 0.5;
+        let lower = (f.floor().max(0.0) as usize).min(tiles - 1);
+        let upper = (lower + 1).min(tiles - 1);
+        (lower, upper, (f - lower as f32).clamp(0.0, 1.0))
+    };
+    for y in 0..height {
+        let (ty0, ty1, wy) = neighbours(y, tiles_y, height);
+        for x in 0..width {
+            let (tx0, tx1, wx) = neighbours(x, tiles_x, width);
+            let v = luma[y * width + x] as usize;
+            let at = |ty: usize, tx: usize| luts[ty * tiles_x + tx][v] as f32;
+            let top = at(ty0, tx0) * (1.0 - wx) + at(ty0, tx1) * wx;
+            let bottom = at(ty1, tx0) * (1.0 - wx) + at(ty1, tx1) * wx;
+            luma[y * width + x] = (top * (1.0 - wy) + bottom * wy).round() as u8;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn gray(width: u32, height: u32, f: impl Fn(u32, u32) -> u8) -> DynamicImage {
+        DynamicImage::ImageRgb8(RgbImage::from_fn(width, height, |x, y| {
+            image::Rgb([f(x, y); 3])
+        }))
+    }
+
+    #[test]
+    fn test_skips_color_and_disabled() {
+        let color = DynamicImage::ImageRgb8(RgbImage::from_pixel(4, 4, image::Rgb([200, 40, 40])));
+        assert!(Normalization::Clahe.apply(&color).is_none());
+        assert!(Normalization::None.apply(&gray(4, 4, |_, _| 0)).is_none());
+
+        let luma = DynamicImage::ImageLuma8(image::GrayImage::from_pixel(4, 4, image::Luma([7])));
+        assert!(Normalization::Equalize.apply(&luma).is_some());
+    }
+
+    #[test]
+    fn test_equalize_stretches_dim_frame() {
+        // A dark frame using only 10..=40
+        let img = gray(64, 4, |x, _| 10 + (x / 2) as u8);
+        let out = Normalization::Equalize.apply(&img).unwrap().into_rgb8();
+        let values: Vec<u8> = out.pixels().map(|p| p.0[0]).collect();
+        assert_eq!(*values.iter().min().unwrap(), 0);
+        assert_eq!(*values.iter().max().unwrap(), 255);
+        // Order is kept: the row is a ramp and still is
+        assert!(values[..64].windows(2).all(|w| w[0] <= w[1]));
+
+        // Flat frames stay flat
+        let flat = Normalization::Equalize
+            .apply(&gray(8, 8, |_, _| 90))
+            .unwrap();
+        assert!(flat.into_rgb8().pixels().all(|p| p.0 == [90; 3]));
+    }
+
+    #[test]
+    fn test_clahe_local_contrast() {
+        // Dim textured left half next to a bright flat right half; a global
+        // mapping would spend most of the range on the jump between them
+        let img = gray(128, 128, |x, y| match x < 64 {
+            true => 20 + ((x + y) % 8) as u8 * 4,
+            false => 200,
+        });
+        let out = Normalization::Clahe.apply(&img).unwrap().into_rgb8();
+        let range = |img: &RgbImage| {
+            let values = (0..128).flat_map(|y| (0..32).map(move |x| (x, y)));
+            let (min, max) = values.fold((255, 0), |(min, max), (x, y)| {
+                let v = img.get_pixel(x, y).0[0];
+                (v.min(min), v.max(max))
+            });
+            max - min
+        };
+        assert!(range(&out) > range(&img.to_rgb8()));
+
+        // Mapping stays monotonic within a tile
+        assert!(out.get_pixel(7, 0).0[0] > out.get_pixel(0, 0).0[0]);
+    }
+}
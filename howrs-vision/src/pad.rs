@@ -0,0 +1,154 @@
+//! Presentation attack detection (PAD): a small classifier telling a live
+//! face from a printed photo or a screen by its texture, such as the
+//! MiniFASNet models of Silent-Face-Anti-Spoofing.
+//!
+//! The model sees the face box enlarged by [`PadModel::scale`], since the
+//! surroundings (a phone's bezel, the edge of a print) give attacks away,
+//! resized to its input as BGR in `[0, 255]`. It outputs one logit per
+//! class, [`LIVE_CLASS`] being the live face and the others kinds of attack.
+
+use crate::error::{Context, Result, ResultExt};
+use crate::face::{self, Detection};
+use crate::model;
+use image::imageops::FilterType;
+use image::{DynamicImage, GenericImageView};
+use ort::{session::Session, value::TensorRef};
+use std::path::Path;
+
+/// Index of the live class in the model output
+pub const LIVE_CLASS: usize = 1;
+/// Enlargement of the face box MiniFASNetV2 was trained with
+pub const DEFAULT_SCALE: f32 = 2.7;
+/// Live probability below which a face is taken for an attack
+pub const DEFAULT_THRESHOLD: f32 = 0.5;
+/// Input size used when the model doesn't fix one
+const DEFAULT_INPUT: (u32, u32) = (80, 80);
+
+pub struct PadModel {
+    session: Session,
+    /// Enlargement of the face box before cropping
+    pub scale: f32,
+    /// Least live probability accepted
+    pub threshold: f32,
+}
+
+impl PadModel {
+    pub fn new(session: Session) -> Self {
+        Self {
+            session,
+            scale: DEFAULT_SCALE,
+            threshold: DEFAULT_THRESHOLD,
+        }
+    }
+
+    /// Load the model from an ONNX file
+    pub fn load(path: &Path) -> Result<Self> {
+        Ok(Self::new(
+            model::file_session(path).context("load anti-spoofing model")?,
+        ))
+    }
+
+    pub fn with_scale(mut self, scale: f32) -> Self {
+        self.scale = scale;
+        self
+    }
+
+    pub fn with_threshold(mut self, threshold: f32) -> Self {
+        self.threshold = threshold;
+        self
+    }
+
+    /// Probability that the face at `detection` in `img` is a live one
+    #[tracing::instrument(name = "pad", level = "debug", skip_all)]
+    pub fn live_probability(&mut self, img: &DynamicImage, detection: &Detection) -> Result<f32> {
+        let (width, height) = face::encoder_input_size(&self.session).unwrap_or(DEFAULT_INPUT);
+        let (x, y, w, h) = crop_box(&detection.bbox, self.scale, img.dimensions());
+        let crop = img
+            .crop_imm(x, y, w, h)
+            .resize_exact(width, height, FilterType::Triangle)
+            .to_rgb8();
+
+        let plane = (width * height) as usize;
+        let mut input = vec![0.0f32; 3 * plane];
+        for (i, px) in crop.pixels().enumerate() {
+            input[i] = px[2] as f32;
+            input[plane + i] = px[1] as f32;
+            input[2 * plane + i] = px[0] as f32;
+        }
+        let shape = [1usize, 3, height as usize, width as usize];
+        let tensor = TensorRef::from_array_view((shape, &*input))?;
+        let outputs = self.session.run(ort::inputs![tensor])?;
+        let (_, logits) = outputs[0].try_extract_tensor::<f32>()?;
+        softmax(logits)
+            .get(LIVE_CLASS)
+            .copied()
+            .inference("anti-spoofing model has no live class")
+    }
+
+    /// Whether the face passes [`Self::threshold`]; `Err` carries its live
+    /// probability otherwise
+    pub fn check(&mut self, img: &DynamicImage, detection: &Detection) -> Result<Result<f32, f32>> {
+        let live = self.live_probability(img, detection)?;
+        Ok(if live >= self.threshold {
+            Ok(live)
+        } else {
+            Err(live)
+        })
+    }
+}
+
+/// `(x, y, w, h)` of `bbox` enlarged by `scale` around its center, shrunk to
+/// fit and shifted inside a `frame` sized image
+fn crop_box(bbox: &[f32; 4], scale: f32, frame: (u32, u32)) -> (u32, u32, u32, u32) {
+    let (frame_w, frame_h) = (frame.0 as f32, frame.1 as f32);
+    let [x, y, w, h] = *bbox;
+    let (w, h) = (w.max(1.0), h.max(1.0));
+    let scale = scale.min((frame_w - 1.0) / w).min((frame_h - 1.0) / h);
+    let (new_w, new_h) = (w * scale, h * scale);
+    let (cx, cy) = (x + w / 2.0, y + h / 2.0);
+    let left = (cx - new_w / 2.0).clamp(0.0, frame_w - new_w);
+    let top = (cy - new_h / 2.0).clamp(0.0, frame_h - new_h);
+    (
+        left as u32,
+        top as u32,
+        (new_w as u32).max(1),
+        (new_h as u32).max(1),
+    )
+}
+
+fn softmax(logits: &[f32]) -> Vec<f32> {
+    let max = logits.iter().copied().fold(f32::NEG_INFINITY, f32::max);
+    let exp: Vec<f32> = logits.iter().map(|l| (l - max).exp()).collect();
+    let sum: f32 = exp.iter().sum();
+    exp.into_iter().map(|e| e / sum).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_crop_box() {
+        // Room all around: enlarged around the center
+        assert_eq!(
+            crop_box(&[280.0, 200.0, 80.0, 80.0], 2.0, (640, 480)),
+            (240, 160, 160, 160)
+        );
+        // Near the edge: shifted inside rather than cut off
+        assert_eq!(
+            crop_box(&[0.0, 0.0, 80.0, 80.0], 2.0, (640, 480)),
+            (0, 0, 160, 160)
+        );
+        // Too big for the frame: the scale shrinks to fit
+        let (x, y, w, h) = crop_box(&[200.0, 100.0, 200.0, 200.0], 2.7, (640, 480));
+        assert!(x + w <= 640 && y + h <= 480);
+        assert!(h >= 470);
+    }
+
+    #[test]
+    fn test_softmax() {
+        let p = softmax(&[1.0, 3.0, 1.0]);
+        assert!((p.iter().sum::<f32>() - 1.0).abs() < 1e-6);
+        assert!(p[LIVE_CLASS] > 0.7);
+    }
+}
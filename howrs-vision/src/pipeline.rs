@@ -1,66 +1,543 @@
-use anyhow::{Context, Result};
-use image::DynamicImage;
+use image::imageops::FilterType;
+use image::{DynamicImage, GenericImageView};
 use ort::session::Session;
+use std::fmt;
+use std::path::PathBuf;
+use std::time::{Duration, Instant};
 
-use crate::face::{self, Detection, Embedding};
+use crate::cancel::CancelToken;
+use crate::detector::{self, Backend, Detector};
+use crate::error::{Context, Error, Result};
+use crate::face::{self, AlignTemplate, Detection, Embedding, SizeFilter};
+use crate::model::{self, Provider, Registry};
+use crate::normalize::Normalization;
+use crate::pad::PadModel;
+use crate::quality::QualityGate;
+
+/// Detector confidence used unless [`Pipeline::with_thresholds`] says otherwise
+pub const DEFAULT_SCORE_THRESHOLD: f32 = 0.6;
+
+/// NMS overlap used unless [`Pipeline::with_thresholds`] says otherwise
+pub const DEFAULT_NMS_THRESHOLD: f32 = 0.3;
+
+/// Time spent in each stage for one frame
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct PipelineTimings {
+    /// Reading the frame from the camera; filled in by the caller, since the
+    /// pipeline only sees the finished image
+    pub capture: Duration,
+    pub detect: Duration,
+    pub align: Duration,
+    pub encode: Duration,
+}
+
+impl PipelineTimings {
+    pub fn total(&self) -> Duration {
+        self.capture + self.detect + self.align + self.encode
+    }
+}
+
+impl fmt::Display for PipelineTimings {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let ms = |d: Duration| d.as_secs_f64() * 1000.0;
+        write!(
+            f,
+            "capture {:.1} ms, detect {:.1} ms, align {:.1} ms, encode {:.1} ms (total {:.1} ms)",
+            ms(self.capture),
+            ms(self.detect),
+            ms(self.align),
+            ms(self.encode),
+            ms(self.total())
+        )
+    }
+}
 
 /// Full pipeline: detect faces → align → encode
 pub struct Pipeline {
-    pub detector: Session,
+    pub detector: Box<dyn Detector>,
     pub encoder: Session,
+    /// Crop expected by the encoder
+    pub alignment: AlignTemplate,
+    /// Average each embedding with that of the mirrored face
+    pub flip_augment: bool,
+    /// Faces outside these bounds are ignored
+    pub size_filter: SizeFilter,
+    /// Only look for faces in this centered fraction of the frame
+    pub roi: Option<f32>,
+    /// Frames are shrunk so their longer side is at most this before
+    /// detection; faces are still aligned from the full frame
+    pub detect_size: Option<u32>,
+    /// Minimum detector confidence
+    pub score_threshold: f32,
+    /// Overlap above which the weaker of two detections is dropped
+    pub nms_threshold: f32,
+    /// Applied to grayscale frames before detection and to the aligned face
+    /// before encoding
+    pub normalization: Normalization,
+    /// Faces below this quality are not encoded by [`Self::process_image`],
+    /// which fails with [`crate::quality::LowQuality`] instead
+    pub quality_gate: QualityGate,
+    /// Anti-spoofing model a face must pass before
+    /// [`Pipeline::run_stream`] reports a match
+    pub pad: Option<PadModel>,
+    /// Checked before each stage; once cancelled, processing fails with
+    /// [`crate::cancel::Cancelled`]
+    pub cancel: CancelToken,
 }
 
 impl Pipeline {
+    /// Pipeline with the embedded models and default options, the same as
+    /// `Pipeline::builder().build()`
     pub fn new() -> Result<Self> {
-        Ok(Self {
-            detector: crate::model::detector_session()?,
-            encoder: crate::model::recog_session()?,
-        })
+        Self::builder().build()
+    }
+
+    /// Choose models, execution provider and options before loading
+    pub fn builder() -> PipelineBuilder {
+        PipelineBuilder::default()
+    }
+
+    /// Pipeline with another detector backend, see [`detector::load`]
+    pub fn from_detector(detector: Box<dyn Detector>) -> Result<Self> {
+        let alignment = Registry::default_encoder()
+            .alignment
+            .unwrap_or(AlignTemplate::ARCFACE_112);
+        Ok(Self::from_parts(
+            detector,
+            crate::model::recog_session()?,
+            alignment,
+        ))
+    }
+
+    /// Pipeline from an already loaded detector and recognition model, which
+    /// expects faces aligned to `alignment`
+    pub fn from_parts(
+        detector: Box<dyn Detector>,
+        encoder: Session,
+        alignment: AlignTemplate,
+    ) -> Self {
+        Self {
+            detector,
+            encoder,
+            alignment,
+            flip_augment: false,
+            size_filter: SizeFilter::default(),
+            roi: None,
+            detect_size: None,
+            score_threshold: DEFAULT_SCORE_THRESHOLD,
+            nms_threshold: DEFAULT_NMS_THRESHOLD,
+            normalization: Normalization::default(),
+            quality_gate: QualityGate::default(),
+            pad: None,
+            cancel: CancelToken::default(),
+        }
+    }
+
+    /// Use another recognition model, which expects faces aligned to `alignment`
+    pub fn with_encoder(mut self, encoder: Session, alignment: AlignTemplate) -> Self {
+        self.encoder = encoder;
+        self.alignment = alignment;
+        self
+    }
+
+    /// Stop processing frames once `cancel` is cancelled
+    pub fn with_cancel(mut self, cancel: CancelToken) -> Self {
+        self.cancel = cancel;
+        self
+    }
+
+    /// Restrict detection to a centered region, see [`Detector::detect_roi`]
+    pub fn with_roi(mut self, roi: Option<f32>) -> Self {
+        self.roi = roi;
+        self
+    }
+
+    /// Detector confidence and NMS overlap thresholds, used by every entry point
+    pub fn with_thresholds(mut self, score_threshold: f32, nms_threshold: f32) -> Self {
+        self.score_threshold = score_threshold;
+        self.nms_threshold = nms_threshold;
+        self
+    }
+
+    /// Shrink frames so their longer side is at most `detect_size` before
+    /// detection, see [`Self::detect_size`]
+    pub fn with_detect_size(mut self, detect_size: Option<u32>) -> Self {
+        self.detect_size = detect_size;
+        self
+    }
+
+    /// Ignore detected faces outside `size_filter`
+    pub fn with_size_filter(mut self, size_filter: SizeFilter) -> Self {
+        self.size_filter = size_filter;
+        self
+    }
+
+    /// Normalize the contrast of grayscale (IR) frames
+    pub fn with_normalization(mut self, normalization: Normalization) -> Self {
+        self.normalization = normalization;
+        self
     }
 
-    /// Process an image: detect best face and return embedding
+    /// Skip encoding faces that fail `quality_gate`
+    pub fn with_quality_gate(mut self, quality_gate: QualityGate) -> Self {
+        self.quality_gate = quality_gate;
+        self
+    }
+
+    /// Check matched faces with an anti-spoofing model
+    pub fn with_pad(mut self, pad: Option<PadModel>) -> Self {
+        self.pad = pad;
+        self
+    }
+
+    /// Enable horizontal-flip test-time augmentation, see [`face::encode_face_flip`]
+    pub fn with_flip_augment(mut self, flip_augment: bool) -> Self {
+        self.flip_augment = flip_augment;
+        self
+    }
+
+    /// Run the models once on a blank frame, so the first real frame doesn't
+    /// pay for lazy initialization in the runtime (memory arenas, kernel
+    /// selection, execution provider compilation). Returns the time it took.
+    pub fn warm_up(&mut self) -> Result<Duration> {
+        let start = Instant::now();
+        let (img, detection) = warm_up_input();
+        self.detect_best(&img)?;
+        self.encode_detection(&img, &detection)?;
+        if let Some(pad) = &mut self.pad {
+            pad.live_probability(&img, &detection)?;
+        }
+        Ok(start.elapsed())
+    }
+
+    /// Process an image: detect best face and return embedding, along with
+    /// how long each stage took
     pub fn process_image(
         &mut self,
         img: &DynamicImage,
-        score_threshold: f32,
-        nms_threshold: f32,
-    ) -> Result<(Detection, Embedding)> {
-        // Detect faces
-        let detections =
-            face::detect_faces(&mut self.detector, img, score_threshold, nms_threshold)
-                .context("detecting faces")?;
-
-        if detections.is_empty() {
-            anyhow::bail!("No face detected in image");
+    ) -> Result<(Detection, Embedding, PipelineTimings)> {
+        let mut timings = PipelineTimings::default();
+
+        self.cancel.check()?;
+        let start = Instant::now();
+        let best = self.detect_best(img);
+        timings.detect = start.elapsed();
+        let best = best?.ok_or(Error::NoFace)?;
+        self.quality_gate.check(img, &best)?;
+
+        self.cancel.check()?;
+        let embedding = self.encode_timed(img, &best, &mut timings)?;
+
+        Ok((best, embedding, timings))
+    }
+
+    /// Detect every face, align and encode each one, best detection first.
+    ///
+    /// For frames with several people, e.g. group photos or a kiosk. Faces
+    /// failing the quality gate are left out rather than failing the frame.
+    pub fn process_all(&mut self, img: &DynamicImage) -> Result<Vec<(Detection, Embedding)>> {
+        self.cancel.check()?;
+        let detections = self.detect_all(img)?;
+
+        let mut faces = Vec::with_capacity(detections.len());
+        for detection in detections {
+            if let Err(low) = self.quality_gate.check(img, &detection) {
+                tracing::debug!("skipping face: {}", low);
+                continue;
+            }
+            self.cancel.check()?;
+            let embedding = self.encode_detection(img, &detection)?;
+            faces.push((detection, embedding));
         }
+        Ok(faces)
+    }
 
-        // Pick the best detection (highest score)
-        let best = detections
-            .iter()
-            .max_by(|a, b| a.score.partial_cmp(&b.score).unwrap())
-            .unwrap();
+    /// Detect faces and return the highest scoring one, if any
+    pub fn detect_best(&mut self, img: &DynamicImage) -> Result<Option<Detection>> {
+        Ok(self.detect_all(img)?.into_iter().next())
+    }
 
-        // Align and crop the face
-        let face_img = face::align_face(img, best, 112).context("aligning face")?;
+    /// Detect faces passing the size filter, highest scoring first
+    pub fn detect_all(&mut self, img: &DynamicImage) -> Result<Vec<Detection>> {
+        let search = Search {
+            roi: self.roi,
+            detect_size: self.detect_size,
+            score_threshold: self.score_threshold,
+            nms_threshold: self.nms_threshold,
+            normalization: self.normalization,
+            size_filter: self.size_filter,
+        };
+        search.run(&mut *self.detector, img)
+    }
 
-        // Encode to embedding
-        let embedding = face::encode_face(&mut self.encoder, &face_img).context("encoding face")?;
+    /// [`Self::detect_best`] for several images, see [`Detector::detect_batch`]
+    pub fn detect_best_batch(&mut self, imgs: &[DynamicImage]) -> Result<Vec<Option<Detection>>> {
+        let mut scales = vec![(1.0, 1.0); imgs.len()];
+        let prepared: Vec<DynamicImage> = match (self.normalization, self.detect_size) {
+            (Normalization::None, None) => Vec::new(),
+            (n, _) => imgs
+                .iter()
+                .zip(&mut scales)
+                .map(|(img, scale)| {
+                    let (small, s) = downscale(img, self.detect_size);
+                    *scale = s;
+                    let img = small.unwrap_or_else(|| img.clone());
+                    n.apply(&img).unwrap_or(img)
+                })
+                .collect(),
+        };
+        let batch = if prepared.is_empty() { imgs } else { &prepared };
+        let detections = self
+            .detector
+            .detect_batch(batch, self.score_threshold, self.nms_threshold)
+            .context("detecting faces")?;
 
-        Ok((best.clone(), embedding))
+        Ok(detections
+            .into_iter()
+            .zip(imgs.iter().zip(scales))
+            .map(|(mut d, (img, scale))| {
+                rescale(&mut d, scale);
+                highest_scoring(self.size_filter.apply(d, img.dimensions()))
+            })
+            .collect())
     }
 
-    /// Process and return only embedding (convenience method)
-    pub fn extract_embedding(
+    /// Align and encode an already detected face
+    pub fn encode_detection(
         &mut self,
         img: &DynamicImage,
-        _score_threshold: f32,
-        nms_threshold: f32,
+        detection: &Detection,
     ) -> Result<Embedding> {
-        let (_detection, embedding) = self.process_image(img, 0.6, nms_threshold)?;
+        self.encode_timed(img, detection, &mut PipelineTimings::default())
+    }
+
+    pub(crate) fn encode_timed(
+        &mut self,
+        img: &DynamicImage,
+        detection: &Detection,
+        timings: &mut PipelineTimings,
+    ) -> Result<Embedding> {
+        // Align and crop the face
+        let start = Instant::now();
+        let mut face_img =
+            face::align_face_to(img, detection, &self.alignment).context("aligning face")?;
+        if let Some(normalized) = self.normalization.apply(&face_img) {
+            crate::pool::frames().recycle_image(std::mem::replace(&mut face_img, normalized));
+        }
+        timings.align = start.elapsed();
+
+        // Encode to embedding
+        let start = Instant::now();
+        let embedding = if self.flip_augment {
+            face::encode_face_flip(&mut self.encoder, &face_img)
+        } else {
+            face::encode_face(&mut self.encoder, &face_img)
+        }
+        .context("encoding face");
+        timings.encode = start.elapsed();
+        crate::pool::frames().recycle_image(face_img);
+        embedding
+    }
+
+    /// Process and return only embedding (convenience method)
+    pub fn extract_embedding(&mut self, img: &DynamicImage) -> Result<Embedding> {
+        let (_detection, embedding, _timings) = self.process_image(img)?;
         Ok(embedding)
     }
 }
 
+/// Detection settings shared by [`Pipeline`] and [`crate::detect()`]
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct Search {
+    pub roi: Option<f32>,
+    pub detect_size: Option<u32>,
+    pub score_threshold: f32,
+    pub nms_threshold: f32,
+    pub normalization: Normalization,
+    pub size_filter: SizeFilter,
+}
+
+impl Search {
+    /// Faces in `img` passing the size filter, highest scoring first
+    pub fn run(&self, detector: &mut dyn Detector, img: &DynamicImage) -> Result<Vec<Detection>> {
+        let dimensions = img.dimensions();
+        let (small, scale) = downscale(img, self.detect_size);
+        let img = small.as_ref().unwrap_or(img);
+        let normalized = self.normalization.apply(img);
+        let img = normalized.as_ref().unwrap_or(img);
+        let (score_threshold, nms_threshold) = (self.score_threshold, self.nms_threshold);
+        let detections = match self.roi {
+            Some(fraction) => detector.detect_roi(img, fraction, score_threshold, nms_threshold),
+            None => detector.detect(img, score_threshold, nms_threshold),
+        }
+        .context("detecting faces");
+        if let Some(normalized) = normalized {
+            crate::pool::frames().recycle_image(normalized);
+        }
+
+        let mut detections = detections?;
+        rescale(&mut detections, scale);
+        let mut detections = self.size_filter.apply(detections, dimensions);
+        detections.sort_by(|a, b| b.score.total_cmp(&a.score));
+        Ok(detections)
+    }
+}
+
+/// A blank VGA frame and a face box in its center: the detector still runs
+/// over every anchor, and the encoder gets a full-size crop
+pub fn warm_up_input() -> (DynamicImage, Detection) {
+    let detection = Detection {
+        bbox: [220.0, 140.0, 200.0, 200.0],
+        score: 1.0,
+        landmarks: [
+            280.0, 200.0, 360.0, 200.0, 320.0, 240.0, 290.0, 280.0, 350.0, 280.0,
+        ],
+    };
+    (DynamicImage::new_rgb8(640, 480), detection)
+}
+
+/// `img` shrunk to fit `detect_size`, if it doesn't already, and the factors
+/// mapping coordinates on it back to `img`. Rounding the shorter side can
+/// change the aspect ratio slightly, so there is one per axis.
+fn downscale(img: &DynamicImage, detect_size: Option<u32>) -> (Option<DynamicImage>, (f32, f32)) {
+    let (width, height) = img.dimensions();
+    match detect_size {
+        Some(size) if width.max(height) > size => {
+            let small = img.resize(size, size, FilterType::Triangle);
+            let scale = (
+                width as f32 / small.width() as f32,
+                height as f32 / small.height() as f32,
+            );
+            (Some(small), scale)
+        }
+        _ => (None, (1.0, 1.0)),
+    }
+}
+
+/// Scale detections found on a downscaled frame back to the full frame, so
+/// faces are aligned from its full resolution
+fn rescale(detections: &mut [Detection], scale: (f32, f32)) {
+    if scale == (1.0, 1.0) {
+        return;
+    }
+    for d in detections {
+        d.rescale(scale);
+    }
+}
+
+/// Models and options for a [`Pipeline`], see [`Pipeline::builder`]
+#[derive(Debug, Clone)]
+pub struct PipelineBuilder {
+    backend: Backend,
+    detector_model: Option<PathBuf>,
+    encoder_model: Option<PathBuf>,
+    alignment: Option<AlignTemplate>,
+    provider: Provider,
+    score_threshold: f32,
+    nms_threshold: f32,
+    detect_size: Option<u32>,
+    flip_augment: bool,
+}
+
+impl Default for PipelineBuilder {
+    fn default() -> Self {
+        Self {
+            backend: Backend::default(),
+            detector_model: None,
+            encoder_model: None,
+            alignment: None,
+            provider: Provider::default(),
+            score_threshold: DEFAULT_SCORE_THRESHOLD,
+            nms_threshold: DEFAULT_NMS_THRESHOLD,
+            detect_size: None,
+            flip_augment: false,
+        }
+    }
+}
+
+impl PipelineBuilder {
+    /// Detector implementation; SCRFD also needs [`Self::detector_model`]
+    pub fn backend(mut self, backend: Backend) -> Self {
+        self.backend = backend;
+        self
+    }
+
+    /// ONNX file for the detector instead of the embedded YuNet
+    pub fn detector_model(mut self, path: impl Into<Option<PathBuf>>) -> Self {
+        self.detector_model = path.into();
+        self
+    }
+
+    /// ONNX file for the recognition model instead of the embedded SFace
+    pub fn encoder_model(mut self, path: impl Into<Option<PathBuf>>) -> Self {
+        self.encoder_model = path.into();
+        self
+    }
+
+    /// Crop the recognition model expects; defaults to the embedded model's,
+    /// or the 112x112 ArcFace crop for [`Self::encoder_model`]
+    pub fn alignment(mut self, alignment: impl Into<Option<AlignTemplate>>) -> Self {
+        self.alignment = alignment.into();
+        self
+    }
+
+    /// Where both models run
+    pub fn provider(mut self, provider: Provider) -> Self {
+        self.provider = provider;
+        self
+    }
+
+    /// See [`Pipeline::with_thresholds`]
+    pub fn thresholds(mut self, score_threshold: f32, nms_threshold: f32) -> Self {
+        self.score_threshold = score_threshold;
+        self.nms_threshold = nms_threshold;
+        self
+    }
+
+    /// See [`Pipeline::with_detect_size`]
+    pub fn detect_size(mut self, detect_size: impl Into<Option<u32>>) -> Self {
+        self.detect_size = detect_size.into();
+        self
+    }
+
+    /// See [`Pipeline::with_flip_augment`]
+    pub fn flip_augment(mut self, flip_augment: bool) -> Self {
+        self.flip_augment = flip_augment;
+        self
+    }
+
+    /// Load the models
+    pub fn build(self) -> Result<Pipeline> {
+        let detector =
+            detector::load_on(self.backend, self.detector_model.as_deref(), self.provider)?;
+        let (encoder, alignment) = match &self.encoder_model {
+            Some(path) => (
+                model::file_session_on(path, self.provider).context("load recognition model")?,
+                AlignTemplate::ARCFACE_112,
+            ),
+            None => (
+                model::recog_session_on(self.provider)?,
+                Registry::default_encoder()
+                    .alignment
+                    .unwrap_or(AlignTemplate::ARCFACE_112),
+            ),
+        };
+
+        Ok(
+            Pipeline::from_parts(detector, encoder, self.alignment.unwrap_or(alignment))
+                .with_thresholds(self.score_threshold, self.nms_threshold)
+                .with_detect_size(self.detect_size)
+                .with_flip_augment(self.flip_augment),
+        )
+    }
+}
+
+fn highest_scoring(detections: Vec<Detection>) -> Option<Detection> {
+    detections
+        .into_iter()
+        .max_by(|a, b| a.score.total_cmp(&b.score))
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -70,4 +547,59 @@ mod tests {
         let result = Pipeline::new();
         assert!(result.is_ok());
     }
+
+    #[test]
+    fn test_timings() {
+        let timings = PipelineTimings {
+            capture: Duration::from_millis(30),
+            detect: Duration::from_millis(12),
+            align: Duration::from_micros(500),
+            encode: Duration::from_millis(8),
+        };
+        assert_eq!(timings.total(), Duration::from_micros(50_500));
+        assert_eq!(
+            timings.to_string(),
+            "capture 30.0 ms, detect 12.0 ms, align 0.5 ms, encode 8.0 ms (total 50.5 ms)"
+        );
+    }
+
+    #[test]
+    fn test_builder_options() {
+        let builder = Pipeline::builder();
+        assert_eq!(builder.score_threshold, DEFAULT_SCORE_THRESHOLD);
+        assert_eq!(builder.provider, Provider::Auto);
+
+        let builder = builder
+            .backend(Backend::Scrfd)
+            .thresholds(0.4, 0.5)
+            .detect_size(320)
+            .alignment(AlignTemplate::ARCFACE_112);
+        assert_eq!(builder.backend, Backend::Scrfd);
+        assert_eq!(builder.nms_threshold, 0.5);
+        assert_eq!(builder.detect_size, Some(320));
+        assert_eq!(builder.alignment, Some(AlignTemplate::ARCFACE_112));
+        // SCRFD isn't embedded, so it can't be built without a model file
+        assert!(builder.build().is_err());
+    }
+
+    #[test]
+    fn test_rescale() {
+        let mut detections = vec![Detection {
+            bbox: [10.0, 20.0, 30.0, 40.0],
+            score: 0.9,
+            landmarks: [1.0; 10],
+        }];
+        rescale(&mut detections, (2.0, 3.0));
+        assert_eq!(detections[0].bbox, [20.0, 60.0, 60.0, 120.0]);
+        assert_eq!(detections[0].landmarks[..4], [2.0, 3.0, 2.0, 3.0]);
+        assert_eq!(detections[0].score, 0.9);
+
+        // 641x481 to a 320 long side: 320x240, not quite half along y
+        let (small, (sx, sy)) = downscale(&DynamicImage::new_rgb8(641, 481), Some(320));
+        assert_eq!(small.unwrap().dimensions(), (320, 240));
+        assert_eq!((sx, sy), (641.0 / 320.0, 481.0 / 240.0));
+        assert!(downscale(&DynamicImage::new_rgb8(320, 240), Some(320))
+            .0
+            .is_none());
+    }
 }
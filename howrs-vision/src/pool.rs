@@ -0,0 +1,151 @@
+//! Recycled buffers for the per-frame hot path
+//!
+//! Camera frames, aligned faces and model input tensors are all large and
+//! all the same size from one frame to the next, so instead of allocating
+//! them per frame they are taken from a process-wide pool and handed back
+//! once the frame has been processed. In the steady state the auth loop
+//! then performs no large allocations.
+
+use image::DynamicImage;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+
+/// Buffers kept around per pool; enough for a frame in flight plus a few
+/// intermediate images without holding on to memory forever
+const MAX_FREE: usize = 8;
+
+static FRAMES: FramePool = BufferPool::new(MAX_FREE);
+static TENSORS: TensorPool = BufferPool::new(MAX_FREE);
+
+/// Pool of RGB byte buffers shared by the camera, preprocessing and alignment
+pub type FramePool = BufferPool<u8>;
+/// Pool of `f32` model input buffers
+pub type TensorPool = BufferPool<f32>;
+
+/// Process-wide pool of RGB frame buffers
+pub fn frames() -> &'static FramePool {
+    &FRAMES
+}
+
+/// Process-wide pool of model input buffers
+pub fn tensors() -> &'static TensorPool {
+    &TENSORS
+}
+
+/// Counters describing how well a pool is recycling
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct PoolStats {
+    /// Buffers that had to be freshly allocated
+    pub allocations: u64,
+    /// Requests served from a recycled buffer
+    pub reuses: u64,
+    /// Buffers currently waiting in the pool
+    pub free: usize,
+}
+
+pub struct BufferPool<T> {
+    free: Mutex<Vec<Vec<T>>>,
+    max_free: usize,
+    allocations: AtomicU64,
+    reuses: AtomicU64,
+}
+
+impl<T: Copy + Default> BufferPool<T> {
+    pub const fn new(max_free: usize) -> Self {
+        Self {
+            free: Mutex::new(Vec::new()),
+            max_free,
+            allocations: AtomicU64::new(0),
+            reuses: AtomicU64::new(0),
+        }
+    }
+
+    /// Get a buffer of exactly `len` default-initialized elements
+    pub fn take(&self, len: usize) -> Vec<T> {
+        let recycled = {
+            let mut free = self.free.lock().unwrap_or_else(|e| e.into_inner());
+            free.iter()
+                .position(|b| b.capacity() >= len)
+                .map(|i| free.swap_remove(i))
+        };
+
+        match recycled {
+            Some(mut buf) => {
+                self.reuses.fetch_add(1, Ordering::Relaxed);
+                buf.clear();
+                buf.resize(len, T::default());
+                buf
+            }
+            None => {
+                self.allocations.fetch_add(1, Ordering::Relaxed);
+                vec![T::default(); len]
+            }
+        }
+    }
+
+    /// Hand a buffer back for reuse
+    pub fn recycle(&self, buf: Vec<T>) {
+        if buf.capacity() == 0 {
+            return;
+        }
+        let mut free = self.free.lock().unwrap_or_else(|e| e.into_inner());
+        if free.len() < self.max_free {
+            free.push(buf);
+        }
+    }
+
+    pub fn stats(&self) -> PoolStats {
+        PoolStats {
+            allocations: self.allocations.load(Ordering::Relaxed),
+            reuses: self.reuses.load(Ordering::Relaxed),
+            free: self.free.lock().map(|f| f.len()).unwrap_or(0),
+        }
+    }
+}
+
+impl FramePool {
+    /// Recycle the buffer behind an RGB image; other formats are just dropped
+    pub fn recycle_image(&self, img: DynamicImage) {
+        if let DynamicImage::ImageRgb8(buf) = img {
+            self.recycle(buf.into_raw());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pool_reuses_buffers() {
+        let pool = FramePool::new(2);
+        let buf = pool.take(1024);
+        assert_eq!(buf.len(), 1024);
+        let ptr = buf.as_ptr();
+        pool.recycle(buf);
+
+        // Same or smaller request is served from the recycled allocation
+        let buf = pool.take(512);
+        assert_eq!(buf.len(), 512);
+        assert!(buf.iter().all(|&b| b == 0));
+        assert_eq!(buf.as_ptr(), ptr);
+
+        let stats = pool.stats();
+        assert_eq!(stats.allocations, 1);
+        assert_eq!(stats.reuses, 1);
+        assert_eq!(stats.free, 0);
+    }
+
+    #[test]
+    fn test_pool_bounded() {
+        let pool = TensorPool::new(1);
+        pool.recycle(vec![0.0; 4]);
+        pool.recycle(vec![0.0; 4]);
+        assert_eq!(pool.stats().free, 1);
+
+        // Too small to serve the request: allocate fresh
+        let buf = pool.take(16);
+        assert_eq!(buf.len(), 16);
+        assert_eq!(pool.stats().allocations, 1);
+    }
+}
@@ -0,0 +1,433 @@
+//! Cheap per-frame quality signals used to guide the user
+//!
+//! These are heuristics computed from the frame and detection landmarks, not
+//! model outputs, so they are fast enough to run on every captured frame.
+
+use crate::face::{Detection, FaceSize};
+use image::{DynamicImage, GenericImageView};
+use std::fmt;
+
+/// Mean luma below which a frame is considered too dark
+pub const MIN_BRIGHTNESS: f32 = 40.0;
+/// Mean luma above which a frame is considered washed out
+pub const MAX_BRIGHTNESS: f32 = 220.0;
+/// Minimum face width as a fraction of frame width
+pub const MIN_FACE_FRACTION: f32 = 0.12;
+/// Maximum absolute yaw/pitch offset (in eye distances) for a frontal face
+pub const MAX_FRONTAL_OFFSET: f32 = 0.25;
+
+/// What the user should do to get a usable frame
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Feedback {
+    NoFace,
+    TooDark,
+    TooBright,
+    TooSmall,
+    LookAtCamera,
+    /// Head orientation doesn't match the requested guided pose
+    WrongPose,
+    Good,
+}
+
+impl Feedback {
+    pub fn message(&self) -> &'static str {
+        match self {
+            Feedback::NoFace => "no face found",
+            Feedback::TooDark => "too dark",
+            Feedback::TooBright => "too bright",
+            Feedback::TooSmall => "face too small, move closer",
+            Feedback::LookAtCamera => "look at the camera",
+            Feedback::WrongPose => "turn your head as asked",
+            Feedback::Good => "face found",
+        }
+    }
+}
+
+/// Approximate head orientation derived from the five landmarks.
+///
+/// Both values are offsets of the nose tip in units of eye distance:
+/// `yaw` is horizontal (positive = nose towards image right) and `pitch` is
+/// vertical relative to the midpoint between the eye line and mouth line
+/// (positive = nose towards the mouth, i.e. looking down).
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct HeadPose {
+    pub yaw: f32,
+    pub pitch: f32,
+}
+
+impl HeadPose {
+    pub fn from_landmarks(landmarks: &[f32; 10]) -> Self {
+        let eye_mid = (
+            (landmarks[0] + landmarks[2]) / 2.0,
+            (landmarks[1] + landmarks[3]) / 2.0,
+        );
+        let nose = (landmarks[4], landmarks[5]);
+        let mouth_mid = (
+            (landmarks[6] + landmarks[8]) / 2.0,
+            (landmarks[7] + landmarks[9]) / 2.0,
+        );
+        let eye_dist = ((landmarks[2] - landmarks[0]).powi(2)
+            + (landmarks[3] - landmarks[1]).powi(2))
+        .sqrt()
+        .max(f32::EPSILON);
+
+        Self {
+            yaw: (nose.0 - eye_mid.0) / eye_dist,
+            pitch: (nose.1 - (eye_mid.1 + mouth_mid.1) / 2.0) / eye_dist,
+        }
+    }
+
+    pub fn is_frontal(&self) -> bool {
+        self.yaw.abs() <= MAX_FRONTAL_OFFSET && self.pitch.abs() <= MAX_FRONTAL_OFFSET
+    }
+}
+
+/// Minimum yaw/pitch offset for a deliberately turned head
+pub const MIN_TURN_OFFSET: f32 = 0.12;
+/// Maximum yaw/pitch offset still considered a slight turn
+pub const MAX_TURN_OFFSET: f32 = 0.6;
+
+/// Head poses requested during guided enrollment
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Pose {
+    Straight,
+    Left,
+    Right,
+    Up,
+}
+
+impl Pose {
+    pub const GUIDED: [Pose; 4] = [Pose::Straight, Pose::Left, Pose::Right, Pose::Up];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Pose::Straight => "straight",
+            Pose::Left => "left",
+            Pose::Right => "right",
+            Pose::Up => "up",
+        }
+    }
+
+    /// Whether `pose` satisfies this target.
+    ///
+    /// Left/right are from the user's point of view on an unmirrored camera
+    /// image, so turning left moves the nose towards the image's right edge.
+    pub fn matches(&self, pose: &HeadPose) -> bool {
+        let turned = |v: f32| (MIN_TURN_OFFSET..=MAX_TURN_OFFSET).contains(&v);
+        match self {
+            Pose::Straight => pose.is_frontal(),
+            Pose::Left => turned(pose.yaw) && pose.pitch.abs() <= MAX_FRONTAL_OFFSET,
+            Pose::Right => turned(-pose.yaw) && pose.pitch.abs() <= MAX_FRONTAL_OFFSET,
+            Pose::Up => turned(-pose.pitch) && pose.yaw.abs() <= MAX_FRONTAL_OFFSET,
+        }
+    }
+
+    /// Whether the head moved towards this pose by at least
+    /// [`MIN_TURN_OFFSET`] between `from` and `to`, wherever it started,
+    /// e.g. to answer a liveness challenge
+    pub fn turned(&self, from: &HeadPose, to: &HeadPose) -> bool {
+        let (yaw, pitch) = (to.yaw - from.yaw, to.pitch - from.pitch);
+        match self {
+            Pose::Straight => yaw.abs() < MIN_TURN_OFFSET && pitch.abs() < MIN_TURN_OFFSET,
+            Pose::Left => yaw >= MIN_TURN_OFFSET,
+            Pose::Right => -yaw >= MIN_TURN_OFFSET,
+            Pose::Up => -pitch >= MIN_TURN_OFFSET,
+        }
+    }
+}
+
+/// Quality measurements for one frame
+#[derive(Debug, Clone, Copy)]
+pub struct FrameQuality {
+    /// Mean luma (0-255) of the face region, or of the whole frame without a face
+    pub brightness: f32,
+    /// Face width divided by frame width
+    pub face_fraction: Option<f32>,
+    pub pose: Option<HeadPose>,
+}
+
+impl FrameQuality {
+    pub fn measure(img: &DynamicImage, detection: Option<&Detection>) -> Self {
+        let (width, height) = img.dimensions();
+        let region = detection
+            .map(|d| clamp_bbox(&d.bbox, width, height))
+            .unwrap_or((0, 0, width, height));
+
+        Self {
+            brightness: mean_luma(img, region),
+            face_fraction: detection.map(|d| d.bbox[2] / width.max(1) as f32),
+            pose: detection.map(|d| HeadPose::from_landmarks(&d.landmarks)),
+        }
+    }
+
+    pub fn feedback(&self) -> Feedback {
+        self.feedback_for(Pose::Straight)
+    }
+
+    /// Like [`FrameQuality::feedback`], but checks the head against `target`
+    pub fn feedback_for(&self, target: Pose) -> Feedback {
+        if self.brightness < MIN_BRIGHTNESS {
+            return Feedback::TooDark;
+        }
+        if self.brightness > MAX_BRIGHTNESS {
+            return Feedback::TooBright;
+        }
+        let (Some(fraction), Some(pose)) = (self.face_fraction, self.pose) else {
+            return Feedback::NoFace;
+        };
+        if fraction < MIN_FACE_FRACTION {
+            return Feedback::TooSmall;
+        }
+        if !target.matches(&pose) {
+            return match target {
+                Pose::Straight => Feedback::LookAtCamera,
+                _ => Feedback::WrongPose,
+            };
+        }
+        Feedback::Good
+    }
+}
+
+/// Minimum quality for a face to be worth encoding during authentication.
+///
+/// A blurry or barely detected face takes as long to encode as a good one and
+/// only produces a low score, so such frames are skipped. Zero or `None`
+/// disables a check.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct QualityGate {
+    /// Detector confidence
+    pub min_score: f32,
+    /// See [`sharpness`]
+    pub min_sharpness: f32,
+    pub min_face_size: Option<FaceSize>,
+}
+
+/// Why [`QualityGate::check`] rejected a frame
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum LowQuality {
+    Score(f32),
+    Sharpness(f32),
+    /// Longer side of the face box in pixels
+    FaceSize(f32),
+}
+
+impl fmt::Display for LowQuality {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LowQuality::Score(score) => write!(f, "low detection score ({:.2})", score),
+            LowQuality::Sharpness(sharpness) => {
+                write!(f, "face too blurry (sharpness {:.1})", sharpness)
+            }
+            LowQuality::FaceSize(px) => write!(f, "face too small ({:.0} px)", px),
+        }
+    }
+}
+
+impl std::error::Error for LowQuality {}
+
+impl QualityGate {
+    pub fn check(&self, img: &DynamicImage, detection: &Detection) -> Result<(), LowQuality> {
+        if detection.score < self.min_score {
+            return Err(LowQuality::Score(detection.score));
+        }
+        let size = detection.bbox[2].max(detection.bbox[3]);
+        if self
+            .min_face_size
+            .is_some_and(|min| size < min.pixels(img.dimensions()))
+        {
+            return Err(LowQuality::FaceSize(size));
+        }
+        // Checked last, it is the only one that reads the pixels
+        if self.min_sharpness > 0.0 {
+            let sharpness = sharpness(img, detection);
+            if sharpness < self.min_sharpness {
+                return Err(LowQuality::Sharpness(sharpness));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Variance of the Laplacian of the face region: high for crisp edges, low
+/// for motion blur or an out of focus face. Depends on the camera, so useful
+/// thresholds come from looking at `howrs test` output.
+pub fn sharpness(img: &DynamicImage, detection: &Detection) -> f32 {
+    let (width, height) = img.dimensions();
+    let (x0, y0, x1, y1) = clamp_bbox(&detection.bbox, width, height);
+    if x1 - x0 < 3 || y1 - y0 < 3 {
+        return 0.0;
+    }
+    let face = img.crop_imm(x0, y0, x1 - x0, y1 - y0).to_luma8();
+    let (w, h) = face.dimensions();
+    let at = |x: u32, y: u32| face.get_pixel(x, y)[0] as f32;
+
+    let mut sum = 0.0;
+    let mut sum_sq = 0.0;
+    for y in 1..h - 1 {
+        for x in 1..w - 1 {
+            let lap = at(x - 1, y) + at(x + 1, y) + at(x, y - 1) + at(x, y + 1) - 4.0 * at(x, y);
+            sum += lap;
+            sum_sq += lap * lap;
+        }
+    }
+    let n = ((w - 2) * (h - 2)) as f32;
+    let mean = sum / n;
+    sum_sq / n - mean * mean
+}
+
+fn clamp_bbox(bbox: &[f32; 4], width: u32, height: u32) -> (u32, u32, u32, u32) {
+    let x0 = (bbox[0].max(0.0) as u32).min(width);
+    let y0 = (bbox[1].max(0.0) as u32).min(height);
+    let x1 = ((bbox[0] + bbox[2]).max(0.0) as u32).min(width);
+    let y1 = ((bbox[1] + bbox[3]).max(0.0) as u32).min(height);
+    (x0, y0, x1, y1)
+}
+
+fn mean_luma(img: &DynamicImage, (x0, y0, x1, y1): (u32, u32, u32, u32)) -> f32 {
+    if x1 <= x0 || y1 <= y0 {
+        return 0.0;
+    }
+    let luma = img.to_luma8();
+    let mut sum = 0u64;
+    for y in y0..y1 {
+        for x in x0..x1 {
+            sum += luma.get_pixel(x, y)[0] as u64;
+        }
+    }
+    sum as f32 / ((x1 - x0) as u64 * (y1 - y0) as u64) as f32
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn frontal_detection() -> Detection {
+        Detection {
+            bbox: [20.0, 20.0, 60.0, 60.0],
+            score: 0.9,
+            landmarks: [
+                35.0, 40.0, // eye
+                65.0, 40.0, // eye
+                50.0, 52.0, // nose
+                38.0, 64.0, // mouth
+                62.0, 64.0, // mouth
+            ],
+        }
+    }
+
+    #[test]
+    fn test_head_pose_frontal() {
+        let pose = HeadPose::from_landmarks(&frontal_detection().landmarks);
+        assert!(pose.yaw.abs() < 1e-6);
+        assert!(pose.is_frontal());
+    }
+
+    #[test]
+    fn test_head_pose_turned() {
+        let mut det = frontal_detection();
+        det.landmarks[4] = 62.0;
+        let pose = HeadPose::from_landmarks(&det.landmarks);
+        assert!(pose.yaw > MAX_FRONTAL_OFFSET);
+        assert!(!pose.is_frontal());
+    }
+
+    #[test]
+    fn test_guided_poses() {
+        let mut det = frontal_detection();
+        let frontal = HeadPose::from_landmarks(&det.landmarks);
+        assert!(Pose::Straight.matches(&frontal));
+        assert!(!Pose::Left.matches(&frontal));
+
+        // Nose towards the image's right edge: user turned to their left
+        det.landmarks[4] = 56.0;
+        let left = HeadPose::from_landmarks(&det.landmarks);
+        assert!(Pose::Left.matches(&left));
+        assert!(!Pose::Right.matches(&left));
+
+        det.landmarks[4] = 50.0;
+        det.landmarks[5] = 46.0;
+        let up = HeadPose::from_landmarks(&det.landmarks);
+        assert!(Pose::Up.matches(&up));
+    }
+
+    #[test]
+    fn test_turned() {
+        let mut det = frontal_detection();
+        let start = HeadPose::from_landmarks(&det.landmarks);
+        det.landmarks[4] = 56.0;
+        let left = HeadPose::from_landmarks(&det.landmarks);
+        assert!(Pose::Left.turned(&start, &left));
+        assert!(!Pose::Right.turned(&start, &left));
+        assert!(Pose::Right.turned(&left, &start));
+        assert!(Pose::Straight.turned(&start, &start));
+
+        // Relative to where the head started, not to the camera
+        det.landmarks[4] = 58.0;
+        let further = HeadPose::from_landmarks(&det.landmarks);
+        assert!(!Pose::Left.turned(&left, &further));
+    }
+
+    #[test]
+    fn test_quality_gate() {
+        // Checkerboard face region: plenty of edges
+        let sharp = DynamicImage::ImageRgb8(image::RgbImage::from_fn(100, 100, |x, y| {
+            image::Rgb([if (x + y) % 2 == 0 { 40 } else { 200 }; 3])
+        }));
+        let flat = DynamicImage::ImageRgb8(image::RgbImage::from_pixel(
+            100,
+            100,
+            image::Rgb([128, 128, 128]),
+        ));
+        let det = frontal_detection();
+        assert!(sharpness(&sharp, &det) > 1000.0);
+        assert_eq!(sharpness(&flat, &det), 0.0);
+
+        assert_eq!(QualityGate::default().check(&flat, &det), Ok(()));
+        let gate = QualityGate {
+            min_score: 0.5,
+            min_sharpness: 100.0,
+            min_face_size: Some(FaceSize::Pixels(40)),
+        };
+        assert_eq!(gate.check(&sharp, &det), Ok(()));
+        assert_eq!(gate.check(&flat, &det), Err(LowQuality::Sharpness(0.0)));
+
+        let mut weak = det.clone();
+        weak.score = 0.3;
+        assert_eq!(gate.check(&sharp, &weak), Err(LowQuality::Score(0.3)));
+        let mut small = det.clone();
+        small.bbox[2] = 30.0;
+        small.bbox[3] = 30.0;
+        assert_eq!(gate.check(&sharp, &small), Err(LowQuality::FaceSize(30.0)));
+    }
+
+    #[test]
+    fn test_feedback() {
+        let dark = DynamicImage::new_rgb8(100, 100);
+        let det = frontal_detection();
+        assert_eq!(
+            FrameQuality::measure(&dark, Some(&det)).feedback(),
+            Feedback::TooDark
+        );
+
+        let lit = DynamicImage::ImageRgb8(image::RgbImage::from_pixel(
+            100,
+            100,
+            image::Rgb([128, 128, 128]),
+        ));
+        assert_eq!(
+            FrameQuality::measure(&lit, None).feedback(),
+            Feedback::NoFace
+        );
+        assert_eq!(
+            FrameQuality::measure(&lit, Some(&det)).feedback(),
+            Feedback::Good
+        );
+
+        let mut small = det.clone();
+        small.bbox[2] = 5.0;
+        assert_eq!(
+            FrameQuality::measure(&lit, Some(&small)).feedback(),
+            Feedback::TooSmall
+        );
+    }
+}
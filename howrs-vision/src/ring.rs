@@ -0,0 +1,129 @@
+//! The last few frames of a camera, with when each was captured
+//!
+//! [`CameraManager::with_history`](crate::video::CameraManager::with_history)
+//! keeps one, so anything looking back over a scan, such as the motion
+//! check in [`crate::motion`] or a debug dump of a failed scan, uses the
+//! frames the scan already read rather than capturing its own.
+
+use crate::pool;
+use image::RgbImage;
+use std::collections::VecDeque;
+use std::time::Instant;
+
+/// A frame and the monotonic time it was read from the camera
+#[derive(Debug, Clone)]
+pub struct TimedFrame {
+    pub frame: RgbImage,
+    pub captured: Instant,
+}
+
+/// Ring buffer of the newest frames, oldest first
+#[derive(Debug, Default)]
+pub struct FrameRing {
+    frames: VecDeque<TimedFrame>,
+    capacity: usize,
+}
+
+impl FrameRing {
+    /// Keep up to `capacity` frames; with 0, [`Self::push`] keeps nothing
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            frames: VecDeque::with_capacity(capacity),
+            capacity,
+        }
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    /// Keep a copy of `frame`, dropping the oldest one when full. The copy
+    /// goes into a buffer from [`pool::frames`], and the dropped frame's
+    /// buffer back to it.
+    pub fn push(&mut self, frame: &RgbImage, captured: Instant) {
+        if self.capacity == 0 {
+            return;
+        }
+        if self.frames.len() == self.capacity {
+            if let Some(oldest) = self.frames.pop_front() {
+                pool::frames().recycle(oldest.frame.into_raw());
+            }
+        }
+        let mut buf = pool::frames().take(frame.as_raw().len());
+        buf.copy_from_slice(frame.as_raw());
+        let copy = RgbImage::from_raw(frame.width(), frame.height(), buf)
+            .expect("buffer sized for the frame");
+        self.frames.push_back(TimedFrame {
+            frame: copy,
+            captured,
+        });
+    }
+
+    pub fn len(&self) -> usize {
+        self.frames.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.frames.is_empty()
+    }
+
+    /// Oldest first
+    pub fn iter(&self) -> impl DoubleEndedIterator<Item = &TimedFrame> + ExactSizeIterator {
+        self.frames.iter()
+    }
+
+    pub fn latest(&self) -> Option<&TimedFrame> {
+        self.frames.back()
+    }
+
+    /// The newest frame captured at or before `time`
+    pub fn at(&self, time: Instant) -> Option<&TimedFrame> {
+        self.frames.iter().rev().find(|f| f.captured <= time)
+    }
+
+    pub fn clear(&mut self) {
+        for f in self.frames.drain(..) {
+            pool::frames().recycle(f.frame.into_raw());
+        }
+    }
+}
+
+impl Drop for FrameRing {
+    fn drop(&mut self) {
+        self.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn test_frame_ring() {
+        let start = Instant::now();
+        let mut ring = FrameRing::new(3);
+        for i in 0..5u8 {
+            let frame = RgbImage::from_pixel(2, 2, image::Rgb([i, 0, 0]));
+            ring.push(&frame, start + Duration::from_millis(i as u64 * 100));
+        }
+
+        // Only the newest three are kept, oldest first
+        assert_eq!(ring.len(), 3);
+        let firsts: Vec<u8> = ring.iter().map(|f| f.frame.get_pixel(0, 0).0[0]).collect();
+        assert_eq!(firsts, [2, 3, 4]);
+        assert_eq!(ring.latest().unwrap().frame.get_pixel(0, 0).0[0], 4);
+
+        let at = ring.at(start + Duration::from_millis(350)).unwrap();
+        assert_eq!(at.captured, start + Duration::from_millis(300));
+        assert!(ring.at(start).is_none());
+
+        ring.clear();
+        assert!(ring.is_empty());
+
+        // Capacity 0 keeps nothing
+        let mut none = FrameRing::new(0);
+        none.push(&RgbImage::new(2, 2), start);
+        assert!(none.latest().is_none());
+    }
+}
@@ -0,0 +1,203 @@
+//! Camera-free check that the detection and recognition models run and give
+//! sane output, behind `howrs selftest`.
+//!
+//! A broken ONNX Runtime install fails to load or run the models; an
+//! execution provider that loads but computes garbage gives non-finite,
+//! non-normalized or input-independent embeddings. The input is a face drawn
+//! at runtime, so nothing but the models has to be installed.
+
+use crate::error::{Context, Error, Result};
+use crate::face::{self, Detection, Embedding};
+use crate::pipeline::{self, Pipeline};
+use image::{DynamicImage, Rgb, RgbImage};
+use std::time::{Duration, Instant};
+
+/// Size of the [`synthetic_face`] frame
+pub const FRAME_SIZE: (u32, u32) = (640, 480);
+/// Least similarity between two encodings of the same face
+const MIN_REPEAT_SIMILARITY: f32 = 0.999;
+/// Most similarity between the face and a blank frame; an encoder that
+/// returns the same vector whatever it is given goes over it
+const MAX_BLANK_SIMILARITY: f32 = 0.99;
+
+/// What [`run`] measured
+#[derive(Debug, Clone)]
+pub struct SelfTest {
+    /// Faces the detector found in the drawing. A drawing isn't always taken
+    /// for a face, so none is not an error; the drawn landmarks are encoded
+    /// instead.
+    pub faces: usize,
+    /// Length of the embedding
+    pub dimension: usize,
+    /// Similarity of two encodings of the same face, 1 for a deterministic
+    /// encoder
+    pub repeat_similarity: f32,
+    /// Similarity of the face and a blank frame
+    pub blank_similarity: f32,
+    pub elapsed: Duration,
+}
+
+/// Detect and encode [`synthetic_face`] with `pipeline` and check the output
+pub fn run(pipeline: &mut Pipeline) -> Result<SelfTest> {
+    let start = Instant::now();
+    let (img, drawn) = synthetic_face();
+
+    let detections = pipeline.detect_all(&img).context("running the detector")?;
+    for detection in &detections {
+        check_detection(detection, FRAME_SIZE)?;
+    }
+    let face = detections.first().cloned().unwrap_or(drawn);
+
+    let embedding = pipeline
+        .encode_detection(&img, &face)
+        .context("running the encoder")?;
+    check_embedding(&embedding)?;
+    let again = pipeline.encode_detection(&img, &face)?;
+    let repeat_similarity = face::match_embedding(&embedding, &again);
+    if repeat_similarity < MIN_REPEAT_SIMILARITY {
+        return Err(Error::inference(format!(
+            "encoding the same face twice gave different embeddings (similarity {:.4})",
+            repeat_similarity
+        )));
+    }
+
+    let (blank, blank_face) = pipeline::warm_up_input();
+    let other = pipeline.encode_detection(&blank, &blank_face)?;
+    check_embedding(&other)?;
+    let blank_similarity = face::match_embedding(&embedding, &other);
+    if blank_similarity > MAX_BLANK_SIMILARITY {
+        return Err(Error::inference(format!(
+            "the encoder gives a face and a blank frame the same embedding (similarity {:.4})",
+            blank_similarity
+        )));
+    }
+
+    Ok(SelfTest {
+        faces: detections.len(),
+        dimension: embedding.dim(),
+        repeat_similarity,
+        blank_similarity,
+        elapsed: start.elapsed(),
+    })
+}
+
+/// A face drawn on a gray background: skin-colored oval, eyes, brows, nose
+/// and mouth, and its box and five landmarks in the detector's order
+pub fn synthetic_face() -> (DynamicImage, Detection) {
+    let (width, height) = FRAME_SIZE;
+    let (cx, cy) = (320.0, 240.0);
+    let mut img = RgbImage::from_pixel(width, height, Rgb([96, 96, 96]));
+
+    let mut ellipse = |x0: f32, y0: f32, rx: f32, ry: f32, color: [u8; 3]| {
+        for y in (y0 - ry) as u32..=(y0 + ry) as u32 {
+            for x in (x0 - rx) as u32..=(x0 + rx) as u32 {
+                let (dx, dy) = ((x as f32 - x0) / rx, (y as f32 - y0) / ry);
+                if dx * dx + dy * dy <= 1.0 {
+                    img.put_pixel(x, y, Rgb(color));
+                }
+            }
+        }
+    };
+    ellipse(cx, cy, 78.0, 102.0, [222, 178, 150]);
+    for side in [-1.0, 1.0] {
+        let eye = cx + side * 34.0;
+        ellipse(eye, cy - 38.0, 22.0, 5.0, [92, 62, 48]);
+        ellipse(eye, cy - 20.0, 15.0, 7.0, [245, 245, 245]);
+        ellipse(eye, cy - 20.0, 6.0, 6.0, [40, 30, 28]);
+        ellipse(cx + side * 8.0, cy + 22.0, 5.0, 4.0, [150, 100, 84]);
+        ellipse(cx + side * 64.0, cy + 30.0, 16.0, 10.0, [228, 160, 142]);
+    }
+    ellipse(cx, cy + 10.0, 7.0, 16.0, [200, 150, 124]);
+    ellipse(cx, cy + 56.0, 26.0, 8.0, [170, 70, 72]);
+
+    let detection = Detection {
+        bbox: [cx - 78.0, cy - 102.0, 156.0, 204.0],
+        score: 1.0,
+        landmarks: [
+            cx - 34.0,
+            cy - 20.0,
+            cx + 34.0,
+            cy - 20.0,
+            cx,
+            cy + 20.0,
+            cx - 24.0,
+            cy + 56.0,
+            cx + 24.0,
+            cy + 56.0,
+        ],
+    };
+    (DynamicImage::ImageRgb8(img), detection)
+}
+
+/// A detection with finite values and a box overlapping the frame
+fn check_detection(detection: &Detection, (width, height): (u32, u32)) -> Result<()> {
+    let finite = detection
+        .bbox
+        .iter()
+        .chain(&detection.landmarks)
+        .all(|v| v.is_finite());
+    if !finite || !(0.0..=1.0).contains(&detection.score) {
+        return Err(Error::inference(format!(
+            "the detector returned an invalid face: {:?}",
+            detection
+        )));
+    }
+    let [x, y, w, h] = detection.bbox;
+    if w <= 0.0 || h <= 0.0 || x + w < 0.0 || y + h < 0.0 || x > width as f32 || y > height as f32 {
+        return Err(Error::inference(format!(
+            "the detector returned a face box {:?} outside the {}x{} frame",
+            detection.bbox, width, height
+        )));
+    }
+    Ok(())
+}
+
+/// One row of finite values with unit length
+fn check_embedding(embedding: &Embedding) -> Result<()> {
+    let vector = embedding.vector();
+    if vector.nrows() != 1 || vector.ncols() == 0 {
+        return Err(Error::inference(format!(
+            "the encoder returned an embedding of shape {:?}",
+            vector.shape()
+        )));
+    }
+    if !vector.iter().all(|v| v.is_finite()) {
+        return Err(Error::inference("the encoder returned non-finite values"));
+    }
+    let norm = vector.iter().map(|v| v * v).sum::<f32>().sqrt();
+    if (norm - 1.0).abs() > 1e-3 {
+        return Err(Error::inference(format!(
+            "the encoder returned an embedding of norm {:.4}, not 1",
+            norm
+        )));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_synthetic_face() {
+        let (img, detection) = synthetic_face();
+        check_detection(&detection, FRAME_SIZE).unwrap();
+        let img = img.to_rgb8();
+        // Pupils are dark on a light face
+        let pupil = img.get_pixel(detection.landmarks[0] as u32, detection.landmarks[1] as u32);
+        let cheek = img.get_pixel(detection.landmarks[0] as u32, detection.landmarks[5] as u32);
+        assert!(pupil[0] < 64 && cheek[0] > 192);
+    }
+
+    #[test]
+    fn test_check_output() {
+        let embedding = Embedding::new(vec![0.6, 0.8]).unwrap();
+        assert!(check_embedding(&embedding).is_ok());
+
+        let (_, mut detection) = synthetic_face();
+        detection.bbox[0] = 1000.0;
+        assert!(check_detection(&detection, FRAME_SIZE).is_err());
+        detection.bbox[0] = f32::NAN;
+        assert!(check_detection(&detection, FRAME_SIZE).is_err());
+    }
+}
@@ -0,0 +1,119 @@
+//! Vector kernels for comparing embeddings
+//!
+//! Everything builds on stable Rust. The portable kernel keeps several
+//! independent partial sums so LLVM can vectorize it for whatever the target
+//! baseline offers (SSE2, NEON, RVV). With the `simd` feature, x86-64 CPUs
+//! with AVX2 and FMA and aarch64 CPUs with NEON get hand-written kernels,
+//! picked at runtime, so one package runs on every CPU of its architecture.
+
+/// Dot product of `a` and `b` over their common prefix
+pub fn dot(a: &[f32], b: &[f32]) -> f32 {
+    let len = a.len().min(b.len());
+    let (a, b) = (&a[..len], &b[..len]);
+
+    #[cfg(all(feature = "simd", target_arch = "x86_64"))]
+    if std::arch::is_x86_feature_detected!("avx2") && std::arch::is_x86_feature_detected!("fma") {
+        // SAFETY: the CPU has the features the kernel is compiled for
+        return unsafe { x86::dot(a, b) };
+    }
+    #[cfg(all(feature = "simd", target_arch = "aarch64"))]
+    if std::arch::is_aarch64_feature_detected!("neon") {
+        // SAFETY: as above
+        return unsafe { neon::dot(a, b) };
+    }
+    portable::dot(a, b)
+}
+
+/// The kernel [`dot`] uses on this CPU, for `howrs info`
+pub fn backend() -> &'static str {
+    #[cfg(all(feature = "simd", target_arch = "x86_64"))]
+    if std::arch::is_x86_feature_detected!("avx2") && std::arch::is_x86_feature_detected!("fma") {
+        return "avx2+fma";
+    }
+    #[cfg(all(feature = "simd", target_arch = "aarch64"))]
+    if std::arch::is_aarch64_feature_detected!("neon") {
+        return "neon";
+    }
+    "portable"
+}
+
+mod portable {
+    const LANES: usize = 8;
+
+    pub fn dot(a: &[f32], b: &[f32]) -> f32 {
+        // A single running sum is a dependency chain LLVM may not reorder
+        let mut sums = [0.0f32; LANES];
+        let mut a_chunks = a.chunks_exact(LANES);
+        let mut b_chunks = b.chunks_exact(LANES);
+        for (x, y) in (&mut a_chunks).zip(&mut b_chunks) {
+            for ((sum, x), y) in sums.iter_mut().zip(x).zip(y) {
+                *sum += x * y;
+            }
+        }
+        let tail: f32 = a_chunks
+            .remainder()
+            .iter()
+            .zip(b_chunks.remainder())
+            .map(|(x, y)| x * y)
+            .sum();
+        sums.iter().sum::<f32>() + tail
+    }
+}
+
+#[cfg(all(feature = "simd", target_arch = "x86_64"))]
+mod x86 {
+    use std::arch::x86_64::*;
+
+    /// # Safety
+    /// The CPU must support AVX2 and FMA, and `a` and `b` be the same length
+    #[target_feature(enable = "avx2,fma")]
+    pub unsafe fn dot(a: &[f32], b: &[f32]) -> f32 {
+        let chunks = a.len() / 8;
+        let mut acc = _mm256_setzero_ps();
+        for i in 0..chunks {
+            let x = _mm256_loadu_ps(a.as_ptr().add(i * 8));
+            let y = _mm256_loadu_ps(b.as_ptr().add(i * 8));
+            acc = _mm256_fmadd_ps(x, y, acc);
+        }
+        let mut lanes = [0.0f32; 8];
+        _mm256_storeu_ps(lanes.as_mut_ptr(), acc);
+        lanes.iter().sum::<f32>() + super::portable::dot(&a[chunks * 8..], &b[chunks * 8..])
+    }
+}
+
+#[cfg(all(feature = "simd", target_arch = "aarch64"))]
+mod neon {
+    use std::arch::aarch64::*;
+
+    /// # Safety
+    /// The CPU must support NEON, and `a` and `b` be the same length
+    #[target_feature(enable = "neon")]
+    pub unsafe fn dot(a: &[f32], b: &[f32]) -> f32 {
+        let chunks = a.len() / 4;
+        let mut acc = vdupq_n_f32(0.0);
+        for i in 0..chunks {
+            let x = vld1q_f32(a.as_ptr().add(i * 4));
+            let y = vld1q_f32(b.as_ptr().add(i * 4));
+            acc = vfmaq_f32(acc, x, y);
+        }
+        vaddvq_f32(acc) + super::portable::dot(&a[chunks * 4..], &b[chunks * 4..])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_dot_matches_sequential_sum() {
+        for len in [0, 1, 7, 8, 9, 128, 131, 512] {
+            let a: Vec<f32> = (0..len).map(|i| (i as f32 * 0.37).sin()).collect();
+            let b: Vec<f32> = (0..len).map(|i| (i as f32 * 0.11).cos()).collect();
+            let expected: f32 = a.iter().zip(&b).map(|(x, y)| x * y).sum();
+            assert!((dot(&a, &b) - expected).abs() < 1e-4, "len {}", len);
+            assert!((portable::dot(&a, &b) - expected).abs() < 1e-4);
+        }
+        // Common prefix only
+        assert_eq!(dot(&[1.0, 2.0, 3.0], &[4.0, 5.0]), 14.0);
+    }
+}
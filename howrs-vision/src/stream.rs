@@ -0,0 +1,279 @@
+//! Frame loop shared by everything that watches the camera: capture, detect,
+//! encode and match, reported to a callback as [`StreamEvent`]s.
+//!
+//! Without a [`Matcher`] the loop only detects, e.g. to tell whether someone
+//! is in front of the camera.
+
+use image::DynamicImage;
+use std::time::{Duration, Instant};
+
+use crate::error::{Error, Report, Result};
+use crate::face::{Detection, Embedding};
+use crate::pipeline::{Pipeline, PipelineTimings};
+use crate::pool;
+use crate::quality::LowQuality;
+use crate::video::FrameSource;
+
+/// Index and similarity of the candidate most similar to an embedding,
+/// `None` if there is nothing to compare against
+pub type ScoreFn<'a> = Box<dyn Fn(&Embedding) -> Option<(usize, f32)> + 'a>;
+
+/// Scores embeddings against the enrolled faces
+pub struct Matcher<'a> {
+    pub score: ScoreFn<'a>,
+    /// Similarity at which a candidate matches and the stream ends
+    pub threshold: f32,
+}
+
+/// How [`Pipeline::run_stream`] runs
+#[derive(Default)]
+pub struct StreamOptions<'a> {
+    /// Stop once this passes; `None` runs until the callback stops it
+    pub deadline: Option<Instant>,
+    /// Try every face in the frame rather than only the best one
+    pub all_faces: bool,
+    pub matcher: Option<Matcher<'a>>,
+}
+
+/// What happened to a frame, in the order it happens
+#[derive(Debug)]
+pub enum StreamEvent<'a> {
+    /// A frame was read, taking `capture`
+    FrameCaptured {
+        frame: &'a DynamicImage,
+        capture: Duration,
+    },
+    /// A frame, or one face in it, gave nothing to match; `frame` is `None`
+    /// when capturing failed
+    FrameSkipped {
+        frame: Option<&'a DynamicImage>,
+        reason: SkipReason,
+    },
+    FaceDetected {
+        frame: &'a DynamicImage,
+        detection: &'a Detection,
+        timings: PipelineTimings,
+    },
+    /// The face was encoded and compared with the best candidate
+    ScoreComputed {
+        frame: &'a DynamicImage,
+        detection: &'a Detection,
+        embedding: &'a Embedding,
+        candidate: usize,
+        score: f32,
+        timings: PipelineTimings,
+    },
+    /// The last score met the threshold, and the face passed the pipeline's
+    /// anti-spoofing model if it has one; the stream ends after this
+    Matched {
+        embedding: &'a Embedding,
+        candidate: usize,
+        score: f32,
+    },
+}
+
+/// Why [`StreamEvent::FrameSkipped`] was sent
+#[derive(Debug)]
+pub enum SkipReason {
+    Capture(Error),
+    NoFace,
+    LowQuality(LowQuality),
+    /// The face matched, but the anti-spoofing model gave it this live
+    /// probability, under its threshold
+    Spoof(f32),
+    /// Detection or encoding failed
+    Failed(Error),
+}
+
+impl std::fmt::Display for SkipReason {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SkipReason::Capture(e) => write!(f, "capture failed: {}", Report(e)),
+            SkipReason::NoFace => f.write_str("no face detected"),
+            SkipReason::LowQuality(low) => low.fmt(f),
+            SkipReason::Spoof(live) => {
+                write!(f, "face looks like a photo or a screen (live {:.2})", live)
+            }
+            SkipReason::Failed(e) => write!(f, "{}", Report(e)),
+        }
+    }
+}
+
+/// Whether the stream goes on after an event
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Flow {
+    Continue,
+    Stop,
+}
+
+/// Outcome of one frame
+enum Step {
+    Continue,
+    Stop,
+    Matched(usize, f32),
+}
+
+impl From<Flow> for Step {
+    fn from(flow: Flow) -> Self {
+        match flow {
+            Flow::Continue => Step::Continue,
+            Flow::Stop => Step::Stop,
+        }
+    }
+}
+
+impl Pipeline {
+    /// Read frames from `camera` and run them through the pipeline until a
+    /// candidate matches, the deadline passes or `on_event` stops the stream.
+    ///
+    /// Returns the matching candidate and its score. An error from
+    /// `on_event` ends the stream with that error, and so does cancelling
+    /// the pipeline's token.
+    pub fn run_stream<E: From<Error>>(
+        &mut self,
+        camera: &mut impl FrameSource,
+        opts: &StreamOptions<'_>,
+        mut on_event: impl FnMut(StreamEvent<'_>) -> Result<Flow, E>,
+    ) -> Result<Option<(usize, f32)>, E> {
+        while opts
+            .deadline
+            .is_none_or(|deadline| Instant::now() < deadline)
+        {
+            self.cancel.check().map_err(Error::from)?;
+            let start = Instant::now();
+            let frame = match camera.frame() {
+                Ok(frame) => DynamicImage::ImageRgb8(frame),
+                Err(e) => {
+                    let reason = SkipReason::Capture(e);
+                    match on_event(StreamEvent::FrameSkipped {
+                        frame: None,
+                        reason,
+                    })? {
+                        Flow::Continue => continue,
+                        Flow::Stop => return Ok(None),
+                    }
+                }
+            };
+            let capture = start.elapsed();
+
+            let step = self.stream_frame(&frame, capture, opts, &mut on_event);
+            pool::frames().recycle_image(frame);
+            match step? {
+                Step::Continue => {}
+                Step::Stop => return Ok(None),
+                Step::Matched(candidate, score) => return Ok(Some((candidate, score))),
+            }
+        }
+        Ok(None)
+    }
+
+    fn stream_frame<E: From<Error>>(
+        &mut self,
+        frame: &DynamicImage,
+        capture: Duration,
+        opts: &StreamOptions<'_>,
+        on_event: &mut impl FnMut(StreamEvent<'_>) -> Result<Flow, E>,
+    ) -> Result<Step, E> {
+        if on_event(StreamEvent::FrameCaptured { frame, capture })? == Flow::Stop {
+            return Ok(Step::Stop);
+        }
+
+        let start = Instant::now();
+        let detections = match opts.all_faces {
+            true => self.detect_all(frame),
+            false => self.detect_best(frame).map(Vec::from_iter),
+        };
+        let detections = match detections {
+            Ok(detections) if detections.is_empty() => {
+                return skipped(on_event, frame, SkipReason::NoFace)
+            }
+            Ok(detections) => detections,
+            Err(e) => return skipped(on_event, frame, SkipReason::Failed(e)),
+        };
+        let detect = start.elapsed();
+
+        for detection in &detections {
+            let mut timings = PipelineTimings {
+                capture,
+                detect,
+                ..Default::default()
+            };
+            let event = StreamEvent::FaceDetected {
+                frame,
+                detection,
+                timings,
+            };
+            if on_event(event)? == Flow::Stop {
+                return Ok(Step::Stop);
+            }
+            let Some(matcher) = &opts.matcher else {
+                continue;
+            };
+
+            if let Err(low) = self.quality_gate.check(frame, detection) {
+                match skipped(on_event, frame, SkipReason::LowQuality(low))? {
+                    Step::Continue => continue,
+                    step => return Ok(step),
+                }
+            }
+            self.cancel.check().map_err(Error::from)?;
+            let embedding = match self.encode_timed(frame, detection, &mut timings) {
+                Ok(embedding) => embedding,
+                Err(e) => match skipped(on_event, frame, SkipReason::Failed(e))? {
+                    Step::Continue => continue,
+                    step => return Ok(step),
+                },
+            };
+            let Some((candidate, score)) = (matcher.score)(&embedding) else {
+                continue;
+            };
+
+            let event = StreamEvent::ScoreComputed {
+                frame,
+                detection,
+                embedding: &embedding,
+                candidate,
+                score,
+                timings,
+            };
+            if on_event(event)? == Flow::Stop {
+                return Ok(Step::Stop);
+            }
+            if score >= matcher.threshold {
+                if let Some(pad) = &mut self.pad {
+                    let checked = pad.check(frame, detection);
+                    let reason = match checked {
+                        Ok(Ok(_)) => None,
+                        Ok(Err(live)) => Some(SkipReason::Spoof(live)),
+                        Err(e) => Some(SkipReason::Failed(e)),
+                    };
+                    if let Some(reason) = reason {
+                        match skipped(on_event, frame, reason)? {
+                            Step::Continue => continue,
+                            step => return Ok(step),
+                        }
+                    }
+                }
+                on_event(StreamEvent::Matched {
+                    embedding: &embedding,
+                    candidate,
+                    score,
+                })?;
+                return Ok(Step::Matched(candidate, score));
+            }
+        }
+        Ok(Step::Continue)
+    }
+}
+
+fn skipped<E>(
+    on_event: &mut impl FnMut(StreamEvent<'_>) -> Result<Flow, E>,
+    frame: &DynamicImage,
+    reason: SkipReason,
+) -> Result<Step, E> {
+    on_event(StreamEvent::FrameSkipped {
+        frame: Some(frame),
+        reason,
+    })
+    .map(Step::from)
+}
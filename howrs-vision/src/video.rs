@@ -1,22 +1,380 @@
-use anyhow::{Context, Result};
-use image::{ImageBuffer, Rgb};
-use v4l::buffer::Type;
-use v4l::io::mmap::Stream;
+use crate::error::{Error, Report, Result, ResultExt};
+use crate::pool;
+use crate::ring::FrameRing;
+use image::{ImageBuffer, Rgb, RgbImage};
+use std::fs::File;
+use std::io::{Read, Write};
+use std::os::unix::fs::{DirBuilderExt, MetadataExt, OpenOptionsExt, PermissionsExt};
+use std::os::unix::io::AsRawFd;
+use std::path::{Path, PathBuf};
+use std::process::{Child, ChildStdout, Command, Stdio};
+use std::sync::{Arc, OnceLock};
+use std::time::{Duration, Instant};
+use v4l::buffer::{Metadata, Type};
+use v4l::device::Handle;
 use v4l::io::traits::CaptureStream;
+use v4l::io::{mmap, userptr};
 use v4l::video::Capture;
 use v4l::{Device, Format, FourCC};
 
+/// Directory holding per-device lock files, shared by every process using
+/// howrs. It must be owned by root and not writable by everyone; packaging
+/// creates it setgid to the `howrs` group, whose members may take the locks.
+const LOCK_DIR: &str = "/run/howrs/lock";
+/// Mode of a lock file: root and the directory's group
+const LOCK_MODE: u32 = 0o660;
+/// Where the kernel lists V4L2 devices
+const SYSFS_VIDEO: &str = "/sys/class/video4linux";
+/// Prefix of a `camera` naming a USB camera by its IDs rather than its
+/// `/dev/videoN` node, which can change across boots
+pub const USB_PREFIX: &str = "usb:";
+/// `camera` value picking the device by itself: the first IR capture node,
+/// as on Windows Hello modules that also have a color one, else the first
+/// capture node. `auto:rgb` prefers color nodes instead.
+pub const AUTO_CAMERA: &str = "auto";
+
+/// Exclusive advisory lock serializing access to one camera device.
+///
+/// Concurrent authentication requests (e.g. two PAM prompts on a user switch
+/// screen) queue on this lock instead of failing with a busy device.
+///
+/// While queued, a request holds a shared lock on a second file, so a
+/// background user such as `howrs-greeter-helper` can see with
+/// [`CameraLock::contended`] that it should let go of the camera.
+pub struct CameraLock {
+    _file: File,
+}
+
+impl CameraLock {
+    /// Wait for the lock on `device` until `deadline`
+    pub fn acquire(device: &str, deadline: Instant) -> Result<Self> {
+        let file = open_lock_file(&lock_path(device, "lock")?)?;
+        // Taken on the first busy attempt and released once we hold the lock
+        let mut waiting = None;
+
+        loop {
+            if try_flock(&file, libc::LOCK_EX)? {
+                return Ok(Self { _file: file });
+            }
+            if Instant::now() >= deadline {
+                return Err(Error::camera(format!("camera {} is busy", device)));
+            }
+            if waiting.is_none() {
+                let wait = open_lock_file(&lock_path(device, "wait")?)?;
+                if try_flock(&wait, libc::LOCK_SH)? {
+                    waiting = Some(wait);
+                }
+            }
+            tracing::debug!("camera {} busy, waiting", device);
+            std::thread::sleep(Duration::from_millis(50));
+        }
+    }
+
+    /// Whether another process is queued in [`CameraLock::acquire`] for `device`
+    pub fn contended(device: &str) -> bool {
+        let Ok(path) = lock_path(device, "wait") else {
+            return false;
+        };
+        let Ok(wait) = std::fs::OpenOptions::new()
+            .read(true)
+            .custom_flags(libc::O_NOFOLLOW)
+            .open(path)
+        else {
+            return false;
+        };
+        // Dropping `wait` releases the probe right away
+        matches!(try_flock(&wait, libc::LOCK_EX), Ok(false))
+    }
+}
+
+/// Open the lock file at `path`, creating it if missing.
+///
+/// A link planted in its place is refused rather than followed, and the
+/// mode is set on the open file, so a lock never lends root's access to
+/// another file.
+fn open_lock_file(path: &Path) -> Result<File> {
+    let file = std::fs::OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create(true)
+        .truncate(false)
+        .mode(LOCK_MODE)
+        .custom_flags(libc::O_NOFOLLOW)
+        .open(path)
+        .camera(format!("open camera lock {}", path.display()))?;
+    let meta = file
+        .metadata()
+        .camera(format!("open camera lock {}", path.display()))?;
+    if !meta.is_file() {
+        return Err(Error::camera(format!(
+            "camera lock {} is not a regular file",
+            path.display()
+        )));
+    }
+    // Undo the umask so other members of the group can lock too
+    if meta.uid() == unsafe { libc::geteuid() } && meta.mode() & 0o777 != LOCK_MODE {
+        file.set_permissions(std::fs::Permissions::from_mode(LOCK_MODE))
+            .camera(format!("set mode of camera lock {}", path.display()))?;
+    }
+    Ok(file)
+}
+
+/// Whether the process runs setuid or setgid, e.g. as the PAM module
+/// inside `sudo`
+fn is_elevated() -> bool {
+    unsafe { libc::getuid() != libc::geteuid() || libc::getgid() != libc::getegid() }
+}
+
+/// Non-blocking `flock`; `false` if someone else holds a conflicting lock
+fn try_flock(file: &File, operation: libc::c_int) -> Result<bool> {
+    let ret = unsafe { libc::flock(file.as_raw_fd(), operation | libc::LOCK_NB) };
+    if ret == 0 {
+        return Ok(true);
+    }
+    let err = std::io::Error::last_os_error();
+    if err.raw_os_error() == Some(libc::EWOULDBLOCK) {
+        return Ok(false);
+    }
+    Err(err).camera("lock camera")
+}
+
+fn lock_path(device: &str, kind: &str) -> Result<PathBuf> {
+    Ok(lock_dir()?.join(lock_name(device, kind)))
+}
+
+fn lock_name(device: &str, kind: &str) -> String {
+    // Every name of a device (by-id link, `usb:` ID) shares its lock
+    let device = resolve_name(device).unwrap_or_else(|_| device.to_string());
+    let name: String = device
+        .trim_start_matches('/')
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c } else { '-' })
+        // A `gst:` pipeline can be longer than a file name may be
+        .take(200)
+        .collect();
+    format!("howrs-{}.{}", name, kind)
+}
+
+/// [`LOCK_DIR`], once it is known to be safe to create locks in.
+///
+/// Without packaging, root creates it readable only by itself; anyone else
+/// then gets an error rather than a lock in a directory others can write.
+fn lock_dir() -> Result<&'static Path> {
+    let dir = Path::new(LOCK_DIR);
+    let meta = match std::fs::symlink_metadata(dir) {
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound && unsafe { libc::geteuid() } == 0 => {
+            std::fs::DirBuilder::new()
+                .recursive(true)
+                .mode(0o755)
+                .create(dir)
+                .camera(format!("create camera lock directory {}", LOCK_DIR))?;
+            std::fs::symlink_metadata(dir)
+        }
+        meta => meta,
+    }
+    .camera(format!("camera lock directory {}", LOCK_DIR))?;
+    if !meta.is_dir() || meta.uid() != 0 || meta.mode() & 0o002 != 0 {
+        return Err(Error::camera(format!(
+            "camera lock directory {} must be a directory owned by root and not writable by everyone",
+            LOCK_DIR
+        )));
+    }
+    Ok(dir)
+}
+
+/// Buffers queued with the driver when nothing else is configured
+pub const DEFAULT_BUFFERS: u32 = 4;
+
+/// How frames are exchanged with the driver
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum IoMethod {
+    /// Buffers allocated by the driver and mapped into the process
+    #[default]
+    Mmap,
+    /// Buffers allocated by the process and handed to the driver, for
+    /// drivers whose mmap support is broken
+    UserPtr,
+}
+
+/// Stack a camera is captured through
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum CaptureBackend {
+    /// The device node is streamed directly
+    #[default]
+    V4l2,
+    /// Frames come from a PipeWire node through `gst-launch-1.0
+    /// pipewiresrc`, for cameras without a usable V4L2 node such as the MIPI
+    /// sensors behind libcamera (Intel IPU6). Needs the `pipewire` feature.
+    Pipewire,
+}
+
+/// Prefix of a `camera` that is a GStreamer pipeline rather than a device,
+/// e.g. `gst:rtspsrc location=rtsp://10.0.0.5/stream ! decodebin`
+pub const GST_PREFIX: &str = "gst:";
+
+/// `gst-launch-1.0`, run by absolute path so `PATH` can't pick another one
+pub const GST_LAUNCH: &str = "/usr/bin/gst-launch-1.0";
+
+/// Whether opening `device` with `settings` runs [`GST_LAUNCH`]
+pub fn runs_gstreamer(device: &str, settings: &CaptureSettings) -> bool {
+    device.starts_with(GST_PREFIX) || settings.backend == CaptureBackend::Pipewire
+}
+
+/// Size frames from GStreamer (a [`GST_PREFIX`] pipeline or PipeWire) are
+/// converted to, letterboxed if the source's aspect ratio differs
+pub const GST_FRAME_SIZE: (u32, u32) = (640, 480);
+
+/// How a device is streamed from. Some UVC IR modules drop or corrupt frames
+/// with the default 4 mmap buffers and need fewer, more or userptr ones.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CaptureSettings {
+    pub backend: CaptureBackend,
+    /// Buffers queued with the driver, at least 1
+    pub buffers: u32,
+    pub io: IoMethod,
+}
+
+impl Default for CaptureSettings {
+    fn default() -> Self {
+        Self {
+            backend: CaptureBackend::V4l2,
+            buffers: DEFAULT_BUFFERS,
+            io: IoMethod::Mmap,
+        }
+    }
+}
+
+/// A capture stream of either [`IoMethod`]
+enum DeviceStream {
+    Mmap(mmap::Stream<'static>),
+    UserPtr(userptr::Stream),
+}
+
+impl DeviceStream {
+    fn open(dev: &Device, settings: &CaptureSettings) -> Result<Self> {
+        if settings.buffers == 0 {
+            return Err(Error::camera("at least one capture buffer is needed"));
+        }
+        let stream = match settings.io {
+            IoMethod::Mmap => Self::Mmap(
+                mmap::Stream::with_buffers(dev, Type::VideoCapture, settings.buffers)
+                    .camera("map capture buffers")?,
+            ),
+            IoMethod::UserPtr => Self::UserPtr(
+                userptr::Stream::with_buffers(dev, Type::VideoCapture, settings.buffers)
+                    .camera("allocate capture buffers")?,
+            ),
+        };
+        Ok(stream)
+    }
+
+    fn handle(&self) -> Arc<Handle> {
+        match self {
+            Self::Mmap(stream) => stream.handle(),
+            Self::UserPtr(stream) => stream.handle(),
+        }
+    }
+
+    fn next(&mut self) -> std::io::Result<(&[u8], &Metadata)> {
+        match self {
+            Self::Mmap(stream) => stream.next(),
+            Self::UserPtr(stream) => stream.next(),
+        }
+    }
+}
+
 pub struct Camera {
-    stream: Stream<'static>,
+    source: Source,
     width: u32,
     height: u32,
-    fourcc: FourCC,
+}
+
+enum Source {
+    Device {
+        path: String,
+        node: String,
+        stream: DeviceStream,
+        fourcc: FourCC,
+        _lock: CameraLock,
+    },
+    File(VideoFile),
+    /// A `gst-launch-1.0` pipeline, PipeWire's included
+    Gstreamer {
+        name: String,
+        frames: VideoFile,
+        _lock: CameraLock,
+    },
+}
+
+/// Kind of sensor behind a camera device
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SensorKind {
+    Rgb,
+    /// Infrared, as used by face login cameras; these only deliver greyscale
+    Ir,
+}
+
+impl SensorKind {
+    /// Guess from the pixel format the device ended up delivering
+    pub fn from_fourcc(fourcc: FourCC) -> Self {
+        if fourcc == FourCC::new(b"GREY") || GreyDepth::from_fourcc(fourcc).is_some() {
+            SensorKind::Ir
+        } else {
+            SensorKind::Rgb
+        }
+    }
+}
+
+/// Which device frames come from and in what format
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DeviceInfo {
+    pub path: String,
+    /// The V4L2 node `path` resolved to, see [`resolve_device`]
+    pub node: Option<String>,
+    /// Pixel format, e.g. `YUYV`
+    pub fourcc: String,
+    pub sensor: SensorKind,
+}
+
+/// Frames written as raw RGB24 by a child process, `ffmpeg` decoding a file
+/// or `gst-launch-1.0` running a pipeline
+struct VideoFile {
+    child: Child,
+    frames: ChildStdout,
+}
+
+impl Drop for VideoFile {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
 }
 
 impl Camera {
-    pub fn open(device: &str) -> Result<Self> {
-        let dev = Device::with_path(device).context("open camera")?;
-        let mut fmt = dev.format().context("get format")?;
+    /// Open `device`, failing right away if another request holds it
+    pub fn open(device: &str, settings: &CaptureSettings) -> Result<Self> {
+        Self::open_until(device, Instant::now(), settings)
+    }
+
+    /// Open `device`, queuing behind other users of the camera until
+    /// `deadline`. `device` may also be a GStreamer pipeline after
+    /// [`GST_PREFIX`], whose last element outputs raw video. With
+    /// [`CaptureBackend::Pipewire`], `device` is the PipeWire node's name or
+    /// serial, or empty for the default camera.
+    pub fn open_until(device: &str, deadline: Instant, settings: &CaptureSettings) -> Result<Self> {
+        if let Some(pipeline) = device.strip_prefix(GST_PREFIX) {
+            let lock = CameraLock::acquire(device, deadline)?;
+            return Self::open_gst(device, pipeline, lock);
+        }
+        if settings.backend == CaptureBackend::Pipewire {
+            let lock = CameraLock::acquire(device, deadline)?;
+            return Self::open_pipewire(device, lock);
+        }
+        let node = resolve_device(device)?;
+        tracing::debug!("camera {} is {}", device, node);
+        let lock = CameraLock::acquire(&node, deadline)?;
+        let dev = Device::with_path(&node).camera("open camera")?;
+        let mut fmt = dev.format().camera("get format")?;
         // Prefer RGB, fallback to YUYV, else accept existing format
         let desired = Format::new(fmt.width, fmt.height, FourCC::new(b"RGB3"));
         fmt = dev.set_format(&desired).unwrap_or(fmt);
@@ -27,97 +385,1009 @@ impl Camera {
         let fourcc = fmt.fourcc;
         let width = fmt.width;
         let height = fmt.height;
-        let stream = Stream::with_buffers(&dev, Type::VideoCapture, 4).context("stream")?;
+        let stream = DeviceStream::open(&dev, settings).camera("stream")?;
+        Ok(Self {
+            source: Source::Device {
+                path: device.to_string(),
+                node,
+                stream,
+                fourcc,
+                _lock: lock,
+            },
+            width,
+            height,
+        })
+    }
+
+    fn open_pipewire(node: &str, lock: CameraLock) -> Result<Self> {
+        if !cfg!(feature = "pipewire") {
+            return Err(Error::camera(
+                "howrs was built without the pipewire feature",
+            ));
+        }
+        let source = if node.is_empty() {
+            "pipewiresrc".to_string()
+        } else {
+            format!("pipewiresrc target-object=\"{}\"", node)
+        };
+        Self::open_gst(node, &source, lock)
+    }
+
+    /// Run the GStreamer `pipeline` with [`GST_LAUNCH`], converting its
+    /// output to RGB at [`GST_FRAME_SIZE`].
+    ///
+    /// GStreamer loads plugins from wherever its environment points, so it
+    /// gets a fixed one, and it isn't run at all inside a setuid program
+    /// such as `su` or `sudo`, whose caller controls the environment; the
+    /// daemon opens such cameras instead.
+    fn open_gst(name: &str, pipeline: &str, lock: CameraLock) -> Result<Self> {
+        if is_elevated() {
+            return Err(Error::camera(format!(
+                "not running {} in a setuid program, the daemon has to open {}",
+                GST_LAUNCH, name
+            )));
+        }
+        let (width, height) = GST_FRAME_SIZE;
+        let mut command = Command::new(GST_LAUNCH);
+        command.env_clear().env("PATH", "/usr/bin:/bin");
+        // Where pipewiresrc finds the session's PipeWire socket; only the
+        // caller's own, since a setuid caller was refused above
+        if let Some(runtime) = std::env::var_os("XDG_RUNTIME_DIR") {
+            command.env("XDG_RUNTIME_DIR", runtime);
+        }
+        // gst-launch escapes spaces inside each argument, so the pipeline
+        // goes in word by word
+        let mut child = command
+            .arg("-q")
+            .args(pipeline.split_whitespace())
+            .args(["!", "videoconvert", "!", "videoscale", "!"])
+            .arg(format!(
+                "video/x-raw,format=RGB,width={},height={},pixel-aspect-ratio=1/1",
+                width, height
+            ))
+            .args(["!", "fdsink", "fd=1"])
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .spawn()
+            .camera(format!("run {}", GST_LAUNCH))?;
+        let frames = child.stdout.take().camera("gst-launch-1.0 stdout")?;
         Ok(Self {
-            stream,
+            source: Source::Gstreamer {
+                name: name.to_string(),
+                frames: VideoFile { child, frames },
+                _lock: lock,
+            },
             width,
             height,
-            fourcc,
         })
     }
 
+    /// Play back a recorded clip instead of a camera, decoded with `ffmpeg`.
+    ///
+    /// Frames come as fast as they decode, and [`Self::frame`] fails once the
+    /// clip ends. Needs no camera access, so it works without root.
+    pub fn from_video_file(path: &Path) -> Result<Self> {
+        let (width, height) = probe_dimensions(path)?;
+        let mut child = Command::new("ffmpeg")
+            .args(["-v", "error", "-nostdin", "-i"])
+            .arg(path)
+            .args(["-an", "-f", "rawvideo", "-pix_fmt", "rgb24", "-"])
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .spawn()
+            .camera("run ffmpeg")?;
+        let frames = child.stdout.take().camera("ffmpeg stdout")?;
+        Ok(Self {
+            source: Source::File(VideoFile { child, frames }),
+            width,
+            height,
+        })
+    }
+
+    /// The device being read, `None` when playing back a video file
+    pub fn device_info(&self) -> Option<DeviceInfo> {
+        match &self.source {
+            Source::Device {
+                path, node, fourcc, ..
+            } => Some(DeviceInfo {
+                path: path.clone(),
+                node: Some(node.clone()),
+                fourcc: fourcc.to_string().trim_end().to_string(),
+                sensor: SensorKind::from_fourcc(*fourcc),
+            }),
+            Source::Gstreamer { name, .. } => Some(DeviceInfo {
+                path: name.clone(),
+                node: None,
+                fourcc: "RGB3".to_string(),
+                sensor: SensorKind::Rgb,
+            }),
+            Source::File(_) => None,
+        }
+    }
+
+    /// The next frame in the driver's queue, which may have been captured
+    /// a few frames ago if the caller was busy
+    #[tracing::instrument(name = "capture", level = "debug", skip_all)]
     pub fn frame(&mut self) -> Result<ImageBuffer<Rgb<u8>, Vec<u8>>> {
-        let (data, meta) = self.stream.next().context("capture frame")?;
-        log::debug!(
-            "captured frame: width={} height={} fourcc={:?} seq={:?} len={}",
-            self.width,
-            self.height,
-            self.fourcc,
-            meta.sequence,
-            data.len()
-        );
-        let buf = match self.fourcc {
-            f if f == FourCC::new(b"RGB3") => data.to_vec(),
-            f if f == FourCC::new(b"YUYV") => yuyv_to_rgb(self.width, self.height, &data)?,
-            f if f == FourCC::new(b"GREY") => grey_to_rgb(self.width, self.height, &data)?,
-            other => {
-                log::warn!(
+        self.read(false)
+    }
+
+    /// The most recent frame: any older ones queued up while the caller was
+    /// busy, e.g. before the user sat down or during a slow detection, are
+    /// skipped. Blocks only if no frame is ready.
+    #[tracing::instrument(name = "capture", level = "debug", skip_all)]
+    pub fn latest_frame(&mut self) -> Result<RgbImage> {
+        self.read(true)
+    }
+
+    fn read(&mut self, latest: bool) -> Result<RgbImage> {
+        let expected = (self.width * self.height * 3) as usize;
+        // Recycled through `pool::frames()` once the caller is done with the frame
+        let mut buf = pool::frames().take(expected);
+        let converted = match &mut self.source {
+            Source::Device { stream, fourcc, .. } => {
+                convert_frame(stream, *fourcc, self.width, self.height, &mut buf, latest)
+            }
+            Source::File(video) => match video.frames.read_exact(&mut buf) {
+                Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => {
+                    Err(Error::camera("end of video"))
+                }
+                result => result.camera("read video frame"),
+            },
+            Source::Gstreamer { name, frames, .. } => match frames.frames.read_exact(&mut buf) {
+                Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => Err(Error::camera(
+                    format!("GStreamer pipeline of {:?} ended", name),
+                )),
+                result => result.camera("read GStreamer frame"),
+            },
+        };
+        if let Err(e) = converted {
+            pool::frames().recycle(buf);
+            return Err(e);
+        }
+
+        Ok(ImageBuffer::from_raw(self.width, self.height, buf)
+            .ok_or_else(|| Error::camera("failed to build image buffer"))?)
+    }
+}
+
+/// Anything [`Pipeline::run_stream`](crate::Pipeline::run_stream) can read
+/// frames from
+pub trait FrameSource {
+    fn frame(&mut self) -> Result<RgbImage>;
+}
+
+/// Streams always want the newest frame
+impl FrameSource for Camera {
+    fn frame(&mut self) -> Result<RgbImage> {
+        self.latest_frame()
+    }
+}
+
+/// Failed reads in a row after which [`CameraManager`] reopens the device;
+/// a single timeout or corrupt buffer isn't worth it
+const REOPEN_AFTER: u32 = 3;
+/// First wait before reopening a device that failed to open
+const MIN_BACKOFF: Duration = Duration::from_millis(50);
+const MAX_BACKOFF: Duration = Duration::from_millis(800);
+
+/// A camera device that is closed and reopened, with backoff, when it stops
+/// delivering frames, so a USB reset or a brief disconnect (common on IR
+/// modules) costs a few frames rather than the whole authentication window
+pub struct CameraManager {
+    device: String,
+    settings: CaptureSettings,
+    camera: Option<Camera>,
+    info: Option<DeviceInfo>,
+    /// Reopening stops being attempted past this
+    deadline: Instant,
+    /// Reads that failed in a row on the open device
+    failures: u32,
+    /// Wait before the next reopen attempt, doubled by each failed one
+    backoff: Duration,
+    retry_at: Instant,
+    /// Copies of the frames returned, see [`Self::with_history`]
+    history: FrameRing,
+}
+
+impl CameraManager {
+    /// Open `device` like [`Camera::open_until`], keeping it usable until
+    /// `deadline`
+    pub fn open_until(device: &str, deadline: Instant, settings: &CaptureSettings) -> Result<Self> {
+        let camera = Camera::open_until(device, deadline, settings)?;
+        Ok(Self {
+            device: device.to_string(),
+            settings: *settings,
+            info: camera.device_info(),
+            camera: Some(camera),
+            deadline,
+            failures: 0,
+            backoff: MIN_BACKOFF,
+            retry_at: Instant::now(),
+            history: FrameRing::default(),
+        })
+    }
+
+    /// Keep copies of the last `frames` frames returned, with the time each
+    /// was read, in [`Self::history`]. They survive the device being
+    /// reopened.
+    pub fn with_history(mut self, frames: usize) -> Self {
+        self.history = FrameRing::new(frames);
+        self
+    }
+
+    /// Recent frames, empty unless asked for with [`Self::with_history`]
+    pub fn history(&self) -> &FrameRing {
+        &self.history
+    }
+
+    /// The device as first opened
+    pub fn device_info(&self) -> Option<DeviceInfo> {
+        self.info.clone()
+    }
+
+    /// [`Camera::latest_frame`]. After [`REOPEN_AFTER`] failed reads the
+    /// device is closed, releasing it, and the following calls try to open it
+    /// again, waiting longer after each failed attempt.
+    pub fn frame(&mut self) -> Result<RgbImage> {
+        let camera = match &mut self.camera {
+            Some(camera) => camera,
+            None => self.reopen()?,
+        };
+        match camera.latest_frame() {
+            Ok(frame) => {
+                self.failures = 0;
+                self.history.push(&frame, Instant::now());
+                Ok(frame)
+            }
+            Err(e) => {
+                self.failures += 1;
+                if self.failures >= REOPEN_AFTER {
+                    tracing::warn!(
+                        "camera {} stopped delivering frames: {}",
+                        self.device,
+                        Report(&e)
+                    );
+                    self.camera = None;
+                    self.failures = 0;
+                    self.backoff = MIN_BACKOFF;
+                    self.retry_at = Instant::now();
+                }
+                Err(e)
+            }
+        }
+    }
+
+    fn reopen(&mut self) -> Result<&mut Camera> {
+        let wait = self.retry_at.min(self.deadline);
+        std::thread::sleep(wait.saturating_duration_since(Instant::now()));
+        if Instant::now() >= self.deadline {
+            return Err(Error::camera(format!("camera {} is gone", self.device)));
+        }
+        match Camera::open_until(&self.device, Instant::now(), &self.settings) {
+            Ok(camera) => {
+                tracing::info!("camera {} reopened", self.device);
+                Ok(self.camera.insert(camera))
+            }
+            Err(e) => {
+                self.retry_at = Instant::now() + self.backoff;
+                self.backoff = next_backoff(self.backoff);
+                Err(e.context(format!("reopening camera {}", self.device)))
+            }
+        }
+    }
+}
+
+impl FrameSource for CameraManager {
+    fn frame(&mut self) -> Result<RgbImage> {
+        CameraManager::frame(self)
+    }
+}
+
+fn next_backoff(backoff: Duration) -> Duration {
+    (backoff * 2).min(MAX_BACKOFF)
+}
+
+/// A V4L2 output device such as v4l2loopback, fed RGB frames with `write()`
+/// so any video player can show them
+pub struct Loopback {
+    _device: Device,
+    file: File,
+    width: u32,
+    height: u32,
+}
+
+impl Loopback {
+    /// Open `path` and set it to `width`x`height` RGB24
+    pub fn open(path: &Path, width: u32, height: u32) -> Result<Self> {
+        let device = Device::with_path(path).camera("open loopback device")?;
+        let format = Format::new(width, height, FourCC::new(b"RGB3"));
+        v4l::video::Output::set_format(&device, &format).camera("set loopback format")?;
+        let file = std::fs::OpenOptions::new()
+            .write(true)
+            .open(path)
+            .camera(format!("open {}", path.display()))?;
+        Ok(Self {
+            _device: device,
+            file,
+            width,
+            height,
+        })
+    }
+
+    pub fn write(&mut self, frame: &RgbImage) -> Result<()> {
+        if frame.dimensions() != (self.width, self.height) {
+            return Err(Error::camera(format!(
+                "frame is {}x{}, loopback was set up for {}x{}",
+                frame.width(),
+                frame.height(),
+                self.width,
+                self.height
+            )));
+        }
+        self.file
+            .write_all(frame.as_raw())
+            .camera("write loopback frame")
+    }
+}
+
+/// The device node `device` names: itself, the target of a link such as
+/// `/dev/v4l/by-id/...`, for `usb:VID:PID[:N]` the `N`th (from 0) capture
+/// node of that USB camera (cameras with an IR and a color sensor often have
+/// both behind one ID), or the node [`AUTO_CAMERA`] picks.
+pub fn resolve_device(device: &str) -> Result<String> {
+    let Some(prefer) = device.strip_prefix(AUTO_CAMERA) else {
+        return resolve_name(device);
+    };
+    let prefer = match prefer {
+        "" | ":ir" => SensorKind::Ir,
+        ":rgb" => SensorKind::Rgb,
+        _ => {
+            return Err(Error::camera(format!(
+                "camera {} is not auto, auto:ir or auto:rgb",
+                device
+            )))
+        }
+    };
+    let nodes: Vec<_> = capture_nodes(Path::new(SYSFS_VIDEO))?
+        .into_iter()
+        .map(|name| {
+            let sensor = probe_sensor(&dev_path(&name));
+            (name, sensor)
+        })
+        .collect();
+    let node = pick_node(&nodes, prefer).camera("no camera connected")?;
+    tracing::debug!("camera {} picked {} among {:?}", device, node, nodes);
+    Ok(dev_path(node))
+}
+
+/// [`resolve_device`] for names that don't need the devices opened
+fn resolve_name(device: &str) -> Result<String> {
+    if let Some(spec) = device.strip_prefix(USB_PREFIX) {
+        return Ok(dev_path(&find_usb_device(Path::new(SYSFS_VIDEO), spec)?));
+    }
+    if device.starts_with('/') {
+        if let Ok(path) = std::fs::canonicalize(device) {
+            return Ok(path.to_string_lossy().into_owned());
+        }
+    }
+    Ok(device.to_string())
+}
+
+fn dev_path(node: &str) -> String {
+    format!("/dev/{}", node)
+}
+
+/// Capture nodes (`videoN`) listed under `sysfs`, in order. Nodes other than
+/// the first of each interface carry metadata.
+fn capture_nodes(sysfs: &Path) -> Result<Vec<String>> {
+    let mut nodes: Vec<(u32, String)> = std::fs::read_dir(sysfs)
+        .camera(format!("list {}", sysfs.display()))?
+        .filter_map(|entry| {
+            let name = entry.ok()?.file_name().into_string().ok()?;
+            let n = name.strip_prefix("video")?.parse().ok()?;
+            Some((n, name))
+        })
+        .filter(|(_, name)| read_attribute(&sysfs.join(name).join("index")).is_ok_and(|i| i == "0"))
+        .collect();
+    nodes.sort();
+    Ok(nodes.into_iter().map(|(_, name)| name).collect())
+}
+
+fn read_attribute(path: &Path) -> std::io::Result<String> {
+    std::fs::read_to_string(path).map(|s| s.trim().to_lowercase())
+}
+
+/// Name (`videoN`) of the node matching `VID:PID[:N]` under `sysfs`
+fn find_usb_device(sysfs: &Path, spec: &str) -> Result<String> {
+    let mut parts = spec.split(':');
+    let (Some(vendor), Some(product)) = (parts.next(), parts.next()) else {
+        return Err(Error::camera(format!(
+            "camera {}{} is not VID:PID[:N]",
+            USB_PREFIX, spec
+        )));
+    };
+    let nth: usize = match parts.next() {
+        Some(n) => n
+            .parse()
+            .camera(format!("camera {}{}: bad index", USB_PREFIX, spec))?,
+        None => 0,
+    };
+    capture_nodes(sysfs)?
+        .into_iter()
+        .filter(|name| {
+            // `device` is the USB interface; its parent has the IDs
+            let usb = sysfs.join(name).join("device/..");
+            read_attribute(&usb.join("idVendor")).is_ok_and(|v| v == vendor.to_lowercase())
+                && read_attribute(&usb.join("idProduct")).is_ok_and(|p| p == product.to_lowercase())
+        })
+        .nth(nth)
+        .camera(format!("no camera {}{} connected", USB_PREFIX, spec))
+}
+
+/// IR if the device at `path` only offers greyscale formats, `None` if it
+/// can't be queried
+fn probe_sensor(path: &str) -> Option<SensorKind> {
+    let formats = Device::with_path(path).ok()?.enum_formats().ok()?;
+    if formats.is_empty() {
+        return None;
+    }
+    let ir = formats
+        .iter()
+        .all(|format| SensorKind::from_fourcc(format.fourcc) == SensorKind::Ir);
+    Some(if ir { SensorKind::Ir } else { SensorKind::Rgb })
+}
+
+/// The first node with the `prefer`red sensor, else the first usable one
+fn pick_node(nodes: &[(String, Option<SensorKind>)], prefer: SensorKind) -> Option<&str> {
+    nodes
+        .iter()
+        .find(|(_, sensor)| *sensor == Some(prefer))
+        .or_else(|| nodes.iter().find(|(_, sensor)| sensor.is_some()))
+        .map(|(name, _)| name.as_str())
+}
+
+/// `(width, height)` of the first video stream in `path`
+fn probe_dimensions(path: &Path) -> Result<(u32, u32)> {
+    let output = Command::new("ffprobe")
+        .args(["-v", "error", "-select_streams", "v:0"])
+        .args(["-show_entries", "stream=width,height", "-of", "csv=s=x:p=0"])
+        .arg(path)
+        .output()
+        .camera("run ffprobe")?;
+    if !output.status.success() {
+        return Err(Error::camera(format!(
+            "ffprobe {}: {}",
+            path.display(),
+            String::from_utf8_lossy(&output.stderr).trim()
+        )));
+    }
+    parse_dimensions(&String::from_utf8_lossy(&output.stdout))
+        .camera(format!("no video stream in {}", path.display()))
+}
+
+fn parse_dimensions(probe: &str) -> Option<(u32, u32)> {
+    let (width, height) = probe.lines().next()?.trim().split_once('x')?;
+    Some((width.parse().ok()?, height.parse().ok()?))
+}
+
+/// Most queued frames [`Camera::latest_frame`] skips, in case the camera
+/// fills buffers as fast as they are dequeued
+const MAX_STALE: usize = 8;
+
+/// Dequeue a frame into `buf` as RGB. With `latest`, frames are dequeued
+/// until none is waiting and only the last one is converted.
+fn convert_frame(
+    stream: &mut DeviceStream,
+    fourcc: FourCC,
+    width: u32,
+    height: u32,
+    buf: &mut [u8],
+    latest: bool,
+) -> Result<()> {
+    let handle = stream.handle();
+    let mut skipped = 0;
+    let (data, meta) = loop {
+        let (data, meta) = stream.next().camera("capture frame")?;
+        // A newer frame is already waiting: this one is stale
+        if latest && skipped < MAX_STALE && handle.poll(libc::POLLIN, 0).camera("poll camera")? > 0
+        {
+            skipped += 1;
+            continue;
+        }
+        break (data, meta);
+    };
+    tracing::debug!(
+        "captured frame: width={} height={} fourcc={:?} seq={:?} len={} skipped={}",
+        width,
+        height,
+        fourcc,
+        meta.sequence,
+        data.len(),
+        skipped
+    );
+    match fourcc {
+        f if f == FourCC::new(b"RGB3") => copy_rgb(data, buf),
+        f if f == FourCC::new(b"YUYV") => yuyv_to_rgb(width, height, data, buf),
+        f if f == FourCC::new(b"GREY") => grey_to_rgb(width, height, data, buf),
+        other => match GreyDepth::from_fourcc(other) {
+            Some(depth) => deep_grey_to_rgb(depth, width, height, data, buf),
+            None => {
+                tracing::warn!(
                     "unexpected pixel format {:?}, passing through raw len={}",
                     other,
                     data.len()
                 );
-                data.to_vec()
+                copy_rgb(data, buf)
             }
+        },
+    }
+}
+
+fn copy_rgb(data: &[u8], out: &mut [u8]) -> Result<()> {
+    if data.len() < out.len() {
+        tracing::error!(
+            "buffer too small: got {}, expected {}",
+            data.len(),
+            out.len()
+        );
+        return Err(Error::camera("buffer too small"));
+    } else if data.len() > out.len() {
+        tracing::warn!(
+            "buffer larger than expected ({} > {}), truncating",
+            data.len(),
+            out.len()
+        );
+    }
+    out.copy_from_slice(&data[..out.len()]);
+    Ok(())
+}
+
+/// Fraction bits of the fixed-point [`YuvTables`]
+const YUV_SHIFT: u32 = 14;
+
+/// BT.601 chroma terms for every chroma byte, scaled by `1 << YUV_SHIFT`, so
+/// a pixel costs four lookups and integer adds rather than float math
+struct YuvTables {
+    r_v: [i32; 256],
+    g_u: [i32; 256],
+    g_v: [i32; 256],
+    b_u: [i32; 256],
+}
+
+fn yuv_tables() -> &'static YuvTables {
+    static TABLES: OnceLock<YuvTables> = OnceLock::new();
+    TABLES.get_or_init(|| {
+        let term = |coefficient: f32, c: usize| {
+            (coefficient * (c as f32 - 128.0) * (1 << YUV_SHIFT) as f32).round() as i32
         };
-        let expected = (self.width * self.height * 3) as usize;
-        if buf.len() < expected {
-            log::error!(
-                "buffer too small: got {}, expected {} (fourcc {:?})",
-                buf.len(),
-                expected,
-                self.fourcc
-            );
-            return Err(anyhow::anyhow!("buffer too small"));
-        } else if buf.len() > expected {
-            log::warn!(
-                "buffer larger than expected ({} > {}), truncating",
-                buf.len(),
-                expected
-            );
+        YuvTables {
+            r_v: std::array::from_fn(|c| term(1.402, c)),
+            g_u: std::array::from_fn(|c| term(-0.344136, c)),
+            g_v: std::array::from_fn(|c| term(-0.714136, c)),
+            b_u: std::array::from_fn(|c| term(1.772, c)),
         }
-        Ok(ImageBuffer::from_raw(self.width, self.height, buf)
-            .ok_or_else(|| anyhow::anyhow!("failed to build image buffer"))?)
-    }
+    })
 }
 
-fn yuyv_to_rgb(width: u32, height: u32, data: &[u8]) -> Result<Vec<u8>> {
+fn yuyv_to_rgb(width: u32, height: u32, data: &[u8], out: &mut [u8]) -> Result<()> {
     let expected = (width * height * 2) as usize;
     if data.len() < expected {
-        return Err(anyhow::anyhow!("short YUYV buffer"));
+        return Err(Error::camera("short YUYV buffer"));
     }
-    let mut out = Vec::with_capacity((width * height * 3) as usize);
-    let mut chunks = data.chunks_exact(4);
-    while let Some(chunk) = chunks.next() {
-        let y0 = chunk[0] as f32;
-        let u = chunk[1] as f32 - 128.0;
-        let y1 = chunk[2] as f32;
-        let v = chunk[3] as f32 - 128.0;
-        for &y in &[y0, y1] {
-            let r = y + 1.402 * v;
-            let g = y - 0.344136 * u - 0.714136 * v;
-            let b = y + 1.772 * u;
-            out.push(clamp(r));
-            out.push(clamp(g));
-            out.push(clamp(b));
+    let tables = yuv_tables();
+    let channel = |v: i32| (v >> YUV_SHIFT).clamp(0, 255) as u8;
+    // Two pixels share each chroma pair
+    for (chunk, px) in data[..expected]
+        .chunks_exact(4)
+        .zip(out.chunks_exact_mut(6))
+    {
+        let (u, v) = (chunk[1] as usize, chunk[3] as usize);
+        let (r, g, b) = (tables.r_v[v], tables.g_u[u] + tables.g_v[v], tables.b_u[u]);
+        for (&y, rgb) in [chunk[0], chunk[2]].iter().zip(px.chunks_exact_mut(3)) {
+            let y = (y as i32) << YUV_SHIFT;
+            rgb[0] = channel(y + r);
+            rgb[1] = channel(y + g);
+            rgb[2] = channel(y + b);
         }
     }
-    Ok(out)
+    Ok(())
 }
 
 fn clamp(v: f32) -> u8 {
     v.max(0.0).min(255.0) as u8
 }
 
-fn grey_to_rgb(width: u32, height: u32, data: &[u8]) -> Result<Vec<u8>> {
+fn grey_to_rgb(width: u32, height: u32, data: &[u8], out: &mut [u8]) -> Result<()> {
     let expected = (width * height) as usize;
     if data.len() < expected {
-        return Err(anyhow::anyhow!("short GREY buffer"));
+        return Err(Error::camera("short GREY buffer"));
+    }
+    for (&y, rgb) in data.iter().take(expected).zip(out.chunks_exact_mut(3)) {
+        rgb.fill(y);
+    }
+    Ok(())
+}
+
+/// Layout of a grayscale format with more than 8 bits per pixel, as sent by
+/// some IR sensors
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum GreyDepth {
+    /// One little-endian 16-bit word per pixel with this many significant bits
+    /// (`Y10 `, `Y12 `, `Y16 `)
+    Unpacked(u32),
+    /// MIPI CSI-2 packing, 4 pixels in 5 bytes (`Y10P`)
+    Packed10,
+    /// MIPI CSI-2 packing, 2 pixels in 3 bytes (`Y12P`)
+    Packed12,
+}
+
+impl GreyDepth {
+    fn from_fourcc(fourcc: FourCC) -> Option<Self> {
+        Some(match &fourcc.repr {
+            b"Y10 " => GreyDepth::Unpacked(10),
+            b"Y12 " => GreyDepth::Unpacked(12),
+            b"Y16 " => GreyDepth::Unpacked(16),
+            b"Y10P" => GreyDepth::Packed10,
+            b"Y12P" => GreyDepth::Packed12,
+            _ => return None,
+        })
+    }
+
+    fn bits(self) -> u32 {
+        match self {
+            GreyDepth::Unpacked(bits) => bits,
+            GreyDepth::Packed10 => 10,
+            GreyDepth::Packed12 => 12,
+        }
+    }
+
+    /// Bytes holding `pixels` pixels
+    fn frame_len(self, pixels: usize) -> usize {
+        match self {
+            GreyDepth::Unpacked(_) => pixels * 2,
+            GreyDepth::Packed10 => pixels.div_ceil(4) * 5,
+            GreyDepth::Packed12 => pixels.div_ceil(2) * 3,
+        }
+    }
+
+    /// Unpack `data` into one sample per pixel
+    fn unpack(self, data: &[u8], samples: &mut [u16]) {
+        match self {
+            GreyDepth::Unpacked(bits) => {
+                let mask = (1u32 << bits) - 1;
+                for (b, s) in data.chunks_exact(2).zip(samples.iter_mut()) {
+                    *s = (u16::from_le_bytes([b[0], b[1]]) as u32 & mask) as u16;
+                }
+            }
+            GreyDepth::Packed10 => {
+                for (b, px) in data.chunks_exact(5).zip(samples.chunks_mut(4)) {
+                    for (i, s) in px.iter_mut().enumerate() {
+                        *s = (b[i] as u16) << 2 | (b[4] as u16 >> (2 * i)) & 0b11;
+                    }
+                }
+            }
+            GreyDepth::Packed12 => {
+                for (b, px) in data.chunks_exact(3).zip(samples.chunks_mut(2)) {
+                    px[0] = (b[0] as u16) << 4 | b[2] as u16 & 0xf;
+                    if let Some(s) = px.get_mut(1) {
+                        *s = (b[1] as u16) << 4 | b[2] as u16 >> 4;
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Fraction of pixels clipped at each end by the contrast stretch, so a few
+/// hot or dead pixels don't decide the range
+const STRETCH_CLIP: f32 = 0.005;
+
+/// Convert a high bit depth grayscale frame to 8-bit RGB.
+///
+/// IR sensors rarely use their full range, so a plain shift down to 8 bits
+/// leaves a dark, flat image. Instead the range actually present in the frame
+/// (minus [`STRETCH_CLIP`] at each end) is stretched over 0-255.
+fn deep_grey_to_rgb(
+    depth: GreyDepth,
+    width: u32,
+    height: u32,
+    data: &[u8],
+    out: &mut [u8],
+) -> Result<()> {
+    let pixels = (width * height) as usize;
+    if data.len() < depth.frame_len(pixels) {
+        return Err(Error::camera(format!(
+            "short {}-bit grey buffer",
+            depth.bits()
+        )));
+    }
+    let mut samples = vec![0u16; pixels];
+    depth.unpack(data, &mut samples);
+
+    let (low, high) = stretch_range(&samples, depth.bits());
+    let scale = 255.0 / (high - low).max(1) as f32;
+    for (&s, rgb) in samples.iter().zip(out.chunks_exact_mut(3)) {
+        rgb.fill(clamp((s.saturating_sub(low)) as f32 * scale));
+    }
+    Ok(())
+}
+
+/// Sample values bounding all but [`STRETCH_CLIP`] of the pixels at each end
+fn stretch_range(samples: &[u16], bits: u32) -> (u16, u16) {
+    // 1024 bins are plenty to place the bounds and keep the histogram small
+    let shift = bits.saturating_sub(10);
+    let mut histogram = [0u32; 1024];
+    for &s in samples {
+        histogram[(s >> shift) as usize] += 1;
+    }
+
+    let clip = (samples.len() as f32 * STRETCH_CLIP) as u32;
+    let bound = |bins: &mut dyn Iterator<Item = usize>| {
+        let mut seen = 0;
+        for i in bins {
+            seen += histogram[i];
+            if seen > clip {
+                return i;
+            }
+        }
+        0
+    };
+    let low = bound(&mut (0..1024));
+    let high = bound(&mut (0..1024).rev()).max(low);
+    ((low << shift) as u16, (((high + 1) << shift) - 1) as u16)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sensor_from_fourcc() {
+        assert_eq!(
+            SensorKind::from_fourcc(FourCC::new(b"GREY")),
+            SensorKind::Ir
+        );
+        assert_eq!(
+            SensorKind::from_fourcc(FourCC::new(b"Y10P")),
+            SensorKind::Ir
+        );
+        assert_eq!(
+            SensorKind::from_fourcc(FourCC::new(b"YUYV")),
+            SensorKind::Rgb
+        );
+        assert_eq!(
+            SensorKind::from_fourcc(FourCC::new(b"MJPG")),
+            SensorKind::Rgb
+        );
+    }
+
+    #[test]
+    fn test_camera_lock_queues() {
+        // Only root can lock before packaging has set up the directory
+        if lock_dir().is_err() {
+            return;
+        }
+        let device = format!("/dev/howrs-test-{}", std::process::id());
+        let first = CameraLock::acquire(&device, Instant::now()).unwrap();
+
+        // Held by someone else: a zero deadline gives up immediately
+        assert!(CameraLock::acquire(&device, Instant::now()).is_err());
+
+        // Released before the deadline: the waiting request gets it
+        let queued = device.clone();
+        let waiter = std::thread::spawn(move || {
+            CameraLock::acquire(&queued, Instant::now() + Duration::from_secs(5)).is_ok()
+        });
+        std::thread::sleep(Duration::from_millis(200));
+        // The holder can tell someone is queued
+        assert!(CameraLock::contended(&device));
+        drop(first);
+        assert!(waiter.join().unwrap());
+        assert!(!CameraLock::contended(&device));
+
+        let _ = std::fs::remove_file(lock_path(&device, "lock").unwrap());
+        let _ = std::fs::remove_file(lock_path(&device, "wait").unwrap());
+    }
+
+    #[test]
+    fn test_lock_file_not_followed() {
+        let dir = std::env::temp_dir().join(format!("howrs-test-lock-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        let target = dir.join("target");
+        std::fs::write(&target, "").unwrap();
+        std::fs::set_permissions(&target, std::fs::Permissions::from_mode(0o600)).unwrap();
+
+        // A link in place of the lock is refused, and its target left alone
+        let link = dir.join("howrs-video0.lock");
+        std::os::unix::fs::symlink(&target, &link).unwrap();
+        assert!(open_lock_file(&link).is_err());
+        let mode = std::fs::metadata(&target).unwrap().mode();
+        assert_eq!(mode & 0o777, 0o600);
+
+        std::fs::remove_file(&link).unwrap();
+        let file = open_lock_file(&link).unwrap();
+        assert_eq!(file.metadata().unwrap().mode() & 0o777, LOCK_MODE);
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_find_usb_device() {
+        let root = std::env::temp_dir().join(format!("howrs-test-sysfs-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&root);
+        let class = root.join("class");
+        // One camera with a color and an IR interface, each with a metadata
+        // node, and another camera
+        for (node, usb, interface, index) in [
+            ("video0", "1-1", "1-1:1.0", "0"),
+            ("video1", "1-1", "1-1:1.0", "1"),
+            ("video2", "1-1", "1-1:1.2", "0"),
+            ("video3", "1-1", "1-1:1.2", "1"),
+            ("video4", "2-1", "2-1:1.0", "0"),
+        ] {
+            let device = root.join("devices").join(usb);
+            std::fs::create_dir_all(device.join(interface)).unwrap();
+            let (vendor, product) = if usb == "1-1" {
+                ("04f2", "b6d0")
+            } else {
+                ("046d", "085c")
+            };
+            std::fs::write(device.join("idVendor"), format!("{}\n", vendor)).unwrap();
+            std::fs::write(device.join("idProduct"), format!("{}\n", product)).unwrap();
+            std::fs::create_dir_all(class.join(node)).unwrap();
+            std::fs::write(class.join(node).join("index"), index).unwrap();
+            std::os::unix::fs::symlink(device.join(interface), class.join(node).join("device"))
+                .unwrap();
+        }
+
+        assert_eq!(find_usb_device(&class, "04f2:b6d0").unwrap(), "video0");
+        assert_eq!(find_usb_device(&class, "04F2:B6D0:1").unwrap(), "video2");
+        assert_eq!(find_usb_device(&class, "046d:085c").unwrap(), "video4");
+        assert!(find_usb_device(&class, "04f2:b6d0:2").is_err());
+        assert!(find_usb_device(&class, "04f2").is_err());
+        assert_eq!(
+            capture_nodes(&class).unwrap(),
+            ["video0", "video2", "video4"]
+        );
+        let _ = std::fs::remove_dir_all(&root);
+    }
+
+    #[test]
+    fn test_pick_node() {
+        let nodes = [
+            ("video0".to_string(), Some(SensorKind::Rgb)),
+            ("video2".to_string(), Some(SensorKind::Ir)),
+            ("video4".to_string(), None),
+        ];
+        assert_eq!(pick_node(&nodes, SensorKind::Ir), Some("video2"));
+        assert_eq!(pick_node(&nodes, SensorKind::Rgb), Some("video0"));
+        assert_eq!(pick_node(&nodes[..1], SensorKind::Ir), Some("video0"));
+        assert_eq!(pick_node(&nodes[2..], SensorKind::Ir), None);
+    }
+
+    #[test]
+    fn test_gst_lock_path() {
+        let pipeline = format!(
+            "gst:videotestsrc pattern={} ! videoconvert",
+            "x".repeat(400)
+        );
+        let name = lock_name(&pipeline, "lock");
+        assert!(name.starts_with("howrs-gst-videotestsrc-pattern-"));
+        assert!(name.len() < 255);
+    }
+
+    #[test]
+    fn test_runs_gstreamer() {
+        let v4l2 = CaptureSettings::default();
+        let pipewire = CaptureSettings {
+            backend: CaptureBackend::Pipewire,
+            ..v4l2
+        };
+        assert!(!runs_gstreamer("/dev/video0", &v4l2));
+        assert!(runs_gstreamer("gst:videotestsrc", &v4l2));
+        assert!(runs_gstreamer("Integrated Camera", &pipewire));
+    }
+
+    #[test]
+    fn test_camera_manager() {
+        let mut backoff = MIN_BACKOFF;
+        for _ in 0..10 {
+            backoff = next_backoff(backoff);
+        }
+        assert_eq!(backoff, MAX_BACKOFF);
+
+        assert!(CameraManager::open_until(
+            "/dev/howrs-no-such-camera",
+            Instant::now(),
+            &CaptureSettings::default()
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn test_parse_dimensions() {
+        assert_eq!(parse_dimensions("640x480\n"), Some((640, 480)));
+        assert_eq!(parse_dimensions(""), None);
+        assert_eq!(parse_dimensions("N/Ax480\n"), None);
+    }
+
+    #[test]
+    fn test_convert_into_buffer() {
+        // Two pixels of mid grey with neutral chroma
+        let mut out = [0u8; 6];
+        yuyv_to_rgb(2, 1, &[128, 128, 128, 128], &mut out).unwrap();
+        assert_eq!(out, [128; 6]);
+
+        grey_to_rgb(2, 1, &[10, 20], &mut out).unwrap();
+        assert_eq!(out, [10, 10, 10, 20, 20, 20]);
+
+        assert!(yuyv_to_rgb(2, 2, &[0; 4], &mut [0; 12]).is_err());
+        assert!(copy_rgb(&[0; 5], &mut out).is_err());
     }
-    let mut out = Vec::with_capacity((width * height * 3) as usize);
-    for &y in data.iter().take(expected) {
-        out.push(y);
-        out.push(y);
-        out.push(y);
+
+    /// The float conversion [`yuyv_to_rgb`] replaced
+    fn yuyv_to_rgb_scalar(y: u8, u: u8, v: u8) -> [u8; 3] {
+        let (y, u, v) = (y as f32, u as f32 - 128.0, v as f32 - 128.0);
+        [
+            clamp(y + 1.402 * v),
+            clamp(y - 0.344136 * u - 0.714136 * v),
+            clamp(y + 1.772 * u),
+        ]
+    }
+
+    #[test]
+    fn test_yuyv_matches_scalar() {
+        let mut worst = 0;
+        let mut out = [0u8; 6];
+        for y in (0..=255u8).step_by(3) {
+            for u in (0..=255u8).step_by(5) {
+                for v in (0..=255u8).step_by(5) {
+                    yuyv_to_rgb(2, 1, &[y, u, y, v], &mut out).unwrap();
+                    let expected = yuyv_to_rgb_scalar(y, u, v);
+                    for (a, b) in out[..3].iter().zip(expected) {
+                        worst = worst.max(a.abs_diff(b));
+                    }
+                    assert_eq!(out[..3], out[3..]);
+                }
+            }
+        }
+        // Only rounding of values right at a step differs
+        assert!(worst <= 1, "off by {}", worst);
+    }
+
+    #[test]
+    fn test_unpack_grey_depth() {
+        let fourcc = |s: &[u8; 4]| GreyDepth::from_fourcc(FourCC::new(s));
+        assert_eq!(fourcc(b"Y10 "), Some(GreyDepth::Unpacked(10)));
+        assert_eq!(fourcc(b"GREY"), None);
+
+        let mut samples = [0u16; 4];
+        GreyDepth::Unpacked(10).unpack(&[0xff, 0x03, 0x00, 0x02, 0, 0, 0, 0], &mut samples);
+        assert_eq!(samples[..2], [1023, 512]);
+
+        // MSBs 0x80, 0x40, 0x00, 0xff; LSBs 3, 0, 1, 2
+        GreyDepth::Packed10.unpack(&[0x80, 0x40, 0x00, 0xff, 0b10_01_00_11], &mut samples);
+        assert_eq!(samples, [515, 256, 1, 1022]);
+
+        GreyDepth::Packed12.unpack(&[0xab, 0xcd, 0x21], &mut samples[..2]);
+        assert_eq!(samples[..2], [0xab1, 0xcd2]);
+    }
+
+    #[test]
+    fn test_deep_grey_stretch() {
+        // A dim 10-bit frame using only 100..=400 fills the 8-bit range
+        let values: Vec<u16> = (0..4).map(|i| 100 + i * 100).collect();
+        let data: Vec<u8> = values.iter().flat_map(|v| v.to_le_bytes()).collect();
+        let mut out = [0u8; 12];
+        deep_grey_to_rgb(GreyDepth::Unpacked(10), 4, 1, &data, &mut out).unwrap();
+        assert_eq!(out[0], 0);
+        assert_eq!(out[9], 255);
+        assert!(out[3] > 60 && out[3] < out[6]);
+
+        // Flat frames don't divide by zero
+        deep_grey_to_rgb(GreyDepth::Unpacked(16), 2, 1, &[0, 1, 0, 1], &mut out[..6]).unwrap();
+
+        assert!(deep_grey_to_rgb(GreyDepth::Packed10, 4, 1, &[0; 4], &mut out).is_err());
     }
-    Ok(out)
 }
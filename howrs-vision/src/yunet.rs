@@ -14,10 +14,13 @@
 //! w = dw * stride / input_size
 //! h = dh * stride / input_size
 
-use anyhow::Result;
+use crate::error::{Error, Result, ResultExt};
+use crate::face::Detection;
 use ndarray::Array2;
 
 const STRIDES: [usize; 3] = [8, 16, 32];
+/// Grid cells decoded at most per image, the highest scoring first
+pub const MAX_CANDIDATES: usize = 1000;
 
 #[derive(Debug, Clone)]
 pub struct RawDetection {
@@ -26,6 +29,19 @@ pub struct RawDetection {
     pub landmarks: [f32; 10], // 5 points: x1,y1,x2,y2,...,x5,y5 (normalized [0,1])
 }
 
+impl RawDetection {
+    /// This detection in pixels of the `size`x`size` canvas it was decoded from
+    pub fn to_pixels(&self, size: u32) -> Detection {
+        let mut d = Detection {
+            bbox: self.bbox,
+            score: self.score,
+            landmarks: self.landmarks,
+        };
+        d.rescale((size as f32, size as f32));
+        d
+    }
+}
+
 /// Decode YuNet output tensors to detection boxes using anchor-free grid-based decoding
 ///
 /// YuNet outputs 3 tensors per scale (stride 8, 16, 32):
@@ -37,6 +53,9 @@ pub struct RawDetection {
 /// - Stride 8:  80x80 grid = 6400 locations
 /// - Stride 16: 40x40 grid = 1600 locations
 /// - Stride 32: 20x20 grid = 400 locations
+///
+/// Only the [`MAX_CANDIDATES`] best scoring locations above
+/// `score_threshold` are decoded.
 pub fn decode_detections(
     cls_scores: Vec<Array2<f32>>,
     bbox_preds: Vec<Array2<f32>>,
@@ -44,88 +63,95 @@ pub fn decode_detections(
     score_threshold: f32,
     input_size: usize,
 ) -> Result<Vec<RawDetection>> {
-    let mut detections = Vec::new();
-
-    // Process each scale (stride)
+    // Find the grid cells above threshold first, so only the best
+    // MAX_CANDIDATES of a noisy frame are decoded
+    let mut candidates = Vec::new();
     for (scale_idx, &stride) in STRIDES.iter().enumerate() {
         let scores = &cls_scores[scale_idx];
-        let bboxes = &bbox_preds[scale_idx];
-        let landmarks = &landmark_preds[scale_idx];
-
         let feature_size = input_size / stride;
         let num_boxes = scores.shape()[0];
 
         // Sanity check
         if num_boxes != feature_size * feature_size {
-            anyhow::bail!(
+            return Err(Error::inference(format!(
                 "Expected {} boxes for stride {} ({}x{} grid), got {}",
                 feature_size * feature_size,
                 stride,
                 feature_size,
                 feature_size,
                 num_boxes
-            );
+            )));
         }
 
-        // Iterate through grid locations
-        for i in 0..feature_size {
-            for j in 0..feature_size {
-                let idx = i * feature_size + j;
-                let score = scores[[idx, 0]];
-
-                // Filter by score threshold
-                if score < score_threshold {
-                    continue;
-                }
-
-                // Get deltas from network
-                let dx = bboxes[[idx, 0]];
-                let dy = bboxes[[idx, 1]];
-                let dw = bboxes[[idx, 2]];
-                let dh = bboxes[[idx, 3]];
-
-                // Anchor-free decoding: directly map from grid to image coordinates
-                // Center point
-                let cx_px = (j as f32 + dx) * stride as f32;
-                let cy_px = (i as f32 + dy) * stride as f32;
-
-                // Width and height (linear, no exp)
-                let w_px = dw * stride as f32;
-                let h_px = dh * stride as f32;
-
-                // Normalize to [0, 1]
-                let cx = cx_px / input_size as f32;
-                let cy = cy_px / input_size as f32;
-                let w = w_px / input_size as f32;
-                let h = h_px / input_size as f32;
-
-                // Convert from center format to corner format (x, y, w, h)
-                let x = cx - w / 2.0;
-                let y = cy - h / 2.0;
-
-                // Decode landmarks similarly (anchor-free, grid-based)
-                let mut lms = [0.0f32; 10];
-                for k in 0..5 {
-                    let lm_dx = landmarks[[idx, k * 2]];
-                    let lm_dy = landmarks[[idx, k * 2 + 1]];
-
-                    // Map from grid to image coordinates
-                    let lm_x_px = (j as f32 + lm_dx) * stride as f32;
-                    let lm_y_px = (i as f32 + lm_dy) * stride as f32;
-
-                    // Normalize to [0, 1]
-                    lms[k * 2] = lm_x_px / input_size as f32;
-                    lms[k * 2 + 1] = lm_y_px / input_size as f32;
-                }
-
-                detections.push(RawDetection {
-                    bbox: [x, y, w, h],
-                    score,
-                    landmarks: lms,
-                });
+        // Filter by score threshold
+        for idx in 0..num_boxes {
+            let score = scores[[idx, 0]];
+            if score >= score_threshold {
+                candidates.push((scale_idx, idx, score));
             }
         }
     }
+    if candidates.len() > MAX_CANDIDATES {
+        candidates.select_nth_unstable_by(MAX_CANDIDATES, |a, b| b.2.total_cmp(&a.2));
+        candidates.truncate(MAX_CANDIDATES);
+        // Back to grid order, as when nothing is dropped
+        candidates.sort_unstable_by_key(|&(scale_idx, idx, _)| (scale_idx, idx));
+    }
+
+    let mut detections = Vec::with_capacity(candidates.len());
+    for (scale_idx, idx, score) in candidates {
+        let stride = STRIDES[scale_idx];
+        let bboxes = &bbox_preds[scale_idx];
+        let landmarks = &landmark_preds[scale_idx];
+        let feature_size = input_size / stride;
+        let (i, j) = (idx / feature_size, idx % feature_size);
+
+        // Get deltas from network
+        let dx = bboxes[[idx, 0]];
+        let dy = bboxes[[idx, 1]];
+        let dw = bboxes[[idx, 2]];
+        let dh = bboxes[[idx, 3]];
+
+        // Anchor-free decoding: directly map from grid to image coordinates
+        // Center point
+        let cx_px = (j as f32 + dx) * stride as f32;
+        let cy_px = (i as f32 + dy) * stride as f32;
+
+        // Width and height (linear, no exp)
+        let w_px = dw * stride as f32;
+        let h_px = dh * stride as f32;
+
+        // Normalize to [0, 1]
+        let cx = cx_px / input_size as f32;
+        let cy = cy_px / input_size as f32;
+        let w = w_px / input_size as f32;
+        let h = h_px / input_size as f32;
+
+        // Convert from center format to corner format (x, y, w, h)
+        let x = cx - w / 2.0;
+        let y = cy - h / 2.0;
+
+        // Decode landmarks similarly (anchor-free, grid-based)
+        let mut lms = [0.0f32; 10];
+        for k in 0..5 {
+            let lm_dx = landmarks[[idx, k * 2]];
+            let lm_dy = landmarks[[idx, k * 2 + 1]];
+
+            // Map from grid to image coordinates
+            let lm_x_px = (j as f32 + lm_dx) * stride as f32;
+            let lm_y_px = (i as f32 + lm_dy) * stride as f32;
+
+            // Normalize to [0, 1]
+            lms[k * 2] = lm_x_px / input_size as f32;
+            lms[k * 2 + 1] = lm_y_px / input_size as f32;
+        }
+
+        detections.push(RawDetection {
+            bbox: [x, y, w, h],
+            score,
+            landmarks: lms,
+        });
+    }
 
     Ok(detections)
 }
@@ -161,27 +187,27 @@ pub fn parse_yunet_outputs(
         if let Some((shape, data)) = outputs.get(idx) {
             // Verify shape
             if shape.len() != 3 || shape[0] != 1 || shape[2] != 1 {
-                anyhow::bail!(
+                return Err(Error::inference(format!(
                     "Unexpected cls shape at index {}: {:?}, expected [1, {}, 1]",
-                    idx,
-                    shape,
-                    expected_count
-                );
+                    idx, shape, expected_count
+                )));
             }
             let actual_count = shape[1] as usize;
             if actual_count != expected_count {
-                anyhow::bail!(
+                return Err(Error::inference(format!(
                     "Expected {} locations for cls at index {}, got {}",
-                    expected_count,
-                    idx,
-                    actual_count
-                );
+                    expected_count, idx, actual_count
+                )));
             }
 
-            let arr = Array2::from_shape_vec((expected_count, 1), data.to_vec())?;
+            let arr = Array2::from_shape_vec((expected_count, 1), data.to_vec())
+                .inference("reshape detector output")?;
             cls_scores_vec.push(arr);
         } else {
-            anyhow::bail!("Missing cls output at index {}", idx);
+            return Err(Error::inference(format!(
+                "Missing cls output at index {}",
+                idx
+            )));
         }
     }
 
@@ -190,18 +216,22 @@ pub fn parse_yunet_outputs(
     for (idx, &expected_count) in expected_counts.iter().enumerate() {
         if let Some((shape, data)) = outputs.get(idx + 3) {
             if shape.len() != 3 || shape[0] != 1 || shape[2] != 1 {
-                anyhow::bail!(
+                return Err(Error::inference(format!(
                     "Unexpected obj shape at index {}: {:?}, expected [1, {}, 1]",
                     idx + 3,
                     shape,
                     expected_count
-                );
+                )));
             }
 
-            let arr = Array2::from_shape_vec((expected_count, 1), data.to_vec())?;
+            let arr = Array2::from_shape_vec((expected_count, 1), data.to_vec())
+                .inference("reshape detector output")?;
             obj_scores_vec.push(arr);
         } else {
-            anyhow::bail!("Missing obj output at index {}", idx + 3);
+            return Err(Error::inference(format!(
+                "Missing obj output at index {}",
+                idx + 3
+            )));
         }
     }
 
@@ -215,18 +245,22 @@ pub fn parse_yunet_outputs(
     for (idx, &expected_count) in expected_counts.iter().enumerate() {
         if let Some((shape, data)) = outputs.get(idx + 6) {
             if shape.len() != 3 || shape[0] != 1 || shape[2] != 4 {
-                anyhow::bail!(
+                return Err(Error::inference(format!(
                     "Unexpected bbox shape at index {}: {:?}, expected [1, {}, 4]",
                     idx + 6,
                     shape,
                     expected_count
-                );
+                )));
             }
 
-            let arr = Array2::from_shape_vec((expected_count, 4), data.to_vec())?;
+            let arr = Array2::from_shape_vec((expected_count, 4), data.to_vec())
+                .inference("reshape detector output")?;
             bbox_preds.push(arr);
         } else {
-            anyhow::bail!("Missing bbox output at index {}", idx + 6);
+            return Err(Error::inference(format!(
+                "Missing bbox output at index {}",
+                idx + 6
+            )));
         }
     }
 
@@ -234,18 +268,22 @@ pub fn parse_yunet_outputs(
     for (idx, &expected_count) in expected_counts.iter().enumerate() {
         if let Some((shape, data)) = outputs.get(idx + 9) {
             if shape.len() != 3 || shape[0] != 1 || shape[2] != 10 {
-                anyhow::bail!(
+                return Err(Error::inference(format!(
                     "Unexpected kps shape at index {}: {:?}, expected [1, {}, 10]",
                     idx + 9,
                     shape,
                     expected_count
-                );
+                )));
             }
 
-            let arr = Array2::from_shape_vec((expected_count, 10), data.to_vec())?;
+            let arr = Array2::from_shape_vec((expected_count, 10), data.to_vec())
+                .inference("reshape detector output")?;
             landmark_preds.push(arr);
         } else {
-            anyhow::bail!("Missing kps output at index {}", idx + 9);
+            return Err(Error::inference(format!(
+                "Missing kps output at index {}",
+                idx + 9
+            )));
         }
     }
 
@@ -352,4 +390,26 @@ mod tests {
         assert!((det.landmarks[0] - 0.5).abs() < 1e-5);
         assert!((det.landmarks[1] - 0.5).abs() < 1e-5);
     }
+
+    #[test]
+    fn test_decode_caps_candidates() {
+        // Every location above threshold, scored by position
+        let input_size = 640;
+        let (mut scores, mut bboxes, mut landmarks) = (Vec::new(), Vec::new(), Vec::new());
+        let mut offset = 0;
+        for stride in STRIDES {
+            let n = (input_size / stride).pow(2);
+            let data = (0..n).map(|i| (offset + i) as f32).collect();
+            offset += n;
+            scores.push(Array2::from_shape_vec((n, 1), data).unwrap());
+            bboxes.push(Array2::zeros((n, 4)));
+            landmarks.push(Array2::zeros((n, 10)));
+        }
+
+        let detections = decode_detections(scores, bboxes, landmarks, 0.0, input_size).unwrap();
+        assert_eq!(detections.len(), MAX_CANDIDATES);
+        let lowest = (offset - MAX_CANDIDATES) as f32;
+        assert!(detections.iter().all(|d| d.score >= lowest));
+        assert!(detections.windows(2).all(|w| w[0].score < w[1].score));
+    }
 }
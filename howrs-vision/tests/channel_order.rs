@@ -32,7 +32,7 @@ fn test_channel_order() -> Result<()> {
     println!("Embedding (RGB order):");
     print!("  First 10: [");
     for i in 0..10 {
-        print!("{:.4}", embedding_rgb.vector[[0, i]]);
+        print!("{:.4}", embedding_rgb.vector()[[0, i]]);
         if i < 9 {
             print!(", ");
         }
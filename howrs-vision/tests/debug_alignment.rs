@@ -142,12 +142,12 @@ fn debug_embedding_variance() -> Result<()> {
         let embedding = face::encode_face(&mut recognizer, &aligned)?;
 
         println!("\n{}", img_path);
-        println!("  Embedding shape: {:?}", embedding.vector.shape());
+        println!("  Embedding shape: {:?}", embedding.vector().shape());
 
         // Print first 10 values
         print!("  First 10 values: [");
-        for i in 0..10.min(embedding.vector.len()) {
-            print!("{:.4}", embedding.vector[[0, i]]);
+        for i in 0..10.min(embedding.vector().len()) {
+            print!("{:.4}", embedding.vector()[[0, i]]);
             if i < 9 {
                 print!(", ");
             }
@@ -155,7 +155,7 @@ fn debug_embedding_variance() -> Result<()> {
         println!("]");
 
         // Compute statistics
-        let values: Vec<f32> = embedding.vector.iter().copied().collect();
+        let values: Vec<f32> = embedding.vector().iter().copied().collect();
         let mean: f32 = values.iter().sum::<f32>() / values.len() as f32;
         let variance: f32 =
             values.iter().map(|x| (x - mean).powi(2)).sum::<f32>() / values.len() as f32;
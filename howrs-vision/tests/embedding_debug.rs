@@ -40,7 +40,7 @@ fn test_embedding_distinctiveness() -> Result<()> {
         println!("{:20} embedding:", name);
 
         // Print full embedding in compact format
-        let vec: Vec<f32> = embedding.vector.iter().copied().collect();
+        let vec: Vec<f32> = embedding.vector().iter().copied().collect();
         println!(
             "  Values: [{:.4}, {:.4}, {:.4}, ..., {:.4}, {:.4}, {:.4}]",
             vec[0],
@@ -81,8 +81,8 @@ fn test_embedding_distinctiveness() -> Result<()> {
     // Compute element-wise correlation between embeddings
     println!("\n=== Element-wise Analysis ===\n");
     if embeddings.len() >= 2 {
-        let emb1 = &embeddings[0].1.vector;
-        let emb2 = &embeddings[2].1.vector; // Different person
+        let emb1 = &embeddings[0].1.vector();
+        let emb2 = &embeddings[2].1.vector(); // Different person
 
         let vec1: Vec<f32> = emb1.iter().copied().collect();
         let vec2: Vec<f32> = emb2.iter().copied().collect();
@@ -16,7 +16,7 @@ fn extract_embedding(
     }
 
     let aligned = face::align_face(&img, &detections[0], 112)?;
-    face::encode_face(recognizer, &aligned)
+    Ok(face::encode_face(recognizer, &aligned)?)
 }
 
 /// Test that embeddings from the same person (eason) have HIGH similarity
@@ -155,13 +155,13 @@ fn test_encoding() -> Result<()> {
 
     // Verify embedding shape
     assert_eq!(
-        embedding.vector.shape(),
+        embedding.vector().shape(),
         &[1, 128],
         "Embedding should be shape [1, 128]"
     );
 
     // Verify embedding is normalized (L2 norm ≈ 1.0)
-    let norm: f32 = embedding.vector.iter().map(|x| x * x).sum::<f32>().sqrt();
+    let norm: f32 = embedding.vector().iter().map(|x| x * x).sum::<f32>().sqrt();
     assert!(
         (norm - 1.0).abs() < 0.01,
         "Embedding should be L2 normalized, got norm={}",
@@ -299,5 +299,5 @@ fn extract_embedding(
     }
 
     let aligned = face::align_face(&img, &detections[0], 112)?;
-    face::encode_face(recognizer, &aligned)
+    Ok(face::encode_face(recognizer, &aligned)?)
 }
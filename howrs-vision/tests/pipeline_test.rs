@@ -1,5 +1,5 @@
 use anyhow::Result;
-use howrs_vision::pipeline::Pipeline;
+use howrs_vision::pipeline::{Pipeline, DEFAULT_NMS_THRESHOLD, DEFAULT_SCORE_THRESHOLD};
 
 #[test]
 fn test_pipeline_initialization() -> Result<()> {
@@ -22,3 +22,16 @@ fn test_pipeline_components() -> Result<()> {
     println!("✓ Pipeline components verified");
     Ok(())
 }
+
+#[test]
+fn test_pipeline_thresholds() -> Result<()> {
+    let pipeline = Pipeline::new()?;
+    assert_eq!(pipeline.score_threshold, DEFAULT_SCORE_THRESHOLD);
+    assert_eq!(pipeline.nms_threshold, DEFAULT_NMS_THRESHOLD);
+
+    // Carried by the pipeline, so every entry point uses the same ones
+    let pipeline = pipeline.with_thresholds(0.4, 0.5);
+    assert_eq!(pipeline.score_threshold, 0.4);
+    assert_eq!(pipeline.nms_threshold, 0.5);
+    Ok(())
+}
@@ -100,10 +100,8 @@ fn test_visual_detection_comparison() -> Result<()> {
         println!("\n#{} - Score: {:.4}", i + 1, det.score);
 
         // Convert to pixel coordinates on 640x640 canvas
-        let x_px = det.bbox[0] * target_size as f32;
-        let y_px = det.bbox[1] * target_size as f32;
-        let w_px = det.bbox[2] * target_size as f32;
-        let h_px = det.bbox[3] * target_size as f32;
+        let on_canvas = det.to_pixels(target_size);
+        let [x_px, y_px, w_px, h_px] = on_canvas.bbox;
 
         println!(
             "  BBox on canvas: x={:.1}, y={:.1}, w={:.1}, h={:.1}",
@@ -122,25 +120,29 @@ fn test_visual_detection_comparison() -> Result<()> {
         // Show landmarks
         println!("  Landmarks (on canvas):");
         let landmark_names = ["Left eye", "Right eye", "Nose", "Left mouth", "Right mouth"];
-        for (j, name) in landmark_names.iter().enumerate() {
-            let lm_x = det.landmarks[j * 2] * target_size as f32;
-            let lm_y = det.landmarks[j * 2 + 1] * target_size as f32;
-            println!("    {}: ({:.1}, {:.1})", name, lm_x, lm_y);
+        for (name, lm) in landmark_names
+            .iter()
+            .zip(on_canvas.landmarks.chunks_exact(2))
+        {
+            println!("    {}: ({:.1}, {:.1})", name, lm[0], lm[1]);
         }
 
         // Convert landmarks to original image coordinates
         println!("  Landmarks (on original image):");
-        for (j, name) in landmark_names.iter().enumerate() {
-            let lm_x = det.landmarks[j * 2] * target_size as f32 - offset_x as f32;
-            let lm_y = det.landmarks[j * 2 + 1] * target_size as f32 - offset_y as f32;
-            println!("    {}: ({:.1}, {:.1})", name, lm_x, lm_y);
+        let mut on_image = on_canvas.clone();
+        on_image.translate((-(offset_x as f32), -(offset_y as f32)));
+        for (name, lm) in landmark_names
+            .iter()
+            .zip(on_image.landmarks.chunks_exact(2))
+        {
+            println!("    {}: ({:.1}, {:.1})", name, lm[0], lm[1]);
         }
 
         // Check landmark geometry
         let left_eye_x = det.landmarks[0];
         let right_eye_x = det.landmarks[2];
         let eye_distance = (right_eye_x - left_eye_x).abs() * target_size as f32;
-        let face_width = det.bbox[2] * target_size as f32;
+        let face_width = w_px;
 
         println!(
             "  Eye distance: {:.1}px ({:.1}% of face width)",
@@ -1,6 +1,6 @@
 use anyhow::Result;
-use howrs_vision::{face, model};
-use image::{DynamicImage, GenericImageView, Rgb, RgbImage};
+use howrs_vision::{draw, face, model};
+use image::{DynamicImage, GenericImageView};
 use std::path::Path;
 
 #[test]
@@ -32,31 +32,18 @@ fn visualize_alignment_process() -> Result<()> {
         det.bbox[0], det.bbox[1], det.bbox[2], det.bbox[3]
     );
 
-    // Draw detection on original image
-    let mut vis_img = img.to_rgb8();
-
-    // Draw landmarks
     for i in 0..5 {
-        let lm_x = det.landmarks[i * 2] as u32;
-        let lm_y = det.landmarks[i * 2 + 1] as u32;
-        // Draw a small cross at landmark position
-        let color = Rgb([255, 0, 0]);
-        for dx in 0..5i32 {
-            for dy in 0..5i32 {
-                let px = (lm_x as i32 + dx - 2).max(0) as u32;
-                let py = (lm_y as i32 + dy - 2).max(0) as u32;
-                if px < vis_img.width() && py < vis_img.height() {
-                    if dx == 2 || dy == 2 {
-                        vis_img.put_pixel(px, py, color);
-                    }
-                }
-            }
-        }
-        println!("  Landmark {}: ({}, {})", i, lm_x, lm_y);
+        println!(
+            "  Landmark {}: ({:.0}, {:.0})",
+            i,
+            det.landmarks[i * 2],
+            det.landmarks[i * 2 + 1]
+        );
     }
+    let vis_img = face::annotate(&img, &detections[..1]);
 
     let output_name = "vis_original_with_detection.png";
-    DynamicImage::ImageRgb8(vis_img).save(output_name)?;
+    vis_img.save(output_name)?;
     println!("\nSaved visualization: {}", output_name);
 
     // Now get aligned face and visualize reference landmarks on it
@@ -73,20 +60,7 @@ fn visualize_alignment_process() -> Result<()> {
     ];
 
     for &(x, y) in &ref_landmarks {
-        let color = Rgb([255, 255, 0]); // yellow
-                                        // Draw a cross
-        for d in 0..7i32 {
-            let px = (x as i32 + d - 3).max(0) as u32;
-            let py = y as u32;
-            if px < 112 {
-                aligned_rgb.put_pixel(px, py, color);
-            }
-            let px = x as u32;
-            let py = (y as i32 + d - 3).max(0) as u32;
-            if py < 112 {
-                aligned_rgb.put_pixel(px, py, color);
-            }
-        }
+        draw::cross(&mut aligned_rgb, x, y, 3, draw::YELLOW);
     }
 
     let output_aligned = "vis_aligned_with_reference.png";
@@ -53,10 +53,16 @@ cargo build --bin howrs --release --features openvino
 # Compile PAM module
 cargo build --lib --release --features openvino
 
+# Compile greeter helper
+cargo build -p howrs-greeter-helper --release
+
 %install
 # Install binary
 install -D -m 755 target/release/howrs %{buildroot}%{_sbindir}/howrs
 
+# Install greeter helper
+install -D -m 755 target/release/howrs-greeter-helper %{buildroot}%{_libexecdir}/howrs-greeter-helper
+
 # Install PAM module
 install -D -m 755 target/release/libhowrs.so %{buildroot}/%{_lib}/security/libhowrs.so
 
@@ -66,12 +72,32 @@ install -D -m 644 packaging/config.toml %{buildroot}/usr/local/etc/howrs/config.
 # Create face storage directory (readable by all users, but only root can write)
 install -d -m 755 %{buildroot}/usr/local/etc/howrs
 
+# Translations go here; the template lists every message
+install -d -m 755 %{buildroot}/usr/local/share/howrs/locale
+
+# Install polkit actions for enrolling/purging through the daemon
+install -D -m 644 packaging/org.howrs.policy %{buildroot}%{_datadir}/polkit-1/actions/org.howrs.policy
+
+# Install the socket-activated daemon
+install -D -m 644 packaging/howrs.socket %{buildroot}%{_unitdir}/howrs.socket
+install -D -m 644 packaging/howrs.service %{buildroot}%{_unitdir}/howrs.service
+
+# Install the camera lock directory
+install -D -m 644 packaging/howrs.tmpfiles %{buildroot}%{_tmpfilesdir}/howrs.conf
+
 # Install SELinux policy (if it exists and is not empty)
 if [ -s packaging/howrs_pam.pp ]; then
     install -D -m 644 packaging/howrs_pam.pp %{buildroot}%{_datadir}/selinux/packages/%{name}/howrs_pam.pp
 fi
 
+%pre
+# Members may take camera locks and read faces
+getent group howrs > /dev/null || groupadd -r howrs
+
 %post
+%systemd_post howrs.socket howrs.service
+%tmpfiles_create howrs.conf
+
 # Load SELinux policy module (if available)
 if [ $1 -eq 1 ] ; then
     if [ -f %{_datadir}/selinux/packages/%{name}/howrs_pam.pp ]; then
@@ -91,7 +117,12 @@ if [ -d /usr/local/etc/howrs ]; then
     find /usr/local/etc/howrs -type f -exec chmod 644 {} \; 2>/dev/null || :
 fi
 
+%preun
+%systemd_preun howrs.socket howrs.service
+
 %postun
+%systemd_postun_with_restart howrs.service
+
 # Remove SELinux policy module on uninstall (if it was installed)
 if [ $1 -eq 0 ] ; then
     if command -v semodule > /dev/null 2>&1 && selinuxenabled 2>/dev/null; then
@@ -103,11 +134,17 @@ fi
 
 %files
 %license LICENSE
-%doc README.md
+%doc README.md packaging/locale/template.toml
 %{_sbindir}/howrs
+%{_libexecdir}/howrs-greeter-helper
 /%{_lib}/security/libhowrs.so
 %dir /usr/local/etc/howrs
 %config(noreplace) /usr/local/etc/howrs/config.toml
+%dir /usr/local/share/howrs/locale
+%{_datadir}/polkit-1/actions/org.howrs.policy
+%{_unitdir}/howrs.socket
+%{_unitdir}/howrs.service
+%{_tmpfilesdir}/howrs.conf
 %dir %{_datadir}/selinux/packages/%{name}
 %{_datadir}/selinux/packages/%{name}/howrs_pam.pp
 
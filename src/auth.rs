@@ -0,0 +1,1009 @@
+//! Face scan against a user's enrolled records, or against every enrolled
+//! user for `match=any-enrolled`.
+//!
+//! Shared by the PAM module's in-process stage, by `howrs daemon`, which
+//! keeps one [`Pipeline`] loaded across requests, and by the `howrs-ffi` C API.
+
+use crate::config::{self, CompanionConfig, CompanionMode, Config, FallbackStage};
+use crate::debug_dump::DebugBundle;
+use crate::error::{Error, Report, Result, ResultExt};
+use crate::i18n::{self, Msg};
+use crate::matcher::{self, RecordMatrix};
+use crate::metrics::{self, Stage};
+use crate::{identity, storage, Pipeline};
+use howrs_vision::cancel::CancelToken;
+use howrs_vision::depth::{DepthCamera, DepthGate, Relief};
+use howrs_vision::detector::Backend;
+use howrs_vision::exposure::Bracketing;
+use howrs_vision::face::AlignTemplate;
+use howrs_vision::model::{self, ModelInfo, ModelKind, Precision, Registry};
+use howrs_vision::motion::Motion;
+use howrs_vision::pad::PadModel;
+use howrs_vision::quality::{Feedback, FrameQuality, HeadPose, Pose};
+use howrs_vision::stream::{Flow, Matcher, SkipReason, StreamEvent, StreamOptions};
+use howrs_vision::video::{self, CameraManager};
+use howrs_vision::{pool, Camera, Embedding};
+use std::path::Path;
+use std::time::{Duration, Instant};
+use zeroize::Zeroizing;
+
+/// Load the models with the detection and encoding settings from `config`.
+///
+/// Default models fetched into `model_dir` take precedence over the ones
+/// embedded in the binary.
+pub fn load_pipeline(config: &Config) -> Result<Pipeline> {
+    load_pipeline_at(config, config.model_precision())
+}
+
+/// [`load_pipeline`] with the default models of the given precision
+pub fn load_pipeline_at(config: &Config, precision: Precision) -> Result<Pipeline> {
+    if config.is_low_memory() {
+        tracing::info!("using the low memory profile");
+    }
+    model::set_intra_threads(config.inference_threads());
+    let backend = config.detection.backend.into();
+    let detector_model = match (&config.detection.model, backend) {
+        (Some(path), _) => Some(path.clone()),
+        (None, Backend::YuNet) => {
+            default_model(config, ModelKind::Detector, precision).installed(&config.model_dir)
+        }
+        (None, _) => None,
+    };
+
+    let recognition = &config.recognition;
+    let alignment = recognition.alignment.map(AlignTemplate::from);
+    let (encoder_model, alignment) = match &recognition.model {
+        Some(path) => (Some(path.clone()), alignment),
+        None => {
+            let info = default_model(config, ModelKind::Encoder, precision);
+            (
+                info.installed(&config.model_dir),
+                alignment.or(info.alignment),
+            )
+        }
+    };
+
+    Ok(Pipeline::builder()
+        .backend(backend)
+        .detector_model(detector_model)
+        .encoder_model(encoder_model)
+        .alignment(alignment)
+        .thresholds(
+            config.detection.score_threshold(),
+            config.detection.nms_threshold(),
+        )
+        .detect_size(config.detect_size())
+        .flip_augment(config.use_flip_augment())
+        .build()?
+        .with_normalization(config.normalization.into())
+        .with_size_filter(config.detection.size_filter()))
+}
+
+/// Default model of `kind` at `precision`, or the float one when the
+/// quantized variant hasn't been fetched
+fn default_model(config: &Config, kind: ModelKind, precision: Precision) -> &'static ModelInfo {
+    let info = Registry::variant(kind, precision);
+    if precision != Precision::Fp32 && info.installed(&config.model_dir).is_none() {
+        tracing::warn!(
+            "{} is not installed in {}, using the float model",
+            info.name,
+            config.model_dir.display()
+        );
+        return Registry::variant(kind, Precision::Fp32);
+    }
+    info
+}
+
+/// [`load_pipeline`] plus the region of interest, which only applies to
+/// authentication: the user is looking at the screen, so roughly centered.
+/// Also loads the `[liveness]` anti-spoofing model, if any.
+pub fn load_auth_pipeline(config: &Config) -> Result<Pipeline> {
+    let liveness = &config.liveness;
+    let pad = match &liveness.pad_model {
+        Some(path) => Some(
+            PadModel::load(path)?
+                .with_scale(liveness.pad_scale)
+                .with_threshold(liveness.pad_threshold),
+        ),
+        None => None,
+    };
+    Ok(load_pipeline(config)?
+        .with_pad(pad)
+        .with_roi(config.detection.roi)
+        .with_thresholds(
+            config.detection.auth_score_threshold(),
+            config.detection.nms_threshold(),
+        )
+        .with_quality_gate(config.quality.gate()))
+}
+
+/// Try each `[pam.fallback]` stage in turn: `daemon` gets the socket and
+/// timeout to use, `in_process` the loaded config and scan deadline. A
+/// malformed config is replaced by the defaults, see
+/// [`config::load_auth_config`].
+///
+/// `None` when no stage gave an answer or the scan was cancelled.
+pub fn with_fallback<T>(
+    daemon: impl Fn(&Path, Duration) -> Result<T>,
+    in_process: impl Fn(&Config, Instant) -> Result<T>,
+) -> Result<Option<T>> {
+    let (config, error) = config::load_auth_config(None)?;
+    if let Some(e) = error {
+        tracing::warn!("using the default config: {}", Report(&e));
+    }
+    let fallback = &config.pam.fallback;
+
+    for stage in &fallback.stages {
+        let _span = tracing::info_span!("stage", ?stage).entered();
+        let start_time = Instant::now();
+        let timeout = fallback.stage_timeout(*stage, config.scan_durnation);
+        let result = match stage {
+            FallbackStage::Daemon => daemon(&fallback.daemon_socket, timeout),
+            // GStreamer would run with the caller's environment
+            FallbackStage::InProcess if runs_gstreamer(&config) && identity::is_elevated() => {
+                tracing::info!("the camera needs gst-launch-1.0, asking the daemon instead");
+                daemon(&fallback.daemon_socket, timeout)
+            }
+            FallbackStage::InProcess => in_process(&config, start_time + timeout),
+        };
+
+        match result {
+            Ok(matched) => return Ok(Some(matched)),
+            Err(e) if e.is_cancelled() => {
+                tracing::info!("cancelled after {:?}", start_time.elapsed());
+                return Ok(None);
+            }
+            Err(e) => tracing::warn!(
+                "{:?} stage failed after {:?}: {}",
+                stage,
+                start_time.elapsed(),
+                Report(&e)
+            ),
+        }
+    }
+
+    Ok(None)
+}
+
+/// Whether a scan with `config` runs `gst-launch-1.0` for one of its cameras
+fn runs_gstreamer(config: &Config) -> bool {
+    let capture = config.capture.settings();
+    std::iter::once(&config.camera)
+        .chain(config.companion.as_ref().map(|c| &c.camera))
+        .any(|camera| video::runs_gstreamer(camera, &capture))
+}
+
+/// Outcome of [`authenticate`]
+#[derive(Debug)]
+pub enum AuthResult {
+    /// The face in front of the camera belongs to the user
+    Matched,
+    /// The scan ran and gave up, for this reason
+    Failed(AuthFailure),
+    /// No stage could scan, or the scan was cancelled: use the password
+    Unavailable,
+    /// The configuration couldn't be loaded, or the scan broke
+    Error(Error),
+}
+
+/// Why a scan ended without a match
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum AuthFailure {
+    /// The camera couldn't be opened, or stayed busy past the deadline
+    NoCamera,
+    /// Frames were read but none had a usable face
+    NoFaceDetected,
+    /// Most frames were too dark to find a face in, e.g. an IR emitter
+    /// that isn't lit
+    FaceTooDark,
+    /// Faces were compared and the best scored `score`, under the threshold.
+    /// `None` when the daemon withheld it, see [`AuthFailure::without_score`].
+    BelowThreshold { score: Option<f32> },
+    /// The face looked like a photo or a screen: a `[liveness]` check, the
+    /// `[companion]` camera or the `[depth]` camera rejected it
+    SpoofSuspected,
+    /// The deadline passed before the camera delivered a frame
+    Timeout,
+}
+
+impl AuthFailure {
+    /// What to tell the user, in their language; [`Display`] is the
+    /// English kept for logs
+    ///
+    /// [`Display`]: std::fmt::Display
+    pub fn message(&self) -> &'static str {
+        i18n::text(match self {
+            AuthFailure::NoCamera => Msg::NoCamera,
+            AuthFailure::NoFaceDetected => Msg::NoFace,
+            AuthFailure::FaceTooDark => Msg::TooDark,
+            AuthFailure::BelowThreshold { .. } => Msg::NotRecognized,
+            AuthFailure::SpoofSuspected => Msg::Spoof,
+            AuthFailure::Timeout => Msg::Timeout,
+        })
+    }
+
+    /// Token sent in the daemon's `FAIL` reply
+    pub fn code(&self) -> &'static str {
+        match self {
+            AuthFailure::NoCamera => "no-camera",
+            AuthFailure::NoFaceDetected => "no-face",
+            AuthFailure::FaceTooDark => "too-dark",
+            AuthFailure::BelowThreshold { .. } => "below-threshold",
+            AuthFailure::SpoofSuspected => "spoof",
+            AuthFailure::Timeout => "timeout",
+        }
+    }
+
+    /// The same failure with the score left out. How close a face came
+    /// tells whoever holds a photo which way to adjust it, so the daemon
+    /// only reports it to root and the user being authenticated.
+    pub fn without_score(self) -> Self {
+        match self {
+            AuthFailure::BelowThreshold { .. } => AuthFailure::BelowThreshold { score: None },
+            other => other,
+        }
+    }
+
+    /// Encode as `code` plus the score where there is one
+    pub fn encode(&self) -> String {
+        match self {
+            AuthFailure::BelowThreshold { score: Some(score) } => {
+                format!("{} {}", self.code(), score)
+            }
+            _ => self.code().to_string(),
+        }
+    }
+
+    pub fn decode(s: &str) -> Result<Self> {
+        Ok(match s.split_once(' ') {
+            Some(("below-threshold", score)) => AuthFailure::BelowThreshold {
+                score: Some(score.parse().daemon("malformed below-threshold score")?),
+            },
+            None if s == "below-threshold" => AuthFailure::BelowThreshold { score: None },
+            None if s == "no-camera" => AuthFailure::NoCamera,
+            None if s == "no-face" => AuthFailure::NoFaceDetected,
+            None if s == "too-dark" => AuthFailure::FaceTooDark,
+            None if s == "spoof" => AuthFailure::SpoofSuspected,
+            None if s == "timeout" => AuthFailure::Timeout,
+            _ => return Err(Error::daemon(format!("unknown failure {:?}", s))),
+        })
+    }
+}
+
+/// Message for the person at the camera, who is not told the score
+impl std::fmt::Display for AuthFailure {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            AuthFailure::NoCamera => "no camera available",
+            AuthFailure::NoFaceDetected => "no face detected",
+            AuthFailure::FaceTooDark => "too dark to see a face",
+            AuthFailure::BelowThreshold { .. } => "face not recognized",
+            AuthFailure::SpoofSuspected => "face looks like a photo or a screen",
+            AuthFailure::Timeout => "the camera sent no frames in time",
+        })
+    }
+}
+
+/// What a scan has seen so far, to tell why it ended without a match
+#[derive(Debug, Clone, Default)]
+pub struct ScanTally {
+    frames: usize,
+    faces: usize,
+    dark: usize,
+    best: Option<f32>,
+    /// Matches rejected by the anti-spoofing model, the motion check, the
+    /// companion or depth camera, or the challenge
+    rejected: usize,
+}
+
+impl ScanTally {
+    pub fn observe(&mut self, event: &StreamEvent<'_>) {
+        match event {
+            StreamEvent::FrameCaptured { .. } => self.frames += 1,
+            StreamEvent::FaceDetected { .. } => self.faces += 1,
+            StreamEvent::FrameSkipped {
+                frame: Some(frame),
+                reason: SkipReason::NoFace | SkipReason::LowQuality(_),
+            } if FrameQuality::measure(frame, None).feedback() == Feedback::TooDark => {
+                self.dark += 1;
+            }
+            StreamEvent::ScoreComputed { score, .. } => {
+                self.best = Some(self.best.map_or(*score, |best| best.max(*score)));
+            }
+            StreamEvent::FrameSkipped {
+                reason: SkipReason::Spoof(_),
+                ..
+            } => self.rejected += 1,
+            _ => {}
+        }
+    }
+
+    /// The most telling reason for not having matched
+    pub fn failure(&self) -> AuthFailure {
+        match self.best {
+            _ if self.rejected > 0 => AuthFailure::SpoofSuspected,
+            Some(score) => AuthFailure::BelowThreshold { score: Some(score) },
+            None if self.frames == 0 => AuthFailure::Timeout,
+            None if self.dark * 2 >= self.frames => AuthFailure::FaceTooDark,
+            None => AuthFailure::NoFaceDetected,
+        }
+    }
+}
+
+/// Shows the user a liveness challenge, e.g. "Turn your head slightly left"
+pub type Prompt<'a> = &'a dyn Fn(&str);
+
+/// [`Prompt`] writing to stderr, for the terminal; the PAM module uses the
+/// application's conversation instead, so graphical logins show it too
+pub fn print_prompt(message: &str) {
+    eprintln!("{}", message);
+}
+
+/// Check whether the face in front of the camera belongs to `user`, walking
+/// the `[pam.fallback]` stages, within `timeout` overall.
+///
+/// This is what the PAM module and the C API run. Challenges are printed
+/// with [`print_prompt`].
+pub fn authenticate(user: &str, timeout: Duration) -> AuthResult {
+    authenticate_with(user, timeout, &CancelToken::new(), &print_prompt)
+}
+
+/// [`authenticate`], giving up as soon as `cancel` is cancelled and showing
+/// challenges through `prompt`
+pub fn authenticate_with(
+    user: &str,
+    timeout: Duration,
+    cancel: &CancelToken,
+    prompt: Prompt<'_>,
+) -> AuthResult {
+    let deadline = Instant::now() + timeout;
+    let result = with_fallback(
+        |socket, daemon_timeout| {
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            crate::daemon::request_auth(socket, user, daemon_timeout.min(remaining), cancel, prompt)
+        },
+        |config, stage_deadline| {
+            in_process(
+                config,
+                user,
+                stage_deadline.min(deadline),
+                cancel.clone(),
+                prompt,
+            )
+        },
+    );
+    match result {
+        Ok(Some(result)) => result,
+        Ok(None) => AuthResult::Unavailable,
+        Err(e) => AuthResult::Error(e),
+    }
+}
+
+/// Load the models and scan until `deadline` or until `cancel` is cancelled.
+///
+/// A user without enrolled faces is an error, so the caller falls back to
+/// the password rather than counting a failed attempt.
+pub fn in_process(
+    config: &Config,
+    username: &str,
+    deadline: Instant,
+    cancel: CancelToken,
+    prompt: Prompt<'_>,
+) -> Result<AuthResult> {
+    let records = storage::load_records(username)?;
+    if records.is_empty() {
+        return Err(Error::storage(format!(
+            "no faces enrolled for {}",
+            username
+        )));
+    }
+
+    let mut pipeline = load_auth_pipeline(config)?.with_cancel(cancel);
+    scan(&mut pipeline, config, username, &records, deadline, prompt)
+}
+
+/// Load the models and scan for any enrolled user until `deadline` or until
+/// `cancel` is cancelled
+pub fn in_process_identify(
+    config: &Config,
+    deadline: Instant,
+    cancel: CancelToken,
+    prompt: Prompt<'_>,
+) -> Result<Option<String>> {
+    let gallery = load_gallery()?;
+    if gallery.is_empty() {
+        return Ok(None);
+    }
+
+    let mut pipeline = load_auth_pipeline(config)?.with_cancel(cancel);
+    identify(&mut pipeline, config, &gallery, deadline, prompt)
+}
+
+/// Records of every enrolled user that has at least one
+pub fn load_gallery() -> Result<Vec<(String, Vec<storage::FaceRecord>)>> {
+    let mut gallery = Vec::new();
+    for user in storage::enrolled_users()? {
+        let records = storage::load_records(&user)?;
+        if !records.is_empty() {
+            gallery.push((user, records));
+        }
+    }
+    Ok(gallery)
+}
+
+/// Scan camera frames with an already loaded pipeline until a record matches
+/// or `deadline` passes. With `[liveness] challenge`, `prompt` asks the user
+/// to turn their head once the face matches.
+///
+/// Returns [`AuthResult::Matched`] or [`AuthResult::Failed`]. Fails with
+/// [`Cancelled`](howrs_vision::cancel::Cancelled) once the pipeline's cancel
+/// token is cancelled.
+#[tracing::instrument(name = "scan", skip_all, fields(user = %username))]
+pub fn scan(
+    pipeline: &mut Pipeline,
+    config: &Config,
+    username: &str,
+    records: &[storage::FaceRecord],
+    deadline: Instant,
+    prompt: Prompt<'_>,
+) -> Result<AuthResult> {
+    let start = Instant::now();
+    let gallery = [(username, records)];
+    let result = scan_frames(pipeline, config, &gallery, false, deadline, prompt);
+    metrics::observe(Stage::Total, start.elapsed());
+    Ok(match result? {
+        Ok(_) => AuthResult::Matched,
+        Err(failure) => AuthResult::Failed(failure),
+    })
+}
+
+/// [`scan`] against every user in `gallery`, returning whoever matched best.
+///
+/// Every face in the frame is tried, since a kiosk often sees several people.
+#[tracing::instrument(name = "identify", skip_all, fields(users = gallery.len()))]
+pub fn identify(
+    pipeline: &mut Pipeline,
+    config: &Config,
+    gallery: &[(String, Vec<storage::FaceRecord>)],
+    deadline: Instant,
+    prompt: Prompt<'_>,
+) -> Result<Option<String>> {
+    let gallery: Vec<(&str, &[storage::FaceRecord])> = gallery
+        .iter()
+        .map(|(user, records)| (user.as_str(), records.as_slice()))
+        .collect();
+    let start = Instant::now();
+    let result = scan_frames(pipeline, config, &gallery, true, deadline, prompt);
+    metrics::observe(Stage::Total, start.elapsed());
+    match result? {
+        Ok(index) => Ok(Some(gallery[index].0.to_string())),
+        Err(failure) => {
+            tracing::info!("no enrolled user matched: {}", failure);
+            Ok(None)
+        }
+    }
+}
+
+/// Frames kept for the motion check and the debug dump, about the last
+/// second at the rate a scan reads them
+const HISTORY_FRAMES: usize = 8;
+/// How far back the motion check looks
+const MOTION_WINDOW: Duration = Duration::from_secs(1);
+
+/// Index into `gallery` of the first user to match above the threshold, from
+/// the best face of each frame or, with `all_faces`, from any face, and to
+/// pass the configured liveness checks; otherwise why nobody did
+fn scan_frames(
+    pipeline: &mut Pipeline,
+    config: &Config,
+    gallery: &[(&str, &[storage::FaceRecord])],
+    all_faces: bool,
+    deadline: Instant,
+    prompt: Prompt<'_>,
+) -> Result<Result<usize, AuthFailure>> {
+    // Another prompt may be using the camera; wait our turn within our own window
+    let start = Instant::now();
+    let capture = config.capture.settings();
+    // Reopened if it drops out mid-scan, e.g. after a USB reset
+    let history = match config.liveness.min_motion > 0.0 || config.debug_dump.is_some() {
+        true => HISTORY_FRAMES,
+        false => 0,
+    };
+    let mut camera = match CameraManager::open_until(&config.camera, deadline, &capture) {
+        Ok(camera) => camera.with_history(history),
+        Err(e) => {
+            tracing::warn!("camera unavailable: {}", Report(&e));
+            metrics::record_camera_error();
+            return Ok(Err(AuthFailure::NoCamera));
+        }
+    };
+    let mut companion = match &config.companion {
+        Some(companion) => match Camera::open_until(&companion.camera, deadline, &capture) {
+            Ok(camera) => Some((companion, camera)),
+            Err(e) => {
+                tracing::warn!("companion camera unavailable: {}", Report(&e));
+                metrics::record_camera_error();
+                return Ok(Err(AuthFailure::NoCamera));
+            }
+        },
+        None => None,
+    };
+    let mut depth = match &config.depth {
+        Some(depth) => match DepthCamera::open_until(&depth.camera, deadline, depth.scale) {
+            Ok(camera) => Some((depth.gate(), camera)),
+            Err(e) => {
+                tracing::warn!("depth camera unavailable: {}", Report(&e));
+                metrics::record_camera_error();
+                return Ok(Err(AuthFailure::NoCamera));
+            }
+        },
+        None => None,
+    };
+    metrics::observe(Stage::CameraOpen, start.elapsed());
+    let mut bracketing = match camera.device_info().and_then(|info| info.node) {
+        Some(node) if config.capture.bracketing => Bracketing::open(&node).unwrap_or_else(|e| {
+            tracing::debug!("no exposure bracketing: {}", Report(&e));
+            None
+        }),
+        _ => None,
+    };
+
+    let sensor = camera.device_info().map(|info| info.sensor.into());
+    let now = storage::unix_now();
+    let matrices: Vec<RecordMatrix> = gallery
+        .iter()
+        .map(|(_, records)| {
+            RecordMatrix::new(records)
+                .for_sensor(sensor, config.sensor_match)
+                .weighted(records, config.weighting.weights(), now)
+        })
+        .collect();
+    for ((username, _), matrix) in gallery.iter().zip(&matrices) {
+        let usable = matrix.len() - matrix.skipped().len();
+        if usable < config.min_matching_records {
+            tracing::warn!(
+                user = username,
+                "{} usable face(s) but min_matching_records = {}; this user can't match",
+                usable,
+                config.min_matching_records
+            );
+        }
+    }
+    // Candidates are numbered across the whole gallery, user by user
+    let owners: Vec<(usize, usize)> = gallery
+        .iter()
+        .enumerate()
+        .flat_map(|(user, (_, records))| (0..records.len()).map(move |index| (user, index)))
+        .collect();
+    let offsets: Vec<usize> = gallery
+        .iter()
+        .scan(0, |offset, (_, records)| {
+            let start = *offset;
+            *offset += records.len();
+            Some(start)
+        })
+        .collect();
+    let opts = StreamOptions {
+        deadline: Some(deadline),
+        all_faces,
+        matcher: Some(Matcher {
+            score: Box::new(|embedding| {
+                let start = Instant::now();
+                let best = best_in_gallery(&matrices, embedding, config);
+                metrics::observe(Stage::Match, start.elapsed());
+                best.map(|(user, index, score)| (offsets[user] + index, score))
+            }),
+            threshold: config.similarity_threshold(),
+        }),
+    };
+
+    let mut tally = ScanTally::default();
+    let mut bundle = config.debug_dump.as_ref().map(|_| DebugBundle::default());
+    // The stream ends on every match; an unconfirmed one resumes it
+    loop {
+        let mut probe = Zeroizing::new(Vec::new());
+        let mut face = None;
+        let matched = pipeline.run_stream(&mut camera, &opts, |event| {
+            tally.observe(&event);
+            if let Some(bundle) = &mut bundle {
+                bundle.observe(&event);
+            }
+            if let Some(exposure) = &mut bracketing {
+                if let Err(e) = bracket(exposure, &event) {
+                    tracing::warn!("exposure bracketing stopped: {}", Report(&e));
+                    bracketing = None;
+                }
+            }
+            match event {
+                StreamEvent::FrameCaptured { capture, .. } => {
+                    metrics::observe(Stage::Capture, capture);
+                }
+                StreamEvent::FrameSkipped { reason, .. } => match reason {
+                    SkipReason::Capture(_) => metrics::record_camera_error(),
+                    SkipReason::LowQuality(low) => {
+                        tracing::debug!("skipping frame: {}", low);
+                        metrics::record_skipped_frame();
+                    }
+                    SkipReason::Spoof(live) => {
+                        tracing::info!(live, "anti-spoofing model rejected the face");
+                    }
+                    SkipReason::NoFace | SkipReason::Failed(_) => {}
+                },
+                StreamEvent::ScoreComputed {
+                    frame,
+                    detection,
+                    timings,
+                    ..
+                } => {
+                    face = Some((detection.clone(), (frame.width(), frame.height())));
+                    metrics::observe(
+                        Stage::Embed,
+                        timings.detect + timings.align + timings.encode,
+                    );
+                }
+                StreamEvent::Matched { embedding, .. } => {
+                    *probe = embedding.vector().iter().copied().collect();
+                }
+                StreamEvent::FaceDetected { .. } => {}
+            }
+            Ok::<_, Error>(Flow::Continue)
+        })?;
+        let Some((candidate, score)) = matched else {
+            let failure = tally.failure();
+            if let Some(bundle) = bundle {
+                let user = match gallery {
+                    [(user, _)] => user,
+                    _ => "any-enrolled",
+                };
+                let dir = config.debug_dump.as_deref();
+                let threshold = config.similarity_threshold();
+                match bundle.write(dir, user, threshold, &failure, camera.history()) {
+                    Ok(path) => tracing::info!("debug bundle written to {}", path.display()),
+                    Err(e) => tracing::warn!("failed to write debug bundle: {}", Report(&e)),
+                }
+            }
+            return Ok(Err(failure));
+        };
+
+        let (user, index) = owners[candidate];
+        let (username, records) = gallery[user];
+        let (detection, source) = face.matching("match without a scored face")?;
+        if config.liveness.min_motion > 0.0 {
+            // The stream stopped on the match, so the newest frame kept is
+            // the one `detection` is in
+            let motion = Motion::measure(camera.history(), &detection.bbox, MOTION_WINDOW);
+            if !motion.is_some_and(|m| m.change >= config.liveness.min_motion) {
+                tracing::info!(user = username, ?motion, "face did not move");
+                tally.rejected += 1;
+                continue;
+            }
+        }
+        if let Some((companion, companion_camera)) = &mut companion {
+            if !confirm_companion(
+                pipeline,
+                config,
+                companion,
+                companion_camera,
+                records,
+                score,
+            )? {
+                tracing::info!(user = username, score, "companion camera did not confirm");
+                tally.rejected += 1;
+                continue;
+            }
+        }
+        if let Some((gate, depth_camera)) = &mut depth {
+            if !confirm_depth(pipeline, gate, depth_camera, &detection.bbox, source)? {
+                tally.rejected += 1;
+                continue;
+            }
+        }
+        if config.liveness.challenge {
+            let start = HeadPose::from_landmarks(&detection.landmarks);
+            let challenge = Challenge {
+                matrix: &matrices[user],
+                start,
+                deadline,
+            };
+            if !challenge.run(pipeline, config, &mut camera, prompt)? {
+                tracing::info!(user = username, "head turn challenge not met");
+                tally.rejected += 1;
+                continue;
+            }
+        }
+
+        tracing::info!(
+            user = username,
+            score,
+            probability = config.calibration.calibration().probability(score),
+            "face matched"
+        );
+        if let Err(e) = storage::record_match(username, &records[index].id, &probe) {
+            tracing::warn!("failed to update match stats: {}", Report(&e));
+        }
+        return Ok(Ok(user));
+    }
+}
+
+/// Feed `bracketing` the brightness of frames without a face, and whether
+/// one was found
+fn bracket(bracketing: &mut Bracketing, event: &StreamEvent<'_>) -> howrs_vision::Result<()> {
+    match event {
+        StreamEvent::FrameSkipped {
+            frame: Some(frame),
+            reason: SkipReason::NoFace,
+        } => bracketing.observe(FrameQuality::measure(frame, None).brightness, false),
+        StreamEvent::FaceDetected { .. } => bracketing.observe(0.0, true),
+        _ => Ok(()),
+    }
+}
+
+/// Frames read from the companion or depth camera to confirm a match; the
+/// first ones after it starts streaming are often dark or empty
+const CONFIRM_FRAMES: usize = 5;
+
+/// Whether the companion camera agrees that `records`' owner, who matched
+/// with `score` on the main camera, is in front of it
+fn confirm_companion(
+    pipeline: &mut Pipeline,
+    config: &Config,
+    companion: &CompanionConfig,
+    camera: &mut Camera,
+    records: &[storage::FaceRecord],
+    score: f32,
+) -> Result<bool> {
+    let sensor = camera.device_info().map(|info| info.sensor.into());
+    let matrix = RecordMatrix::new(records)
+        .for_sensor(sensor, config.sensor_match)
+        .weighted(records, config.weighting.weights(), storage::unix_now());
+
+    for _ in 0..CONFIRM_FRAMES {
+        pipeline.cancel.check()?;
+        let frame = match camera.frame() {
+            Ok(frame) => image::DynamicImage::ImageRgb8(frame),
+            Err(e) => {
+                tracing::warn!("companion camera: {}", Report(&e));
+                metrics::record_camera_error();
+                return Ok(false);
+            }
+        };
+        let confirmed = match (pipeline.detect_best(&frame)?, companion.mode) {
+            (None, _) => false,
+            (Some(_), CompanionMode::Detect) => true,
+            (Some(detection), CompanionMode::Fuse) => {
+                let embedding = pipeline.encode_detection(&frame, &detection)?;
+                matrix
+                    .best_match(&embedding, config.fusion)
+                    .is_some_and(|(_, other)| {
+                        let fused = companion.fuse(score, other);
+                        tracing::debug!(score, companion = other, fused, "fused scores");
+                        fused >= config.similarity_threshold()
+                    })
+            }
+        };
+        pool::frames().recycle_image(frame);
+        if confirmed {
+            return Ok(true);
+        }
+    }
+    Ok(false)
+}
+
+/// Whether the depth camera sees relief in `bbox`, where the matched face is
+/// in a `source` sized frame
+fn confirm_depth(
+    pipeline: &Pipeline,
+    gate: &DepthGate,
+    camera: &mut DepthCamera,
+    bbox: &[f32; 4],
+    source: (u32, u32),
+) -> Result<bool> {
+    for _ in 0..CONFIRM_FRAMES {
+        pipeline.cancel.check()?;
+        let frame = match camera.frame() {
+            Ok(frame) => frame,
+            Err(e) => {
+                tracing::warn!("depth camera: {}", Report(&e));
+                metrics::record_camera_error();
+                return Ok(false);
+            }
+        };
+        let Some(relief) = Relief::measure(&frame, bbox, source) else {
+            tracing::info!("face is outside the depth camera's view");
+            return Ok(false);
+        };
+        match gate.check(&relief) {
+            Ok(()) => {
+                tracing::debug!(?relief, "face has depth");
+                return Ok(true);
+            }
+            Err(not_live) => tracing::info!(?relief, "depth check failed: {}", not_live),
+        }
+    }
+    Ok(false)
+}
+
+/// Head turn asked of a matched user, for `[liveness] challenge`
+struct Challenge<'a> {
+    /// Records of the matched user
+    matrix: &'a RecordMatrix,
+    /// Pose of the face that matched
+    start: HeadPose,
+    deadline: Instant,
+}
+
+impl Challenge<'_> {
+    /// Ask for a turn to a random side and wait until a face turned that way
+    /// from `start` still matches, or `deadline` passes
+    fn run(
+        &self,
+        pipeline: &mut Pipeline,
+        config: &Config,
+        camera: &mut CameraManager,
+        prompt: Prompt<'_>,
+    ) -> Result<bool> {
+        // Random, so a recording can't know which way to turn; UUIDv4 bits
+        // come from the OS random source
+        let turn = match uuid::Uuid::new_v4().as_bytes()[0] & 1 {
+            0 => Pose::Left,
+            _ => Pose::Right,
+        };
+        prompt(i18n::text(match turn {
+            Pose::Left => Msg::TurnLeft,
+            _ => Msg::TurnRight,
+        }));
+
+        while Instant::now() < self.deadline {
+            pipeline.cancel.check()?;
+            let frame = match camera.frame() {
+                Ok(frame) => image::DynamicImage::ImageRgb8(frame),
+                Err(e) => {
+                    tracing::debug!("capture failed: {}", Report(&e));
+                    metrics::record_camera_error();
+                    continue;
+                }
+            };
+            let turned = match pipeline.detect_best(&frame)? {
+                Some(detection)
+                    if turn
+                        .turned(&self.start, &HeadPose::from_landmarks(&detection.landmarks)) =>
+                {
+                    let embedding = pipeline.encode_detection(&frame, &detection)?;
+                    self.matrix
+                        .best_match(&embedding, config.fusion)
+                        .is_some_and(|(_, score)| score >= config.similarity_threshold())
+                }
+                _ => false,
+            };
+            pool::frames().recycle_image(frame);
+            if turned {
+                return Ok(true);
+            }
+        }
+        Ok(false)
+    }
+}
+
+/// Best `(user, record, score)` across the record matrices of a gallery
+fn best_in_gallery(
+    matrices: &[RecordMatrix],
+    embedding: &Embedding,
+    config: &Config,
+) -> Option<(usize, usize, f32)> {
+    matrices
+        .iter()
+        .enumerate()
+        .filter_map(|(user, matrix)| {
+            matrix
+                .quorum_match(embedding, config.fusion, config.min_matching_records)
+                .map(|(index, score)| (user, index, score))
+        })
+        .max_by(|a, b| a.2.total_cmp(&b.2))
+}
+
+/// Capture a face to enroll: the best frontal, well-exposed face seen before
+/// `deadline`, stopping early on a confident detection. The record notes
+/// which camera it came from.
+#[tracing::instrument(name = "capture_enrollment", skip_all)]
+pub fn capture_enrollment(
+    pipeline: &mut Pipeline,
+    config: &Config,
+    deadline: Instant,
+) -> Result<Option<storage::FaceRecord>> {
+    let mut camera = Camera::open_until(&config.camera, deadline, &config.capture.settings())?;
+    let mut best: Option<(f32, Embedding)> = None;
+
+    while Instant::now() < deadline {
+        pipeline.cancel.check()?;
+        let img = image::DynamicImage::ImageRgb8(camera.frame()?);
+        let detection = pipeline.detect_best(&img)?;
+        let feedback = FrameQuality::measure(&img, detection.as_ref()).feedback_for(Pose::Straight);
+
+        if let (Feedback::Good, Some(detection)) = (feedback, detection) {
+            let score = detection.score;
+            if best.as_ref().is_none_or(|(b, _)| score > *b) {
+                best = Some((score, pipeline.encode_detection(&img, &detection)?));
+            }
+            if score > 0.8 {
+                pool::frames().recycle_image(img);
+                break;
+            }
+        }
+        pool::frames().recycle_image(img);
+    }
+
+    Ok(best.map(|(score, embedding)| {
+        let vector = embedding.vector().iter().copied().collect();
+        let mut record = storage::FaceRecord::new(vec![vector], None);
+        record.meta.capture = camera.device_info().map(Into::into);
+        record.meta.quality = Some(score);
+        record
+    }))
+}
+
+/// Capture a face with [`capture_enrollment`] and add it to `user`'s store,
+/// trimmed to `max_records_per_user`. `false` if no usable face was seen.
+pub fn enroll(
+    pipeline: &mut Pipeline,
+    config: &Config,
+    user: &str,
+    deadline: Instant,
+) -> Result<bool> {
+    match capture_enrollment(pipeline, config, deadline)? {
+        Some(record) => {
+            let mut records = storage::load_records(user)?;
+            if let Some((_, score)) = matcher::find_duplicate(&records, &record)? {
+                return Err(Error::matching(format!(
+                    "face nearly identical to an enrolled one (similarity {:.3})",
+                    score
+                )));
+            }
+            records.push(record);
+            storage::save_records(user, &records)?;
+            let removed = storage::enforce_cap(user, config)?;
+            if !removed.is_empty() {
+                tracing::info!(user, removed = removed.len(), "pruned face records");
+            }
+            Ok(true)
+        }
+        None => Ok(false),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::{DynamicImage, Rgb, RgbImage};
+
+    fn skipped(tally: &mut ScanTally, frame: &DynamicImage) {
+        tally.observe(&StreamEvent::FrameCaptured {
+            frame,
+            capture: Duration::ZERO,
+        });
+        tally.observe(&StreamEvent::FrameSkipped {
+            frame: Some(frame),
+            reason: SkipReason::NoFace,
+        });
+    }
+
+    #[test]
+    fn test_tally_failure() {
+        assert_eq!(ScanTally::default().failure(), AuthFailure::Timeout);
+
+        let dark = DynamicImage::ImageRgb8(RgbImage::from_pixel(32, 32, Rgb([5, 5, 5])));
+        let lit = DynamicImage::ImageRgb8(RgbImage::from_pixel(32, 32, Rgb([120, 120, 120])));
+        let mut tally = ScanTally::default();
+        skipped(&mut tally, &dark);
+        skipped(&mut tally, &lit);
+        assert_eq!(tally.failure(), AuthFailure::FaceTooDark);
+        skipped(&mut tally, &lit);
+        assert_eq!(tally.failure(), AuthFailure::NoFaceDetected);
+
+        // A compared face explains the failure better than dark frames
+        tally.best = Some(0.3);
+        assert_eq!(
+            tally.failure(),
+            AuthFailure::BelowThreshold { score: Some(0.3) }
+        );
+    }
+}
@@ -1,8 +1,28 @@
-use anyhow::{Context, Result};
+use crate::error::{Error, Result, ResultExt};
+use crate::identity;
+use crate::matcher::{Fusion, Metric, PruneStrategy, RecordWeights, SensorMatch};
+use howrs_vision::calibration::Calibration;
+use howrs_vision::depth::DepthGate;
+use howrs_vision::detector::Backend;
+use howrs_vision::face::{AlignTemplate, FaceSize, SizeFilter};
+use howrs_vision::model::{self, Precision};
+use howrs_vision::normalize::Normalization;
+use howrs_vision::pad;
+use howrs_vision::pipeline::{DEFAULT_NMS_THRESHOLD, DEFAULT_SCORE_THRESHOLD};
+use howrs_vision::quality::QualityGate;
+use howrs_vision::video::{CaptureBackend, CaptureSettings, IoMethod, DEFAULT_BUFFERS};
 use once_cell::sync::Lazy;
 use serde::{Deserialize, Serialize};
-use std::path::Path;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
 
+/// Environment variable overriding the config file location
+pub const CONFIG_ENV: &str = "HOWRS_CONFIG";
+
+/// Distribution-neutral system config, preferred over the compile-time default
+pub const SYSTEM_CONFIG_PATH: &str = "/etc/howrs/config.toml";
+
+/// Compile-time default config location, used when nothing else is found
 pub static CONFIG_PATH: Lazy<&'static Path> = Lazy::new(|| {
     Path::new(option_env!("HOWRS_CONFIG_PATH").unwrap_or("/usr/local/etc/howrs/config.toml"))
 });
@@ -11,39 +31,1095 @@ pub static FACE_STORE_PREFIX: Lazy<&'static Path> = Lazy::new(|| {
     Path::new(option_env!("HOWRS_FACE_STORE_PREFIX").unwrap_or("/usr/local/etc/howrs"))
 });
 
+/// Translations of user-facing messages, see [`crate::i18n`]
+pub static LOCALE_DIR: Lazy<&'static Path> = Lazy::new(|| {
+    Path::new(option_env!("HOWRS_LOCALE_DIR").unwrap_or("/usr/local/share/howrs/locale"))
+});
+
+/// Flag file that turns face authentication off for every user
+pub static DISABLED_PATH: Lazy<&'static Path> =
+    Lazy::new(|| Path::new(option_env!("HOWRS_DISABLED_PATH").unwrap_or("/etc/howrs/disabled")));
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Config {
+    /// Least similarity (or, under `metric = "euclidean"`, greatest
+    /// distance) of a match
     pub threshold: f32,
+    #[serde(default)]
+    pub metric: Metric,
+    /// Fail authentication when this file is malformed, instead of warning
+    /// and using the defaults
+    #[serde(default)]
+    pub strict_mode: bool,
     pub camera: String,
     pub scan_durnation: u32,
+    #[serde(default)]
+    pub capture: CaptureConfig,
+    /// Also encode the mirrored face and average both embeddings. Slower,
+    /// but more accurate; re-enroll after changing it.
+    #[serde(default)]
+    pub flip_augment: bool,
+    /// How a record with several embeddings is scored
+    #[serde(default)]
+    pub fusion: Fusion,
+    /// How records enrolled with another kind of sensor (IR or RGB) than the
+    /// camera in use are scored
+    #[serde(default)]
+    pub sensor_match: SensorMatch,
+    /// Distinct enrolled records a face has to match above `threshold`;
+    /// more than 1 lowers the false accept risk but needs as many enrollments
+    #[serde(default = "default_min_matching_records")]
+    pub min_matching_records: usize,
+    #[serde(default)]
+    pub weighting: WeightingConfig,
+    /// Enrolling beyond this many records per user removes old ones, chosen by
+    /// `prune_strategy`; 0 means no limit
+    #[serde(default)]
+    pub max_records_per_user: usize,
+    #[serde(default)]
+    pub prune_strategy: PruneStrategy,
+    /// Contrast normalization of grayscale (IR) frames; re-enroll after
+    /// changing it
+    #[serde(default)]
+    pub normalization: NormalizationConfig,
+    /// Where `howrs models fetch` installs models; one found here is used
+    /// instead of the copy embedded in the binary
+    #[serde(default = "default_model_dir")]
+    pub model_dir: PathBuf,
+    /// Float or quantized default models; the int8 ones must be fetched first
+    #[serde(default)]
+    pub precision: PrecisionConfig,
+    /// Profile for boards with little RAM, such as a Raspberry Pi: see
+    /// [`Config::is_low_memory`]
+    #[serde(default)]
+    pub low_memory: LowMemoryConfig,
+    #[serde(default)]
+    pub pam: PamConfig,
+    #[serde(default)]
+    pub daemon: DaemonConfig,
+    #[serde(default)]
+    pub calibration: CalibrationConfig,
+    #[serde(default)]
+    pub detection: DetectionConfig,
+    #[serde(default)]
+    pub recognition: RecognitionConfig,
+    #[serde(default)]
+    pub quality: QualityConfig,
+    #[serde(default)]
+    pub liveness: LivenessConfig,
+    /// Second camera that must agree before a match is accepted
+    #[serde(default)]
+    pub companion: Option<CompanionConfig>,
+    /// Depth camera whose view of the face must have relief
+    #[serde(default)]
+    pub depth: Option<DepthConfig>,
+    #[serde(default)]
+    pub store: StoreConfig,
+    /// Directory receiving a bundle (last frame, faces, scores, timings) of
+    /// every scan that ends without a match, for bug reports. Unset, no frame
+    /// is ever written.
+    #[serde(default)]
+    pub debug_dump: Option<PathBuf>,
 }
 
 impl Default for Config {
     fn default() -> Self {
         Self {
             threshold: 0.6,
+            metric: Metric::default(),
+            strict_mode: false,
             camera: "/dev/video0".to_string(),
             scan_durnation: 5,
+            capture: CaptureConfig::default(),
+            flip_augment: false,
+            fusion: Fusion::default(),
+            sensor_match: SensorMatch::default(),
+            min_matching_records: default_min_matching_records(),
+            weighting: WeightingConfig::default(),
+            max_records_per_user: 0,
+            prune_strategy: PruneStrategy::default(),
+            normalization: NormalizationConfig::default(),
+            model_dir: default_model_dir(),
+            precision: PrecisionConfig::default(),
+            low_memory: LowMemoryConfig::default(),
+            pam: PamConfig::default(),
+            daemon: DaemonConfig::default(),
+            calibration: CalibrationConfig::default(),
+            detection: DetectionConfig::default(),
+            recognition: RecognitionConfig::default(),
+            quality: QualityConfig::default(),
+            liveness: LivenessConfig::default(),
+            companion: None,
+            depth: None,
+            store: StoreConfig::default(),
+            debug_dump: None,
+        }
+    }
+}
+
+impl Config {
+    /// `threshold` as the cosine similarity scores are compared with
+    pub fn similarity_threshold(&self) -> f32 {
+        self.metric.similarity_threshold(self.threshold)
+    }
+
+    /// Whether the low memory profile applies: detection at no more than
+    /// [`LOW_MEMORY_DETECT_SIZE`] pixels, int8 models, one inference thread
+    /// and no flip augmentation. `auto` turns it on below
+    /// [`LOW_MEMORY_RAM`] of total RAM.
+    pub fn is_low_memory(&self) -> bool {
+        match self.low_memory {
+            LowMemoryConfig::On => true,
+            LowMemoryConfig::Off => false,
+            LowMemoryConfig::Auto => total_memory().is_some_and(|ram| ram < LOW_MEMORY_RAM),
+        }
+    }
+
+    /// Precision of the default models to load
+    pub fn model_precision(&self) -> Precision {
+        match self.is_low_memory() {
+            true => Precision::Int8,
+            false => self.precision.resolve(&self.model_dir),
+        }
+    }
+
+    /// Longer side frames are shrunk to before detection
+    pub fn detect_size(&self) -> Option<u32> {
+        match self.is_low_memory() {
+            true => Some(
+                self.detection
+                    .detect_size
+                    .map_or(LOW_MEMORY_DETECT_SIZE, |size| {
+                        size.min(LOW_MEMORY_DETECT_SIZE)
+                    }),
+            ),
+            false => self.detection.detect_size,
+        }
+    }
+
+    /// `flip_augment`, unless the low memory profile rules it out
+    pub fn use_flip_augment(&self) -> bool {
+        self.flip_augment && !self.is_low_memory()
+    }
+
+    /// Threads ONNX Runtime may use per inference, 0 for one per core
+    pub fn inference_threads(&self) -> usize {
+        match self.is_low_memory() {
+            true => 1,
+            false => 0,
+        }
+    }
+}
+
+/// Total RAM below which `low_memory = "auto"` turns the profile on
+pub const LOW_MEMORY_RAM: u64 = 2 << 30;
+
+/// Detector input size of the low memory profile
+pub const LOW_MEMORY_DETECT_SIZE: u32 = 320;
+
+/// Total RAM in bytes, from `/proc/meminfo`
+fn total_memory() -> Option<u64> {
+    parse_mem_total(&std::fs::read_to_string("/proc/meminfo").ok()?)
+}
+
+fn parse_mem_total(meminfo: &str) -> Option<u64> {
+    let line = meminfo.lines().find_map(|l| l.strip_prefix("MemTotal:"))?;
+    let kib: u64 = line.trim().strip_suffix("kB")?.trim().parse().ok()?;
+    Some(kib * 1024)
+}
+
+fn default_min_matching_records() -> usize {
+    1
+}
+
+fn default_model_dir() -> PathBuf {
+    PathBuf::from("/usr/local/share/howrs/models")
+}
+
+/// `[capture]`: how frames are streamed from `camera` and the companion
+/// camera. Some UVC IR modules drop frames or return garbage with the
+/// defaults and need another buffer count or `userptr`.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+#[serde(default)]
+pub struct CaptureConfig {
+    /// With `pipewire`, `camera` names a PipeWire node instead of a device
+    pub backend: CaptureBackendConfig,
+    /// Buffers queued with the driver
+    pub buffers: u32,
+    pub io: IoMethodConfig,
+    /// Step the exposure and gain while frames are too dark or bright to
+    /// find a face, keeping whatever finds one for the rest of the scan
+    pub bracketing: bool,
+}
+
+impl Default for CaptureConfig {
+    fn default() -> Self {
+        Self {
+            backend: CaptureBackendConfig::default(),
+            buffers: DEFAULT_BUFFERS,
+            io: IoMethodConfig::default(),
+            bracketing: true,
+        }
+    }
+}
+
+impl CaptureConfig {
+    pub fn settings(&self) -> CaptureSettings {
+        CaptureSettings {
+            backend: self.backend.into(),
+            buffers: self.buffers,
+            io: self.io.into(),
+        }
+    }
+}
+
+/// Stack cameras are captured through
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum CaptureBackendConfig {
+    #[default]
+    V4l2,
+    Pipewire,
+}
+
+impl From<CaptureBackendConfig> for CaptureBackend {
+    fn from(backend: CaptureBackendConfig) -> Self {
+        match backend {
+            CaptureBackendConfig::V4l2 => CaptureBackend::V4l2,
+            CaptureBackendConfig::Pipewire => CaptureBackend::Pipewire,
+        }
+    }
+}
+
+/// V4L2 streaming I/O method
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum IoMethodConfig {
+    #[default]
+    Mmap,
+    Userptr,
+}
+
+impl From<IoMethodConfig> for IoMethod {
+    fn from(io: IoMethodConfig) -> Self {
+        match io {
+            IoMethodConfig::Mmap => IoMethod::Mmap,
+            IoMethodConfig::Userptr => IoMethod::UserPtr,
+        }
+    }
+}
+
+/// Settings that only apply to the PAM module
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct PamConfig {
+    #[serde(default)]
+    pub fallback: FallbackConfig,
+}
+
+/// Settings for `howrs daemon`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DaemonConfig {
+    /// Address for the OpenMetrics endpoint, e.g. `127.0.0.1:9464`; off when unset
+    #[serde(default)]
+    pub metrics_addr: Option<String>,
+    /// Run one inference on a blank frame at startup, so the first
+    /// authentication doesn't pay for runtime initialization
+    #[serde(default = "default_warm_up")]
+    pub warm_up: bool,
+}
+
+impl Default for DaemonConfig {
+    fn default() -> Self {
+        Self {
+            metrics_addr: None,
+            warm_up: default_warm_up(),
+        }
+    }
+}
+
+fn default_warm_up() -> bool {
+    true
+}
+
+/// Logistic curve turning similarity into a match probability; refit with
+/// `howrs-eval` on your own faces
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+#[serde(default)]
+pub struct CalibrationConfig {
+    pub slope: f32,
+    pub intercept: f32,
+}
+
+impl Default for CalibrationConfig {
+    fn default() -> Self {
+        let Calibration { slope, intercept } = Calibration::SFACE;
+        Self { slope, intercept }
+    }
+}
+
+impl CalibrationConfig {
+    pub fn calibration(&self) -> Calibration {
+        Calibration {
+            slope: self.slope,
+            intercept: self.intercept,
+        }
+    }
+}
+
+/// `[weighting]`: lower the scores of old or poorly captured records, so
+/// recent enrollments win as the user's appearance drifts
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+#[serde(default)]
+pub struct WeightingConfig {
+    /// Similarity taken off per year since a record was enrolled
+    pub age_per_year: f32,
+    /// Cap on the age penalty
+    pub max_age: f32,
+    /// Similarity taken off a record enrolled with detector confidence `q`
+    /// is `quality * (1 - q)`
+    pub quality: f32,
+}
+
+impl Default for WeightingConfig {
+    fn default() -> Self {
+        Self {
+            age_per_year: 0.0,
+            max_age: 0.05,
+            quality: 0.0,
+        }
+    }
+}
+
+impl WeightingConfig {
+    pub fn weights(&self) -> RecordWeights {
+        RecordWeights {
+            age_per_year: self.age_per_year,
+            max_age: self.max_age,
+            quality: self.quality,
+        }
+    }
+}
+
+/// Face detector settings
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct DetectionConfig {
+    #[serde(default)]
+    pub backend: DetectorBackend,
+    /// ONNX file for the detector; required for `scrfd`, optional for `yunet`
+    #[serde(default)]
+    pub model: Option<PathBuf>,
+    /// Smaller faces are ignored
+    #[serde(default)]
+    pub min_face_size: Option<FaceSizeConfig>,
+    /// Larger faces are ignored
+    #[serde(default)]
+    pub max_face_size: Option<FaceSizeConfig>,
+    /// Centered fraction of the frame searched during authentication, e.g. 0.6
+    #[serde(default)]
+    pub roi: Option<f32>,
+    /// Frames are shrunk so their longer side is at most this many pixels
+    /// before detection; faces are still aligned from the full frame
+    #[serde(default)]
+    pub detect_size: Option<u32>,
+    /// Minimum detector confidence; IR cameras often need a lower one.
+    /// Defaults to 0.6, and 0.5 during authentication.
+    #[serde(default)]
+    pub threshold: Option<f32>,
+    /// Overlap above which the weaker of two detections is dropped
+    #[serde(default)]
+    pub nms_threshold: Option<f32>,
+}
+
+/// Face detector implementation
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum DetectorBackend {
+    /// OpenCV YuNet, embedded in the binary
+    #[default]
+    Yunet,
+    /// InsightFace SCRFD (keypoint variant), better with off-angle faces
+    Scrfd,
+}
+
+impl From<DetectorBackend> for Backend {
+    fn from(backend: DetectorBackend) -> Self {
+        match backend {
+            DetectorBackend::Yunet => Backend::YuNet,
+            DetectorBackend::Scrfd => Backend::Scrfd,
+        }
+    }
+}
+
+/// Face size in pixels (an integer, `80`) or as a fraction of the shorter
+/// side of the frame (a float, `0.1`)
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum FaceSizeConfig {
+    Pixels(u32),
+    Fraction(f32),
+}
+
+impl From<FaceSizeConfig> for FaceSize {
+    fn from(size: FaceSizeConfig) -> Self {
+        match size {
+            FaceSizeConfig::Pixels(px) => FaceSize::Pixels(px),
+            FaceSizeConfig::Fraction(f) => FaceSize::Fraction(f),
+        }
+    }
+}
+
+impl DetectionConfig {
+    pub fn score_threshold(&self) -> f32 {
+        self.threshold.unwrap_or(DEFAULT_SCORE_THRESHOLD)
+    }
+
+    /// Authentication accepts weaker detections by default, since the
+    /// recognition threshold still has to be met
+    pub fn auth_score_threshold(&self) -> f32 {
+        self.threshold.unwrap_or(0.5)
+    }
+
+    pub fn nms_threshold(&self) -> f32 {
+        self.nms_threshold.unwrap_or(DEFAULT_NMS_THRESHOLD)
+    }
+
+    pub fn size_filter(&self) -> SizeFilter {
+        SizeFilter {
+            min: self.min_face_size.map(FaceSize::from),
+            max: self.max_face_size.map(FaceSize::from),
+        }
+    }
+}
+
+/// Face recognition model settings
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct RecognitionConfig {
+    /// ONNX file replacing the embedded SFace encoder; re-enroll after changing it
+    #[serde(default)]
+    pub model: Option<PathBuf>,
+    /// Crop the model expects; defaults to the embedded model's
+    #[serde(default)]
+    pub alignment: Option<AlignmentConfig>,
+}
+
+/// Reference crop for face alignment
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum AlignmentConfig {
+    /// 112x112 ArcFace crop (SFace, most ArcFace exports)
+    #[serde(rename = "arcface-112")]
+    Arcface112,
+    /// 96x112 SphereFace/CosFace crop
+    #[serde(rename = "arcface-96x112")]
+    Arcface96x112,
+    /// 128x128 crop
+    #[serde(rename = "arcface-128")]
+    Arcface128,
+}
+
+impl From<AlignmentConfig> for AlignTemplate {
+    fn from(alignment: AlignmentConfig) -> Self {
+        match alignment {
+            AlignmentConfig::Arcface112 => AlignTemplate::ARCFACE_112,
+            AlignmentConfig::Arcface96x112 => AlignTemplate::ARCFACE_96X112,
+            AlignmentConfig::Arcface128 => AlignTemplate::ARCFACE_128,
+        }
+    }
+}
+
+/// Frames skipped during authentication instead of being encoded; 0 turns a
+/// check off
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct QualityConfig {
+    /// Detector confidence, 0-1
+    pub min_score: f32,
+    /// Variance of the Laplacian over the face; `howrs test -v` prints it
+    pub min_sharpness: f32,
+    pub min_face_size: Option<FaceSizeConfig>,
+}
+
+impl QualityConfig {
+    pub fn gate(&self) -> QualityGate {
+        QualityGate {
+            min_score: self.min_score,
+            min_sharpness: self.min_sharpness,
+            min_face_size: self.min_face_size.map(FaceSize::from),
+        }
+    }
+}
+
+/// Checks, on top of the match, that a live person is in front of the camera
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct LivenessConfig {
+    /// Once the face matches, ask the user to turn their head to a random
+    /// side and accept only when they do within the scan time
+    pub challenge: bool,
+    /// ONNX anti-spoofing (presentation attack detection) model, e.g.
+    /// MiniFASNetV2, run on faces that match
+    pub pad_model: Option<PathBuf>,
+    /// Least live probability from `pad_model` accepted
+    pub pad_threshold: f32,
+    /// Enlargement of the face box `pad_model` was trained with
+    pub pad_scale: f32,
+    /// Least change of the matched face over the last second, see
+    /// [`howrs_vision::motion::Motion`]; 0 turns the check off
+    pub min_motion: f32,
+}
+
+impl Default for LivenessConfig {
+    fn default() -> Self {
+        Self {
+            challenge: false,
+            pad_model: None,
+            pad_threshold: pad::DEFAULT_THRESHOLD,
+            pad_scale: pad::DEFAULT_SCALE,
+            min_motion: 0.0,
+        }
+    }
+}
+
+/// `[companion]`: a second camera, typically the color one next to an IR
+/// camera, checked once `camera` has matched. A printed photo or a screen
+/// rarely passes for a face on both sensors at once.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CompanionConfig {
+    pub camera: String,
+    #[serde(default)]
+    pub mode: CompanionMode,
+    /// Share of the companion camera's score in the fused one, 0-1
+    #[serde(default = "default_companion_weight")]
+    pub weight: f32,
+}
+
+fn default_companion_weight() -> f32 {
+    0.5
+}
+
+impl CompanionConfig {
+    /// Score compared with `threshold` in [`CompanionMode::Fuse`]
+    pub fn fuse(&self, main: f32, companion: f32) -> f32 {
+        let weight = self.weight.clamp(0.0, 1.0);
+        (1.0 - weight) * main + weight * companion
+    }
+}
+
+/// What the companion camera has to confirm
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum CompanionMode {
+    /// A face is detected in its frame
+    #[default]
+    Detect,
+    /// The face there also matches, and both scores fused by `weight` meet
+    /// the threshold
+    Fuse,
+}
+
+/// `[depth]`: a depth camera (RealSense, time-of-flight) delivering `Z16`
+/// frames, checked once a face has matched: a photo or a screen is flat
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DepthConfig {
+    pub camera: String,
+    /// Millimetres per depth unit; 1 for RealSense
+    #[serde(default = "default_depth_scale")]
+    pub scale: f32,
+    /// Least relief of the face in millimetres, see [`DepthGate`]
+    #[serde(default = "default_min_relief")]
+    pub min_relief: f32,
+}
+
+fn default_depth_scale() -> f32 {
+    1.0
+}
+
+fn default_min_relief() -> f32 {
+    DepthGate::default().min_relief
+}
+
+impl DepthConfig {
+    pub fn gate(&self) -> DepthGate {
+        DepthGate {
+            min_relief: self.min_relief,
+            ..Default::default()
         }
     }
 }
 
+/// `[store]`: ownership and modes of the face store, applied whenever it is
+/// written and checked by `howrs doctor`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct StoreConfig {
+    /// Mode of each user's directory
+    pub dir_mode: u32,
+    /// Mode of the face and match files
+    pub file_mode: u32,
+    /// Group given read access to the store, for display managers whose
+    /// greeter authenticates without root; ignored while it doesn't exist
+    pub group: Option<String>,
+}
+
+impl Default for StoreConfig {
+    fn default() -> Self {
+        Self {
+            dir_mode: 0o700,
+            file_mode: 0o600,
+            group: Some("howrs".to_string()),
+        }
+    }
+}
+
+/// Preprocessing of grayscale frames
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum NormalizationConfig {
+    #[default]
+    None,
+    /// Global histogram equalization
+    Equalize,
+    /// Adaptive (tiled) histogram equalization, better with uneven lighting
+    Clahe,
+}
+
+impl From<NormalizationConfig> for Normalization {
+    fn from(normalization: NormalizationConfig) -> Self {
+        match normalization {
+            NormalizationConfig::None => Normalization::None,
+            NormalizationConfig::Equalize => Normalization::Equalize,
+            NormalizationConfig::Clahe => Normalization::Clahe,
+        }
+    }
+}
+
+/// Which variant of the default models to load
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum PrecisionConfig {
+    #[default]
+    Fp32,
+    Int8,
+    /// Whichever `howrs models benchmark` found faster, float until it has run
+    Auto,
+}
+
+impl PrecisionConfig {
+    /// Resolve `Auto` against the benchmark result saved in `model_dir`
+    pub fn resolve(self, model_dir: &Path) -> Precision {
+        match self {
+            PrecisionConfig::Fp32 => Precision::Fp32,
+            PrecisionConfig::Int8 => Precision::Int8,
+            PrecisionConfig::Auto => model::selected_precision(model_dir).unwrap_or_default(),
+        }
+    }
+}
+
+/// Whether to use the low memory profile, see [`Config::is_low_memory`]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum LowMemoryConfig {
+    /// On when the machine has less than [`LOW_MEMORY_RAM`]
+    #[default]
+    Auto,
+    On,
+    Off,
+}
+
+/// One rung of the authentication fallback ladder
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum FallbackStage {
+    /// Ask the `howrs daemon` helper, which keeps models loaded
+    Daemon,
+    /// Load the models inside the PAM process and scan directly
+    InProcess,
+}
+
+/// `[pam.fallback]`: stages tried in order until one gives an answer.
+///
+/// A stage that errors or times out hands over to the next one; a definite
+/// "face did not match" ends the ladder. When every stage fails the module
+/// reports the authentication info as unavailable so PAM falls through to
+/// the password prompt.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct FallbackConfig {
+    pub stages: Vec<FallbackStage>,
+    pub daemon_socket: PathBuf,
+    /// Seconds to wait for the daemon's verdict
+    pub daemon_timeout: u32,
+    /// Seconds allowed for the in-process scan; `0` uses `scan_durnation`
+    pub in_process_timeout: u32,
+}
+
+impl Default for FallbackConfig {
+    fn default() -> Self {
+        Self {
+            stages: vec![FallbackStage::Daemon, FallbackStage::InProcess],
+            daemon_socket: PathBuf::from("/run/howrs/daemon.sock"),
+            daemon_timeout: 5,
+            in_process_timeout: 0,
+        }
+    }
+}
+
+impl FallbackConfig {
+    /// Time allowed for `stage`, given the top-level `scan_durnation`
+    pub fn stage_timeout(&self, stage: FallbackStage, scan_durnation: u32) -> Duration {
+        let secs = match (stage, self.in_process_timeout) {
+            (FallbackStage::Daemon, _) => self.daemon_timeout,
+            (FallbackStage::InProcess, 0) => scan_durnation,
+            (FallbackStage::InProcess, secs) => secs,
+        };
+        Duration::from_secs(secs as u64)
+    }
+
+    /// Longest the whole ladder may take
+    pub fn timeout(&self, scan_durnation: u32) -> Duration {
+        self.stages
+            .iter()
+            .map(|&stage| self.stage_timeout(stage, scan_durnation))
+            .sum()
+    }
+}
+
+/// `$HOWRS_CONFIG`, unless we are running with elevated privileges.
+///
+/// The PAM module runs inside setuid programs like `sudo`, whose environment
+/// is controlled by the unprivileged caller; honoring the variable there
+/// would let anyone swap in a config with a zero threshold.
+fn config_env_override() -> Option<PathBuf> {
+    if identity::is_elevated() {
+        return None;
+    }
+    std::env::var_os(CONFIG_ENV)
+        .filter(|v| !v.is_empty())
+        .map(PathBuf::from)
+}
+
+/// System config used by the PAM module and daemon: `$HOWRS_CONFIG`, then
+/// [`SYSTEM_CONFIG_PATH`] if it exists, then the compile-time [`CONFIG_PATH`]
+pub fn config_path() -> PathBuf {
+    if let Some(path) = config_env_override() {
+        return path;
+    }
+    let system = Path::new(SYSTEM_CONFIG_PATH);
+    if system.exists() {
+        return system.to_path_buf();
+    }
+    CONFIG_PATH.to_path_buf()
+}
+
+/// Per-user config, `$XDG_CONFIG_HOME/howrs/config.toml`
+pub fn user_config_path() -> Option<PathBuf> {
+    directories::BaseDirs::new().map(|dirs| dirs.config_dir().join("howrs").join("config.toml"))
+}
+
+/// Config for CLI-only operations: `$HOWRS_CONFIG`, then the per-user config
+/// if it exists, then [`config_path`]
+pub fn cli_config_path() -> PathBuf {
+    if let Some(path) = config_env_override() {
+        return path;
+    }
+    match user_config_path() {
+        Some(path) if path.exists() => path,
+        _ => config_path(),
+    }
+}
+
 pub fn load_config(path: Option<&Path>) -> Result<Config> {
-    let path = path.unwrap_or(&CONFIG_PATH);
+    let default_path = config_path();
+    let path = path.unwrap_or(&default_path);
     if !path.exists() {
         return Ok(Config::default());
     }
-    let raw = std::fs::read_to_string(path)
-        .with_context(|| format!("reading config at {}", path.display()))?;
-    toml::from_str(&raw).with_context(|| format!("parsing config {}", path.display()))
+    let raw =
+        std::fs::read_to_string(path).config(format!("reading config at {}", path.display()))?;
+    toml::from_str(&raw).config(format!("parsing config {}", path.display()))
+}
+
+/// Config for authentication. A file that can't be read or parsed gives
+/// the compiled defaults along with the error to warn about, so a typo
+/// doesn't lock everyone out of sudo; unless it sets `strict_mode = true`,
+/// in which case the error is returned.
+pub fn load_auth_config(path: Option<&Path>) -> Result<(Config, Option<Error>)> {
+    let default_path = config_path();
+    let path = path.unwrap_or(&default_path);
+    match load_config(Some(path)) {
+        Ok(config) => Ok((config, None)),
+        Err(e) if is_strict(path) => Err(e.context("strict_mode is set")),
+        Err(e) => Ok((Config::default(), Some(e))),
+    }
+}
+
+/// Whether the (malformed) file at `path` asks for `strict_mode`, read
+/// leniently since it didn't parse as a [`Config`]
+fn is_strict(path: &Path) -> bool {
+    let Ok(raw) = std::fs::read_to_string(path) else {
+        return false;
+    };
+    match raw.parse::<toml::Table>() {
+        Ok(table) => table.get("strict_mode").and_then(toml::Value::as_bool) == Some(true),
+        // Broken TOML: look for the line itself
+        Err(_) => raw.lines().any(|line| {
+            line.split('#').next().unwrap_or_default().replace(' ', "") == "strict_mode=true"
+        }),
+    }
+}
+
+/// Whether the kill switch file exists
+pub fn is_disabled() -> bool {
+    DISABLED_PATH.exists()
+}
+
+/// Create or remove the kill switch file
+pub fn set_disabled(disabled: bool) -> Result<()> {
+    let path: &Path = &DISABLED_PATH;
+    if disabled {
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent).config(format!("creating {}", parent.display()))?;
+        }
+        std::fs::write(path, "").config(format!("creating {}", path.display()))
+    } else if path.exists() {
+        std::fs::remove_file(path).config(format!("removing {}", path.display()))
+    } else {
+        Ok(())
+    }
 }
 
 pub fn save_config(cfg: &Config, path: Option<&Path>) -> Result<()> {
-    let path = path.unwrap_or(&CONFIG_PATH);
-    let data = toml::to_string_pretty(cfg)?;
+    let default_path = config_path();
+    let path = path.unwrap_or(&default_path);
+    let data = toml::to_string_pretty(cfg).config("serializing the config")?;
     if let Some(parent) = path.parent() {
-        std::fs::create_dir_all(parent)?;
+        std::fs::create_dir_all(parent).config(format!("creating {}", parent.display()))?;
+    }
+    std::fs::write(path, data).config(format!("writing {}", path.display()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_store_modes() {
+        let cfg: Config = toml::from_str(
+            "threshold = 0.6\ncamera = \"/dev/video0\"\nscan_durnation = 5\n\
+             [store]\ndir_mode = 0o750\n",
+        )
+        .unwrap();
+        assert_eq!(cfg.store.dir_mode, 0o750);
+        assert_eq!(cfg.store.file_mode, 0o600);
+        assert_eq!(cfg.store.group.as_deref(), Some("howrs"));
+    }
+
+    #[test]
+    fn test_metric() {
+        let cfg: Config =
+            toml::from_str("threshold = 0.6\ncamera = \"/dev/video0\"\nscan_durnation = 5\n")
+                .unwrap();
+        assert_eq!(cfg.metric, Metric::Cosine);
+        assert_eq!(cfg.similarity_threshold(), 0.6);
+
+        let cfg: Config = toml::from_str(
+            "threshold = 1.128\nmetric = \"euclidean\"\ncamera = \"/dev/video0\"\nscan_durnation = 5\n",
+        )
+        .unwrap();
+        assert!((cfg.similarity_threshold() - 0.3638).abs() < 1e-4);
+    }
+
+    #[test]
+    fn test_capture() {
+        let cfg: Config =
+            toml::from_str("threshold = 0.6\ncamera = \"/dev/video0\"\nscan_durnation = 5\n")
+                .unwrap();
+        assert_eq!(cfg.capture.settings(), CaptureSettings::default());
+
+        let cfg: Config = toml::from_str(
+            "threshold = 0.6\ncamera = \"/dev/video0\"\nscan_durnation = 5\n\
+             [capture]\nbuffers = 2\nio = \"userptr\"\n",
+        )
+        .unwrap();
+        assert_eq!(cfg.capture.settings().buffers, 2);
+        assert_eq!(cfg.capture.settings().io, IoMethod::UserPtr);
+
+        let cfg: Config = toml::from_str(
+            "threshold = 0.6\ncamera = \"\"\nscan_durnation = 5\n\
+             [capture]\nbackend = \"pipewire\"\n",
+        )
+        .unwrap();
+        assert_eq!(cfg.capture.settings().backend, CaptureBackend::Pipewire);
+    }
+
+    #[test]
+    fn test_fallback_defaults() {
+        let cfg: Config =
+            toml::from_str("threshold = 0.6\ncamera = \"/dev/video0\"\nscan_durnation = 5\n")
+                .unwrap();
+        assert_eq!(
+            cfg.pam.fallback.stages,
+            [FallbackStage::Daemon, FallbackStage::InProcess]
+        );
+
+        let cfg: Config = toml::from_str(
+            "threshold = 0.6\ncamera = \"/dev/video0\"\nscan_durnation = 5\n\n[pam.fallback]\nstages = [\"in-process\"]\n",
+        )
+        .unwrap();
+        assert_eq!(cfg.pam.fallback.stages, [FallbackStage::InProcess]);
+        assert_eq!(cfg.pam.fallback.daemon_timeout, 5);
+        assert_eq!(cfg.pam.fallback.timeout(7), Duration::from_secs(7));
+
+        let fallback = FallbackConfig {
+            in_process_timeout: 3,
+            ..Default::default()
+        };
+        assert_eq!(fallback.timeout(7), Duration::from_secs(8));
+    }
+
+    #[test]
+    fn test_companion() {
+        let cfg: Config = toml::from_str(
+            "threshold = 0.6\ncamera = \"/dev/video2\"\nscan_durnation = 5\n\n[companion]\ncamera = \"/dev/video0\"\nmode = \"fuse\"\n",
+        )
+        .unwrap();
+        let companion = cfg.companion.unwrap();
+        assert_eq!(companion.mode, CompanionMode::Fuse);
+        assert!((companion.fuse(0.8, 0.4) - 0.6).abs() < 1e-6);
+
+        let cfg: Config =
+            toml::from_str("threshold = 0.6\ncamera = \"/dev/video0\"\nscan_durnation = 5\n")
+                .unwrap();
+        assert!(cfg.companion.is_none());
+    }
+
+    #[test]
+    fn test_liveness_defaults() {
+        let cfg: Config =
+            toml::from_str("threshold = 0.6\ncamera = \"/dev/video0\"\nscan_durnation = 5\n")
+                .unwrap();
+        assert!(!cfg.liveness.challenge);
+        assert_eq!(cfg.liveness.min_motion, 0.0);
+
+        let cfg: Config = toml::from_str(
+            "threshold = 0.6\ncamera = \"/dev/video0\"\nscan_durnation = 5\n\n[liveness]\nchallenge = true\n",
+        )
+        .unwrap();
+        assert!(cfg.liveness.challenge);
+        assert!(cfg.liveness.pad_model.is_none());
+        assert_eq!(cfg.liveness.pad_scale, pad::DEFAULT_SCALE);
+    }
+
+    #[test]
+    fn test_face_size_units() {
+        let cfg: Config = toml::from_str(
+            "threshold = 0.6\ncamera = \"/dev/video0\"\nscan_durnation = 5\n\n[detection]\nmin_face_size = 80\nmax_face_size = 0.9\ndetect_size = 320\n",
+        )
+        .unwrap();
+        assert_eq!(
+            cfg.detection.min_face_size,
+            Some(FaceSizeConfig::Pixels(80))
+        );
+        assert_eq!(
+            cfg.detection.max_face_size,
+            Some(FaceSizeConfig::Fraction(0.9))
+        );
+        assert_eq!(cfg.detection.backend, DetectorBackend::Yunet);
+        assert_eq!(cfg.detection.detect_size, Some(320));
+    }
+
+    #[test]
+    fn test_quality_gate() {
+        let cfg: Config = toml::from_str(
+            "threshold = 0.6\ncamera = \"/dev/video0\"\nscan_durnation = 5\n\n[quality]\nmin_sharpness = 40.0\nmin_face_size = 0.2\n",
+        )
+        .unwrap();
+        let gate = cfg.quality.gate();
+        assert_eq!(gate.min_score, 0.0);
+        assert_eq!(gate.min_sharpness, 40.0);
+        assert_eq!(gate.min_face_size, Some(FaceSize::Fraction(0.2)));
+    }
+
+    #[test]
+    fn test_recognition_alignment() {
+        let cfg: Config = toml::from_str(
+            "threshold = 0.6\ncamera = \"/dev/video0\"\nscan_durnation = 5\n\n[recognition]\nmodel = \"/opt/cosface.onnx\"\nalignment = \"arcface-96x112\"\n",
+        )
+        .unwrap();
+        let alignment = AlignTemplate::from(cfg.recognition.alignment.unwrap());
+        assert_eq!((alignment.width, alignment.height), (96, 112));
+        assert!(cfg.recognition.model.is_some());
+    }
+
+    #[test]
+    fn test_normalization() {
+        let base = "threshold = 0.6\ncamera = \"/dev/video0\"\nscan_durnation = 5\n";
+        let cfg: Config = toml::from_str(base).unwrap();
+        assert_eq!(cfg.normalization, NormalizationConfig::None);
+
+        let cfg: Config = toml::from_str(&format!("normalization = \"clahe\"\n{}", base)).unwrap();
+        assert_eq!(Normalization::from(cfg.normalization), Normalization::Clahe);
+    }
+
+    #[test]
+    fn test_low_memory() {
+        assert_eq!(
+            parse_mem_total("MemTotal:         945512 kB\nMemFree:  1 kB\n"),
+            Some(945512 * 1024)
+        );
+        assert_eq!(parse_mem_total("MemFree: 1 kB\n"), None);
+
+        let base = "threshold = 0.6\ncamera = \"/dev/video0\"\nscan_durnation = 5\n\
+                    flip_augment = true\n";
+        let cfg: Config = toml::from_str(&format!("low_memory = \"on\"\n{}", base)).unwrap();
+        assert!(cfg.is_low_memory());
+        assert_eq!(cfg.model_precision(), Precision::Int8);
+        assert_eq!(cfg.detect_size(), Some(LOW_MEMORY_DETECT_SIZE));
+        assert!(!cfg.use_flip_augment());
+        assert_eq!(cfg.inference_threads(), 1);
+
+        let cfg: Config = toml::from_str(&format!(
+            "low_memory = \"off\"\n{}[detection]\ndetect_size = 480\n",
+            base
+        ))
+        .unwrap();
+        assert_eq!(cfg.model_precision(), Precision::Fp32);
+        assert_eq!(cfg.detect_size(), Some(480));
+        assert!(cfg.use_flip_augment());
+        assert_eq!(cfg.inference_threads(), 0);
+    }
+
+    #[test]
+    fn test_auth_config_fallback() {
+        let path =
+            std::env::temp_dir().join(format!("howrs-test-auth-{}.toml", std::process::id()));
+        let valid = "threshold = 0.7\ncamera = \"/dev/video0\"\nscan_durnation = 5\n";
+        std::fs::write(&path, valid).unwrap();
+        let (cfg, error) = load_auth_config(Some(&path)).unwrap();
+        assert_eq!(cfg.threshold, 0.7);
+        assert!(error.is_none());
+
+        // A typo falls back to the defaults
+        std::fs::write(&path, "threshold = \"high\"\n").unwrap();
+        let (cfg, error) = load_auth_config(Some(&path)).unwrap();
+        assert_eq!(cfg.threshold, Config::default().threshold);
+        assert!(error.is_some());
+
+        std::fs::write(&path, "strict_mode = true\nthreshold = \"high\"\n").unwrap();
+        assert!(load_auth_config(Some(&path)).is_err());
+        std::fs::write(&path, "strict_mode = true\n[pam\n").unwrap();
+        assert!(load_auth_config(Some(&path)).is_err());
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_config_env_override() {
+        let path = std::env::temp_dir().join("howrs-test-config.toml");
+        std::env::set_var(CONFIG_ENV, &path);
+        assert_eq!(config_path(), path);
+        assert_eq!(cli_config_path(), path);
+
+        std::env::set_var(CONFIG_ENV, "");
+        assert_ne!(config_path(), path);
+        std::env::remove_var(CONFIG_ENV);
     }
-    std::fs::write(path, data)?;
-    Ok(())
 }
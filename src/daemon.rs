@@ -0,0 +1,634 @@
+//! `howrs daemon`: a long-running helper that keeps the models loaded so the
+//! PAM module doesn't pay the model load on every prompt.
+//!
+//! The protocol is one newline-terminated request per connection on a Unix
+//! socket:
+//!
+//! ```text
+//! -> AUTH <user> <timeout_ms>
+//! -> IDENTIFY <timeout_ms>
+//! -> ENROLL <user> <timeout_ms>
+//! -> PURGE <user>
+//! -> PING
+//! <- SAY <message>            (any number, before the answer)
+//! <- OK | OK <user> | FAIL | FAIL <reason> | ERR <message>
+//! ```
+//!
+//! `SAY` carries a liveness challenge for the client to show the user while
+//! an `AUTH` or `IDENTIFY` scan goes on.
+//!
+//! For `AUTH` the daemon only reports whether the face in front of the
+//! camera matches `<user>`; the PAM module in the requesting process makes
+//! the decision. `IDENTIFY` matches against every enrolled user and answers
+//! `OK <user>` with the one it saw. A failed `AUTH` says why, e.g.
+//! `FAIL too-dark`; `below-threshold` carries the best score only for root
+//! and `<user>` themselves. `ENROLL` and `PURGE` change the face store and are
+//! authorized through polkit (see [`crate::polkit`]). `PING` only answers
+//! `OK`; it is answered once the models are loaded, so it doubles as a way
+//! to start and warm up a socket-activated daemon.
+
+use crate::auth::{self, AuthFailure, AuthResult, Prompt};
+use crate::error::{Error, Report, Result, ResultExt};
+use crate::metrics::{self, Outcome};
+use crate::polkit::Subject;
+use crate::{config::Config, identity, storage, Pipeline};
+use howrs_vision::cancel::CancelToken;
+use std::io::{BufRead, BufReader, Write};
+use std::os::fd::{AsRawFd, FromRawFd, RawFd};
+use std::os::unix::fs::PermissionsExt;
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+/// How often blocked waits check for cancellation
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// One client request
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Request<'a> {
+    Auth { user: &'a str, timeout: Duration },
+    Identify { timeout: Duration },
+    Enroll { user: &'a str, timeout: Duration },
+    Purge { user: &'a str },
+    Ping,
+}
+
+/// Daemon answer to a request
+#[derive(Debug, Clone, PartialEq)]
+pub enum Reply {
+    Ok,
+    /// Answer to `IDENTIFY`: the user whose face was seen
+    Matched(String),
+    /// No match, and for `AUTH` why not
+    Fail(Option<AuthFailure>),
+    Err(String),
+}
+
+impl Reply {
+    fn encode(&self) -> String {
+        match self {
+            Reply::Ok => "OK\n".to_string(),
+            Reply::Matched(user) => format!("OK {}\n", user),
+            Reply::Fail(None) => "FAIL\n".to_string(),
+            Reply::Fail(Some(failure)) => format!("FAIL {}\n", failure.encode()),
+            Reply::Err(msg) => format!("ERR {}\n", msg.replace('\n', " ")),
+        }
+    }
+
+    fn decode(line: &str) -> Result<Self> {
+        let line = line.trim_end();
+        match line {
+            "OK" => Ok(Reply::Ok),
+            "FAIL" => Ok(Reply::Fail(None)),
+            _ => {
+                if let Some(user) = line.strip_prefix("OK ") {
+                    return Ok(Reply::Matched(check_user(user)?.to_string()));
+                }
+                if let Some(failure) = line.strip_prefix("FAIL ") {
+                    return Ok(Reply::Fail(Some(AuthFailure::decode(failure)?)));
+                }
+                match line.strip_prefix("ERR ") {
+                    Some(msg) => Ok(Reply::Err(msg.to_string())),
+                    None => Err(Error::daemon(format!("malformed daemon reply: {:?}", line))),
+                }
+            }
+        }
+    }
+}
+
+/// Ask the daemon at `socket` to authenticate `user`.
+///
+/// `timeout` bounds the whole exchange: the daemon is told to stop scanning
+/// at that point and the socket read gives up shortly after. Cancelling
+/// `cancel` hangs up, which stops the daemon's scan too. Challenges the
+/// daemon sends are passed to `prompt`.
+pub fn request_auth(
+    socket: &Path,
+    user: &str,
+    timeout: Duration,
+    cancel: &CancelToken,
+    prompt: Prompt<'_>,
+) -> Result<AuthResult> {
+    let request = format!("AUTH {} {}", check_user(user)?, timeout.as_millis());
+    match wait_reply(socket, &request, timeout, cancel, prompt)? {
+        Reply::Ok => Ok(AuthResult::Matched),
+        Reply::Fail(Some(failure)) => Ok(AuthResult::Failed(failure)),
+        Reply::Fail(None) => Err(Error::daemon(
+            "daemon did not say why authentication failed",
+        )),
+        Reply::Matched(user) => Err(Error::daemon(format!(
+            "unexpected daemon reply naming {}",
+            user
+        ))),
+        Reply::Err(msg) => Err(Error::daemon(format!("daemon error: {}", msg))),
+    }
+}
+
+/// Ask the daemon at `socket` which enrolled user, if any, is in front of the
+/// camera. Same timeout, cancellation and prompt rules as [`request_auth`].
+pub fn request_identify(
+    socket: &Path,
+    timeout: Duration,
+    cancel: &CancelToken,
+    prompt: Prompt<'_>,
+) -> Result<Option<String>> {
+    let request = format!("IDENTIFY {}", timeout.as_millis());
+    match wait_reply(socket, &request, timeout, cancel, prompt)? {
+        Reply::Matched(user) => Ok(Some(user)),
+        Reply::Fail(_) => Ok(None),
+        Reply::Ok => Err(Error::daemon("daemon did not name the matched user")),
+        Reply::Err(msg) => Err(Error::daemon(format!("daemon error: {}", msg))),
+    }
+}
+
+/// Send `request` and wait for the reply to a scan bounded by `timeout`,
+/// showing `SAY` lines with `prompt` on the way
+fn wait_reply(
+    socket: &Path,
+    request: &str,
+    timeout: Duration,
+    cancel: &CancelToken,
+    prompt: Prompt<'_>,
+) -> Result<Reply> {
+    // Leave the daemon a little slack to send its verdict after the deadline
+    let stream = connect(socket, request, Some(timeout + Duration::from_millis(500)))?;
+
+    let deadline = Instant::now() + timeout + Duration::from_millis(500);
+    let mut reader = BufReader::new(stream);
+    loop {
+        // A line already buffered with the previous one needs no wait
+        while reader.buffer().is_empty() && !poll_readable(reader.get_ref(), POLL_INTERVAL)? {
+            cancel.check()?;
+            if Instant::now() >= deadline {
+                return Err(Error::daemon("timed out waiting for daemon reply"));
+            }
+        }
+        let mut line = String::new();
+        reader
+            .read_line(&mut line)
+            .daemon("waiting for daemon reply")?;
+        match line.strip_prefix("SAY ") {
+            Some(message) => prompt(message.trim_end()),
+            None => return Reply::decode(&line),
+        }
+    }
+}
+
+/// Send `message` to the client as a `SAY` line
+fn say(mut stream: &UnixStream, message: &str) {
+    let line = format!("SAY {}\n", message.replace('\n', " "));
+    if let Err(e) = stream.write_all(line.as_bytes()) {
+        tracing::warn!("failed to send prompt: {}", e);
+    }
+}
+
+/// Ask the daemon at `socket` to capture and enroll a face for `user`.
+///
+/// Returns `false` if no usable face was seen within `timeout`. The daemon
+/// may first show a polkit prompt, so the reply is waited for indefinitely.
+pub fn request_enroll(socket: &Path, user: &str, timeout: Duration) -> Result<bool> {
+    let request = format!("ENROLL {} {}", check_user(user)?, timeout.as_millis());
+    send(socket, &request, None)
+}
+
+/// Ask the daemon at `socket` to remove all faces of `user`
+pub fn request_purge(socket: &Path, user: &str) -> Result<()> {
+    send(socket, &format!("PURGE {}", check_user(user)?), None)?;
+    Ok(())
+}
+
+/// Wait until the daemon at `socket` is up with its models loaded, starting
+/// it if it is socket activated
+pub fn request_ping(socket: &Path, timeout: Duration) -> Result<()> {
+    send(socket, "PING", Some(timeout))?;
+    Ok(())
+}
+
+fn check_user(user: &str) -> Result<&str> {
+    if user.is_empty() || user.contains(char::is_whitespace) {
+        return Err(Error::daemon(format!("invalid user name {:?}", user)));
+    }
+    Ok(user)
+}
+
+fn send(socket: &Path, request: &str, timeout: Option<Duration>) -> Result<bool> {
+    verdict(receive(connect(socket, request, timeout)?)?)
+}
+
+fn connect(socket: &Path, request: &str, timeout: Option<Duration>) -> Result<UnixStream> {
+    let mut stream = UnixStream::connect(socket)
+        .daemon(format!("connecting to daemon at {}", socket.display()))?;
+    stream
+        .set_read_timeout(timeout)
+        .and_then(|()| stream.set_write_timeout(timeout))
+        .and_then(|()| writeln!(stream, "{}", request))
+        .daemon("sending the request to the daemon")?;
+    Ok(stream)
+}
+
+fn receive(stream: UnixStream) -> Result<Reply> {
+    let mut line = String::new();
+    BufReader::new(stream)
+        .read_line(&mut line)
+        .daemon("waiting for daemon reply")?;
+    Reply::decode(&line)
+}
+
+fn verdict(reply: Reply) -> Result<bool> {
+    match reply {
+        Reply::Ok => Ok(true),
+        Reply::Fail(_) => Ok(false),
+        Reply::Matched(user) => Err(Error::daemon(format!(
+            "unexpected daemon reply naming {}",
+            user
+        ))),
+        Reply::Err(msg) => Err(Error::daemon(format!("daemon error: {}", msg))),
+    }
+}
+
+/// Bind `socket` and answer requests until the process is killed.
+///
+/// Under systemd socket activation (`howrs.socket`) the listening socket is
+/// inherited instead, and `socket` is only used for logging.
+pub fn serve(socket: &Path, config: &Config) -> Result<()> {
+    let listener = match activated_listener() {
+        Some(listener) => {
+            tracing::info!("using socket passed by systemd");
+            listener
+        }
+        None => bind(socket)?,
+    };
+
+    if let Some(addr) = &config.daemon.metrics_addr {
+        metrics::spawn_server(addr)?;
+    }
+
+    let mut pipeline = auth::load_auth_pipeline(config)?;
+    if config.daemon.warm_up {
+        match pipeline.warm_up() {
+            Ok(elapsed) => tracing::info!("models warmed up in {:?}", elapsed),
+            Err(e) => tracing::warn!("warm-up failed: {}", Report(&e)),
+        }
+    }
+    tracing::info!("daemon listening on {}", socket.display());
+
+    // Requests are served one at a time: there is only one camera anyway
+    for stream in listener.incoming() {
+        let stream = match stream {
+            Ok(stream) => stream,
+            Err(e) => {
+                tracing::warn!("accept failed: {}", e);
+                continue;
+            }
+        };
+        if let Err(e) = handle(stream, &mut pipeline, config) {
+            tracing::warn!("request failed: {}", Report(&e));
+        }
+    }
+    Ok(())
+}
+
+/// Whether `fd` has data to read or has hung up, waiting at most `timeout`
+pub(crate) fn poll_readable(fd: &impl AsRawFd, timeout: Duration) -> Result<bool> {
+    let mut pollfd = libc::pollfd {
+        fd: fd.as_raw_fd(),
+        events: libc::POLLIN,
+        revents: 0,
+    };
+    let ret = unsafe { libc::poll(&mut pollfd, 1, timeout.as_millis() as libc::c_int) };
+    if ret < 0 {
+        let err = std::io::Error::last_os_error();
+        if err.kind() == std::io::ErrorKind::Interrupted {
+            return Ok(false);
+        }
+        return Err(err).daemon("polling the socket");
+    }
+    Ok(ret > 0)
+}
+
+/// Cancel `cancel` if the client hangs up (or sends more data) before it is
+/// cancelled otherwise
+fn watch_hangup(stream: &UnixStream, cancel: &CancelToken) {
+    while !cancel.is_cancelled() {
+        match poll_readable(stream, POLL_INTERVAL) {
+            Ok(false) => {}
+            Ok(true) | Err(_) => {
+                tracing::debug!("client hung up");
+                cancel.cancel();
+            }
+        }
+    }
+}
+
+fn bind(socket: &Path) -> Result<UnixListener> {
+    if socket.exists() {
+        std::fs::remove_file(socket)
+            .daemon(format!("removing stale socket {}", socket.display()))?;
+    }
+    if let Some(parent) = socket.parent() {
+        std::fs::create_dir_all(parent).daemon(format!("creating {}", parent.display()))?;
+    }
+    let listener = UnixListener::bind(socket).daemon(format!("binding {}", socket.display()))?;
+    // PAM runs inside arbitrary login programs, so any local user must be able to ask
+    std::fs::set_permissions(socket, std::fs::Permissions::from_mode(0o666))
+        .daemon(format!("changing the mode of {}", socket.display()))?;
+    Ok(listener)
+}
+
+/// The listener passed by systemd socket activation, if any (`sd_listen_fds`)
+fn activated_listener() -> Option<UnixListener> {
+    let pid = std::env::var("LISTEN_PID").ok();
+    let fds = std::env::var("LISTEN_FDS").ok();
+    let fd = listen_fd(pid.as_deref(), fds.as_deref(), std::process::id())?;
+    // Not meant for any children we start, e.g. pkcheck
+    std::env::remove_var("LISTEN_PID");
+    std::env::remove_var("LISTEN_FDS");
+    std::env::remove_var("LISTEN_FDNAMES");
+    // SAFETY: systemd hands this fd to our pid alone, and it is taken only once
+    Some(unsafe { UnixListener::from_raw_fd(fd) })
+}
+
+/// First passed fd, when the activation variables are meant for `own_pid`
+fn listen_fd(pid: Option<&str>, fds: Option<&str>, own_pid: u32) -> Option<RawFd> {
+    /// `SD_LISTEN_FDS_START`
+    const FIRST_FD: RawFd = 3;
+    let pid: u32 = pid?.parse().ok()?;
+    let fds: u32 = fds?.parse().ok()?;
+    (pid == own_pid && fds >= 1).then_some(FIRST_FD)
+}
+
+#[tracing::instrument(name = "request", skip_all)]
+fn handle(stream: UnixStream, pipeline: &mut Pipeline, config: &Config) -> Result<()> {
+    let mut line = String::new();
+    stream
+        .set_read_timeout(Some(Duration::from_secs(1)))
+        .and_then(|()| stream.try_clone())
+        .and_then(|reader| BufReader::new(reader).read_line(&mut line))
+        .daemon("reading the request")?;
+
+    // A client that gives up, e.g. because the password was typed, stops the scan
+    let cancel = CancelToken::new();
+    pipeline.cancel = cancel.clone();
+    let result = std::thread::scope(|scope| {
+        scope.spawn(|| watch_hangup(&stream, &cancel));
+        let result = dispatch(&line, &stream, pipeline, config);
+        // Also stops the watcher
+        cancel.cancel();
+        result
+    });
+
+    let reply = match result {
+        Ok(reply) => reply,
+        Err(e) if e.is_cancelled() => return Ok(()),
+        Err(e) => Reply::Err(format!("{}", Report(&e))),
+    };
+
+    let mut stream = stream;
+    stream
+        .write_all(reply.encode().as_bytes())
+        .daemon("sending the reply")
+}
+
+fn dispatch(
+    line: &str,
+    stream: &UnixStream,
+    pipeline: &mut Pipeline,
+    config: &Config,
+) -> Result<Reply> {
+    let from_verdict = |ok: bool| if ok { Reply::Ok } else { Reply::Fail(None) };
+    let prompt = |message: &str| say(stream, message);
+    match parse_request(line) {
+        Ok(Request::Auth { user, timeout }) => {
+            let scan_duration = Duration::from_secs(config.scan_durnation as u64);
+            let deadline = Instant::now() + timeout.min(scan_duration);
+            let result = authenticate(pipeline, config, user, deadline, &prompt);
+            metrics::record_outcome(match &result {
+                Ok(AuthResult::Matched) => Outcome::Success,
+                Ok(_) => Outcome::Failure,
+                Err(e) if e.is_cancelled() => Outcome::Cancelled,
+                Err(_) => Outcome::Error,
+            });
+            result.map(|result| match result {
+                AuthResult::Matched => Reply::Ok,
+                AuthResult::Failed(failure) if shows_score(stream, user) => {
+                    Reply::Fail(Some(failure))
+                }
+                AuthResult::Failed(failure) => Reply::Fail(Some(failure.without_score())),
+                // Not returned by a scan
+                AuthResult::Unavailable => Reply::Err("unavailable".to_string()),
+                AuthResult::Error(e) => Reply::Err(format!("{}", Report(&e))),
+            })
+        }
+        Ok(Request::Identify { timeout }) => {
+            let scan_duration = Duration::from_secs(config.scan_durnation as u64);
+            let deadline = Instant::now() + timeout.min(scan_duration);
+            let result = identify(pipeline, config, deadline, &prompt);
+            metrics::record_outcome(match &result {
+                Ok(Some(_)) => Outcome::Success,
+                Ok(None) => Outcome::Failure,
+                Err(e) if e.is_cancelled() => Outcome::Cancelled,
+                Err(_) => Outcome::Error,
+            });
+            Ok(result?.map_or(Reply::Fail(None), Reply::Matched))
+        }
+        Ok(Request::Enroll { user, timeout }) => authorize(stream, user)
+            .and_then(|()| {
+                // The polkit prompt may have taken a while; the capture window starts now
+                auth::enroll(pipeline, config, user, Instant::now() + timeout)
+            })
+            .map(from_verdict),
+        Ok(Request::Purge { user }) => {
+            authorize(stream, user).and_then(|()| storage::purge(user).map(|()| Reply::Ok))
+        }
+        Ok(Request::Ping) => Ok(Reply::Ok),
+        Err(e) => Err(e),
+    }
+}
+
+/// Whether the client may learn the score of a scan for `user`: only root,
+/// such as `sudo` or a display manager, and `user`'s own screen locker
+fn shows_score(stream: &UnixStream, user: &str) -> bool {
+    let Ok(subject) = Subject::from_stream(stream) else {
+        return false;
+    };
+    subject.uid == 0 || identity::lookup(user).is_ok_and(|account| account.uid == subject.uid)
+}
+
+/// Check the client may manage the faces of `user`, which must be a real
+/// account so it can't name a path outside the store
+fn authorize(stream: &UnixStream, user: &str) -> Result<()> {
+    let account = identity::lookup(user)?;
+    let subject = Subject::from_stream(stream)?;
+    let action = subject.action_for(account.uid);
+    tracing::info!(uid = subject.uid, user, action, "authorizing");
+    subject.check(action)
+}
+
+fn authenticate(
+    pipeline: &mut Pipeline,
+    config: &Config,
+    user: &str,
+    deadline: Instant,
+    prompt: Prompt<'_>,
+) -> Result<AuthResult> {
+    let records = storage::load_records(user)?;
+    if records.is_empty() {
+        return Err(Error::daemon(format!("no faces enrolled for {}", user)));
+    }
+    let _locked = storage::lock_in_memory(&records);
+    auth::scan(pipeline, config, user, &records, deadline, prompt)
+}
+
+fn identify(
+    pipeline: &mut Pipeline,
+    config: &Config,
+    deadline: Instant,
+    prompt: Prompt<'_>,
+) -> Result<Option<String>> {
+    let gallery = auth::load_gallery()?;
+    if gallery.is_empty() {
+        return Ok(None);
+    }
+    let _locked: Vec<_> = gallery
+        .iter()
+        .map(|(_, records)| storage::lock_in_memory(records))
+        .collect();
+    auth::identify(pipeline, config, &gallery, deadline, prompt)
+}
+
+fn parse_request(line: &str) -> Result<Request<'_>> {
+    let parse_timeout = |timeout_ms: &str| -> Result<Duration> {
+        let timeout_ms: u64 = timeout_ms.parse().daemon("invalid timeout")?;
+        Ok(Duration::from_millis(timeout_ms))
+    };
+
+    let mut parts = line.split_whitespace();
+    match (parts.next(), parts.next(), parts.next(), parts.next()) {
+        (Some("AUTH"), Some(user), Some(timeout_ms), None) => Ok(Request::Auth {
+            user,
+            timeout: parse_timeout(timeout_ms)?,
+        }),
+        (Some("IDENTIFY"), Some(timeout_ms), None, None) => Ok(Request::Identify {
+            timeout: parse_timeout(timeout_ms)?,
+        }),
+        (Some("ENROLL"), Some(user), Some(timeout_ms), None) => Ok(Request::Enroll {
+            user,
+            timeout: parse_timeout(timeout_ms)?,
+        }),
+        (Some("PURGE"), Some(user), None, None) => Ok(Request::Purge { user }),
+        (Some("PING"), None, None, None) => Ok(Request::Ping),
+        _ => Err(Error::daemon(format!(
+            "malformed request: {:?}",
+            line.trim_end()
+        ))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_request() {
+        assert_eq!(
+            parse_request("AUTH alice 2500\n").unwrap(),
+            Request::Auth {
+                user: "alice",
+                timeout: Duration::from_millis(2500)
+            }
+        );
+        assert_eq!(
+            parse_request("ENROLL bob 10000\n").unwrap(),
+            Request::Enroll {
+                user: "bob",
+                timeout: Duration::from_millis(10000)
+            }
+        );
+        assert_eq!(
+            parse_request("IDENTIFY 3000\n").unwrap(),
+            Request::Identify {
+                timeout: Duration::from_millis(3000)
+            }
+        );
+        assert_eq!(
+            parse_request("PURGE bob\n").unwrap(),
+            Request::Purge { user: "bob" }
+        );
+
+        assert!(parse_request("AUTH alice\n").is_err());
+        assert!(parse_request("PURGE bob 10\n").is_err());
+        assert!(parse_request("AUTH alice 10 extra\n").is_err());
+        assert_eq!(parse_request("PING\n").unwrap(), Request::Ping);
+
+        assert!(parse_request("IDENTIFY alice 10\n").is_err());
+        assert!(parse_request("PING now\n").is_err());
+        assert!(parse_request("HELLO\n").is_err());
+    }
+
+    #[test]
+    fn test_reply_roundtrip() {
+        for reply in [
+            Reply::Ok,
+            Reply::Matched("alice".into()),
+            Reply::Fail(None),
+            Reply::Fail(Some(AuthFailure::FaceTooDark)),
+            Reply::Fail(Some(AuthFailure::BelowThreshold { score: Some(0.25) })),
+            Reply::Fail(Some(AuthFailure::BelowThreshold { score: None })),
+            Reply::Err("no camera".into()),
+        ] {
+            assert_eq!(Reply::decode(&reply.encode()).unwrap(), reply);
+        }
+        assert!(Reply::decode("MAYBE\n").is_err());
+        assert!(Reply::decode("OK two users\n").is_err());
+        assert!(Reply::decode("FAIL because\n").is_err());
+
+        // Other users are told a face didn't match, but not how close it came
+        let failure = AuthFailure::BelowThreshold { score: Some(0.5) };
+        assert_eq!(failure.without_score().encode(), "below-threshold");
+    }
+
+    #[test]
+    fn test_listen_fd() {
+        assert_eq!(listen_fd(Some("42"), Some("1"), 42), Some(3));
+        // Inherited from a parent that was activated, not us
+        assert_eq!(listen_fd(Some("41"), Some("1"), 42), None);
+        assert_eq!(listen_fd(Some("42"), Some("0"), 42), None);
+        assert_eq!(listen_fd(None, None, 42), None);
+    }
+
+    #[test]
+    fn test_request_auth_prompts() {
+        let socket = std::env::temp_dir().join(format!("howrs-test-{}.sock", std::process::id()));
+        let _ = std::fs::remove_file(&socket);
+        let listener = UnixListener::bind(&socket).unwrap();
+        let daemon = std::thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            let mut line = String::new();
+            BufReader::new(&stream).read_line(&mut line).unwrap();
+            say(&stream, "Turn your head slightly left");
+            (&stream).write_all(Reply::Ok.encode().as_bytes()).unwrap();
+        });
+
+        let prompts = std::cell::RefCell::new(Vec::new());
+        let result = request_auth(
+            &socket,
+            "alice",
+            Duration::from_secs(5),
+            &CancelToken::new(),
+            &|message| prompts.borrow_mut().push(message.to_string()),
+        );
+        daemon.join().unwrap();
+        let _ = std::fs::remove_file(&socket);
+        assert!(matches!(result, Ok(AuthResult::Matched)));
+        assert_eq!(*prompts.borrow(), ["Turn your head slightly left"]);
+    }
+
+    #[test]
+    fn test_request_auth_unreachable() {
+        let socket = std::env::temp_dir().join("howrs-test-no-daemon.sock");
+        let _ = std::fs::remove_file(&socket);
+        let cancel = CancelToken::new();
+        let timeout = Duration::from_millis(100);
+        assert!(request_auth(&socket, "alice", timeout, &cancel, &|_| {}).is_err());
+    }
+}
@@ -0,0 +1,272 @@
+//! Bundle of what a failed scan saw, for bug reports.
+//!
+//! With `debug_dump` set, every scan that ends without a match leaves a
+//! timestamped directory with the last frame the scan looked at, plain and
+//! with the faces detected in it drawn on, the frames the camera kept before
+//! it, every score computed and the time each stage took. Directories are
+//! private to the user writing them, root for the daemon.
+
+use crate::auth::AuthFailure;
+use crate::error::{Result, ResultExt};
+use crate::privacy::{self, FrameSink};
+use howrs_vision::pipeline::PipelineTimings;
+use howrs_vision::ring::FrameRing;
+use howrs_vision::stream::StreamEvent;
+use howrs_vision::{face, Detection};
+use image::DynamicImage;
+use serde::Serialize;
+use std::os::unix::fs::DirBuilderExt;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+/// A face found in a frame
+#[derive(Debug, Serialize)]
+struct Face {
+    frame: usize,
+    bbox: [f32; 4],
+    confidence: f32,
+    landmarks: [f32; 10],
+}
+
+impl Face {
+    fn new(frame: usize, detection: &Detection) -> Self {
+        Self {
+            frame,
+            bbox: detection.bbox,
+            confidence: detection.score,
+            landmarks: detection.landmarks,
+        }
+    }
+}
+
+/// Stage durations of one frame, in milliseconds
+#[derive(Debug, Serialize)]
+struct Timings {
+    capture: f64,
+    detect: f64,
+    align: f64,
+    encode: f64,
+}
+
+impl From<PipelineTimings> for Timings {
+    fn from(t: PipelineTimings) -> Self {
+        let ms = |d: Duration| d.as_secs_f64() * 1000.0;
+        Self {
+            capture: ms(t.capture),
+            detect: ms(t.detect),
+            align: ms(t.align),
+            encode: ms(t.encode),
+        }
+    }
+}
+
+/// A face compared with the enrolled records
+#[derive(Debug, Serialize)]
+struct Score {
+    face: Face,
+    /// Index of the best record, across users when identifying
+    candidate: usize,
+    score: f32,
+    timings: Timings,
+}
+
+/// A frame, or a face in it, that gave nothing to match
+#[derive(Debug, Serialize)]
+struct Skip {
+    frame: usize,
+    reason: String,
+}
+
+/// A frame the camera kept, saved next to `frame.png`
+#[derive(Debug, Serialize)]
+struct Recent {
+    file: String,
+    /// When it was captured relative to `frame.png`, negative before it
+    offset_ms: f64,
+}
+
+/// Everything recorded about a scan, written out as `report.json`
+#[derive(Debug, Default, Serialize)]
+struct Report {
+    user: String,
+    failure: String,
+    threshold: f32,
+    frames: usize,
+    /// Faces of the last frame, the one saved as `frame.png`
+    faces: Vec<Face>,
+    scores: Vec<Score>,
+    skipped: Vec<Skip>,
+    recent: Vec<Recent>,
+}
+
+/// Collects a scan's events into a bundle, see [`DebugBundle::write`]
+#[derive(Default)]
+pub struct DebugBundle {
+    report: Report,
+    /// When the last frame was seen; it's the newest one kept by then
+    seen: Option<Instant>,
+    /// Detected in that frame, for `annotated.png`
+    detections: Vec<Detection>,
+}
+
+impl DebugBundle {
+    pub fn observe(&mut self, event: &StreamEvent<'_>) {
+        let report = &mut self.report;
+        match event {
+            StreamEvent::FrameCaptured { .. } => {
+                report.frames += 1;
+                report.faces.clear();
+                self.detections.clear();
+                self.seen = Some(Instant::now());
+            }
+            StreamEvent::FrameSkipped { reason, .. } => report.skipped.push(Skip {
+                frame: report.frames,
+                reason: reason.to_string(),
+            }),
+            StreamEvent::FaceDetected { detection, .. } => {
+                report.faces.push(Face::new(report.frames, detection));
+                self.detections.push((*detection).clone());
+            }
+            StreamEvent::ScoreComputed {
+                detection,
+                candidate,
+                score,
+                timings,
+                ..
+            } => report.scores.push(Score {
+                face: Face::new(report.frames, detection),
+                candidate: *candidate,
+                score: *score,
+                timings: (*timings).into(),
+            }),
+            StreamEvent::Matched { .. } => {}
+        }
+    }
+
+    /// Write the bundle of a scan for `user` that failed with `failure` into
+    /// a new directory under `dir`, the `debug_dump` setting, returning it.
+    /// The frames come from `history`, what the scan's camera kept.
+    pub fn write(
+        mut self,
+        dir: Option<&Path>,
+        user: &str,
+        threshold: f32,
+        failure: &AuthFailure,
+        history: &FrameRing,
+    ) -> Result<PathBuf> {
+        let dir = privacy::check(FrameSink::DebugDump, dir)?;
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default();
+        let bundle = dir.join(format!(
+            "{}.{:03}-{}",
+            now.as_secs(),
+            now.subsec_millis(),
+            user
+        ));
+        std::fs::DirBuilder::new()
+            .recursive(true)
+            .mode(0o700)
+            .create(&bundle)
+            .io(format!("creating {}", bundle.display()))?;
+
+        // Not necessarily the newest frame kept: the head turn challenge
+        // reads more after the stream's last one
+        if let Some(last) = self.seen.and_then(|seen| history.at(seen)) {
+            let frame = DynamicImage::ImageRgb8(last.frame.clone());
+            privacy::write_frame(
+                FrameSink::DebugDump,
+                Some(&bundle.join("frame.png")),
+                &frame,
+            )?;
+            let annotated = face::annotate(&frame, &self.detections);
+            privacy::write_frame(
+                FrameSink::DebugDump,
+                Some(&bundle.join("annotated.png")),
+                &annotated,
+            )?;
+
+            for (i, recent) in history.iter().enumerate() {
+                let file = format!("recent-{:02}.png", i);
+                let frame = DynamicImage::ImageRgb8(recent.frame.clone());
+                privacy::write_frame(FrameSink::DebugDump, Some(&bundle.join(&file)), &frame)?;
+                let offset = match recent.captured.checked_duration_since(last.captured) {
+                    Some(after) => after.as_secs_f64(),
+                    None => -last.captured.duration_since(recent.captured).as_secs_f64(),
+                };
+                self.report.recent.push(Recent {
+                    file,
+                    offset_ms: offset * 1000.0,
+                });
+            }
+        }
+        self.report.user = user.to_string();
+        self.report.failure = failure.to_string();
+        self.report.threshold = threshold;
+        let report = bundle.join("report.json");
+        let json = serde_json::to_vec_pretty(&self.report).io("encoding the report")?;
+        std::fs::write(&report, json).io(format!("writing {}", report.display()))?;
+        Ok(bundle)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use howrs_vision::stream::SkipReason;
+    use std::os::unix::fs::PermissionsExt;
+
+    #[test]
+    fn test_write_bundle() {
+        let dir = std::env::temp_dir().join("howrs-test-debug-dump");
+        let _ = std::fs::remove_dir_all(&dir);
+
+        let frame = DynamicImage::new_rgb8(8, 8);
+        let mut history = FrameRing::new(4);
+        let start = Instant::now();
+        history.push(frame.as_rgb8().unwrap(), start - Duration::from_millis(100));
+        history.push(frame.as_rgb8().unwrap(), start);
+        let detection = Detection {
+            bbox: [1.0, 1.0, 4.0, 4.0],
+            score: 0.9,
+            landmarks: [2.0; 10],
+        };
+        let mut bundle = DebugBundle::default();
+        bundle.observe(&StreamEvent::FrameCaptured {
+            frame: &frame,
+            capture: Duration::from_millis(3),
+        });
+        bundle.observe(&StreamEvent::FaceDetected {
+            frame: &frame,
+            detection: &detection,
+            timings: PipelineTimings::default(),
+        });
+        bundle.observe(&StreamEvent::FrameSkipped {
+            frame: Some(&frame),
+            reason: SkipReason::NoFace,
+        });
+        let path = bundle
+            .write(
+                Some(&dir),
+                "alice",
+                0.6,
+                &AuthFailure::BelowThreshold { score: Some(0.4) },
+                &history,
+            )
+            .unwrap();
+
+        let mode = std::fs::metadata(&path).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o700);
+        assert!(path.join("frame.png").is_file());
+        assert!(path.join("annotated.png").is_file());
+        let report: serde_json::Value =
+            serde_json::from_slice(&std::fs::read(path.join("report.json")).unwrap()).unwrap();
+        assert_eq!(report["user"], "alice");
+        assert_eq!(report["frames"], 1);
+        assert_eq!(report["faces"][0]["bbox"][2], 4.0);
+        assert_eq!(report["skipped"][0]["reason"], "no face detected");
+        assert!(path.join("recent-01.png").is_file());
+        assert_eq!(report["recent"][0]["offset_ms"], -100.0);
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+}
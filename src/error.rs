@@ -0,0 +1,197 @@
+//! Errors of the howrs library
+//!
+//! Failures are classified so the PAM module, the daemon and the C API can
+//! pick a status without matching on messages: a camera that is missing is
+//! worth falling back to the password for, a corrupt face store is not.
+//! Camera, model and inference failures come from the vision pipeline as
+//! [`Error::Vision`]. Only the binaries use `anyhow`.
+
+use howrs_vision::cancel::Cancelled;
+pub use howrs_vision::error::{BoxError, Report};
+use std::fmt::Display;
+
+pub type Result<T, E = Error> = std::result::Result<T, E>;
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    /// Camera, model or inference failure, see [`howrs_vision::Error`]
+    #[error(transparent)]
+    Vision(#[from] howrs_vision::Error),
+    /// Reading, writing or decoding the face store
+    #[error("{context}")]
+    Storage {
+        context: String,
+        #[source]
+        source: Option<BoxError>,
+    },
+    /// Comparing faces with the enrolled ones, e.g. a corrupt record
+    #[error("{context}")]
+    Matching {
+        context: String,
+        #[source]
+        source: Option<BoxError>,
+    },
+    /// Loading, parsing or saving the configuration or translations
+    #[error("{context}")]
+    Config {
+        context: String,
+        #[source]
+        source: Option<BoxError>,
+    },
+    /// Looking up an account or checking what it is allowed to do
+    #[error("{context}")]
+    Account {
+        context: String,
+        #[source]
+        source: Option<BoxError>,
+    },
+    /// Talking to `howrs daemon`, or an error the daemon reported
+    #[error("{context}")]
+    Daemon {
+        context: String,
+        #[source]
+        source: Option<BoxError>,
+    },
+    /// Any other file or socket, such as debug dumps and the metrics endpoint
+    #[error("{context}")]
+    Io {
+        context: String,
+        #[source]
+        source: Option<BoxError>,
+    },
+}
+
+macro_rules! kinds {
+    ($($name:ident => $variant:ident),*) => {
+        impl Error {
+            $(
+                pub fn $name(context: impl Display) -> Self {
+                    Error::$variant {
+                        context: context.to_string(),
+                        source: None,
+                    }
+                }
+            )*
+
+            /// Add `context` in front of the message, keeping the kind of
+            /// failure
+            pub fn context(self, context: impl Display) -> Self {
+                match self {
+                    Error::Vision(e) => Error::Vision(e.context(context)),
+                    $(
+                        Error::$variant { .. } => Error::$variant {
+                            context: context.to_string(),
+                            source: Some(Box::new(self)),
+                        },
+                    )*
+                }
+            }
+        }
+
+        /// Classify a foreign error, e.g. `.storage("reading the store key")?`;
+        /// on an `Option`, `None` becomes an error with only the context
+        pub trait ResultExt<T> {
+            $(fn $name(self, context: impl Display) -> Result<T>;)*
+        }
+
+        impl<T, E: Into<BoxError>> ResultExt<T> for std::result::Result<T, E> {
+            $(
+                fn $name(self, context: impl Display) -> Result<T> {
+                    self.map_err(|e| Error::$variant {
+                        context: context.to_string(),
+                        source: Some(e.into()),
+                    })
+                }
+            )*
+        }
+
+        impl<T> ResultExt<T> for Option<T> {
+            $(
+                fn $name(self, context: impl Display) -> Result<T> {
+                    self.ok_or_else(|| Error::$name(context))
+                }
+            )*
+        }
+    };
+}
+
+kinds!(
+    storage => Storage,
+    matching => Matching,
+    config => Config,
+    account => Account,
+    daemon => Daemon,
+    io => Io
+);
+
+impl Error {
+    /// Whether a scan was stopped through its
+    /// [`CancelToken`](howrs_vision::cancel::CancelToken)
+    pub fn is_cancelled(&self) -> bool {
+        matches!(self, Error::Vision(e) if e.is_cancelled())
+    }
+
+    /// Whether the camera couldn't be opened or read, which callers treat
+    /// like having no camera at all
+    pub fn is_camera(&self) -> bool {
+        matches!(self, Error::Vision(howrs_vision::Error::Camera { .. }))
+    }
+}
+
+impl From<Cancelled> for Error {
+    fn from(cancelled: Cancelled) -> Self {
+        Error::Vision(cancelled.into())
+    }
+}
+
+/// Only the face store is postcard encoded
+impl From<postcard::Error> for Error {
+    fn from(e: postcard::Error) -> Self {
+        Error::Storage {
+            context: "malformed face records".to_string(),
+            source: Some(e.into()),
+        }
+    }
+}
+
+/// Add context to an error of this crate or of the vision pipeline without
+/// changing its kind
+pub trait Context<T> {
+    fn context(self, context: impl Display) -> Result<T>;
+}
+
+impl<T, E: Into<Error>> Context<T> for std::result::Result<T, E> {
+    fn context(self, context: impl Display) -> Result<T> {
+        self.map_err(|e| e.into().context(context))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_context_keeps_kind() {
+        let err = Err::<(), _>(std::io::Error::other("disk full"))
+            .storage("writing /etc/howrs/faces/alice/faces.bin")
+            .context("enrolling alice")
+            .unwrap_err();
+        assert!(matches!(err, Error::Storage { .. }));
+        assert_eq!(
+            Report(&err).to_string(),
+            "enrolling alice: writing /etc/howrs/faces/alice/faces.bin: disk full"
+        );
+
+        let err = Err::<(), _>(howrs_vision::Error::camera("no camera connected"))
+            .context("opening the camera")
+            .unwrap_err();
+        assert!(matches!(
+            err,
+            Error::Vision(howrs_vision::Error::Camera { .. })
+        ));
+        assert!(err.is_camera());
+
+        assert!(Error::from(Cancelled).context("scanning").is_cancelled());
+        assert!(!Error::daemon("daemon error: busy").is_cancelled());
+    }
+}
@@ -0,0 +1,167 @@
+//! Command line compatibility with howdy.
+//!
+//! Scripts, desktop integrations and muscle memory written for howdy keep
+//! working when `howrs` is invoked as `howdy` (e.g. through a symlink) or as
+//! `howrs howdy-compat ...`: howdy's subcommands and flags are rewritten into
+//! the equivalent `howrs` arguments before they are parsed.
+
+use anyhow::{bail, Result};
+use std::ffi::OsString;
+use std::path::Path;
+
+/// Binary name that switches to howdy's command line
+pub const HOWDY_NAME: &str = "howdy";
+/// Subcommand that switches to howdy's command line
+pub const COMPAT_COMMAND: &str = "howdy-compat";
+
+/// `howrs` arguments for a howdy-style command line, or `None` when `args`
+/// (including the program name) is a normal `howrs` invocation
+pub fn compat_args(args: Vec<OsString>) -> Option<Result<Vec<String>>> {
+    let program = args.first()?;
+    let rest = if Path::new(program).file_name() == Some(HOWDY_NAME.as_ref()) {
+        &args[1..]
+    } else if args.get(1).is_some_and(|a| a == COMPAT_COMMAND) {
+        &args[2..]
+    } else {
+        return None;
+    };
+    let rest: Option<Vec<String>> = rest.iter().map(|a| a.to_str().map(String::from)).collect();
+    Some(match rest {
+        Some(rest) => translate(&rest),
+        None => Err(anyhow::anyhow!("arguments must be valid UTF-8")),
+    })
+}
+
+/// Rewrite howdy's `[-U user] [-y] [--plain] <command> [args]` into `howrs`
+/// arguments, starting with the program name
+pub fn translate(args: &[String]) -> Result<Vec<String>> {
+    let mut user = None;
+    let mut positional = Vec::new();
+    let mut iter = args.iter();
+    while let Some(arg) = iter.next() {
+        match arg.as_str() {
+            "-U" | "--user" => match iter.next() {
+                Some(name) => user = Some(name.clone()),
+                None => bail!("{} needs a user name", arg),
+            },
+            // howrs never asks for confirmation and has no colored output
+            "-y" | "--plain" => {}
+            "-h" | "--help" => positional.push("help".to_string()),
+            _ if arg.starts_with("--user=") => user = Some(arg["--user=".len()..].to_string()),
+            _ => positional.push(arg.clone()),
+        }
+    }
+
+    let mut out = vec!["howrs".to_string()];
+    let Some((command, rest)) = positional.split_first() else {
+        bail!("usage: howdy <add|remove|clear|list|test|disable|config|snapshot|version>");
+    };
+    let takes_user = match command.as_str() {
+        // howdy asks for a label interactively, howrs stores none
+        "add" => {
+            out.push("enroll".into());
+            true
+        }
+        "remove" => {
+            let Some(id) = rest.first() else {
+                bail!("remove needs the ID of a face, see `howdy list`");
+            };
+            out.extend(["remove".into(), id.clone()]);
+            true
+        }
+        "clear" => {
+            out.push("purge".into());
+            true
+        }
+        "list" | "test" => {
+            out.push(command.clone());
+            true
+        }
+        // `disable 1` turns face auth off, `disable 0` back on
+        "disable" => {
+            let off = match rest.first().map(String::as_str) {
+                Some("1" | "true" | "on") | None => true,
+                Some("0" | "false" | "off") => false,
+                Some(other) => bail!("disable takes 1 or 0, not {}", other),
+            };
+            out.push(if off { "disable" } else { "enable" }.into());
+            false
+        }
+        "config" | "snapshot" => {
+            out.push(command.clone());
+            false
+        }
+        "version" => {
+            out.push("--version".into());
+            false
+        }
+        "help" => {
+            out.push("--help".into());
+            false
+        }
+        other => bail!("unsupported howdy command: {}", other),
+    };
+    if let Some(user) = user {
+        if takes_user {
+            out.extend(["--user".into(), user]);
+        }
+    }
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn howdy(args: &str) -> Result<Vec<String>> {
+        translate(
+            &args
+                .split_whitespace()
+                .map(String::from)
+                .collect::<Vec<_>>(),
+        )
+    }
+
+    #[test]
+    fn test_translate() {
+        assert_eq!(howdy("add").unwrap(), ["howrs", "enroll"]);
+        assert_eq!(
+            howdy("-U alice -y add").unwrap(),
+            ["howrs", "enroll", "--user", "alice"]
+        );
+        assert_eq!(
+            howdy("remove 3f2a --user=bob").unwrap(),
+            ["howrs", "remove", "3f2a", "--user", "bob"]
+        );
+        assert_eq!(
+            howdy("-U bob clear").unwrap(),
+            ["howrs", "purge", "--user", "bob"]
+        );
+        assert_eq!(howdy("disable 1").unwrap(), ["howrs", "disable"]);
+        assert_eq!(howdy("-U bob disable 0").unwrap(), ["howrs", "enable"]);
+        assert_eq!(howdy("version").unwrap(), ["howrs", "--version"]);
+
+        assert!(howdy("").is_err());
+        assert!(howdy("remove").is_err());
+        assert!(howdy("disable maybe").is_err());
+        assert!(howdy("dance").is_err());
+    }
+
+    #[test]
+    fn test_compat_args() {
+        let args = |list: &[&str]| list.iter().map(OsString::from).collect::<Vec<_>>();
+        assert!(compat_args(args(&["howrs", "list"])).is_none());
+        assert_eq!(
+            compat_args(args(&["/usr/bin/howdy", "list"]))
+                .unwrap()
+                .unwrap(),
+            ["howrs", "list"]
+        );
+        assert_eq!(
+            compat_args(args(&["howrs", "howdy-compat", "test"]))
+                .unwrap()
+                .unwrap(),
+            ["howrs", "test"]
+        );
+    }
+}
@@ -0,0 +1,293 @@
+//! Translatable user-facing messages.
+//!
+//! What the PAM module shows in the conversation and what the CLI prints to
+//! guide the user (never logs) is looked up here by [`Msg`]. Distributions
+//! ship translations as TOML tables of key = text in [`LOCALE_DIR`], one
+//! file per language (`de.toml`, `pt_BR.toml`); anything missing falls back
+//! to English. `packaging/locale/template.toml` lists every key.
+//!
+//! The language comes from `LANGUAGE`, `LC_ALL`, `LC_MESSAGES` and `LANG`,
+//! as with gettext. In the daemon that is the daemon's own environment.
+
+use crate::config::LOCALE_DIR;
+use crate::error::{Context, Report, Result, ResultExt};
+use howrs_vision::quality::Feedback;
+use std::collections::HashMap;
+use std::fmt::Display;
+use std::path::Path;
+use std::sync::OnceLock;
+
+/// A user-facing message. `{}` in the text is filled in with [`fill`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Msg {
+    Scanning,
+    ScanningOrEnter,
+    ScanningOrPassword,
+    /// Filled in with the reason, see [`crate::AuthFailure::message`]
+    Failed,
+    RecognizedPressEnter,
+    TurnLeft,
+    TurnRight,
+    NoCamera,
+    NoFace,
+    TooDark,
+    NotRecognized,
+    Spoof,
+    Timeout,
+    PoseStraight,
+    PoseLeft,
+    PoseRight,
+    PoseUp,
+    Verifying,
+    EnrollRequired,
+}
+
+impl Msg {
+    pub const ALL: [Msg; 19] = [
+        Msg::Scanning,
+        Msg::ScanningOrEnter,
+        Msg::ScanningOrPassword,
+        Msg::Failed,
+        Msg::RecognizedPressEnter,
+        Msg::TurnLeft,
+        Msg::TurnRight,
+        Msg::NoCamera,
+        Msg::NoFace,
+        Msg::TooDark,
+        Msg::NotRecognized,
+        Msg::Spoof,
+        Msg::Timeout,
+        Msg::PoseStraight,
+        Msg::PoseLeft,
+        Msg::PoseRight,
+        Msg::PoseUp,
+        Msg::Verifying,
+        Msg::EnrollRequired,
+    ];
+
+    /// Key in a translation file
+    pub fn key(self) -> &'static str {
+        match self {
+            Msg::Scanning => "scanning",
+            Msg::ScanningOrEnter => "scanning-or-enter",
+            Msg::ScanningOrPassword => "scanning-or-password",
+            Msg::Failed => "failed",
+            Msg::RecognizedPressEnter => "recognized-press-enter",
+            Msg::TurnLeft => "turn-left",
+            Msg::TurnRight => "turn-right",
+            Msg::NoCamera => "failure-no-camera",
+            Msg::NoFace => "failure-no-face",
+            Msg::TooDark => "failure-too-dark",
+            Msg::NotRecognized => "failure-not-recognized",
+            Msg::Spoof => "failure-spoof",
+            Msg::Timeout => "failure-timeout",
+            Msg::PoseStraight => "pose-straight",
+            Msg::PoseLeft => "pose-left",
+            Msg::PoseRight => "pose-right",
+            Msg::PoseUp => "pose-up",
+            Msg::Verifying => "verifying",
+            Msg::EnrollRequired => "enroll-required",
+        }
+    }
+
+    pub fn english(self) -> &'static str {
+        match self {
+            Msg::Scanning => "Running facial recognition...",
+            Msg::ScanningOrEnter => {
+                "Running facial recognition (press Enter to use your password)..."
+            }
+            Msg::ScanningOrPassword => "Running facial recognition, or type your password...",
+            Msg::Failed => "Face recognition failed: {}",
+            Msg::RecognizedPressEnter => "Face recognized, press Enter to continue",
+            Msg::TurnLeft => "Turn your head slightly left",
+            Msg::TurnRight => "Turn your head slightly right",
+            Msg::NoCamera => "no camera available",
+            Msg::NoFace => "no face detected",
+            Msg::TooDark => "too dark to see a face",
+            Msg::NotRecognized => "face not recognized",
+            Msg::Spoof => "face looks like a photo or a screen",
+            Msg::Timeout => "the camera sent no frames in time",
+            Msg::PoseStraight => "Look straight at the camera",
+            Msg::PoseLeft => "Turn your head slightly to the left",
+            Msg::PoseRight => "Turn your head slightly to the right",
+            Msg::PoseUp => "Tilt your head slightly up",
+            Msg::Verifying => "Verifying: keep looking at the camera",
+            Msg::EnrollRequired => "A face must be enrolled to log in here (howrs enroll)",
+        }
+    }
+}
+
+/// Key of an enrollment feedback in a translation file; its English text is
+/// [`Feedback::message`]
+pub fn feedback_key(feedback: Feedback) -> &'static str {
+    match feedback {
+        Feedback::NoFace => "feedback-no-face",
+        Feedback::TooDark => "feedback-too-dark",
+        Feedback::TooBright => "feedback-too-bright",
+        Feedback::TooSmall => "feedback-too-small",
+        Feedback::LookAtCamera => "feedback-look-at-camera",
+        Feedback::WrongPose => "feedback-wrong-pose",
+        Feedback::Good => "feedback-good",
+    }
+}
+
+/// Every [`Feedback`], for listing their keys
+pub const FEEDBACK: [Feedback; 7] = [
+    Feedback::NoFace,
+    Feedback::TooDark,
+    Feedback::TooBright,
+    Feedback::TooSmall,
+    Feedback::LookAtCamera,
+    Feedback::WrongPose,
+    Feedback::Good,
+];
+
+/// Translations of one language
+#[derive(Debug, Default)]
+pub struct Catalog {
+    messages: HashMap<String, String>,
+}
+
+impl Catalog {
+    pub fn parse(text: &str) -> Result<Self> {
+        Ok(Self {
+            messages: toml::from_str(text).config("malformed translations")?,
+        })
+    }
+
+    /// The first of `languages` with a file in `dir`
+    pub fn load(dir: &Path, languages: &[String]) -> Result<Option<Self>> {
+        for language in languages {
+            let path = dir.join(format!("{}.toml", language));
+            let text = match std::fs::read_to_string(&path) {
+                Ok(text) => text,
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => continue,
+                Err(e) => return Err(e).config(format!("reading {}", path.display())),
+            };
+            return Self::parse(&text)
+                .context(format!("parsing {}", path.display()))
+                .map(Some);
+        }
+        Ok(None)
+    }
+
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.messages.get(key).map(String::as_str)
+    }
+}
+
+/// Catalog names to try for the locale in these variables, in the order
+/// gettext reads them (`LANGUAGE`, `LC_ALL`, `LC_MESSAGES`, `LANG`): each
+/// `ll_CC` is followed by its bare `ll`
+pub fn languages(language: Option<&str>, locale: Option<&str>) -> Vec<String> {
+    // Like gettext, LANGUAGE is ignored under the C locale
+    let Some(locale) = locale.and_then(strip_locale) else {
+        return Vec::new();
+    };
+    let mut names = Vec::new();
+    let requested = language
+        .into_iter()
+        .flat_map(|l| l.split(':'))
+        .filter_map(strip_locale);
+    for name in requested.chain([locale]) {
+        for name in [name, name.split('_').next().unwrap_or(name)] {
+            if !names.iter().any(|n| n == name) {
+                names.push(name.to_string());
+            }
+        }
+    }
+    names
+}
+
+/// `ll_CC` of `ll_CC.UTF-8@modifier`, or `None` for the C locale and for
+/// anything that isn't a plain name and so can't be a file in `LOCALE_DIR`
+fn strip_locale(locale: &str) -> Option<&str> {
+    let name = locale.split(['.', '@']).next().unwrap_or_default();
+    let plain = name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_');
+    (plain && !name.is_empty() && name != "C" && name != "POSIX").then_some(name)
+}
+
+fn catalog() -> &'static Catalog {
+    static CATALOG: OnceLock<Catalog> = OnceLock::new();
+    CATALOG.get_or_init(|| {
+        let var = |name| std::env::var(name).ok().filter(|v| !v.is_empty());
+        let locale = var("LC_ALL")
+            .or_else(|| var("LC_MESSAGES"))
+            .or_else(|| var("LANG"));
+        let languages = languages(var("LANGUAGE").as_deref(), locale.as_deref());
+        Catalog::load(Path::new(*LOCALE_DIR), &languages)
+            .unwrap_or_else(|e| {
+                tracing::warn!("translations unavailable: {}", Report(&e));
+                None
+            })
+            .unwrap_or_default()
+    })
+}
+
+/// `msg` in the user's language
+pub fn text(msg: Msg) -> &'static str {
+    catalog().get(msg.key()).unwrap_or(msg.english())
+}
+
+/// `feedback` in the user's language
+pub fn feedback(feedback: Feedback) -> &'static str {
+    catalog()
+        .get(feedback_key(feedback))
+        .unwrap_or(feedback.message())
+}
+
+/// `msg` in the user's language with each `{}` replaced by the next of `args`
+pub fn fill(msg: Msg, args: &[&dyn Display]) -> String {
+    let mut parts = text(msg).split("{}");
+    let mut out = parts.next().unwrap_or_default().to_string();
+    for (i, part) in parts.enumerate() {
+        if let Some(arg) = args.get(i) {
+            out.push_str(&arg.to_string());
+        }
+        out.push_str(part);
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_languages() {
+        assert_eq!(languages(None, Some("de_DE.UTF-8")), ["de_DE", "de"]);
+        assert_eq!(
+            languages(Some("pt_BR:fr"), Some("en_US.UTF-8")),
+            ["pt_BR", "pt", "fr", "en_US", "en"]
+        );
+        assert!(languages(Some("de"), Some("C")).is_empty());
+        assert!(languages(None, None).is_empty());
+        assert_eq!(languages(None, Some("../../etc/x")), Vec::<String>::new());
+    }
+
+    #[test]
+    fn test_catalog() {
+        let catalog = Catalog::parse("scanning = \"Gesichtserkennung läuft...\"").unwrap();
+        assert_eq!(
+            catalog.get(Msg::Scanning.key()),
+            Some("Gesichtserkennung läuft...")
+        );
+        assert_eq!(catalog.get(Msg::Failed.key()), None);
+        assert!(Catalog::parse("scanning = 1").is_err());
+    }
+
+    #[test]
+    fn test_template_lists_every_key() {
+        let template = Catalog::parse(include_str!("../packaging/locale/template.toml")).unwrap();
+        for msg in Msg::ALL {
+            assert_eq!(template.get(msg.key()), Some(msg.english()), "{:?}", msg);
+        }
+        for feedback in FEEDBACK {
+            assert_eq!(
+                template.get(feedback_key(feedback)),
+                Some(feedback.message())
+            );
+        }
+        assert_eq!(template.messages.len(), Msg::ALL.len() + FEEDBACK.len());
+    }
+}
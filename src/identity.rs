@@ -1,6 +1,7 @@
-use anyhow::Result;
-use libc::{getpwuid, uid_t};
-use std::ffi::CStr;
+use crate::error::{Error, Result, ResultExt};
+use libc::{getgrnam, getpwnam, getpwuid, uid_t};
+use std::ffi::{CStr, CString};
+use std::path::PathBuf;
 
 pub fn current_user_id() -> Result<String> {
     if let Ok(sudo_uid) = std::env::var("SUDO_UID") {
@@ -10,9 +11,58 @@ pub fn current_user_id() -> Result<String> {
         let uid = libc::geteuid();
         let pwd = getpwuid(uid as uid_t);
         if pwd.is_null() {
-            return Err(anyhow::anyhow!("failed to resolve current user"));
+            return Err(Error::account("failed to resolve current user"));
         }
         let name = CStr::from_ptr((*pwd).pw_name);
         Ok(name.to_string_lossy().into_owned())
     }
 }
+
+/// A local account from the password database
+#[derive(Debug, Clone)]
+pub struct Account {
+    pub name: String,
+    pub uid: u32,
+    pub home: PathBuf,
+}
+
+/// Id of the group called `group`, if there is one
+pub fn group_id(group: &str) -> Option<u32> {
+    let c_group = CString::new(group).ok()?;
+    unsafe {
+        let grp = getgrnam(c_group.as_ptr());
+        (!grp.is_null()).then(|| (*grp).gr_gid)
+    }
+}
+
+/// Look up `user` by name
+pub fn lookup(user: &str) -> Result<Account> {
+    let c_user = CString::new(user).account("user name contains a NUL byte")?;
+    unsafe {
+        let pwd = getpwnam(c_user.as_ptr());
+        if pwd.is_null() {
+            return Err(Error::account(format!("unknown user {}", user)));
+        }
+        Ok(Account {
+            name: user.to_string(),
+            uid: (*pwd).pw_uid,
+            home: PathBuf::from(CStr::from_ptr((*pwd).pw_dir).to_string_lossy().into_owned()),
+        })
+    }
+}
+
+/// Real uid of the process, i.e. who ran it
+pub fn real_uid() -> u32 {
+    unsafe { libc::getuid() }
+}
+
+/// Whether the process runs setuid or setgid, like the PAM module inside
+/// `sudo` or `su`: its environment then belongs to the unprivileged caller
+pub fn is_elevated() -> bool {
+    unsafe { libc::getuid() != libc::geteuid() || libc::getgid() != libc::getegid() }
+}
+
+/// Whether the process runs as root, i.e. may write the system face store
+pub fn is_root() -> bool {
+    unsafe { libc::geteuid() == 0 }
+}
@@ -1,10 +1,28 @@
+pub mod auth;
 pub mod config;
+pub mod daemon;
+pub mod debug_dump;
+pub mod error;
+pub mod howdy;
+pub mod i18n;
 pub mod identity;
+pub mod logging;
 pub mod matcher;
+pub mod metrics;
+pub mod polkit;
+pub mod preview;
+pub mod privacy;
 pub mod storage;
 
+pub use auth::{authenticate, AuthFailure, AuthResult};
+pub use error::{Error, Result};
+
 // Re-export vision types for convenience
-pub use howrs_vision::{face, pipeline, video, Detection, Embedding, Pipeline};
+pub use howrs_vision::{
+    depth, face, pipeline, pool, quality, stream, video, Detection, Embedding, Pipeline,
+};
+#[cfg(feature = "tokio")]
+pub use howrs_vision::nonblocking;
 
 // PAM module for cdylib
 pub mod pam;
@@ -0,0 +1,41 @@
+//! Tracing setup for the CLI, daemon and PAM module.
+//!
+//! The pipeline emits `capture`, `detect`, `align`, `encode` and `match`
+//! spans at debug level; with `HOWRS_LOG=debug` each one is printed with its
+//! duration when it closes, so a single auth attempt shows where time goes.
+
+use tracing::Dispatch;
+use tracing_subscriber::{fmt::format::FmtSpan, util::SubscriberInitExt, EnvFilter};
+
+/// Environment variable holding the filter, in `RUST_LOG` syntax
+pub const FILTER_ENV: &str = "HOWRS_LOG";
+
+fn dispatch(filter: EnvFilter) -> Dispatch {
+    tracing_subscriber::fmt()
+        .with_env_filter(filter)
+        .with_span_events(FmtSpan::CLOSE)
+        .with_writer(std::io::stderr)
+        .with_target(false)
+        .without_time()
+        .into()
+}
+
+/// Install the global subscriber for the CLI and daemon, defaulting to `info`
+pub fn init() {
+    let filter = EnvFilter::try_from_env(FILTER_ENV).unwrap_or_else(|_| EnvFilter::new("info"));
+    let _ = dispatch(filter).try_init();
+}
+
+/// Subscriber for one PAM call, only when `HOWRS_LOG` is set.
+///
+/// The module runs inside someone else's process, so it never installs a
+/// global subscriber; callers scope this with
+/// [`tracing::dispatcher::with_default`]. Inside setuid programs like `sudo`
+/// the variable is the caller's, and the log would show them match scores,
+/// so it is ignored there.
+pub fn pam_dispatch() -> Option<Dispatch> {
+    if crate::identity::is_elevated() {
+        return None;
+    }
+    EnvFilter::try_from_env(FILTER_ENV).ok().map(dispatch)
+}
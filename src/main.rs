@@ -1,13 +1,29 @@
 use std::{
     env,
+    io::{IsTerminal, Write},
+    path::{Path, PathBuf},
     time::{Duration, Instant},
 };
 
 use anyhow::{Context, Result};
 use clap::{Parser, Subcommand};
-use howrs::{config, identity, matcher, storage, Embedding, Pipeline};
-use howrs_vision::video::Camera;
-use log::{info, warn};
+use howrs::{
+    auth::{self, ScanTally},
+    config,
+    error::Report,
+    howdy,
+    i18n::{self, Msg},
+    identity,
+    matcher::{self, Metric, PruneStrategy, RecordMatrix},
+    pool, preview,
+    privacy::{self, FrameSink},
+    quality::{self, Feedback, FrameQuality, Pose},
+    storage,
+    stream::{Flow, Matcher, SkipReason, StreamEvent, StreamOptions},
+    AuthFailure, Embedding, Pipeline,
+};
+use howrs_vision::{eval, model, video::Camera};
+use tracing::{info, warn};
 
 #[derive(Parser)]
 #[command(name = "howrs")]
@@ -16,6 +32,25 @@ use log::{info, warn};
     about = "Rust howdy clone - facial recognition authentication"
 )]
 struct Cli {
+    /// Print per-frame stage timings
+    #[arg(short, long, global = true)]
+    verbose: bool,
+    /// Read frames from this video file instead of the camera
+    #[arg(long, global = true, value_name = "FILE")]
+    input: Option<PathBuf>,
+    /// Minimum face detection confidence (defaults to `detection.threshold`)
+    #[arg(long, global = true, value_name = "SCORE")]
+    detection_threshold: Option<f32>,
+    /// Overlap above which duplicate detections are merged (defaults to
+    /// `detection.nms_threshold`)
+    #[arg(long, global = true, value_name = "IOU")]
+    nms_threshold: Option<f32>,
+    /// Match threshold for this run, in `metric` units (defaults to `threshold`)
+    #[arg(long, global = true, value_name = "SCORE")]
+    threshold: Option<f32>,
+    /// Camera for this run (defaults to `camera`)
+    #[arg(long, global = true, value_name = "DEVICE")]
+    camera: Option<String>,
     #[command(subcommand)]
     command: Commands,
 }
@@ -27,12 +62,97 @@ enum Commands {
         /// User ID to enroll (defaults to current user)
         #[arg(short, long)]
         user: Option<String>,
+        /// Guide through several head poses, storing one face per pose
+        #[arg(short, long)]
+        guided: bool,
+        /// Store the face even if it nearly duplicates an enrolled one
+        #[arg(short, long)]
+        force: bool,
+        /// Enroll the face in a still image instead of capturing one; needs
+        /// --force, since a photo proves nobody was at the camera
+        #[arg(long, value_name = "PATH", conflicts_with = "guided")]
+        image: Option<PathBuf>,
+        /// Save without checking that the live face matches the new record
+        #[arg(long, conflicts_with = "image")]
+        no_verify: bool,
+        /// Frames to sample per pose (defaults to 30, or 50 with --guided)
+        #[arg(long, value_name = "N", conflicts_with = "image")]
+        max_frames: Option<usize>,
     },
     /// Test authentication by matching against enrolled faces
     Test {
         /// User ID to test (defaults to current user)
         #[arg(short, long)]
         user: Option<String>,
+        /// Write annotated frames to a v4l2loopback device or a directory
+        #[arg(long, value_name = "PATH")]
+        debug_out: Option<PathBuf>,
+        /// Keep matching and show a live score until Ctrl+C
+        #[arg(short, long)]
+        continuous: bool,
+        /// Give up after this many frames, even before `scan_durnation`
+        #[arg(long, value_name = "N")]
+        max_frames: Option<usize>,
+    },
+    /// List enrolled faces and how well they match
+    List {
+        /// User ID to list (defaults to current user)
+        #[arg(short, long)]
+        user: Option<String>,
+    },
+    /// Set or clear the label of an enrolled face, e.g. "no glasses"
+    #[command(alias = "rename")]
+    Label {
+        /// ID of the face, or enough of its start to be unique (see `list`)
+        id: String,
+        /// New label; omit to clear it
+        label: Option<String>,
+        /// User whose face to label (defaults to current user)
+        #[arg(short, long)]
+        user: Option<String>,
+    },
+    /// Move faces staged by a non-root `enroll` into the system store (run with sudo)
+    Commit {
+        /// User whose staged faces to commit (defaults to the sudo caller)
+        #[arg(short, long)]
+        user: Option<String>,
+    },
+    /// Suggest a match threshold for a target false accept rate, from the
+    /// user's own faces against an impostor set
+    Threshold {
+        /// User ID to measure (defaults to current user)
+        #[arg(short, long)]
+        user: Option<String>,
+        /// Fraction of impostor faces allowed to match
+        #[arg(long, default_value_t = 1e-4)]
+        far: f32,
+        /// Impostor embeddings saved by `howrs-eval --export-impostors`
+        /// (defaults to `impostors.bin` in `model_dir`)
+        #[arg(long, value_name = "FILE")]
+        impostors: Option<PathBuf>,
+    },
+    /// Remove the oldest or most redundant enrolled faces
+    Prune {
+        /// User ID to prune (defaults to current user)
+        #[arg(short, long)]
+        user: Option<String>,
+        /// Faces to keep (defaults to `max_records_per_user`)
+        #[arg(short, long)]
+        keep: Option<usize>,
+        /// Which faces go first (defaults to `prune_strategy`)
+        #[arg(short, long, value_enum)]
+        strategy: Option<PruneStrategy>,
+        /// Only show what would be removed
+        #[arg(short = 'n', long)]
+        dry_run: bool,
+    },
+    /// Remove one enrolled face
+    Remove {
+        /// ID of the face, or enough of its start to be unique (see `list`)
+        id: String,
+        /// User whose face to remove (defaults to current user)
+        #[arg(short, long)]
+        user: Option<String>,
     },
     /// Remove all enrolled faces for a user
     Purge {
@@ -42,17 +162,114 @@ enum Commands {
     },
     /// Open config file in editor
     Config,
+    /// Capture a single frame and save it to disk
+    Snapshot {
+        /// Output image path; nothing is saved without it
+        #[arg(short, long)]
+        out: Option<PathBuf>,
+        /// Draw detected face boxes and landmarks on the frame
+        #[arg(short, long)]
+        annotate: bool,
+    },
+    /// Measure camera, detection, encoding and end-to-end latency
+    Benchmark {
+        /// Number of frames to measure
+        #[arg(short, long, default_value_t = 50)]
+        frames: usize,
+        /// User whose enrolled faces are matched (defaults to current user)
+        #[arg(short, long)]
+        user: Option<String>,
+    },
+    /// Turn face authentication off for everyone until `enable`
+    Disable,
+    /// Turn face authentication back on
+    Enable,
+    /// Print version and environment details for bug reports
+    Info,
+    /// Run the models on a synthetic face, without a camera, to check the ONNX
+    /// Runtime install and execution provider
+    Selftest,
+    /// Check the face store's ownership and permissions against `[store]`
+    Doctor {
+        /// Correct the owner and mode of what doesn't match (run with sudo)
+        #[arg(long)]
+        fix_perms: bool,
+        /// Create the store key and sign every enrolled face with it, for a
+        /// store from before faces were signed or whose key was lost. Whatever
+        /// the store holds becomes trusted, so only use it on a store you trust.
+        #[arg(long)]
+        sign_store: bool,
+    },
+    /// Keep models loaded and answer PAM requests over a Unix socket
+    Daemon {
+        /// Socket path (defaults to `pam.fallback.daemon_socket`)
+        #[arg(short, long)]
+        socket: Option<PathBuf>,
+    },
+    /// Download, list and verify the detection and recognition models
+    Models {
+        #[command(subcommand)]
+        action: ModelsCommand,
+    },
+}
+
+#[derive(Subcommand)]
+enum ModelsCommand {
+    /// Show the known models and where each is loaded from
+    List,
+    /// Download models into `model_dir`, checking their pinned SHA-256
+    Fetch {
+        /// Models to fetch (defaults to all)
+        names: Vec<String>,
+        /// Download again even if an intact copy is installed
+        #[arg(short, long)]
+        force: bool,
+        /// Also install models that have no pinned hash, trusting the download
+        #[arg(long)]
+        allow_unpinned: bool,
+    },
+    /// Check installed and embedded models against their pinned SHA-256
+    Verify,
+    /// Time the float and int8 models on this machine and record the faster
+    /// one for `precision = "auto"`
+    Benchmark {
+        /// Timed detect and encode passes per precision
+        #[arg(short, long, default_value_t = 20)]
+        runs: usize,
+    },
 }
 
 fn main() -> Result<()> {
-    env_logger::builder()
-        .filter_level(log::LevelFilter::Info)
-        .format_target(false)
-        .format_timestamp(None)
-        .init();
+    howrs::logging::init();
 
-    let cli = Cli::parse();
-    let cfg = config::load_config(None)?;
+    // Invoked as `howdy` or `howrs howdy-compat`
+    let cli = match howdy::compat_args(env::args_os().collect()) {
+        Some(args) => Cli::parse_from(args?),
+        None => Cli::parse(),
+    };
+    let config_path = match cli.command {
+        // The daemon serves PAM, so it reads the same config the module does;
+        // so do models, which PAM loads from `model_dir`, and the store's
+        // permissions, which apply whoever writes it
+        Commands::Daemon { .. }
+        | Commands::Models { .. }
+        | Commands::Selftest
+        | Commands::Doctor { .. } => config::config_path(),
+        _ => config::cli_config_path(),
+    };
+    let mut cfg = config::load_config(Some(&config_path))?;
+    if let Some(threshold) = cli.detection_threshold {
+        cfg.detection.threshold = Some(threshold);
+    }
+    if let Some(nms_threshold) = cli.nms_threshold {
+        cfg.detection.nms_threshold = Some(nms_threshold);
+    }
+    if let Some(threshold) = cli.threshold {
+        cfg.threshold = threshold;
+    }
+    if let Some(camera) = cli.camera {
+        cfg.camera = camera;
+    }
 
     // Determine user ID
     let default_user = match env::var("SUDO_USER") {
@@ -61,155 +278,1176 @@ fn main() -> Result<()> {
     };
 
     match cli.command {
-        Commands::Enroll { user } => {
+        Commands::Enroll {
+            user,
+            guided,
+            force,
+            image,
+            no_verify,
+            max_frames,
+        } => {
             let user_id = user.unwrap_or(default_user);
-            enroll(&cfg, &user_id)
+            let options = EnrollOptions {
+                guided,
+                force,
+                verify: !no_verify,
+                max_frames,
+            };
+            match image {
+                Some(image) => enroll_image(&cfg, &user_id, &image, force),
+                None => enroll(&cfg, &user_id, options, cli.input.as_deref()),
+            }
         }
-        Commands::Test { user } => {
+        Commands::Test {
+            user,
+            debug_out,
+            continuous,
+            max_frames,
+        } => {
             let user_id = user.unwrap_or(default_user);
-            test(&cfg, &user_id)
+            let status = test(
+                &cfg,
+                &user_id,
+                cli.verbose,
+                cli.input.as_deref(),
+                debug_out.as_deref(),
+                continuous,
+                max_frames,
+            )
+            .unwrap_or_else(|e| {
+                tracing::error!("{:#}", e);
+                TestStatus::Error
+            });
+            if status != TestStatus::Matched {
+                std::process::exit(status as i32);
+            }
+            Ok(())
+        }
+        Commands::List { user } => {
+            let user_id = user.unwrap_or(default_user);
+            list(&cfg, &user_id)
+        }
+        Commands::Label { id, label, user } => {
+            let user_id = user.unwrap_or(default_user);
+            set_label(&user_id, &id, label)
+        }
+        Commands::Commit { user } => {
+            let user_id = user.unwrap_or(default_user);
+            commit(&cfg, &user_id)
+        }
+        Commands::Threshold {
+            user,
+            far,
+            impostors,
+        } => {
+            let user_id = user.unwrap_or(default_user);
+            suggest_threshold(&cfg, &user_id, far, impostors.as_deref())
+        }
+        Commands::Prune {
+            user,
+            keep,
+            strategy,
+            dry_run,
+        } => {
+            let user_id = user.unwrap_or(default_user);
+            prune(&cfg, &user_id, keep, strategy, dry_run)
+        }
+        Commands::Remove { id, user } => {
+            let user_id = user.unwrap_or(default_user);
+            remove(&user_id, &id)
         }
         Commands::Purge { user } => {
             let user_id = user.unwrap_or(default_user);
             purge(&user_id)
         }
-        Commands::Config => open_config(),
+        Commands::Config => open_config(&config_path),
+        Commands::Snapshot { out, annotate } => {
+            snapshot(&cfg, out.as_deref(), annotate, cli.input.as_deref())
+        }
+        Commands::Benchmark { frames, user } => {
+            let user_id = user.unwrap_or(default_user);
+            benchmark(&cfg, &user_id, frames, cli.input.as_deref())
+        }
+        Commands::Disable => set_disabled(true),
+        Commands::Enable => set_disabled(false),
+        Commands::Info => print_info(&cfg, &config_path),
+        Commands::Selftest => selftest(&cfg),
+        Commands::Doctor {
+            fix_perms,
+            sign_store,
+        } => doctor(&cfg, fix_perms, sign_store),
+        Commands::Daemon { socket } => {
+            let socket = socket.unwrap_or_else(|| cfg.pam.fallback.daemon_socket.clone());
+            Ok(howrs::daemon::serve(&socket, &cfg)?)
+        }
+        Commands::Models { action } => match action {
+            ModelsCommand::List => list_models(&cfg),
+            ModelsCommand::Fetch {
+                names,
+                force,
+                allow_unpinned,
+            } => fetch_models(&cfg, &names, force, allow_unpinned),
+            ModelsCommand::Verify => verify_models(&cfg),
+            ModelsCommand::Benchmark { runs } => benchmark_models(&cfg, runs),
+        },
     }
 }
 
-fn enroll(cfg: &config::Config, user_id: &str) -> Result<()> {
-    info!("Enrolling user: {}", user_id);
-    info!("Opening camera: {}", cfg.camera);
+/// Where `enroll` writes new records
+enum EnrollTarget {
+    /// The system store, when running as root
+    System,
+    /// The caller's own staging store under this home directory
+    Staging(PathBuf),
+}
 
-    let mut camera = Camera::open(&cfg.camera).context("Failed to open camera")?;
+impl EnrollTarget {
+    fn for_user(user_id: &str) -> Result<Self> {
+        if identity::is_root() {
+            return Ok(EnrollTarget::System);
+        }
+        let account = identity::lookup(user_id)?;
+        if account.uid != identity::real_uid() {
+            anyhow::bail!("Enrolling another user requires root");
+        }
+        Ok(EnrollTarget::Staging(account.home))
+    }
 
-    let mut pipeline = Pipeline::new().context("Failed to initialize face recognition pipeline")?;
+    /// Records `record` would duplicate: the user's store, plus anything staged
+    fn existing(&self, user_id: &str) -> Result<Vec<storage::FaceRecord>> {
+        let mut records = storage::load_records(user_id)?;
+        if let EnrollTarget::Staging(home) = self {
+            records.extend(storage::load_staged(home)?);
+        }
+        Ok(records)
+    }
+
+    /// Save `record` unless it nearly duplicates an enrolled face and `force`
+    /// is unset. Returns whether it was saved.
+    fn save(&self, user_id: &str, record: storage::FaceRecord, force: bool) -> Result<bool> {
+        let existing = self
+            .existing(user_id)
+            .context("Failed to load enrolled faces")?;
+        if let Some((index, score)) = matcher::find_duplicate(&existing, &record)? {
+            if !force {
+                warn!(
+                    "Face is nearly identical to enrolled face {} (similarity {:.3}); \
+                     not saved. Use --force to store it anyway.",
+                    existing[index].id, score
+                );
+                return Ok(false);
+            }
+            warn!(
+                "Face is nearly identical to enrolled face {} (similarity {:.3})",
+                existing[index].id, score
+            );
+        }
+
+        match self {
+            EnrollTarget::System => storage::save_record(user_id, record),
+            EnrollTarget::Staging(home) => storage::stage_record(home, record),
+        }
+        .context("Failed to save face record")?;
+        Ok(true)
+    }
+
+    fn finish(&self, cfg: &config::Config, user_id: &str) -> Result<()> {
+        match self {
+            EnrollTarget::System => enforce_cap(cfg, user_id),
+            EnrollTarget::Staging(home) => {
+                info!(
+                    "Faces staged in {}; run `sudo howrs commit` to use them for login",
+                    storage::staging_file(home).display()
+                );
+                Ok(())
+            }
+        }
+    }
+}
+
+/// The configured camera, or the `--input` video file when given
+fn open_camera(cfg: &config::Config, input: Option<&Path>) -> Result<Camera> {
+    match input {
+        Some(path) => {
+            info!("Reading frames from: {}", path.display());
+            Camera::from_video_file(path).context("Failed to open video file")
+        }
+        None => {
+            info!("Opening camera: {}", cfg.camera);
+            Camera::open(&cfg.camera, &cfg.capture.settings()).context("Failed to open camera")
+        }
+    }
+}
+
+/// How `enroll` captures and checks new records
+#[derive(Clone, Copy)]
+struct EnrollOptions {
+    guided: bool,
+    /// Save faces that duplicate an enrolled one or fail verification
+    force: bool,
+    /// Match the live face against each new record before saving it
+    verify: bool,
+    /// Frames sampled per pose instead of the default
+    max_frames: Option<usize>,
+}
+
+fn enroll(
+    cfg: &config::Config,
+    user_id: &str,
+    options: EnrollOptions,
+    input: Option<&Path>,
+) -> Result<()> {
+    let target = EnrollTarget::for_user(user_id)?;
+    info!("Enrolling user: {}", user_id);
+    let mut camera = open_camera(cfg, input)?;
+
+    let mut pipeline =
+        auth::load_pipeline(cfg).context("Failed to initialize face recognition pipeline")?;
 
     info!("Camera opened. Capturing frames...");
     info!("Press Ctrl+C to stop.");
 
+    if options.guided {
+        enroll_guided(cfg, &mut camera, &mut pipeline, &target, user_id, options)?;
+        return target.finish(cfg, user_id);
+    }
+
     // Capture multiple frames and try to get a good face
-    let max_attempts = 30;
-    let mut best_detection: Option<howrs::Detection> = None;
-    let mut best_embedding: Option<Embedding> = None;
+    let max_frames = options.max_frames.unwrap_or(30);
+    match capture_pose(&mut camera, &mut pipeline, Pose::Straight, max_frames)? {
+        Some((detection, embeddings)) => {
+            info!(
+                "Best face: score {:.3} ({} sample(s))",
+                detection.score,
+                embeddings.len()
+            );
 
-    for i in 0..max_attempts {
-        let frame = camera.frame().context("Failed to capture frame")?;
+            // Save embeddings
+            let mut record = storage::FaceRecord::new(embedding_vectors(&embeddings), None);
+            record.meta.capture = camera.device_info().map(Into::into);
+            record.meta.quality = Some(detection.score);
 
-        let img = image::DynamicImage::ImageRgb8(frame);
+            if !check_enrollment(cfg, &mut camera, &mut pipeline, &record, options)? {
+                anyhow::bail!("Enrollment failed verification; face not saved.");
+            }
+            if target.save(user_id, record, options.force)? {
+                info!("✓ Face enrolled successfully for user: {}", user_id);
+                target.finish(cfg, user_id)?;
+            }
+            Ok(())
+        }
+        None => {
+            anyhow::bail!(
+                "Failed to detect a face. Please ensure your face is visible and well-lit."
+            );
+        }
+    }
+}
 
-        match pipeline.process_image(&img, 0.6, 0.3) {
-            Ok((detection, embedding)) => {
-                info!(
-                    "Frame {}: Face detected with score {:.3}",
-                    i + 1,
-                    detection.score
-                );
+/// Enroll the face in a still image, e.g. to provision a machine remotely.
+///
+/// Anyone holding a copy of the photo could then be enrolled too, so this
+/// asks for `--force` and marks the record as image-sourced.
+fn enroll_image(cfg: &config::Config, user_id: &str, path: &Path, force: bool) -> Result<()> {
+    warn!("Enrolling from a still image: nobody has to be at the camera for this.");
+    warn!("Only use a photo taken for this purpose, and re-enroll live when possible.");
+    if !force {
+        anyhow::bail!("Refusing to enroll from an image without --force");
+    }
 
-                // Keep the best detection
-                let score = detection.score;
-                if best_detection.is_none() || score > best_detection.as_ref().unwrap().score {
-                    best_detection = Some(detection);
-                    best_embedding = Some(embedding);
-                }
+    let target = EnrollTarget::for_user(user_id)?;
+    info!("Enrolling user {} from {}", user_id, path.display());
+    let img = image::open(path).with_context(|| format!("Failed to open {}", path.display()))?;
+
+    let mut pipeline =
+        auth::load_pipeline(cfg).context("Failed to initialize face recognition pipeline")?;
+    let (detection, embedding, _) = pipeline
+        .process_image(&img)
+        .context("Failed to find a usable face in the image")?;
+    info!("Face: score {:.3}", detection.score);
 
-                // If we got a good enough detection, we're done
-                if score > 0.8 {
-                    info!("High quality face detected!");
-                    break;
+    let mut record = storage::FaceRecord::new(embedding_vectors(&[embedding]), None);
+    record.meta.source = storage::RecordSource::Image;
+    record.meta.quality = Some(detection.score);
+    if target.save(user_id, record, force)? {
+        info!("✓ Face enrolled from image for user: {}", user_id);
+        target.finish(cfg, user_id)?;
+    }
+    Ok(())
+}
+
+/// Capture one record per pose in [`Pose::GUIDED`]
+fn enroll_guided(
+    cfg: &config::Config,
+    camera: &mut Camera,
+    pipeline: &mut Pipeline,
+    target: &EnrollTarget,
+    user_id: &str,
+    options: EnrollOptions,
+) -> Result<()> {
+    let mut enrolled = 0;
+
+    for pose in Pose::GUIDED {
+        info!("{}", pose_prompt(pose));
+        // Give the user a moment to move before sampling
+        std::thread::sleep(Duration::from_millis(1500));
+
+        match capture_pose(camera, pipeline, pose, options.max_frames.unwrap_or(50))? {
+            Some((detection, embeddings)) => {
+                let mut record = storage::FaceRecord::new(
+                    embedding_vectors(&embeddings),
+                    Some(pose.name().to_string()),
+                );
+                record.meta.capture = camera.device_info().map(Into::into);
+                record.meta.quality = Some(detection.score);
+                if !check_enrollment(cfg, camera, pipeline, &record, options)? {
+                    warn!("Skipping pose '{}'", pose.name());
+                    continue;
+                }
+                if target.save(user_id, record, options.force)? {
+                    info!(
+                        "✓ Captured pose '{}' (score {:.3})",
+                        pose.name(),
+                        detection.score
+                    );
+                    enrolled += 1;
                 }
             }
+            None => warn!("Could not capture pose '{}', skipping", pose.name()),
+        }
+    }
+
+    if enrolled == 0 {
+        anyhow::bail!(
+            "Failed to capture any pose. Please ensure your face is visible and well-lit."
+        );
+    }
+
+    info!(
+        "✓ Enrolled {}/{} poses for user: {}",
+        enrolled,
+        Pose::GUIDED.len(),
+        user_id
+    );
+    Ok(())
+}
+
+/// Live frames that must match a new record before it is saved
+const VERIFY_MATCHES: usize = 3;
+/// Frames sampled while verifying
+const VERIFY_ATTEMPTS: usize = 30;
+
+/// Whether `record` may be saved: the live face has to match it above the
+/// threshold [`VERIFY_MATCHES`] times, so a bad capture (wrong person in
+/// frame, glare, motion blur) is caught now rather than at the next login.
+/// With `force` a failed check only warns.
+fn check_enrollment(
+    cfg: &config::Config,
+    camera: &mut Camera,
+    pipeline: &mut Pipeline,
+    record: &storage::FaceRecord,
+    options: EnrollOptions,
+) -> Result<bool> {
+    if !options.verify {
+        return Ok(true);
+    }
+    info!("{}", i18n::text(Msg::Verifying));
+    let threshold = cfg.similarity_threshold();
+    let mut matches = 0;
+    let mut best = f32::NEG_INFINITY;
+
+    for i in 0..VERIFY_ATTEMPTS {
+        let frame = camera.frame().context("Failed to capture frame")?;
+        let img = image::DynamicImage::ImageRgb8(frame);
+        let embedding = match pipeline.detect_best(&img) {
+            Ok(Some(detection)) => pipeline.encode_detection(&img, &detection).ok(),
+            Ok(None) => None,
             Err(e) => {
                 warn!("Frame {}: {}", i + 1, e);
+                None
+            }
+        };
+        pool::frames().recycle_image(img);
+
+        if let Some(embedding) = embedding {
+            let score = matcher::score_record(record, &embedding, cfg.fusion);
+            best = best.max(score);
+            if score >= threshold {
+                matches += 1;
             }
         }
+        eprint!(
+            "\rVerifying: {}/{} matches (best {:.3})  ",
+            matches, VERIFY_MATCHES, best
+        );
+        let _ = std::io::stderr().flush();
+        if matches >= VERIFY_MATCHES {
+            eprintln!();
+            info!("✓ Live face matches the new record");
+            return Ok(true);
+        }
+    }
+    eprintln!();
 
-        // Small delay between frames
-        std::thread::sleep(std::time::Duration::from_millis(100));
+    if best.is_finite() {
+        warn!(
+            "The live face matched the new record {}/{} times (best similarity {:.3}, threshold {:.3}); \
+             it would likely fail at login.",
+            matches, VERIFY_MATCHES, best, threshold
+        );
+    } else {
+        warn!("No face seen while verifying the new record.");
     }
+    if options.force {
+        warn!("Saving it anyway because of --force.");
+        return Ok(true);
+    }
+    warn!("Re-enroll facing the camera in even light, or use --force to store it anyway.");
+    Ok(false)
+}
+
+fn pose_prompt(pose: Pose) -> &'static str {
+    i18n::text(match pose {
+        Pose::Straight => Msg::PoseStraight,
+        Pose::Left => Msg::PoseLeft,
+        Pose::Right => Msg::PoseRight,
+        Pose::Up => Msg::PoseUp,
+    })
+}
+
+/// Embeddings stored per enrolled record
+const SAMPLES_PER_RECORD: usize = 3;
+
+fn embedding_vectors(embeddings: &[Embedding]) -> Vec<Vec<f32>> {
+    embeddings
+        .iter()
+        .map(|e| e.vector().iter().copied().collect())
+        .collect()
+}
+
+/// Sample frames until enough show a good face in the `target` pose.
+///
+/// Returns the best detection seen with the embeddings of the
+/// [`SAMPLES_PER_RECORD`] best frames, stopping early once they are all high
+/// quality.
+fn capture_pose(
+    camera: &mut Camera,
+    pipeline: &mut Pipeline,
+    target: Pose,
+    max_attempts: usize,
+) -> Result<Option<(howrs::Detection, Vec<Embedding>)>> {
+    // Best first
+    let mut samples: Vec<(howrs::Detection, Embedding)> = Vec::new();
+
+    for i in 0..max_attempts {
+        let frame = camera.frame().context("Failed to capture frame")?;
+
+        let img = image::DynamicImage::ImageRgb8(frame);
+
+        let detection = match pipeline.detect_best(&img) {
+            Ok(detection) => detection,
+            Err(e) => {
+                warn!("Frame {}: {}", i + 1, e);
+                None
+            }
+        };
 
-    match (best_detection, best_embedding) {
-        (Some(detection), Some(embedding)) => {
-            info!("Best face: score {:.3}", detection.score);
+        let feedback = FrameQuality::measure(&img, detection.as_ref()).feedback_for(target);
+        print_progress(i + 1, max_attempts, feedback, detection.as_ref());
 
-            // Save embedding
-            let record = storage::FaceRecord {
-                id: uuid::Uuid::new_v4().to_string(),
-                embedding: embedding.vector.iter().copied().collect(),
+        // Only encode frames the user can't improve on
+        if let (Feedback::Good, Some(detection)) = (feedback, detection) {
+            let embedding = match pipeline.encode_detection(&img, &detection) {
+                Ok(embedding) => embedding,
+                Err(e) => {
+                    warn!("Frame {}: {}", i + 1, e);
+                    continue;
+                }
             };
 
-            storage::save_record(user_id, record).context("Failed to save face record")?;
+            // Keep the best detections
+            let at = samples.partition_point(|(d, _)| d.score >= detection.score);
+            samples.insert(at, (detection, embedding));
+            samples.truncate(SAMPLES_PER_RECORD);
 
-            info!("✓ Face enrolled successfully for user: {}", user_id);
-            Ok(())
-        }
-        _ => {
-            anyhow::bail!(
-                "Failed to detect a face. Please ensure your face is visible and well-lit."
-            );
+            // If we got enough good detections, we're done
+            if samples.len() == SAMPLES_PER_RECORD && samples.iter().all(|(d, _)| d.score > 0.8) {
+                eprintln!();
+                info!("High quality face detected!");
+                return Ok(into_record_samples(samples));
+            }
         }
+        pool::frames().recycle_image(img);
+
+        // Small delay between frames
+        std::thread::sleep(std::time::Duration::from_millis(100));
     }
+    eprintln!();
+
+    Ok(into_record_samples(samples))
+}
+
+fn into_record_samples(
+    samples: Vec<(howrs::Detection, Embedding)>,
+) -> Option<(howrs::Detection, Vec<Embedding>)> {
+    let mut samples = samples.into_iter();
+    let (best, embedding) = samples.next()?;
+    let mut embeddings = vec![embedding];
+    embeddings.extend(samples.map(|(_, e)| e));
+    Some((best, embeddings))
 }
 
-fn test(cfg: &config::Config, user_id: &str) -> Result<()> {
+/// Redraw a single-line progress indicator with feedback for the current frame
+fn print_progress(
+    frame: usize,
+    total: usize,
+    feedback: Feedback,
+    detection: Option<&howrs::Detection>,
+) {
+    const WIDTH: usize = 20;
+    let filled = frame * WIDTH / total;
+    let mut message = i18n::feedback(feedback).to_string();
+    if let (Feedback::Good, Some(d)) = (feedback, detection) {
+        message.push_str(&format!(" (score {:.2})", d.score));
+    }
+
+    eprint!(
+        "\r[{}{}] {:>2}/{}  {:<40}",
+        "#".repeat(filled),
+        "-".repeat(WIDTH - filled),
+        frame,
+        total,
+        message
+    );
+    let _ = std::io::stderr().flush();
+}
+
+fn test(
+    cfg: &config::Config,
+    user_id: &str,
+    verbose: bool,
+    input: Option<&Path>,
+    debug_out: Option<&Path>,
+    continuous: bool,
+    max_frames: Option<usize>,
+) -> Result<TestStatus> {
     info!("Testing authentication for user: {}", user_id);
 
     // Load enrolled faces
     let records = storage::load_records(user_id).context("Failed to load face records")?;
 
     if records.is_empty() {
-        anyhow::bail!(
+        tracing::error!(
             "No enrolled faces found for user: {}. Run 'enroll' first.",
             user_id
         );
+        return Ok(TestStatus::NotEnrolled);
     }
 
     info!("Found {} enrolled face(s)", records.len());
-    info!("Opening camera: {}", cfg.camera);
+    let mut camera = match open_camera(cfg, input) {
+        Ok(camera) => camera,
+        Err(e) => {
+            tracing::error!("{:#}", e);
+            return Ok(TestStatus::CameraError);
+        }
+    };
 
-    let mut camera = Camera::open(&cfg.camera).context("Failed to open camera")?;
+    let mut pipeline =
+        auth::load_auth_pipeline(cfg).context("Failed to initialize face recognition pipeline")?;
 
-    let mut pipeline = Pipeline::new().context("Failed to initialize face recognition pipeline")?;
+    let mut preview = debug_out.map(preview::Preview::open).transpose()?;
+    let mut live = continuous.then(LiveReadout::new);
 
     info!("Camera opened. Capturing frames...");
 
-    let start_time = Instant::now();
-    let scan_duration = Duration::from_secs(cfg.scan_durnation as u64);
+    let sensor = camera.device_info().map(|info| info.sensor.into());
+    let matrix = RecordMatrix::new(&records)
+        .for_sensor(sensor, cfg.sensor_match)
+        .weighted(&records, cfg.weighting.weights(), storage::unix_now());
+    let threshold = cfg.similarity_threshold();
+    if matrix.len() - matrix.skipped().len() < cfg.min_matching_records {
+        warn!(
+            "min_matching_records = {} but only {} usable face(s) are enrolled; enroll more",
+            cfg.min_matching_records,
+            matrix.len() - matrix.skipped().len()
+        );
+    }
 
-    while start_time.elapsed() < scan_duration {
-        let frame = camera.frame().context("Failed to capture frame")?;
+    // The live readout runs until interrupted, so nothing may end it early
+    let opts = StreamOptions {
+        deadline: (!continuous)
+            .then(|| Instant::now() + Duration::from_secs(cfg.scan_durnation as u64)),
+        all_faces: false,
+        matcher: Some(Matcher {
+            score: Box::new(|probe| {
+                matrix.quorum_match(probe, cfg.fusion, cfg.min_matching_records)
+            }),
+            threshold: if continuous { f32::INFINITY } else { threshold },
+        }),
+    };
+    let mut capture = Duration::ZERO;
+    let mut tally = ScanTally::default();
+    let mut capture_error = None;
+    let mut frames = 0;
 
-        let img = image::DynamicImage::ImageRgb8(frame);
+    let matched = pipeline.run_stream(&mut camera, &opts, |event| {
+        tally.observe(&event);
+        match event {
+            StreamEvent::FrameCaptured { capture: took, .. } => {
+                frames += 1;
+                if max_frames.is_some_and(|max| frames > max) {
+                    return Ok(Flow::Stop);
+                }
+                capture = took;
+            }
+            StreamEvent::FrameSkipped {
+                reason: SkipReason::Capture(e),
+                ..
+            } => {
+                capture_error = Some(e.context("Failed to capture frame"));
+                return Ok(Flow::Stop);
+            }
+            StreamEvent::FrameSkipped { frame, reason } => {
+                if let (Some(preview), Some(frame)) = (&mut preview, frame) {
+                    if let Err(e) = preview.write(frame, None, None, threshold) {
+                        warn!("Failed to write debug frame: {}", Report(&e));
+                    }
+                }
+                match &live {
+                    Some(live) => live.show_status(&reason.to_string()),
+                    None => {
+                        warn!("{}", reason);
+                        // Small delay between frames
+                        std::thread::sleep(Duration::from_millis(100));
+                    }
+                }
+            }
+            StreamEvent::ScoreComputed {
+                frame,
+                detection,
+                candidate,
+                score,
+                mut timings,
+                ..
+            } => {
+                if let Some(preview) = &mut preview {
+                    if let Err(e) = preview.write(frame, Some(detection), Some(score), threshold) {
+                        warn!("Failed to write debug frame: {}", Report(&e));
+                    }
+                }
+                if let Some(live) = &mut live {
+                    live.show(Some((score, &records[candidate])), threshold);
+                    return Ok(Flow::Continue);
+                }
 
-        match pipeline.extract_embedding(&img, cfg.threshold, 0.3) {
-            Ok(probe_embedding) => {
                 info!("Face detected");
-
-                // Match against stored faces
-                let best_score = matcher::best_score(&records, &probe_embedding);
-
-                if let Some(score) = best_score {
+                if verbose {
+                    timings.capture = capture;
+                    info!("Timings: {}", timings);
                     info!(
-                        "Match score: {:.3} (threshold: {:.3})",
-                        score, cfg.threshold
+                        "Detection score: {:.2}, sharpness: {:.1}",
+                        detection.score,
+                        quality::sharpness(frame, detection)
+                    );
+                }
+                let calibration = cfg.calibration.calibration();
+                info!(
+                    "Match score: {:.3}, ~{:.1}% genuine (threshold: {:.3}, ~{:.1}%)",
+                    score,
+                    calibration.probability(score) * 100.0,
+                    threshold,
+                    calibration.probability(threshold) * 100.0
+                );
+                if cfg.metric != Metric::Cosine {
+                    info!(
+                        "{:?} distance: {:.3} (threshold: {:.3})",
+                        cfg.metric,
+                        cfg.metric.value(score),
+                        cfg.threshold
                     );
-
-                    if score >= cfg.threshold {
-                        info!("✓ Authentication successful!");
-                        return Ok(());
-                    }
+                }
+                if score < threshold {
+                    std::thread::sleep(Duration::from_millis(100));
+                }
+            }
+            StreamEvent::Matched {
+                embedding,
+                candidate,
+                ..
+            } => {
+                info!("✓ Authentication successful!");
+                let probe: Vec<f32> = embedding.vector().iter().copied().collect();
+                if let Err(e) = storage::record_match(user_id, &records[candidate].id, &probe) {
+                    warn!("Failed to update match stats: {}", Report(&e));
                 }
             }
+            StreamEvent::FaceDetected { .. } => {}
+        }
+        Ok::<_, anyhow::Error>(Flow::Continue)
+    })?;
+
+    if let Some(e) = capture_error {
+        tracing::error!("{}", Report(&e));
+        return Ok(TestStatus::CameraError);
+    }
+    if matched.is_some() {
+        return Ok(TestStatus::Matched);
+    }
+    let failure = tally.failure();
+    tracing::error!("Authentication failed: {}", failure);
+    Ok(failure.into())
+}
+
+/// Exit status of `howrs test`, for scripts to branch on
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum TestStatus {
+    Matched = 0,
+    NoMatch = 1,
+    NoFace = 2,
+    CameraError = 3,
+    NotEnrolled = 4,
+    /// Anything else, e.g. the models failed to load
+    Error = 5,
+}
+
+impl From<AuthFailure> for TestStatus {
+    fn from(failure: AuthFailure) -> Self {
+        match failure {
+            AuthFailure::BelowThreshold { .. } | AuthFailure::SpoofSuspected => TestStatus::NoMatch,
+            AuthFailure::NoFaceDetected | AuthFailure::FaceTooDark => TestStatus::NoFace,
+            AuthFailure::NoCamera | AuthFailure::Timeout => TestStatus::CameraError,
+        }
+    }
+}
+
+/// Single status line for `howrs test --continuous`, redrawn in place on a
+/// terminal and printed line by line otherwise
+struct LiveReadout {
+    tty: bool,
+    peak: Option<f32>,
+}
+
+impl LiveReadout {
+    fn new() -> Self {
+        Self {
+            tty: std::io::stdout().is_terminal(),
+            peak: None,
+        }
+    }
+
+    /// Show the best matching record and its score
+    fn show(&mut self, matched: Option<(f32, &storage::FaceRecord)>, threshold: f32) {
+        let Some((score, record)) = matched else {
+            return self.show_status("no enrolled face to match");
+        };
+        let peak = self.peak.map_or(score, |p| p.max(score));
+        self.peak = Some(peak);
+
+        let verdict = if score >= threshold { "✓" } else { "✗" };
+        let mut line = format!(
+            "score {:.3} {} (threshold {:.3}, peak {:.3})  record {}",
+            score,
+            verdict,
+            threshold,
+            peak,
+            &record.id[..8.min(record.id.len())]
+        );
+        if let Some(label) = &record.meta.label {
+            line.push_str(&format!(" [{}]", label));
+        }
+        self.show_status(&line);
+    }
+
+    fn show_status(&self, line: &str) {
+        let mut stdout = std::io::stdout().lock();
+        if self.tty {
+            // Return to column 0 and clear the previous readout
+            let _ = write!(stdout, "\r\x1b[2K{}", line);
+            let _ = stdout.flush();
+        } else {
+            let _ = writeln!(stdout, "{}", line);
+        }
+    }
+}
+
+fn benchmark(
+    cfg: &config::Config,
+    user_id: &str,
+    frames: usize,
+    input: Option<&Path>,
+) -> Result<()> {
+    if frames == 0 {
+        anyhow::bail!("--frames must be at least 1");
+    }
+
+    // Matching is part of the end-to-end time; without records it is skipped
+    let records = storage::load_records(user_id).unwrap_or_default();
+
+    let start = Instant::now();
+    let mut pipeline =
+        auth::load_auth_pipeline(cfg).context("Failed to initialize face recognition pipeline")?;
+    let model_load = start.elapsed();
+
+    let start = Instant::now();
+    let mut camera = open_camera(cfg, input)?;
+    let camera_open = start.elapsed();
+
+    let mut capture = Vec::with_capacity(frames);
+    let mut detect = Vec::with_capacity(frames);
+    let mut encode = Vec::with_capacity(frames);
+    let mut end_to_end = Vec::with_capacity(frames);
+    let mut resolution = (0, 0);
+
+    let source = match input {
+        Some(path) => path.display().to_string(),
+        None => cfg.camera.clone(),
+    };
+    info!("Measuring {} frames from {}...", frames, source);
+    let run_start = Instant::now();
+    for _ in 0..frames {
+        let frame_start = Instant::now();
+        let frame = camera.frame().context("Failed to capture frame")?;
+        capture.push(frame_start.elapsed());
+        resolution = frame.dimensions();
+        let img = image::DynamicImage::ImageRgb8(frame);
+
+        let start = Instant::now();
+        let detection = pipeline.detect_best(&img)?;
+        detect.push(start.elapsed());
+
+        // Frames without a face only contribute to capture and detection
+        if let Some(detection) = detection {
+            let start = Instant::now();
+            let embedding = pipeline.encode_detection(&img, &detection)?;
+            encode.push(start.elapsed());
+
+            let _ = matcher::best_match(&records, &embedding, cfg.fusion);
+            end_to_end.push(frame_start.elapsed());
+        }
+        pool::frames().recycle_image(img);
+    }
+    let run_time = run_start.elapsed();
+    let frame_pool = pool::frames().stats();
+    let tensor_pool = pool::tensors().stats();
+
+    println!(
+        "camera:      {} ({}x{})",
+        source, resolution.0, resolution.1
+    );
+    println!("model load:  {:.1} ms", ms(model_load));
+    println!("camera open: {:.1} ms", ms(camera_open));
+    println!(
+        "throughput:  {:.1} fps over {} frames",
+        frames as f64 / run_time.as_secs_f64(),
+        frames
+    );
+    println!(
+        "buffers:     {} allocated, {} reused",
+        frame_pool.allocations + tensor_pool.allocations,
+        frame_pool.reuses + tensor_pool.reuses
+    );
+    println!(
+        "{:<12} {:>6} {:>9} {:>9} {:>9} {:>9}",
+        "stage", "n", "p50 ms", "p90 ms", "p99 ms", "max ms"
+    );
+    for (name, samples) in [
+        ("capture", &mut capture),
+        ("detect", &mut detect),
+        ("encode", &mut encode),
+        ("end-to-end", &mut end_to_end),
+    ] {
+        if samples.is_empty() {
+            println!("{:<12} {:>6}  (no face detected)", name, 0);
+            continue;
+        }
+        samples.sort();
+        println!(
+            "{:<12} {:>6} {:>9.1} {:>9.1} {:>9.1} {:>9.1}",
+            name,
+            samples.len(),
+            ms(percentile(samples, 0.50)),
+            ms(percentile(samples, 0.90)),
+            ms(percentile(samples, 0.99)),
+            ms(samples[samples.len() - 1])
+        );
+    }
+
+    Ok(())
+}
+
+/// Nearest-rank percentile of an already sorted, non-empty slice
+fn percentile(sorted: &[Duration], p: f64) -> Duration {
+    let rank = (p * sorted.len() as f64).ceil() as usize;
+    sorted[rank.clamp(1, sorted.len()) - 1]
+}
+
+fn ms(d: Duration) -> f64 {
+    d.as_secs_f64() * 1000.0
+}
+
+fn list(cfg: &config::Config, user_id: &str) -> Result<()> {
+    let records = storage::load_records(user_id).context("Failed to load face records")?;
+
+    if records.is_empty() {
+        info!("No enrolled faces for user: {}", user_id);
+        return Ok(());
+    }
+
+    let stats = storage::load_match_stats(user_id).context("Failed to load match stats")?;
+    let probe = stats
+        .last_probe
+        .as_deref()
+        .and_then(|p| matcher::embedding_from_vec(p).ok());
+    // Damaged records are listed but left out of every similarity
+    let dim = matcher::common_dim(&records);
+    let embeddings: Vec<howrs::Result<Embedding>> = records
+        .iter()
+        .map(|r| matcher::check_record(r, dim).and_then(|_| matcher::record_embedding(r)))
+        .collect();
+    let total_hits: u32 = stats.hits.values().sum();
+    let mut damaged = 0;
+
+    info!("{} enrolled face(s) for user: {}", records.len(), user_id);
+    info!(
+        "{:<36}  {:>9}  {:>10}  {:>7}",
+        "ID", "avg-other", "last-probe", "matches"
+    );
+
+    for (i, record) in records.iter().enumerate() {
+        let embedding = match &embeddings[i] {
+            Ok(embedding) => embedding,
             Err(e) => {
-                warn!("{}", e);
+                warn!(
+                    "{:<36}  <- damaged, never matched: {}",
+                    record.id,
+                    Report(e)
+                );
+                damaged += 1;
+                continue;
             }
+        };
+        // Average similarity to the user's other records
+        let others: Vec<f32> = embeddings
+            .iter()
+            .enumerate()
+            .filter(|(j, _)| *j != i)
+            .filter_map(|(_, other)| other.as_ref().ok())
+            .map(|other| matcher::match_embedding(embedding, other))
+            .collect();
+        let avg_other = if others.is_empty() {
+            "-".to_string()
+        } else {
+            format!("{:.3}", others.iter().sum::<f32>() / others.len() as f32)
+        };
+
+        let last_probe = probe
+            .as_ref()
+            .map(|p| format!("{:.3}", matcher::match_embedding(embedding, p)))
+            .unwrap_or_else(|| "-".to_string());
+
+        let hits = stats.hits.get(&record.id).copied().unwrap_or(0);
+        let below_threshold = probe
+            .as_ref()
+            .is_some_and(|p| matcher::match_embedding(embedding, p) < cfg.similarity_threshold());
+
+        let mut line = format!(
+            "{:<36}  {:>9}  {:>10}  {:>7}",
+            record.id, avg_other, last_probe, hits
+        );
+        if let Some(label) = &record.meta.label {
+            line.push_str(&format!("  \"{}\"", label));
+        }
+        if record.meta.source == storage::RecordSource::Image {
+            line.push_str("  (from image)");
         }
+        // A record that never won a match is a candidate for removal
+        if total_hits > 0 && hits == 0 && below_threshold {
+            warn!("{}  <- never matched, consider removing", line);
+        } else {
+            info!("{}", line);
+        }
+    }
+    if damaged > 0 {
+        warn!(
+            "{} damaged face(s) are skipped when matching; `howrs prune` removes them first",
+            damaged
+        );
+    }
 
-        // Small delay between frames
-        std::thread::sleep(std::time::Duration::from_millis(100));
+    Ok(())
+}
+
+fn set_label(user_id: &str, id: &str, label: Option<String>) -> Result<()> {
+    let label = label
+        .map(|l| l.trim().to_string())
+        .filter(|l| !l.is_empty());
+    if label
+        .as_deref()
+        .is_some_and(|l| l.chars().any(char::is_control))
+    {
+        anyhow::bail!("Labels can't contain control characters");
+    }
+    let id = storage::set_label(user_id, id, label.clone()).context("Failed to label face")?;
+    match label {
+        Some(label) => info!("✓ Labelled face {} \"{}\"", id, label),
+        None => info!("✓ Cleared the label of face {}", id),
     }
+    Ok(())
+}
 
-    anyhow::bail!("Authentication failed: No matching face detected")
+fn suggest_threshold(
+    cfg: &config::Config,
+    user_id: &str,
+    far: f32,
+    impostors: Option<&Path>,
+) -> Result<()> {
+    let records = storage::load_records(user_id).context("Failed to load face records")?;
+    let impostors_path = impostors
+        .map(Path::to_path_buf)
+        .unwrap_or_else(|| cfg.model_dir.join(eval::IMPOSTORS_FILE));
+    let impostors = eval::read_embeddings(&impostors_path).context(
+        "Failed to load the impostor set; build one with `howrs-eval --export-impostors`",
+    )?;
+
+    // Every sample of every intact record, tagged with its record
+    let dim = matcher::common_dim(&records);
+    let samples: Vec<(usize, Embedding)> = records
+        .iter()
+        .enumerate()
+        .filter(|(_, r)| matcher::check_record(r, dim).is_ok())
+        .flat_map(|(i, r)| r.embeddings.iter().map(move |v| (i, v)))
+        .filter_map(|(i, v)| matcher::embedding_from_vec(v).ok().map(|e| (i, e)))
+        .collect();
+    if samples.len() < 2 {
+        anyhow::bail!("Enroll at least two samples for user: {}", user_id);
+    }
+    if impostors
+        .first()
+        .is_some_and(|e| e.dim() != samples[0].1.dim())
+    {
+        anyhow::bail!(
+            "{} holds {}-d embeddings, the enrolled faces are {}-d",
+            impostors_path.display(),
+            impostors[0].dim(),
+            samples[0].1.dim()
+        );
+    }
+
+    // Samples of one record are near copies, so pair across records when
+    // there are several
+    let across = records.len() > 1 && samples.iter().any(|(i, _)| *i != samples[0].0);
+    let mut same = Vec::new();
+    for (n, (i, a)) in samples.iter().enumerate() {
+        for (j, b) in &samples[n + 1..] {
+            if !across || i != j {
+                same.push(matcher::match_embedding(a, b));
+            }
+        }
+    }
+    let different: Vec<f32> = samples
+        .iter()
+        .flat_map(|(_, a)| impostors.iter().map(|b| matcher::match_embedding(a, b)))
+        .collect();
+
+    let Some(t) = eval::threshold_for_far(&same, &different, far) else {
+        anyhow::bail!("{} holds no embeddings", impostors_path.display());
+    };
+    info!(
+        "{} genuine pair(s), {} impostor pair(s)",
+        same.len(),
+        t.impostor_pairs
+    );
+    if (t.impostor_pairs as f32) < 1.0 / far {
+        warn!(
+            "Too few impostor pairs to measure a false accept rate of {}; the threshold rejects all of them",
+            far
+        );
+    }
+    info!(
+        "Suggested threshold: {:.3} (false accept rate {:.5}, true accept rate {:.3}, current {:.3})",
+        cfg.metric.value(t.threshold),
+        t.false_accept_rate,
+        t.true_accept_rate,
+        cfg.threshold
+    );
+    Ok(())
+}
+
+fn commit(cfg: &config::Config, user_id: &str) -> Result<()> {
+    if !identity::is_root() {
+        anyhow::bail!("Committing staged faces requires root; run `sudo howrs commit`");
+    }
+    let account = identity::lookup(user_id)?;
+
+    let count = storage::commit_staged(user_id, &account.home, account.uid)
+        .context("Failed to commit staged faces")?;
+    if count == 0 {
+        info!("No staged faces for user: {}", user_id);
+    } else {
+        info!("✓ Committed {} staged face(s) for user: {}", count, user_id);
+        enforce_cap(cfg, user_id)?;
+    }
+    Ok(())
+}
+
+/// Trim the store to `max_records_per_user` after adding faces
+fn enforce_cap(cfg: &config::Config, user_id: &str) -> Result<()> {
+    let removed = storage::enforce_cap(user_id, cfg).context("Failed to prune face records")?;
+    if !removed.is_empty() {
+        info!(
+            "Removed {} face(s) to stay within max_records_per_user = {}",
+            removed.len(),
+            cfg.max_records_per_user
+        );
+    }
+    Ok(())
+}
+
+fn prune(
+    cfg: &config::Config,
+    user_id: &str,
+    keep: Option<usize>,
+    strategy: Option<PruneStrategy>,
+    dry_run: bool,
+) -> Result<()> {
+    let keep = match keep.unwrap_or(cfg.max_records_per_user) {
+        0 => anyhow::bail!(
+            "Pass --keep or set max_records_per_user; use `howrs purge` to remove every face"
+        ),
+        keep => keep,
+    };
+    let strategy = strategy.unwrap_or(cfg.prune_strategy);
+
+    let removed = if dry_run {
+        let records = storage::load_records(user_id).context("Failed to load face records")?;
+        matcher::select_prune(&records, keep, strategy)
+            .into_iter()
+            .map(|i| records[i].clone())
+            .collect()
+    } else {
+        storage::prune(user_id, keep, strategy).context("Failed to prune face records")?
+    };
+
+    if removed.is_empty() {
+        info!("{} has at most {} face(s), nothing to prune", user_id, keep);
+        return Ok(());
+    }
+    let verb = if dry_run { "Would remove" } else { "Removed" };
+    for record in &removed {
+        match &record.meta.label {
+            Some(label) => info!("{} {} ({})", verb, record.id, label),
+            None => info!("{} {}", verb, record.id),
+        }
+    }
+    info!("{} {} face(s) for user: {}", verb, removed.len(), user_id);
+    Ok(())
+}
+
+fn remove(user_id: &str, id: &str) -> Result<()> {
+    let removed = storage::remove_record(user_id, id).context("Failed to remove face")?;
+    info!("✓ Removed face {} of user: {}", removed.id, user_id);
+    Ok(())
 }
 
 fn purge(user_id: &str) -> Result<()> {
@@ -221,8 +1459,398 @@ fn purge(user_id: &str) -> Result<()> {
     Ok(())
 }
 
-fn open_config() -> Result<()> {
-    let config_path = config::CONFIG_PATH.as_os_str();
+fn snapshot(
+    cfg: &config::Config,
+    out: Option<&Path>,
+    annotate: bool,
+    input: Option<&Path>,
+) -> Result<()> {
+    // Refused before the camera comes on if there is nowhere to save to
+    let path = privacy::check(FrameSink::Snapshot, out)?;
+    let mut camera = open_camera(cfg, input)?;
+    let frame = camera.frame().context("Failed to capture frame")?;
+    let mut img = image::DynamicImage::ImageRgb8(frame);
+
+    if annotate {
+        let mut pipeline =
+            auth::load_pipeline(cfg).context("Failed to initialize face recognition pipeline")?;
+        let detections = pipeline
+            .detector
+            .detect(&img, pipeline.score_threshold, pipeline.nms_threshold)
+            .context("Failed to run face detection")?;
+        info!("Detected {} face(s)", detections.len());
+        for detection in &detections {
+            info!(
+                "  score {:.3} at ({:.0}, {:.0}) {:.0}x{:.0}",
+                detection.score,
+                detection.bbox[0],
+                detection.bbox[1],
+                detection.bbox[2],
+                detection.bbox[3]
+            );
+        }
+        img = howrs::face::annotate(&img, &detections);
+    }
+
+    privacy::write_frame(FrameSink::Snapshot, out, &img)?;
+
+    info!(
+        "✓ Saved {}x{} frame to {}",
+        img.width(),
+        img.height(),
+        path.display()
+    );
+    Ok(())
+}
+
+fn set_disabled(disabled: bool) -> Result<()> {
+    config::set_disabled(disabled)
+        .context("Failed to toggle face authentication (are you root?)")?;
+    if disabled {
+        info!(
+            "Face authentication disabled ({} exists)",
+            config::DISABLED_PATH.display()
+        );
+    } else {
+        info!("Face authentication enabled");
+    }
+    Ok(())
+}
+
+fn selftest(cfg: &config::Config) -> Result<()> {
+    let mut pipeline = auth::load_pipeline(cfg).context("Failed to load the models")?;
+    let result = howrs_vision::selftest::run(&mut pipeline).context("Self-test failed")?;
+    if result.faces == 0 {
+        info!("The detector found no face in the drawing; encoded the drawn one");
+    }
+    info!(
+        "✓ Detection and encoding work: {} face(s), {}-d embeddings, repeat similarity {:.4}, blank similarity {:.2} ({:.0} ms)",
+        result.faces,
+        result.dimension,
+        result.repeat_similarity,
+        result.blank_similarity,
+        ms(result.elapsed)
+    );
+    Ok(())
+}
+
+fn doctor(cfg: &config::Config, fix_perms: bool, sign_store: bool) -> Result<()> {
+    if sign_store {
+        let users = storage::sign_store().context("Failed to sign the face store")?;
+        info!(
+            "Signed the faces of {} user(s) with a new key in {}",
+            users.len(),
+            storage::StoreKey::path().display()
+        );
+    }
+    if let Some(group) = &cfg.store.group {
+        if identity::group_id(group).is_none() {
+            info!(
+                "Group {} doesn't exist; only root can read the face store",
+                group
+            );
+        }
+    }
+    let perms = storage::StorePermissions::new(&cfg.store);
+    let issues = storage::audit_permissions(&perms, fix_perms)
+        .context("Failed to audit the face store (are you root?)")?;
+    for issue in &issues {
+        if fix_perms {
+            info!("Fixed {}", issue);
+        } else {
+            warn!("{}", issue);
+        }
+    }
+    if issues.is_empty() {
+        info!("✓ Face store permissions match [store]");
+    } else if !fix_perms {
+        anyhow::bail!(
+            "{} path(s) with wrong owner or mode; run `sudo howrs doctor --fix-perms`",
+            issues.len()
+        );
+    }
+    Ok(())
+}
+
+/// Where distributions and the README install the PAM module
+const PAM_MODULE_PATHS: &[&str] = &[
+    "/usr/local/lib/security/pam_howrs.so",
+    "/usr/lib/security/pam_howrs.so",
+    "/usr/lib64/security/pam_howrs.so",
+    "/lib/security/pam_howrs.so",
+    "/usr/lib/x86_64-linux-gnu/security/pam_howrs.so",
+    "/lib/x86_64-linux-gnu/security/pam_howrs.so",
+];
+
+fn print_info(cfg: &config::Config, config_path: &Path) -> Result<()> {
+    let found = |path: &Path| if path.exists() { "" } else { " (not found)" };
+
+    println!("howrs {}", env!("CARGO_PKG_VERSION"));
+    println!(
+        "config:       {}{}",
+        config_path.display(),
+        found(config_path)
+    );
+    let system_config = config::config_path();
+    if system_config != config_path {
+        println!(
+            "pam config:   {}{}",
+            system_config.display(),
+            found(&system_config)
+        );
+    }
+    println!("face store:   {}", config::FACE_STORE_PREFIX.display());
+    println!("store format: v{}", storage::STORE_VERSION);
+    println!(
+        "store key:    {}{}",
+        storage::StoreKey::path().display(),
+        found(&storage::StoreKey::path())
+    );
+    println!(
+        "disabled:     {}",
+        if config::is_disabled() { "yes" } else { "no" }
+    );
+    match PAM_MODULE_PATHS.iter().map(Path::new).find(|p| p.exists()) {
+        Some(path) => println!("pam module:   {}", path.display()),
+        None => println!("pam module:   not found"),
+    }
+    println!(
+        "camera:       {}{}",
+        cfg.camera,
+        found(Path::new(&cfg.camera))
+    );
+    if let Some(companion) = &cfg.companion {
+        println!(
+            "companion:    {}{}",
+            companion.camera,
+            found(Path::new(&companion.camera))
+        );
+    }
+    if let Some(depth) = &cfg.depth {
+        println!(
+            "depth:        {}{}",
+            depth.camera,
+            found(Path::new(&depth.camera))
+        );
+    }
+
+    println!(
+        "onnxruntime:  {}",
+        model::runtime_info().unwrap_or_else(|| "failed to load".to_string())
+    );
+    let eps: Vec<String> = model::execution_providers()
+        .into_iter()
+        .map(|(name, available)| match available {
+            true => name.to_string(),
+            false => format!("{} (unavailable)", name),
+        })
+        .collect();
+    println!("providers:    {}", eps.join(", "));
+    println!("simd:         {}", howrs_vision::simd::backend());
+    println!(
+        "low memory:   {}",
+        if cfg.is_low_memory() { "yes" } else { "no" }
+    );
+
+    for info in model::Registry::all() {
+        let hash = info
+            .embedded
+            .map(model::sha256_hex)
+            .unwrap_or_else(|| "not embedded".to_string());
+        println!("model:        {} sha256:{}", info.name, hash);
+    }
+
+    Ok(())
+}
+
+fn list_models(cfg: &config::Config) -> Result<()> {
+    info!("Model directory: {}", cfg.model_dir.display());
+    info!(
+        "{:<18}  {:<8}  {:<9}  {:<8}  SOURCE",
+        "NAME", "KIND", "PRECISION", "HASH"
+    );
+    for info in model::Registry::all() {
+        let source = match (info.installed(&cfg.model_dir), info.embedded) {
+            (Some(path), _) => path.display().to_string(),
+            (None, Some(_)) => "embedded".to_string(),
+            (None, None) => "missing, run `howrs models fetch`".to_string(),
+        };
+        let kind = match info.kind {
+            model::ModelKind::Detector => "detector",
+            model::ModelKind::Encoder => "encoder",
+        };
+        let hash = if info.sha256.is_some() {
+            "pinned"
+        } else {
+            "unpinned"
+        };
+        info!(
+            "{:<18}  {:<8}  {:<9}  {:<8}  {}",
+            info.name,
+            kind,
+            info.precision.as_str(),
+            hash,
+            source
+        );
+    }
+    let precision = cfg.model_precision();
+    info!("Loading {} models", precision.as_str());
+    Ok(())
+}
+
+fn fetch_models(
+    cfg: &config::Config,
+    names: &[String],
+    force: bool,
+    allow_unpinned: bool,
+) -> Result<()> {
+    let selected: Vec<&model::ModelInfo> = if names.is_empty() {
+        model::Registry::all().iter().collect()
+    } else {
+        names
+            .iter()
+            .map(|name| {
+                model::Registry::get(name)
+                    .with_context(|| format!("Unknown model {:?}, see `howrs models list`", name))
+            })
+            .collect::<Result<_>>()?
+    };
+    // Refuse before downloading anything
+    if !allow_unpinned {
+        if let Some(info) = selected.iter().find(|info| info.sha256.is_none()) {
+            anyhow::bail!(
+                "{} has no pinned SHA-256; pass --allow-unpinned to trust the download",
+                info.name
+            );
+        }
+    }
+
+    for info in selected {
+        if let (false, Some(path)) = (force, info.installed(&cfg.model_dir)) {
+            if info.sha256.is_none() {
+                info!("{} is already installed, unpinned", info.name);
+                continue;
+            }
+            match info.verify_file(&path) {
+                Ok(_) => {
+                    info!("{} is already installed", info.name);
+                    continue;
+                }
+                Err(e) => warn!("{}, downloading again", Report(&e)),
+            }
+        }
+
+        info!("Downloading {}...", info.name);
+        let path = info
+            .fetch(&cfg.model_dir, allow_unpinned)
+            .with_context(|| format!("Failed to fetch {}", info.name))?;
+        if info.sha256.is_some() {
+            info!("✓ Installed {}", path.display());
+        } else {
+            let bytes = std::fs::read(&path)
+                .with_context(|| format!("Failed to read {}", path.display()))?;
+            let hash = model::sha256_hex(&bytes);
+            warn!(
+                "Installed {} without a pinned hash (sha256:{})",
+                path.display(),
+                hash
+            );
+        }
+    }
+    Ok(())
+}
+
+fn verify_models(cfg: &config::Config) -> Result<()> {
+    let mut failed = 0;
+    for info in model::Registry::all() {
+        let installed = info.installed(&cfg.model_dir);
+        let result = match (&installed, info.embedded) {
+            (Some(path), _) => info.verify_file(path).map(|_| path.display().to_string()),
+            (None, Some(bytes)) => info.verify(bytes).map(|()| "embedded".to_string()),
+            // Quantized variants are optional
+            (None, None) if info.precision != model::Precision::Fp32 => {
+                info!("{}: not installed", info.name);
+                continue;
+            }
+            (None, None) => {
+                warn!("{}: not installed", info.name);
+                failed += 1;
+                continue;
+            }
+        };
+        match result {
+            Ok(source) => info!("✓ {} ({})", info.name, source),
+            Err(e) => {
+                warn!("✗ {}", Report(&e));
+                failed += 1;
+            }
+        }
+    }
+    if failed > 0 {
+        anyhow::bail!("{} model(s) failed verification", failed);
+    }
+    Ok(())
+}
+
+fn benchmark_models(cfg: &config::Config, runs: usize) -> Result<()> {
+    if runs == 0 {
+        anyhow::bail!("--runs must be at least 1");
+    }
+    if cfg.detection.model.is_some() || cfg.recognition.model.is_some() {
+        warn!("Custom models in the config are timed as-is; precision only picks default models");
+    }
+
+    let int8_installed = [model::ModelKind::Detector, model::ModelKind::Encoder]
+        .into_iter()
+        .all(|kind| {
+            model::Registry::variant(kind, model::Precision::Int8)
+                .installed(&cfg.model_dir)
+                .is_some()
+        });
+    if !int8_installed {
+        anyhow::bail!(
+            "The int8 models are not installed; run `howrs models fetch {} {} --allow-unpinned`",
+            model::YUNET_2023MAR_INT8.name,
+            model::SFACE_2021DEC_INT8.name
+        );
+    }
+
+    let (img, detection) = howrs::pipeline::warm_up_input();
+
+    let mut results = Vec::new();
+    for precision in [model::Precision::Fp32, model::Precision::Int8] {
+        let mut pipeline = auth::load_pipeline_at(cfg, precision)
+            .with_context(|| format!("Failed to load the {} models", precision.as_str()))?;
+        // So lazy initialization isn't timed
+        pipeline.warm_up()?;
+
+        let mut samples = Vec::with_capacity(runs);
+        for _ in 0..runs {
+            let start = Instant::now();
+            pipeline.detect_best(&img)?;
+            pipeline.encode_detection(&img, &detection)?;
+            samples.push(start.elapsed());
+        }
+        samples.sort();
+        let median = samples[samples.len() / 2];
+        println!("{:<5} {:>8.1} ms", precision.as_str(), ms(median));
+        results.push((precision, median));
+    }
+
+    let (fastest, _) = results
+        .into_iter()
+        .min_by_key(|(_, median)| *median)
+        .expect("at least one precision was timed");
+    model::save_selected_precision(&cfg.model_dir, fastest)
+        .context("Failed to record the result (run as root?)")?;
+    info!(
+        "✓ {} is faster here; used when precision = \"auto\"",
+        fastest.as_str()
+    );
+    Ok(())
+}
+
+fn open_config(config_path: &Path) -> Result<()> {
     let editor = env::var("EDITOR").unwrap_or_else(|_| "vi".to_string());
 
     info!("Opening config file: {:?}", config_path);
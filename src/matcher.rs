@@ -1,24 +1,744 @@
-use crate::{storage::FaceRecord, Embedding};
+use crate::error::{Error, Report, Result, ResultExt};
+use crate::storage::{FaceRecord, RecordMeta, Sensor};
+use crate::Embedding;
+use ndarray::{s, Array1, Array2};
+use serde::{Deserialize, Serialize};
+use std::ops::Range;
+use zeroize::Zeroize;
 
-pub fn best_score(records: &[FaceRecord], probe: &Embedding) -> Option<f32> {
-    records
+/// How the similarities to a record's embeddings combine into one score
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Fusion {
+    /// Best single embedding
+    #[default]
+    Max,
+    /// Average over all embeddings
+    Mean,
+    /// Similarity to the record's centroid
+    Centroid,
+}
+
+/// What `threshold` is compared with. Scores are cosine similarities
+/// internally, a threshold in another metric is converted to one.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Metric {
+    /// Cosine similarity, in `[-1, 1]`: a match is at least the threshold
+    #[default]
+    Cosine,
+    /// Euclidean distance between the L2-normalized embeddings, in `[0, 2]`:
+    /// a match is at most the threshold. OpenCV's SFace sample uses 1.128.
+    Euclidean,
+}
+
+impl Metric {
+    /// The cosine similarity `threshold` in this metric corresponds to
+    pub fn similarity_threshold(self, threshold: f32) -> f32 {
+        match self {
+            Metric::Cosine => threshold,
+            // |a - b|^2 = 2 - 2 cos for unit vectors
+            Metric::Euclidean => 1.0 - threshold * threshold / 2.0,
+        }
+    }
+
+    /// A cosine `similarity` in this metric's units
+    pub fn value(self, similarity: f32) -> f32 {
+        match self {
+            Metric::Cosine => similarity,
+            Metric::Euclidean => (2.0 - 2.0 * similarity).max(0.0).sqrt(),
+        }
+    }
+}
+
+/// How records captured with another kind of sensor than the probe are
+/// scored. IR and RGB embeddings of the same face differ a lot.
+///
+/// Records enrolled before the sensor was stored always count as a match.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum SensorMatch {
+    /// Score every record alike
+    #[default]
+    Any,
+    /// Take [`SENSOR_MISMATCH_PENALTY`] off the other sensor's records
+    Prefer,
+    /// Skip the other sensor's records
+    Same,
+}
+
+/// Similarity taken off a record from the other kind of sensor under
+/// [`SensorMatch::Prefer`]
+pub const SENSOR_MISMATCH_PENALTY: f32 = 0.1;
+
+/// Score penalties for records whose face may have drifted from the user's
+/// current appearance; all zero (the default) scores every record alike
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct RecordWeights {
+    /// Similarity taken off per year since enrollment
+    pub age_per_year: f32,
+    /// Cap on the age penalty
+    pub max_age: f32,
+    /// Similarity taken off times how far the record's quality is below 1
+    pub quality: f32,
+}
+
+/// Seconds in a (Julian) year
+const YEAR_SECS: f32 = 365.25 * 24.0 * 3600.0;
+
+impl RecordWeights {
+    /// Penalty of a record with `meta` at Unix time `now`. Unknown ages and
+    /// qualities aren't penalized.
+    pub fn penalty(&self, meta: &RecordMeta, now: u64) -> f32 {
+        let age = match meta.created_at {
+            0 => 0.0,
+            created => {
+                let years = now.saturating_sub(created) as f32 / YEAR_SECS;
+                (years * self.age_per_year).min(self.max_age)
+            }
+        };
+        let quality = meta
+            .quality
+            .map_or(0.0, |q| (1.0 - q.clamp(0.0, 1.0)) * self.quality);
+        (age + quality).max(0.0)
+    }
+}
+
+/// Which records are removed first when a store is over its cap
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, clap::ValueEnum)]
+#[serde(rename_all = "kebab-case")]
+pub enum PruneStrategy {
+    /// Earliest enrolled
+    Oldest,
+    /// Most similar on average to the user's other records, i.e. the ones
+    /// adding the least
+    #[default]
+    Redundant,
+}
+
+/// Centroid similarity above which a new record adds nothing over an
+/// enrolled one
+pub const DUPLICATE_SIMILARITY: f32 = 0.95;
+
+pub fn best_score(records: &[FaceRecord], probe: &Embedding, fusion: Fusion) -> Option<f32> {
+    best_match(records, probe, fusion).map(|(_, score)| score)
+}
+
+/// Index and score of the record most similar to `probe`
+pub fn best_match(
+    records: &[FaceRecord],
+    probe: &Embedding,
+    fusion: Fusion,
+) -> Option<(usize, f32)> {
+    RecordMatrix::new(records).best_match(probe, fusion)
+}
+
+/// The embedding dimension most of `records` have; records of another one
+/// are damaged or from another model
+pub fn common_dim(records: &[FaceRecord]) -> usize {
+    let mut counts: Vec<(usize, usize)> = Vec::new();
+    for embedding in records.iter().flat_map(|r| &r.embeddings) {
+        match counts.iter_mut().find(|(dim, _)| *dim == embedding.len()) {
+            Some((_, count)) => *count += 1,
+            None => counts.push((embedding.len(), 1)),
+        }
+    }
+    // The first seen wins a tie
+    counts
         .iter()
-        .map(|r| {
-            let emb = Embedding {
-                vector: ndarray::Array2::from_shape_vec(
-                    (1, r.embedding.len()),
-                    r.embedding.clone(),
-                )
-                .unwrap_or_else(|_| ndarray::Array2::zeros((1, 128))),
-            };
-            match_embedding(&emb, probe)
-        })
-        .fold(None, |acc, s| match acc {
-            Some(best) if best > s => Some(best),
-            _ => Some(s),
-        })
+        .rev()
+        .max_by_key(|&&(_, count)| count)
+        .map_or(0, |&(dim, _)| dim)
+}
+
+/// Why `record` can't be matched against: no embeddings, ones of another
+/// dimension than `dim`, or values that aren't a usable embedding
+pub fn check_record(record: &FaceRecord, dim: usize) -> Result<()> {
+    if record.embeddings.is_empty() {
+        return Err(Error::matching(format!(
+            "face {} has no embeddings",
+            record.id
+        )));
+    }
+    for embedding in &record.embeddings {
+        if embedding.len() != dim {
+            return Err(Error::matching(format!(
+                "face {} has an embedding of {} values, expected {}",
+                record.id,
+                embedding.len(),
+                dim
+            )));
+        }
+        if !embedding.iter().all(|v| v.is_finite()) {
+            return Err(Error::matching(format!(
+                "face {} has non-finite values",
+                record.id
+            )));
+        }
+    }
+    record_embedding(record).map(drop)
+}
+
+/// Stores with at least this many embeddings are scored on several threads
+#[cfg(feature = "parallel-match")]
+const PARALLEL_ROWS: usize = 1024;
+
+/// Every embedding of a set of records stacked into one matrix, so a probe
+/// is scored against all of them with a single matrix-vector product.
+///
+/// Build it once per store and reuse it across frames.
+#[derive(Debug, Clone)]
+pub struct RecordMatrix {
+    /// One row per embedding, grouped by record
+    samples: Array2<f32>,
+    /// One row per record
+    centroids: Array2<f32>,
+    /// Rows of `samples` belonging to each record
+    ranges: Vec<Range<usize>>,
+    /// Sensor each record was captured with, if known
+    sensors: Vec<Option<Sensor>>,
+    /// Added to each record's score, see [`Self::for_sensor`]
+    bias: Vec<f32>,
+    /// Taken off each record's score, see [`Self::weighted`]
+    penalty: Vec<f32>,
+    /// Damaged records, never matched
+    skipped: Vec<usize>,
+}
+
+impl Drop for RecordMatrix {
+    fn drop(&mut self) {
+        for matrix in [&mut self.samples, &mut self.centroids] {
+            if let Some(values) = matrix.as_slice_memory_order_mut() {
+                values.zeroize();
+            }
+        }
+    }
+}
+
+impl RecordMatrix {
+    /// Stack the embeddings of `records`. Damaged ones (see [`check_record`])
+    /// are logged and keep their index but never match.
+    pub fn new(records: &[FaceRecord]) -> Self {
+        let dim = common_dim(records);
+
+        let mut samples = Vec::new();
+        let mut centroids = Vec::with_capacity(records.len() * dim);
+        let mut ranges = Vec::with_capacity(records.len());
+        let mut skipped = Vec::new();
+        let mut rows = 0;
+        for (i, record) in records.iter().enumerate() {
+            if let Err(e) = check_record(record, dim) {
+                tracing::warn!("skipping damaged face: {}", Report(&e));
+                skipped.push(i);
+                centroids.resize(centroids.len() + dim, 0.0);
+                ranges.push(rows..rows);
+                continue;
+            }
+            for embedding in &record.embeddings {
+                samples.extend_from_slice(embedding);
+            }
+            centroids.extend(record.centroid());
+            ranges.push(rows..rows + record.embeddings.len());
+            rows += record.embeddings.len();
+        }
+        let mut bias = vec![0.0; records.len()];
+        for &i in &skipped {
+            bias[i] = f32::NEG_INFINITY;
+        }
+
+        Self {
+            samples: Array2::from_shape_vec((rows, dim), samples).expect("rows have dim columns"),
+            centroids: Array2::from_shape_vec((records.len(), dim), centroids)
+                .expect("rows have dim columns"),
+            ranges,
+            sensors: records
+                .iter()
+                .map(|r| r.meta.capture.as_ref().map(|c| c.sensor))
+                .collect(),
+            bias,
+            penalty: vec![0.0; records.len()],
+            skipped,
+        }
+    }
+
+    /// Take `weights`' penalty for each of `records`, the ones this was
+    /// built from, off its score
+    pub fn weighted(mut self, records: &[FaceRecord], weights: RecordWeights, now: u64) -> Self {
+        self.penalty = records
+            .iter()
+            .map(|r| weights.penalty(&r.meta, now))
+            .collect();
+        self
+    }
+
+    /// Score records for probes from `sensor` under `policy`; an unknown
+    /// probe sensor scores every record alike
+    pub fn for_sensor(mut self, sensor: Option<Sensor>, policy: SensorMatch) -> Self {
+        self.bias = self
+            .sensors
+            .iter()
+            .enumerate()
+            .map(|(i, &record)| match (sensor, record, policy) {
+                _ if self.skipped.contains(&i) => f32::NEG_INFINITY,
+                (Some(probe), Some(record), SensorMatch::Prefer) if probe != record => {
+                    -SENSOR_MISMATCH_PENALTY
+                }
+                (Some(probe), Some(record), SensorMatch::Same) if probe != record => {
+                    f32::NEG_INFINITY
+                }
+                _ => 0.0,
+            })
+            .collect();
+        self
+    }
+
+    /// Number of records
+    pub fn len(&self) -> usize {
+        self.ranges.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.ranges.is_empty()
+    }
+
+    /// Indices of the damaged records left out
+    pub fn skipped(&self) -> &[usize] {
+        &self.skipped
+    }
+
+    /// Similarity of `probe` to each record under `fusion`, as [`score_record`],
+    /// adjusted for the sensor set with [`Self::for_sensor`]. A probe of
+    /// another dimension than the records matches none of them.
+    pub fn scores(&self, probe: &Embedding, fusion: Fusion) -> Vec<f32> {
+        if self.is_empty() {
+            return Vec::new();
+        }
+        if probe.dim() != self.samples.ncols() {
+            tracing::warn!(
+                "probe has {} values but the enrolled faces {}; was the model changed?",
+                probe.dim(),
+                self.samples.ncols()
+            );
+            return vec![f32::NEG_INFINITY; self.len()];
+        }
+        let probe = Array1::from_vec(probe.as_slice().to_vec());
+
+        let scores = if fusion == Fusion::Centroid {
+            similarities(&self.centroids, &probe).to_vec()
+        } else {
+            let sims = similarities(&self.samples, &probe);
+            self.ranges
+                .iter()
+                .map(|range| {
+                    let sims = sims.slice(s![range.clone()]);
+                    match fusion {
+                        Fusion::Mean => sims.sum() / range.len().max(1) as f32,
+                        _ => sims.fold(f32::NEG_INFINITY, |best, &s| best.max(s)),
+                    }
+                })
+                .collect()
+        };
+        scores
+            .into_iter()
+            .zip(&self.bias)
+            .zip(&self.penalty)
+            .map(|((s, b), p)| s + b - p)
+            .collect()
+    }
+
+    /// Index and score of the record most similar to `probe`, among those
+    /// [`Self::for_sensor`] didn't skip
+    #[tracing::instrument(name = "match", level = "debug", skip_all, fields(records = self.len()))]
+    pub fn best_match(&self, probe: &Embedding, fusion: Fusion) -> Option<(usize, f32)> {
+        self.scores(probe, fusion)
+            .into_iter()
+            .enumerate()
+            .filter(|(_, s)| s.is_finite())
+            .fold(None, |acc, (i, s)| match acc {
+                Some((best_i, best)) if best > s => Some((best_i, best)),
+                _ => Some((i, s)),
+            })
+    }
+
+    /// Like [`Self::best_match`], but scored by the `min_records`-th most
+    /// similar record, so a probe has to match that many distinct records to
+    /// pass a threshold. `None` if fewer records can be scored.
+    pub fn quorum_match(
+        &self,
+        probe: &Embedding,
+        fusion: Fusion,
+        min_records: usize,
+    ) -> Option<(usize, f32)> {
+        if min_records <= 1 {
+            return self.best_match(probe, fusion);
+        }
+        let mut scores: Vec<(usize, f32)> = self
+            .scores(probe, fusion)
+            .into_iter()
+            .enumerate()
+            .filter(|(_, s)| s.is_finite())
+            .collect();
+        if scores.len() < min_records {
+            return None;
+        }
+        scores.sort_unstable_by(|a, b| b.1.total_cmp(&a.1));
+        Some((scores[0].0, scores[min_records - 1].1))
+    }
+}
+
+/// Dot product of each row with `probe`, clamped like [`match_embedding`]
+fn similarities(matrix: &Array2<f32>, probe: &Array1<f32>) -> Array1<f32> {
+    #[cfg(feature = "parallel-match")]
+    if matrix.nrows() >= PARALLEL_ROWS {
+        use ndarray::parallel::prelude::*;
+        let sims: Vec<f32> = matrix
+            .outer_iter()
+            .into_par_iter()
+            .map(|row| row.dot(probe))
+            .collect();
+        return Array1::from_vec(sims).mapv_into(clamp_similarity);
+    }
+    matrix.dot(probe).mapv_into(clamp_similarity)
+}
+
+fn clamp_similarity(s: f32) -> f32 {
+    s.clamp(-1.0, 1.0)
+}
+
+/// Similarity of `probe` to one record under `fusion`; like
+/// [`RecordMatrix::scores`], a probe of another dimension matches nothing
+pub fn score_record(record: &FaceRecord, probe: &Embedding, fusion: Fusion) -> f32 {
+    let similarity = |e: &[f32]| {
+        if e.len() != probe.dim() {
+            return f32::NEG_INFINITY;
+        }
+        let dot: f32 = e.iter().zip(probe.as_slice()).map(|(x, y)| x * y).sum();
+        clamp_similarity(dot)
+    };
+    let scores = record.embeddings.iter().map(|e| similarity(e));
+    match fusion {
+        Fusion::Max => scores.fold(f32::NEG_INFINITY, f32::max),
+        Fusion::Mean => scores.sum::<f32>() / record.embeddings.len().max(1) as f32,
+        Fusion::Centroid => similarity(&record.centroid()),
+    }
+}
+
+/// Index and similarity of an enrolled record that `record` nearly duplicates
+pub fn find_duplicate(records: &[FaceRecord], record: &FaceRecord) -> Result<Option<(usize, f32)>> {
+    let duplicate = best_match(records, &record_embedding(record)?, Fusion::Centroid);
+    Ok(duplicate.filter(|&(_, score)| score >= DUPLICATE_SIMILARITY))
+}
+
+/// Indices of the records to remove so that at most `keep` remain, in the
+/// order they would be removed. Damaged records go first.
+pub fn select_prune(records: &[FaceRecord], keep: usize, strategy: PruneStrategy) -> Vec<usize> {
+    let excess = records.len().saturating_sub(keep);
+    if excess == 0 {
+        return Vec::new();
+    }
+    let dim = common_dim(records);
+    let (mut removed, valid): (Vec<usize>, Vec<usize>) =
+        (0..records.len()).partition(|&i| check_record(&records[i], dim).is_err());
+    if removed.len() >= excess {
+        removed.truncate(excess);
+        return removed;
+    }
+
+    match strategy {
+        PruneStrategy::Oldest => {
+            // Stable, so records of unknown age (0) go first in store order
+            let mut order = valid;
+            order.sort_by_key(|&i| records[i].meta.created_at);
+            removed.extend(order);
+            removed.truncate(excess);
+            removed
+        }
+        PruneStrategy::Redundant => {
+            let embeddings: Vec<Option<Embedding>> = (0..records.len())
+                .map(|i| {
+                    valid
+                        .contains(&i)
+                        .then(|| record_embedding(&records[i]).ok())
+                        .flatten()
+                })
+                .collect();
+            let similarity: Vec<Vec<f32>> = embeddings
+                .iter()
+                .map(|a| {
+                    embeddings
+                        .iter()
+                        .map(|b| match (a, b) {
+                            (Some(a), Some(b)) => match_embedding(a, b),
+                            _ => 0.0,
+                        })
+                        .collect()
+                })
+                .collect();
+
+            // Greedy: removing a record changes how redundant the rest are
+            let mut remaining = valid;
+            while removed.len() < excess {
+                let redundancy = |i: usize| {
+                    let others = remaining.iter().filter(|&&j| j != i);
+                    others.map(|&j| similarity[i][j]).sum::<f32>()
+                        / (remaining.len() - 1).max(1) as f32
+                };
+                let pos = (0..remaining.len())
+                    .max_by(|&a, &b| redundancy(remaining[a]).total_cmp(&redundancy(remaining[b])))
+                    .expect("more records than kept");
+                removed.push(remaining.remove(pos));
+            }
+            removed
+        }
+    }
+}
+
+/// The record's centroid as an [`Embedding`]
+pub fn record_embedding(record: &FaceRecord) -> Result<Embedding> {
+    embedding_from_vec(&record.centroid()).matching(format!("face {} is corrupt", record.id))
+}
+
+pub fn embedding_from_vec(vector: &[f32]) -> Result<Embedding> {
+    Ok(Embedding::new(vector.to_vec())?)
 }
 
 pub fn match_embedding(a: &Embedding, b: &Embedding) -> f32 {
     howrs_vision::face::match_embedding(a, b)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_record_matrix_matches_per_record_scores() {
+        let s = std::f32::consts::FRAC_1_SQRT_2;
+        let records = vec![
+            FaceRecord::new(vec![vec![1.0, 0.0], vec![0.0, 1.0]], None),
+            FaceRecord::new(vec![vec![s, s]], None),
+            FaceRecord::new(vec![vec![-1.0, 0.0], vec![0.0, -1.0], vec![s, -s]], None),
+        ];
+        let matrix = RecordMatrix::new(&records);
+        assert_eq!(matrix.len(), 3);
+        let probe = embedding_from_vec(&[0.6, 0.8]).unwrap();
+
+        for fusion in [Fusion::Max, Fusion::Mean, Fusion::Centroid] {
+            let scores = matrix.scores(&probe, fusion);
+            for (record, score) in records.iter().zip(&scores) {
+                assert!((score_record(record, &probe, fusion) - score).abs() < 1e-6);
+            }
+        }
+        assert_eq!(
+            matrix.best_match(&probe, Fusion::Max).map(|(i, _)| i),
+            Some(1)
+        );
+        assert!(RecordMatrix::new(&[])
+            .best_match(&probe, Fusion::Max)
+            .is_none());
+    }
+
+    #[test]
+    fn test_sensor_match() {
+        let mut ir = FaceRecord::new(vec![vec![1.0, 0.0]], None);
+        ir.meta.capture = Some(crate::storage::CaptureDevice {
+            path: "/dev/video2".into(),
+            fourcc: "GREY".into(),
+            sensor: Sensor::Ir,
+        });
+        let legacy = FaceRecord::new(vec![vec![0.8, 0.6]], None);
+        let records = vec![ir, legacy];
+        let probe = embedding_from_vec(&[1.0, 0.0]).unwrap();
+        let best = |policy| {
+            RecordMatrix::new(&records)
+                .for_sensor(Some(Sensor::Rgb), policy)
+                .best_match(&probe, Fusion::Max)
+        };
+
+        assert_eq!(best(SensorMatch::Any), Some((0, 1.0)));
+        let (index, score) = best(SensorMatch::Prefer).unwrap();
+        assert_eq!(index, 0);
+        assert!((score - (1.0 - SENSOR_MISMATCH_PENALTY)).abs() < 1e-6);
+        // The record without a known sensor is still scored
+        assert_eq!(best(SensorMatch::Same).map(|(i, _)| i), Some(1));
+    }
+
+    #[test]
+    fn test_weighted() {
+        const NOW: u64 = 1_000_000_000;
+        let mut old = FaceRecord::new(vec![vec![1.0, 0.0]], None);
+        old.meta.created_at = NOW - 2 * YEAR_SECS as u64;
+        let mut blurry = FaceRecord::new(vec![vec![1.0, 0.0]], None);
+        blurry.meta.created_at = NOW;
+        blurry.meta.quality = Some(0.5);
+        let mut unknown = FaceRecord::new(vec![vec![1.0, 0.0]], None);
+        unknown.meta.created_at = 0;
+        let records = vec![old, blurry, unknown];
+        let weights = RecordWeights {
+            age_per_year: 0.02,
+            max_age: 0.03,
+            quality: 0.1,
+        };
+
+        let penalties: Vec<f32> = records
+            .iter()
+            .map(|r| weights.penalty(&r.meta, NOW))
+            .collect();
+        // Two years at 0.02 capped at 0.03; half quality at 0.1
+        assert!((penalties[0] - 0.03).abs() < 1e-6);
+        assert!((penalties[1] - 0.05).abs() < 1e-6);
+        assert_eq!(penalties[2], 0.0);
+
+        let probe = embedding_from_vec(&[1.0, 0.0]).unwrap();
+        let scores = RecordMatrix::new(&records)
+            .weighted(&records, weights, NOW)
+            .scores(&probe, Fusion::Max);
+        for (score, penalty) in scores.iter().zip(&penalties) {
+            assert!((score - (1.0 - penalty)).abs() < 1e-6);
+        }
+    }
+
+    #[test]
+    fn test_metric() {
+        assert_eq!(Metric::Cosine.similarity_threshold(0.6), 0.6);
+        let similarity = Metric::Euclidean.similarity_threshold(1.128);
+        assert!((similarity - 0.3638).abs() < 1e-4);
+        assert!((Metric::Euclidean.value(similarity) - 1.128).abs() < 1e-4);
+        assert_eq!(Metric::Euclidean.value(1.0), 0.0);
+        assert_eq!(Metric::Euclidean.value(-1.0), 2.0);
+
+        // Matches the distance between the embeddings themselves
+        let a = embedding_from_vec(&[1.0, 0.0]).unwrap();
+        let b = embedding_from_vec(&[0.6, 0.8]).unwrap();
+        let distance = Metric::Euclidean.value(match_embedding(&a, &b));
+        assert!((distance - a.euclidean(&b)).abs() < 1e-6);
+    }
+
+    #[test]
+    fn test_fusion_modes() {
+        let record = FaceRecord::new(vec![vec![1.0, 0.0], vec![0.0, 1.0]], None);
+        let probe = embedding_from_vec(&[1.0, 0.0]).unwrap();
+
+        assert!((score_record(&record, &probe, Fusion::Max) - 1.0).abs() < 1e-6);
+        assert!((score_record(&record, &probe, Fusion::Mean) - 0.5).abs() < 1e-6);
+        let half = std::f32::consts::FRAC_1_SQRT_2;
+        assert!((score_record(&record, &probe, Fusion::Centroid) - half).abs() < 1e-6);
+    }
+
+    #[test]
+    fn test_best_match() {
+        let records = vec![
+            FaceRecord::new(vec![vec![0.0, 1.0]], None),
+            FaceRecord::new(vec![vec![1.0, 0.0]], None),
+        ];
+        let probe = embedding_from_vec(&[1.0, 0.0]).unwrap();
+        assert_eq!(best_match(&records, &probe, Fusion::Max).unwrap().0, 1);
+        assert!(best_match(&[], &probe, Fusion::Max).is_none());
+    }
+
+    #[test]
+    fn test_quorum_match() {
+        let records = vec![
+            FaceRecord::new(vec![vec![0.6, 0.8]], None),
+            FaceRecord::new(vec![vec![1.0, 0.0]], None),
+            FaceRecord::new(vec![vec![0.0, 1.0]], None),
+        ];
+        let matrix = RecordMatrix::new(&records);
+        let probe = embedding_from_vec(&[1.0, 0.0]).unwrap();
+        assert_eq!(
+            matrix.quorum_match(&probe, Fusion::Max, 1),
+            matrix.best_match(&probe, Fusion::Max)
+        );
+        // Scored by the runner-up, but still the best record
+        let (index, score) = matrix.quorum_match(&probe, Fusion::Max, 2).unwrap();
+        assert_eq!(index, 1);
+        assert!((score - 0.6).abs() < 1e-6);
+        assert!(matrix.quorum_match(&probe, Fusion::Max, 4).is_none());
+    }
+
+    #[test]
+    fn test_dimension_mismatch() {
+        let records = vec![
+            FaceRecord::new(vec![vec![1.0, 0.0]], None),
+            FaceRecord::new(vec![vec![1.0, 0.0, 0.0]], None),
+        ];
+        // Tied: the first dimension seen is kept
+        assert_eq!(RecordMatrix::new(&records).skipped(), [1]);
+
+        // A probe from another model matches nothing
+        let matrix = RecordMatrix::new(&records[..1]);
+        let probe = embedding_from_vec(&[1.0, 0.0, 0.0]).unwrap();
+        assert_eq!(matrix.best_match(&probe, Fusion::Max), None);
+        assert_eq!(
+            score_record(&records[0], &probe, Fusion::Centroid),
+            f32::NEG_INFINITY
+        );
+    }
+
+    #[test]
+    fn test_damaged_records() {
+        let mut empty = FaceRecord::new(vec![vec![1.0, 0.0]], None);
+        empty.embeddings.clear();
+        let records = vec![
+            FaceRecord::new(vec![vec![0.0, 1.0]], None),
+            FaceRecord::new(vec![vec![1.0, 0.0, 0.0]], None),
+            FaceRecord::new(vec![vec![f32::NAN, 1.0]], None),
+            empty,
+            FaceRecord::new(vec![vec![0.0, 0.0]], None),
+            FaceRecord::new(vec![vec![0.6, 0.8]], None),
+        ];
+        assert_eq!(common_dim(&records), 2);
+        let matrix = RecordMatrix::new(&records);
+        assert_eq!(matrix.skipped(), [1, 2, 3, 4]);
+
+        // The damaged records score nothing, whatever the fusion
+        let probe = embedding_from_vec(&[1.0, 0.0]).unwrap();
+        for fusion in [Fusion::Max, Fusion::Mean, Fusion::Centroid] {
+            let scores = matrix.scores(&probe, fusion);
+            assert!(scores[1..5].iter().all(|&s| s == f32::NEG_INFINITY));
+            assert_eq!(matrix.best_match(&probe, fusion).unwrap().0, 5);
+        }
+        let matrix = matrix.for_sensor(Some(Sensor::Ir), SensorMatch::Prefer);
+        assert_eq!(matrix.scores(&probe, Fusion::Mean)[1], f32::NEG_INFINITY);
+
+        // Pruning removes them first
+        assert_eq!(select_prune(&records, 4, PruneStrategy::Redundant), [1, 2]);
+        assert_eq!(
+            select_prune(&records, 1, PruneStrategy::Oldest),
+            [1, 2, 3, 4, 0]
+        );
+    }
+
+    #[test]
+    fn test_find_duplicate() {
+        let records = vec![FaceRecord::new(vec![vec![1.0, 0.0]], None)];
+        let same = FaceRecord::new(vec![vec![0.999, 0.04]], None);
+        let other = FaceRecord::new(vec![vec![0.6, 0.8]], None);
+
+        assert_eq!(find_duplicate(&records, &same).unwrap().unwrap().0, 0);
+        assert!(find_duplicate(&records, &other).unwrap().is_none());
+        assert!(find_duplicate(&[], &same).unwrap().is_none());
+    }
+
+    #[test]
+    fn test_select_prune() {
+        let mut records = vec![
+            FaceRecord::new(vec![vec![1.0, 0.0]], None),
+            FaceRecord::new(vec![vec![0.0, 1.0]], None),
+            FaceRecord::new(vec![vec![0.9, 0.1]], None),
+            FaceRecord::new(vec![vec![0.95, 0.05]], None),
+        ];
+        for (i, record) in records.iter_mut().enumerate() {
+            record.meta.created_at = 100 - i as u64;
+        }
+
+        let prune = |keep, strategy| select_prune(&records, keep, strategy);
+        assert_eq!(prune(2, PruneStrategy::Oldest), [3, 2]);
+        // Three near-identical faces: two of them go, the distinct one stays
+        let removed = prune(2, PruneStrategy::Redundant);
+        assert_eq!(removed.len(), 2);
+        assert!(!removed.contains(&1));
+
+        assert!(prune(4, PruneStrategy::Redundant).is_empty());
+        assert_eq!(prune(0, PruneStrategy::Oldest).len(), 4);
+    }
+}
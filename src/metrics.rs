@@ -0,0 +1,305 @@
+//! Process-wide counters exported in OpenMetrics text format.
+//!
+//! Recording is a handful of relaxed atomic operations, so the scan loop
+//! records unconditionally; only `howrs daemon` serves the values, on the
+//! address configured as `[daemon] metrics_addr`.
+
+use crate::error::{Result, ResultExt};
+use howrs_vision::pool::{self, PoolStats};
+use std::fmt::Write as _;
+use std::io::{BufRead, BufReader, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+
+/// Upper bounds (seconds) of the latency histogram buckets
+const BUCKETS: [f64; 11] = [
+    0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0,
+];
+
+/// Outcome of one authentication request
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Outcome {
+    Success,
+    Failure,
+    Error,
+    /// The client gave up first, e.g. the password was typed
+    Cancelled,
+}
+
+impl Outcome {
+    const ALL: [Outcome; 4] = [
+        Outcome::Success,
+        Outcome::Failure,
+        Outcome::Error,
+        Outcome::Cancelled,
+    ];
+
+    fn label(&self) -> &'static str {
+        match self {
+            Outcome::Success => "success",
+            Outcome::Failure => "failure",
+            Outcome::Error => "error",
+            Outcome::Cancelled => "cancelled",
+        }
+    }
+}
+
+/// Timed steps of a face scan
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Stage {
+    /// Waiting for and opening the camera
+    CameraOpen,
+    /// Grabbing and converting one frame
+    Capture,
+    /// Detection, alignment and encoding of one frame
+    Embed,
+    /// Comparing one probe against the enrolled records
+    Match,
+    /// Whole request, from camera open to verdict
+    Total,
+}
+
+impl Stage {
+    const ALL: [Stage; 5] = [
+        Stage::CameraOpen,
+        Stage::Capture,
+        Stage::Embed,
+        Stage::Match,
+        Stage::Total,
+    ];
+
+    fn label(&self) -> &'static str {
+        match self {
+            Stage::CameraOpen => "camera_open",
+            Stage::Capture => "capture",
+            Stage::Embed => "embed",
+            Stage::Match => "match",
+            Stage::Total => "total",
+        }
+    }
+}
+
+struct Histogram {
+    buckets: [AtomicU64; BUCKETS.len()],
+    count: AtomicU64,
+    sum_micros: AtomicU64,
+}
+
+impl Histogram {
+    const fn new() -> Self {
+        Self {
+            buckets: [const { AtomicU64::new(0) }; BUCKETS.len()],
+            count: AtomicU64::new(0),
+            sum_micros: AtomicU64::new(0),
+        }
+    }
+
+    fn observe(&self, d: Duration) {
+        let secs = d.as_secs_f64();
+        // Buckets are stored non-cumulatively and summed when rendering
+        if let Some(i) = BUCKETS.iter().position(|&le| secs <= le) {
+            self.buckets[i].fetch_add(1, Ordering::Relaxed);
+        }
+        self.count.fetch_add(1, Ordering::Relaxed);
+        self.sum_micros
+            .fetch_add(d.as_micros() as u64, Ordering::Relaxed);
+    }
+}
+
+struct Metrics {
+    outcomes: [AtomicU64; Outcome::ALL.len()],
+    stages: [Histogram; Stage::ALL.len()],
+    camera_errors: AtomicU64,
+    skipped_frames: AtomicU64,
+}
+
+static METRICS: Metrics = Metrics {
+    outcomes: [const { AtomicU64::new(0) }; Outcome::ALL.len()],
+    stages: [const { Histogram::new() }; Stage::ALL.len()],
+    camera_errors: AtomicU64::new(0),
+    skipped_frames: AtomicU64::new(0),
+};
+
+/// Count one finished authentication request
+pub fn record_outcome(outcome: Outcome) {
+    METRICS.outcomes[outcome as usize].fetch_add(1, Ordering::Relaxed);
+}
+
+/// Add one latency sample for `stage`
+pub fn observe(stage: Stage, d: Duration) {
+    METRICS.stages[stage as usize].observe(d);
+}
+
+/// Count a failure to open or read the camera
+pub fn record_camera_error() {
+    METRICS.camera_errors.fetch_add(1, Ordering::Relaxed);
+}
+
+/// Count a frame not encoded because the face failed the quality gate
+pub fn record_skipped_frame() {
+    METRICS.skipped_frames.fetch_add(1, Ordering::Relaxed);
+}
+
+/// Render all metrics in OpenMetrics text format
+pub fn render() -> String {
+    let mut out = String::new();
+
+    out.push_str("# TYPE howrs_auth_requests counter\n");
+    out.push_str("# HELP howrs_auth_requests Authentication requests by result.\n");
+    for outcome in Outcome::ALL {
+        let _ = writeln!(
+            out,
+            "howrs_auth_requests_total{{result=\"{}\"}} {}",
+            outcome.label(),
+            METRICS.outcomes[outcome as usize].load(Ordering::Relaxed)
+        );
+    }
+
+    out.push_str("# TYPE howrs_stage_duration_seconds histogram\n");
+    out.push_str("# UNIT howrs_stage_duration_seconds seconds\n");
+    out.push_str("# HELP howrs_stage_duration_seconds Latency of each scan stage.\n");
+    for stage in Stage::ALL {
+        let hist = &METRICS.stages[stage as usize];
+        let mut cumulative = 0;
+        for (le, bucket) in BUCKETS.iter().zip(&hist.buckets) {
+            cumulative += bucket.load(Ordering::Relaxed);
+            let _ = writeln!(
+                out,
+                "howrs_stage_duration_seconds_bucket{{stage=\"{}\",le=\"{}\"}} {}",
+                stage.label(),
+                le,
+                cumulative
+            );
+        }
+        let count = hist.count.load(Ordering::Relaxed);
+        let _ = writeln!(
+            out,
+            "howrs_stage_duration_seconds_bucket{{stage=\"{}\",le=\"+Inf\"}} {}",
+            stage.label(),
+            count
+        );
+        let _ = writeln!(
+            out,
+            "howrs_stage_duration_seconds_sum{{stage=\"{}\"}} {}",
+            stage.label(),
+            hist.sum_micros.load(Ordering::Relaxed) as f64 / 1e6
+        );
+        let _ = writeln!(
+            out,
+            "howrs_stage_duration_seconds_count{{stage=\"{}\"}} {}",
+            stage.label(),
+            count
+        );
+    }
+
+    out.push_str("# TYPE howrs_camera_errors counter\n");
+    out.push_str("# HELP howrs_camera_errors Failures opening or reading the camera.\n");
+    let _ = writeln!(
+        out,
+        "howrs_camera_errors_total {}",
+        METRICS.camera_errors.load(Ordering::Relaxed)
+    );
+
+    out.push_str("# TYPE howrs_skipped_frames counter\n");
+    out.push_str("# HELP howrs_skipped_frames Frames not encoded due to low face quality.\n");
+    let _ = writeln!(
+        out,
+        "howrs_skipped_frames_total {}",
+        METRICS.skipped_frames.load(Ordering::Relaxed)
+    );
+
+    render_pool(&mut out, "frame", pool::frames().stats());
+    render_pool(&mut out, "tensor", pool::tensors().stats());
+
+    out.push_str("# EOF\n");
+    out
+}
+
+fn render_pool(out: &mut String, name: &str, stats: PoolStats) {
+    let _ = writeln!(
+        out,
+        "# TYPE howrs_{name}_pool_allocations counter\n\
+         howrs_{name}_pool_allocations_total {}\n\
+         # TYPE howrs_{name}_pool_reuses counter\n\
+         howrs_{name}_pool_reuses_total {}\n\
+         # TYPE howrs_{name}_pool_free gauge\n\
+         howrs_{name}_pool_free {}",
+        stats.allocations, stats.reuses, stats.free
+    );
+}
+
+/// Serve [`render`] over plain HTTP on `addr` from a background thread
+pub fn spawn_server(addr: &str) -> Result<()> {
+    let listener = TcpListener::bind(addr).io(format!("binding {}", addr))?;
+    tracing::info!(
+        "metrics on http://{}/metrics",
+        listener.local_addr().io("reading the bound address")?
+    );
+
+    std::thread::Builder::new()
+        .name("howrs-metrics".into())
+        .spawn(move || {
+            for stream in listener.incoming().flatten() {
+                if let Err(e) = respond(stream) {
+                    tracing::debug!("metrics request failed: {}", e);
+                }
+            }
+        })
+        .io("spawning the metrics thread")?;
+    Ok(())
+}
+
+fn respond(mut stream: TcpStream) -> std::io::Result<()> {
+    stream.set_read_timeout(Some(Duration::from_secs(2)))?;
+
+    // Every path gets the metrics; just drain the request head
+    let mut reader = BufReader::new(stream.try_clone()?);
+    let mut line = String::new();
+    while reader.read_line(&mut line)? > 2 {
+        line.clear();
+    }
+
+    let body = render();
+    write!(
+        stream,
+        "HTTP/1.1 200 OK\r\n\
+         Content-Type: application/openmetrics-text; version=1.0.0; charset=utf-8\r\n\
+         Content-Length: {}\r\n\
+         Connection: close\r\n\r\n{}",
+        body.len(),
+        body
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_histogram_buckets() {
+        let hist = Histogram::new();
+        hist.observe(Duration::from_millis(3));
+        hist.observe(Duration::from_millis(30));
+        hist.observe(Duration::from_secs(60));
+
+        assert_eq!(hist.buckets[0].load(Ordering::Relaxed), 1);
+        assert_eq!(hist.buckets[3].load(Ordering::Relaxed), 1);
+        // Beyond the last bucket: only visible in +Inf via the count
+        assert_eq!(hist.count.load(Ordering::Relaxed), 3);
+    }
+
+    #[test]
+    fn test_render() {
+        record_outcome(Outcome::Success);
+        observe(Stage::Embed, Duration::from_millis(20));
+        record_camera_error();
+
+        let text = render();
+        assert!(text.contains("howrs_auth_requests_total{result=\"success\"}"));
+        assert!(text.contains("howrs_stage_duration_seconds_bucket{stage=\"embed\",le=\"+Inf\"}"));
+        assert!(text.contains("howrs_frame_pool_allocations_total"));
+        assert!(text.contains("howrs_skipped_frames_total"));
+        assert!(text.ends_with("# EOF\n"));
+    }
+}
@@ -1,48 +1,332 @@
-use anyhow::Result;
-use std::ffi::CStr;
+use crate::auth::{AuthFailure, AuthResult, Prompt};
+use crate::error::{Error, Report, Result};
+use crate::i18n::{self, Msg};
+use howrs_vision::cancel::CancelToken;
+use std::ffi::{CStr, CString};
+use std::io::IsTerminal;
+use std::os::fd::AsRawFd;
 use std::os::raw::{c_char, c_int, c_void};
-use std::time::{Duration, Instant};
+use std::sync::Mutex;
+use std::time::Duration;
 
 // PAM return codes
 const PAM_SUCCESS: c_int = 0;
+const PAM_PERM_DENIED: c_int = 6;
 const PAM_AUTH_ERR: c_int = 7;
 const PAM_USER_UNKNOWN: c_int = 10;
 const PAM_SYSTEM_ERR: c_int = 4;
+const PAM_AUTHINFO_UNAVAIL: c_int = 9;
+const PAM_IGNORE: c_int = 25;
 
 // PAM item types
+const PAM_SERVICE: c_int = 1;
 const PAM_USER: c_int = 2;
+const PAM_AUTHTOK: c_int = 6;
 
 // PAM handle opaque pointer type
 type PamHandle = c_void;
 
-// External PAM function we need
+/// `pam_set_data` name marking a handle whose user was recognized by face
+const FACE_DATA: &CStr = c"howrs_face_authenticated";
+static FACE_MARK: u8 = 1;
+
+// External PAM functions we need
 extern "C" {
     fn pam_get_item(pamh: *const PamHandle, item_type: c_int, item: *mut *const c_void) -> c_int;
+    fn pam_set_item(pamh: *mut PamHandle, item_type: c_int, item: *const c_void) -> c_int;
+    fn pam_get_authtok(
+        pamh: *mut PamHandle,
+        item: c_int,
+        authtok: *mut *const c_char,
+        prompt: *const c_char,
+    ) -> c_int;
+    fn pam_set_data(
+        pamh: *mut PamHandle,
+        module_data_name: *const c_char,
+        data: *mut c_void,
+        cleanup: Option<unsafe extern "C" fn(*mut PamHandle, *mut c_void, c_int)>,
+    ) -> c_int;
+    fn pam_get_data(
+        pamh: *const PamHandle,
+        module_data_name: *const c_char,
+        data: *mut *const c_void,
+    ) -> c_int;
+    fn pam_error(pamh: *mut PamHandle, fmt: *const c_char, ...) -> c_int;
+    fn pam_info(pamh: *mut PamHandle, fmt: *const c_char, ...) -> c_int;
 }
 
+// The signature is fixed by PAM, which guarantees valid argc/argv
+#[allow(clippy::not_unsafe_ptr_arg_deref)]
 #[no_mangle]
 pub extern "C" fn pam_sm_authenticate(
     pamh: *mut PamHandle,
     _flags: c_int,
-    _argc: c_int,
-    _argv: *const *const c_char,
+    argc: c_int,
+    argv: *const *const c_char,
 ) -> c_int {
+    let args = ModuleArgs::parse(unsafe { module_args(argc, argv) });
+    let code = authenticate(pamh, &args);
+    if code == PAM_SUCCESS {
+        // For the session hook; the mark is a static, so nothing to free
+        unsafe {
+            pam_set_data(
+                pamh,
+                FACE_DATA.as_ptr(),
+                std::ptr::addr_of!(FACE_MARK).cast_mut().cast(),
+                None,
+            )
+        };
+    }
+    code
+}
+
+fn authenticate(pamh: *mut PamHandle, args: &ModuleArgs) -> c_int {
+    // Kill switch: behave as if the module weren't configured at all
+    if crate::config::is_disabled() {
+        return PAM_IGNORE;
+    }
+
+    let service = get_pam_item_string(pamh, PAM_SERVICE).unwrap_or_default();
+    if !args.allows(&service) {
+        return PAM_IGNORE;
+    }
+    if args.match_mode == MatchMode::AnyEnrolled {
+        return identify(pamh);
+    }
+
     // Get username from PAM
     let username = match get_pam_user(pamh) {
         Ok(user) => user,
         Err(_) => return PAM_USER_UNKNOWN,
     };
 
-    eprintln!("Running facial recognition...");
+    // On a terminal, Enter skips straight to the password prompt
+    let interactive = std::io::stdin().is_terminal();
+    if args.race && interactive {
+        eprintln!("{}", i18n::text(Msg::ScanningOrPassword));
+        return race(pamh, &username);
+    }
+    if interactive {
+        eprintln!("{}", i18n::text(Msg::ScanningOrEnter));
+    } else {
+        eprintln!("{}", i18n::text(Msg::Scanning));
+    }
+
+    let prompt = |message: &str| show_info(pamh, message);
+    let result = with_enter_watch(interactive, |cancel| {
+        traced(|| run_auth(&username, cancel, &prompt))
+    });
+    report(&result);
+    pam_code(result)
+}
+
+/// The config to authenticate with; a malformed one is logged to syslog and
+/// replaced by the defaults, unless it sets `strict_mode`
+fn auth_config() -> Result<crate::config::Config> {
+    let (config, error) = crate::config::load_auth_config(None)?;
+    if let Some(e) = error {
+        syslog_at(
+            libc::LOG_WARNING,
+            &format!("malformed config, using the defaults: {}", Report(&e)),
+        );
+    }
+    Ok(config)
+}
+
+/// `match=any-enrolled`: accept whichever enrolled user is in front of the
+/// camera, for kiosks and greeters that don't ask for a name first.
+///
+/// The matched user becomes `PAM_USER` when the application hasn't set one.
+/// If it has, e.g. `sudo`, the face must belong to that user: this mode never
+/// switches the account being authenticated.
+fn identify(pamh: *mut PamHandle) -> c_int {
+    let expected = get_pam_user(pamh).ok().filter(|user| !user.is_empty());
+
+    let interactive = std::io::stdin().is_terminal();
+    if interactive {
+        eprintln!("{}", i18n::text(Msg::ScanningOrEnter));
+    } else {
+        eprintln!("{}", i18n::text(Msg::Scanning));
+    }
+    let prompt = |message: &str| show_info(pamh, message);
+    let result = with_enter_watch(interactive, |cancel| {
+        traced(|| run_identify(cancel, &prompt))
+    });
 
-    // Run authentication
-    match run_auth(&username) {
-        Ok(true) => PAM_SUCCESS,
-        Ok(false) => PAM_AUTH_ERR,
+    match result {
+        Ok(Some(Some(user))) => match expected {
+            Some(expected) if expected != user => PAM_AUTH_ERR,
+            Some(_) => PAM_SUCCESS,
+            None => set_pam_user(pamh, &user),
+        },
+        Ok(Some(None)) => PAM_AUTH_ERR,
+        // Every stage broke: give up and let the stack fall through to the password
+        Ok(None) => PAM_AUTHINFO_UNAVAIL,
         Err(_) => PAM_SYSTEM_ERR,
     }
 }
 
+/// Set `PAM_USER` to `user`, which must be a real account
+fn set_pam_user(pamh: *mut PamHandle, user: &str) -> c_int {
+    // The name comes from a face store directory, not from the account database
+    if crate::identity::lookup(user).is_err() {
+        return PAM_USER_UNKNOWN;
+    }
+    let Ok(user) = CString::new(user) else {
+        return PAM_USER_UNKNOWN;
+    };
+    // PAM copies the item
+    match unsafe { pam_set_item(pamh, PAM_USER, user.as_ptr().cast()) } {
+        PAM_SUCCESS => PAM_SUCCESS,
+        _ => PAM_SYSTEM_ERR,
+    }
+}
+
+/// Run `f` with a token that is cancelled when Enter is pressed on an
+/// `interactive` terminal
+fn with_enter_watch<T>(interactive: bool, f: impl FnOnce(&CancelToken) -> T) -> T {
+    let cancel = CancelToken::new();
+    std::thread::scope(|scope| {
+        if interactive {
+            scope.spawn(|| watch_enter(&cancel));
+        }
+        let result = f(&cancel);
+        // Also stops the watcher
+        cancel.cancel();
+        result
+    })
+}
+
+/// Tell the user why the face wasn't accepted
+fn report(result: &AuthResult) {
+    if let AuthResult::Failed(failure) = result {
+        eprintln!("{}", i18n::fill(Msg::Failed, &[&failure.message()]));
+    }
+}
+
+fn pam_code(result: AuthResult) -> c_int {
+    match result {
+        AuthResult::Matched => PAM_SUCCESS,
+        // Nothing was scanned, so this wasn't a failed attempt
+        AuthResult::Failed(AuthFailure::NoCamera) => PAM_AUTHINFO_UNAVAIL,
+        AuthResult::Failed(_) => PAM_AUTH_ERR,
+        // Every stage broke: give up and let the stack fall through to the password
+        AuthResult::Unavailable => PAM_AUTHINFO_UNAVAIL,
+        AuthResult::Error(e) if e.is_camera() => PAM_AUTHINFO_UNAVAIL,
+        AuthResult::Error(_) => PAM_SYSTEM_ERR,
+    }
+}
+
+/// Only trace when asked to: we are running inside someone else's process
+fn traced<T>(f: impl FnOnce() -> T) -> T {
+    match crate::logging::pam_dispatch() {
+        Some(dispatch) => tracing::dispatcher::with_default(&dispatch, f),
+        None => f(),
+    }
+}
+
+/// Ask for the password while the face scan runs; whichever finishes first
+/// wins, so face auth never holds up typing.
+///
+/// A typed password is left in `PAM_AUTHTOK` for the next module, which
+/// needs `try_first_pass`. Submitting an empty line just waits for the scan.
+/// When the face matches first, a newline is pushed into the terminal to end
+/// the pending prompt; where the kernel forbids that, the user is asked to
+/// press Enter.
+fn race(pamh: *mut PamHandle, username: &str) -> c_int {
+    let cancel = CancelToken::new();
+    // Whether the prompt is still waiting for input, and whether the face
+    // scan pushed the newline that ends it
+    let prompt = Mutex::new((true, false));
+
+    std::thread::scope(|scope| {
+        let face = scope.spawn(|| {
+            // The conversation is busy asking for the password, and this
+            // only runs on a terminal anyway
+            let result = traced(|| run_auth(username, &cancel, &crate::auth::print_prompt));
+            if matches!(result, AuthResult::Matched) {
+                let mut prompt = prompt.lock().unwrap_or_else(|e| e.into_inner());
+                if prompt.0 {
+                    prompt.1 = true;
+                    end_prompt();
+                }
+            }
+            result
+        });
+
+        let typed = prompt_password(pamh);
+        let face_won = {
+            let mut prompt = prompt.lock().unwrap_or_else(|e| e.into_inner());
+            prompt.0 = false;
+            prompt.1
+        };
+        if face_won {
+            clear_password(pamh);
+            return PAM_SUCCESS;
+        }
+
+        if typed {
+            cancel.cancel();
+        } else {
+            clear_password(pamh);
+        }
+        let result = face
+            .join()
+            .unwrap_or_else(|_| AuthResult::Error(Error::matching("face scan panicked")));
+        match (typed, result) {
+            // The face matched just as the password was entered
+            (_, AuthResult::Matched) => PAM_SUCCESS,
+            // Leave the password to the next module
+            (true, _) => PAM_AUTHINFO_UNAVAIL,
+            (false, result) => {
+                report(&result);
+                pam_code(result)
+            }
+        }
+    })
+}
+
+/// Prompt for the password through the application's conversation, storing
+/// it as `PAM_AUTHTOK`; whether anything was typed
+fn prompt_password(pamh: *mut PamHandle) -> bool {
+    let mut authtok: *const c_char = std::ptr::null();
+    let ret = unsafe { pam_get_authtok(pamh, PAM_AUTHTOK, &mut authtok, std::ptr::null()) };
+    // Only the first byte is looked at; the password is never copied
+    ret == PAM_SUCCESS && !authtok.is_null() && unsafe { *authtok } != 0
+}
+
+/// Show `message` through the application's conversation, so a graphical
+/// login shows it too rather than only a terminal
+fn show_error(pamh: *mut PamHandle, message: &str) {
+    let Ok(message) = CString::new(message) else {
+        return;
+    };
+    unsafe { pam_error(pamh, c"%s".as_ptr(), message.as_ptr()) };
+}
+
+/// Like [`show_error`], for messages that aren't errors, e.g. a head-turn
+/// challenge
+fn show_info(pamh: *mut PamHandle, message: &str) {
+    let Ok(message) = CString::new(message) else {
+        return;
+    };
+    unsafe { pam_info(pamh, c"%s".as_ptr(), message.as_ptr()) };
+}
+
+fn clear_password(pamh: *mut PamHandle) {
+    unsafe { pam_set_item(pamh, PAM_AUTHTOK, std::ptr::null()) };
+}
+
+/// End a pending terminal prompt by pushing a newline into the input queue
+fn end_prompt() {
+    let newline = b'\n';
+    let ret = unsafe { libc::ioctl(libc::STDIN_FILENO, libc::TIOCSTI, &newline) };
+    if ret != 0 {
+        // TIOCSTI is disabled on many kernels (dev.tty.legacy_tiocsti = 0)
+        eprintln!("\n{}", i18n::text(Msg::RecognizedPressEnter));
+    }
+}
+
 #[no_mangle]
 pub extern "C" fn pam_sm_setcred(
     _pamh: *mut PamHandle,
@@ -53,48 +337,298 @@ pub extern "C" fn pam_sm_setcred(
     return PAM_SUCCESS;
 }
 
+/// `account` hook. With `require_enrolled`, users without an enrolled face
+/// are refused, e.g. on a kiosk that only logs in by face; without it the
+/// module has no say.
+// The signature is fixed by PAM, which guarantees valid argc/argv
+#[allow(clippy::not_unsafe_ptr_arg_deref)]
+#[no_mangle]
+pub extern "C" fn pam_sm_acct_mgmt(
+    pamh: *mut PamHandle,
+    _flags: c_int,
+    argc: c_int,
+    argv: *const *const c_char,
+) -> c_int {
+    if crate::config::is_disabled() {
+        return PAM_IGNORE;
+    }
+    let args = ModuleArgs::parse(unsafe { module_args(argc, argv) });
+    let service = get_pam_item_string(pamh, PAM_SERVICE).unwrap_or_default();
+    if !args.require_enrolled || !args.allows(&service) {
+        return PAM_IGNORE;
+    }
+    let Ok(username) = get_pam_user(pamh) else {
+        return PAM_USER_UNKNOWN;
+    };
+
+    match traced(|| crate::storage::load_records(&username)) {
+        Ok(records) if !records.is_empty() => PAM_SUCCESS,
+        Ok(_) => {
+            show_error(pamh, i18n::text(Msg::EnrollRequired));
+            syslog(&format!("{}: no face enrolled for {}", service, username));
+            PAM_PERM_DENIED
+        }
+        // Fail closed: the policy can't be checked
+        Err(e) => {
+            syslog(&format!(
+                "{}: reading the faces of {}: {}",
+                service,
+                username,
+                Report(&e)
+            ));
+            PAM_SYSTEM_ERR
+        }
+    }
+}
+
+/// `session` hook: logs the session start to syslog, noting whether the
+/// user was recognized by face on this PAM handle
+// The signature is fixed by PAM, which guarantees valid argc/argv
+#[allow(clippy::not_unsafe_ptr_arg_deref)]
+#[no_mangle]
+pub extern "C" fn pam_sm_open_session(
+    pamh: *mut PamHandle,
+    _flags: c_int,
+    argc: c_int,
+    argv: *const *const c_char,
+) -> c_int {
+    if crate::config::is_disabled() {
+        return PAM_IGNORE;
+    }
+    let args = ModuleArgs::parse(unsafe { module_args(argc, argv) });
+    let service = get_pam_item_string(pamh, PAM_SERVICE).unwrap_or_default();
+    if !args.allows(&service) {
+        return PAM_IGNORE;
+    }
+    let username = get_pam_user(pamh).unwrap_or_default();
+
+    let mut mark: *const c_void = std::ptr::null();
+    let by_face = unsafe { pam_get_data(pamh, FACE_DATA.as_ptr(), &mut mark) } == PAM_SUCCESS
+        && !mark.is_null();
+    syslog(&format!(
+        "{}: session opened for {}{}",
+        service,
+        username,
+        if by_face { ", recognized by face" } else { "" }
+    ));
+    PAM_SUCCESS
+}
+
+#[no_mangle]
+pub extern "C" fn pam_sm_close_session(
+    _pamh: *mut PamHandle,
+    _flags: c_int,
+    _argc: c_int,
+    _argv: *const *const c_char,
+) -> c_int {
+    PAM_IGNORE
+}
+
+/// Log `message` to the `authpriv` syslog facility, as PAM modules do
+fn syslog(message: &str) {
+    syslog_at(libc::LOG_INFO, message);
+}
+
+/// [`syslog`] at `priority`, e.g. `LOG_WARNING`
+fn syslog_at(priority: c_int, message: &str) {
+    let Ok(message) = CString::new(message) else {
+        return;
+    };
+    unsafe {
+        libc::syslog(
+            libc::LOG_AUTHPRIV | priority,
+            c"pam_howrs: %s".as_ptr(),
+            message.as_ptr(),
+        )
+    };
+}
+
 fn get_pam_user(pamh: *mut PamHandle) -> Result<String> {
+    get_pam_item_string(pamh, PAM_USER)
+}
+
+fn get_pam_item_string(pamh: *mut PamHandle, item_type: c_int) -> Result<String> {
     unsafe {
-        let mut user_ptr: *const c_void = std::ptr::null();
-        let ret = pam_get_item(pamh, PAM_USER, &mut user_ptr as *mut *const c_void);
-        if ret != PAM_SUCCESS || user_ptr.is_null() {
-            return Err(anyhow::anyhow!("Failed to get PAM user"));
+        let mut item_ptr: *const c_void = std::ptr::null();
+        let ret = pam_get_item(pamh, item_type, &mut item_ptr as *mut *const c_void);
+        if ret != PAM_SUCCESS || item_ptr.is_null() {
+            return Err(Error::account(format!(
+                "Failed to get PAM item {}",
+                item_type
+            )));
         }
-        let user_cstr = CStr::from_ptr(user_ptr as *const c_char);
-        Ok(user_cstr.to_string_lossy().into_owned())
+        let item_cstr = CStr::from_ptr(item_ptr as *const c_char);
+        Ok(item_cstr.to_string_lossy().into_owned())
     }
 }
 
-fn run_auth(username: &str) -> Result<bool> {
-    let config = crate::config::load_config(None)?;
-
-    let records = crate::storage::load_records(username)?;
-    if records.is_empty() {
-        return Ok(false);
+/// Copy the module arguments from the pam.d line
+///
+/// # Safety
+/// `argv` must point to `argc` valid C strings, as PAM guarantees.
+unsafe fn module_args(argc: c_int, argv: *const *const c_char) -> Vec<String> {
+    if argv.is_null() {
+        return Vec::new();
     }
+    (0..argc.max(0) as usize)
+        .map(|i| *argv.add(i))
+        .filter(|arg| !arg.is_null())
+        .map(|arg| CStr::from_ptr(arg).to_string_lossy().into_owned())
+        .collect()
+}
 
-    let mut pipeline = crate::Pipeline::new()?;
+/// Options given after the module name in a pam.d line, e.g.
+/// `auth sufficient pam_howrs.so only=sudo,login` or `skip=sddm`
+#[derive(Debug, Default, PartialEq)]
+struct ModuleArgs {
+    /// Services face auth is limited to; every service when unset
+    only: Option<Vec<String>>,
+    /// Services where face auth is never attempted
+    skip: Vec<String>,
+    /// Prompt for the password while scanning, see [`race`]
+    race: bool,
+    /// Whose faces are matched, `match=user` or `match=any-enrolled`
+    match_mode: MatchMode,
+    /// Refuse accounts without an enrolled face, see [`pam_sm_acct_mgmt`]
+    require_enrolled: bool,
+}
 
-    use howrs_vision::Camera;
-    let mut camera = Camera::open(&config.camera)?;
+/// Which enrolled faces a scan is matched against
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+enum MatchMode {
+    /// Only those of `PAM_USER`
+    #[default]
+    User,
+    /// Those of every enrolled user, see [`identify`]
+    AnyEnrolled,
+}
 
-    let start_time = Instant::now();
-    let scan_duration = Duration::from_secs(config.scan_durnation as u64);
+impl ModuleArgs {
+    fn parse<I, S>(args: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<str>,
+    {
+        let list = |v: &str| -> Vec<String> {
+            v.split(',')
+                .map(str::trim)
+                .filter(|s| !s.is_empty())
+                .map(str::to_string)
+                .collect()
+        };
 
-    while start_time.elapsed() < scan_duration {
-        if let Ok(frame_buf) = camera.frame() {
-            let img = image::DynamicImage::ImageRgb8(frame_buf);
-            // Use lower thresholds for faster processing in PAM context
-            if let Ok(embedding) = pipeline.extract_embedding(&img, 0.5, 0.3) {
-                let score = crate::matcher::best_score(&records, &embedding)
-                    .ok_or_else(|| anyhow::anyhow!("No match found"))?;
+        let mut parsed = Self::default();
+        for arg in args {
+            match arg.as_ref().split_once('=') {
+                Some(("only", v)) => parsed.only.get_or_insert_with(Vec::new).extend(list(v)),
+                Some(("skip", v)) => parsed.skip.extend(list(v)),
+                Some(("match", "user")) => parsed.match_mode = MatchMode::User,
+                Some(("match", "any-enrolled")) => parsed.match_mode = MatchMode::AnyEnrolled,
+                None if arg.as_ref() == "race" => parsed.race = true,
+                None if arg.as_ref() == "require_enrolled" => parsed.require_enrolled = true,
+                _ => tracing::warn!("ignoring unknown module argument {:?}", arg.as_ref()),
+            }
+        }
+        parsed
+    }
 
-                if score >= config.threshold {
-                    return Ok(true);
-                }
+    fn allows(&self, service: &str) -> bool {
+        if self.skip.iter().any(|s| s == service) {
+            return false;
+        }
+        match &self.only {
+            Some(only) => only.iter().any(|s| s == service),
+            None => true,
+        }
+    }
+}
+
+/// Cancel `cancel` when a line is entered on stdin, until it is cancelled
+/// otherwise
+fn watch_enter(cancel: &CancelToken) {
+    let stdin = std::io::stdin();
+    while !cancel.is_cancelled() {
+        match crate::daemon::poll_readable(&stdin, Duration::from_millis(100)) {
+            Ok(false) => {}
+            Ok(true) => {
+                // Read the terminal directly: the buffered `Stdin` could swallow
+                // a password typed ahead. A canonical-mode read stops at the line.
+                let mut line = [0u8; 256];
+                let _ =
+                    unsafe { libc::read(stdin.as_raw_fd(), line.as_mut_ptr().cast(), line.len()) };
+                cancel.cancel();
             }
+            Err(_) => return,
+        }
+    }
+}
+
+/// Walk the `[pam.fallback]` ladder for as long as its stages allow.
+///
+/// [`AuthResult::Unavailable`] when no stage could give an answer, or the
+/// scan was cancelled, so the caller can hand over to the next PAM module
+/// instead of failing the login.
+#[tracing::instrument(name = "pam_auth", skip_all, fields(user = %username))]
+fn run_auth(username: &str, cancel: &CancelToken, prompt: Prompt<'_>) -> AuthResult {
+    match auth_config() {
+        Ok(config) => {
+            let timeout = config.pam.fallback.timeout(config.scan_durnation);
+            crate::auth::authenticate_with(username, timeout, cancel, prompt)
         }
+        Err(e) => AuthResult::Error(e),
     }
+}
+
+/// [`run_auth`] for `match=any-enrolled`: the matched user, if any
+#[tracing::instrument(name = "pam_identify", skip_all)]
+fn run_identify(cancel: &CancelToken, prompt: Prompt<'_>) -> Result<Option<Option<String>>> {
+    // Only for the warning and `strict_mode`: the stages load it themselves
+    auth_config()?;
+    crate::auth::with_fallback(
+        |socket, timeout| crate::daemon::request_identify(socket, timeout, cancel, prompt),
+        |config, deadline| {
+            crate::auth::in_process_identify(config, deadline, cancel.clone(), prompt)
+        },
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_module_args() {
+        let args = ModuleArgs::parse(["only=sudo,login"]);
+        assert!(args.allows("sudo"));
+        assert!(args.allows("login"));
+        assert!(!args.allows("sddm"));
+
+        let args = ModuleArgs::parse(["skip=sddm", "skip=gdm-password"]);
+        assert!(args.allows("sudo"));
+        assert!(!args.allows("sddm"));
+        assert!(!args.allows("gdm-password"));
 
-    Ok(false)
+        // skip wins over only
+        let args = ModuleArgs::parse(["only=sudo,sddm", "skip=sddm", "bogus"]);
+        assert!(args.allows("sudo"));
+        assert!(!args.allows("sddm"));
+
+        assert!(ModuleArgs::parse(Vec::<String>::new()).allows("anything"));
+
+        assert!(ModuleArgs::parse(["race", "only=sudo"]).race);
+        assert!(!ModuleArgs::parse(["only=sudo"]).race);
+        assert!(ModuleArgs::parse(["require_enrolled"]).require_enrolled);
+        assert!(!ModuleArgs::parse(["race"]).require_enrolled);
+
+        assert_eq!(ModuleArgs::parse(["only=sddm"]).match_mode, MatchMode::User);
+        assert_eq!(
+            ModuleArgs::parse(["match=any-enrolled"]).match_mode,
+            MatchMode::AnyEnrolled
+        );
+        // Unknown modes keep matching PAM_USER only
+        assert_eq!(
+            ModuleArgs::parse(["match=everyone"]).match_mode,
+            MatchMode::User
+        );
+    }
 }
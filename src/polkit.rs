@@ -0,0 +1,130 @@
+//! Polkit authorization for managing enrolled faces through `howrs daemon`.
+//!
+//! Clients of the daemon socket are identified by their kernel peer
+//! credentials and checked with `pkcheck`, so a GUI front-end can enroll or
+//! purge faces without running as root. The actions are declared in
+//! `packaging/org.howrs.policy`.
+
+use crate::error::{Error, Result, ResultExt};
+use std::os::fd::AsRawFd;
+use std::os::unix::net::UnixStream;
+use std::process::Command;
+
+/// Manage the caller's own faces
+pub const ACTION_MANAGE_OWN: &str = "org.howrs.manage-own-faces";
+/// Manage faces of any user
+pub const ACTION_MANAGE: &str = "org.howrs.manage-faces";
+
+/// The process on the other end of a Unix socket
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Subject {
+    pub pid: u32,
+    pub uid: u32,
+}
+
+impl Subject {
+    /// Read the peer credentials of `stream`
+    pub fn from_stream(stream: &UnixStream) -> Result<Self> {
+        let mut cred = libc::ucred {
+            pid: 0,
+            uid: 0,
+            gid: 0,
+        };
+        let mut len = std::mem::size_of::<libc::ucred>() as libc::socklen_t;
+        let ret = unsafe {
+            libc::getsockopt(
+                stream.as_raw_fd(),
+                libc::SOL_SOCKET,
+                libc::SO_PEERCRED,
+                &mut cred as *mut libc::ucred as *mut libc::c_void,
+                &mut len,
+            )
+        };
+        if ret != 0 {
+            return Err(std::io::Error::last_os_error()).account("reading peer credentials");
+        }
+        Ok(Subject {
+            pid: cred.pid as u32,
+            uid: cred.uid,
+        })
+    }
+
+    /// The action needed to manage faces of the account with `target_uid`
+    pub fn action_for(&self, target_uid: u32) -> &'static str {
+        if self.uid == target_uid {
+            ACTION_MANAGE_OWN
+        } else {
+            ACTION_MANAGE
+        }
+    }
+
+    /// Ask polkit whether this subject may perform `action`, letting it
+    /// prompt through the subject's authentication agent.
+    ///
+    /// Root is always allowed. This blocks until the user answers the prompt.
+    pub fn check(&self, action: &str) -> Result<()> {
+        if self.uid == 0 {
+            return Ok(());
+        }
+
+        // Pinning the start time stops a recycled pid from inheriting the check
+        let start_time = process_start_time(self.pid)?;
+        let status = Command::new("pkcheck")
+            .arg("--action-id")
+            .arg(action)
+            .arg("--process")
+            .arg(format!("{},{},{}", self.pid, start_time, self.uid))
+            .arg("--allow-user-interaction")
+            .status()
+            .account("running pkcheck")?;
+
+        if !status.success() {
+            return Err(Error::account(format!("not authorized for {}", action)));
+        }
+        Ok(())
+    }
+}
+
+fn process_start_time(pid: u32) -> Result<u64> {
+    let stat = std::fs::read_to_string(format!("/proc/{}/stat", pid))
+        .account(format!("reading /proc/{}/stat", pid))?;
+    parse_start_time(&stat).account(format!("parsing /proc/{}/stat", pid))
+}
+
+fn parse_start_time(stat: &str) -> Result<u64> {
+    // The command name may contain spaces and parentheses; fields resume
+    // after the last ')', starting with field 3 (state). Start time is field 22.
+    let rest = match stat.rfind(')') {
+        Some(i) => &stat[i + 1..],
+        None => return Err(Error::account("no command name")),
+    };
+    match rest.split_whitespace().nth(19) {
+        Some(field) => field.parse().account("malformed start time"),
+        None => Err(Error::account("too few fields")),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_start_time() {
+        let stat = "1234 (a (weird) name) S 1 1234 1234 0 -1 4194560 100 0 0 0 \
+                    5 3 0 0 20 0 1 0 987654 12345678 300";
+        assert_eq!(parse_start_time(stat).unwrap(), 987654);
+        assert!(parse_start_time("1234 (x) S 1").is_err());
+    }
+
+    #[test]
+    fn test_action_for() {
+        let subject = Subject { pid: 1, uid: 1000 };
+        assert_eq!(subject.action_for(1000), ACTION_MANAGE_OWN);
+        assert_eq!(subject.action_for(1001), ACTION_MANAGE);
+    }
+
+    #[test]
+    fn test_own_process_start_time() {
+        assert!(process_start_time(std::process::id()).unwrap() > 0);
+    }
+}
@@ -0,0 +1,106 @@
+//! Annotated frames showing what the recognizer sees.
+//!
+//! `howrs test --debug-out` draws the detected face, its landmarks and the
+//! match score onto every frame and sends the result either to a
+//! v4l2loopback device, where any video player can show it live, or to a
+//! directory as a numbered image sequence.
+
+use crate::error::{Result, ResultExt};
+use howrs_vision::draw;
+use image::DynamicImage;
+use std::os::unix::fs::FileTypeExt;
+use std::path::{Path, PathBuf};
+
+use crate::privacy::{self, FrameSink};
+use crate::video::Loopback;
+use crate::{face, Detection};
+
+/// Where annotated frames go
+pub enum Preview {
+    /// v4l2loopback device, opened on the first frame once its size is known
+    Loopback {
+        path: PathBuf,
+        device: Option<Loopback>,
+    },
+    /// Directory receiving `frame-00001.png`, `frame-00002.png`, ...
+    Directory { dir: PathBuf, count: u32 },
+}
+
+impl Preview {
+    /// Character devices are treated as loopback devices, anything else as a
+    /// directory, which is created if missing
+    pub fn open(path: &Path) -> Result<Self> {
+        let is_device = std::fs::metadata(path)
+            .map(|m| m.file_type().is_char_device())
+            .unwrap_or(false);
+        if is_device {
+            return Ok(Preview::Loopback {
+                path: path.to_path_buf(),
+                device: None,
+            });
+        }
+
+        std::fs::create_dir_all(path).io(format!("create {}", path.display()))?;
+        Ok(Preview::Directory {
+            dir: path.to_path_buf(),
+            count: 0,
+        })
+    }
+
+    /// Annotate `img` with `detection` and its match `score` (green at or
+    /// above `threshold`, red below) and emit it
+    pub fn write(
+        &mut self,
+        img: &DynamicImage,
+        detection: Option<&Detection>,
+        score: Option<f32>,
+        threshold: f32,
+    ) -> Result<()> {
+        let detections = detection.map(std::slice::from_ref).unwrap_or_default();
+        let mut rgb = face::annotate(img, detections).into_rgb8();
+        if let (Some(d), Some(score)) = (detection, score) {
+            let color = if score >= threshold {
+                draw::GREEN
+            } else {
+                draw::RED
+            };
+            let x = d.bbox[0].round() as i64;
+            let y = (d.bbox[1].round() as i64 - draw::TEXT_HEIGHT - 5).max(0);
+            draw::text(&mut rgb, &format!("{:.3}", score), x, y, color);
+        }
+
+        match self {
+            Preview::Loopback { path, device } => {
+                if device.is_none() {
+                    *device = Some(Loopback::open(path, rgb.width(), rgb.height())?);
+                }
+                Ok(device.as_mut().unwrap().write(&rgb)?)
+            }
+            Preview::Directory { dir, count } => {
+                *count += 1;
+                privacy::write_frame(
+                    FrameSink::Preview,
+                    Some(&dir.join(format!("frame-{:05}.png", count))),
+                    &DynamicImage::ImageRgb8(rgb),
+                )
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_directory_sequence() {
+        let dir = std::env::temp_dir().join(format!("howrs-preview-{}", std::process::id()));
+        let mut preview = Preview::open(&dir).unwrap();
+        let img = DynamicImage::new_rgb8(16, 16);
+        preview.write(&img, None, None, 0.6).unwrap();
+        preview.write(&img, None, None, 0.6).unwrap();
+        assert!(dir.join("frame-00001.png").exists());
+        assert!(dir.join("frame-00002.png").exists());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
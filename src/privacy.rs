@@ -0,0 +1,89 @@
+//! Data minimization guard for raw camera frames.
+//!
+//! Howrs only ever needs embeddings, so raw frames must not reach the disk
+//! unless the user explicitly asked for it. Every code path that persists a
+//! frame (snapshots, debug dumps, `--debug-out` previews) goes through
+//! [`write_frame`] with the destination the user set, so the opt-in check
+//! lives in exactly one place.
+//!
+//! Setting `HOWRS_ASSERT_NO_FRAMES=1` turns a refused write into a panic,
+//! which makes any accidental frame write fail loudly in CI.
+
+use crate::error::{Error, Result, ResultExt};
+use image::DynamicImage;
+use std::path::Path;
+
+/// Environment variable enabling the runtime assert mode
+pub const ASSERT_ENV: &str = "HOWRS_ASSERT_NO_FRAMES";
+
+/// Why a raw frame is being persisted
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FrameSink {
+    /// `howrs snapshot`
+    Snapshot,
+    /// Bundle of a failed scan, with `debug_dump` set
+    DebugDump,
+    /// Annotated frames of `howrs test --debug-out` saved to a directory
+    Preview,
+}
+
+impl FrameSink {
+    pub const ALL: [FrameSink; 3] = [
+        FrameSink::Snapshot,
+        FrameSink::DebugDump,
+        FrameSink::Preview,
+    ];
+
+    /// The setting or flag naming where frames of this sink go
+    pub fn opt_in(self) -> &'static str {
+        match self {
+            FrameSink::Snapshot => "--out",
+            FrameSink::DebugDump => "debug_dump",
+            FrameSink::Preview => "--debug-out",
+        }
+    }
+}
+
+/// Whether refused frame writes should panic instead of returning an error
+pub fn assert_mode() -> bool {
+    matches!(
+        std::env::var(ASSERT_ENV).as_deref(),
+        Ok("1") | Ok("true") | Ok("yes")
+    )
+}
+
+/// The destination the user gave for `sink`, as set in its
+/// [`FrameSink::opt_in`] setting or flag, or an error if they gave none (a
+/// panic in assert mode). For sinks that create a directory before their
+/// first frame.
+pub fn check(sink: FrameSink, dest: Option<&Path>) -> Result<&Path> {
+    if let Some(dest) = dest {
+        return Ok(dest);
+    }
+    tracing::warn!(
+        "refusing to write raw frames for {:?}: {} is not set",
+        sink,
+        sink.opt_in()
+    );
+    assert!(
+        !assert_mode(),
+        "raw frame write attempted without opt-in ({:?})",
+        sink
+    );
+    Err(Error::io(format!(
+        "writing raw frames for {:?} requires explicit opt-in with {}",
+        sink,
+        sink.opt_in()
+    )))
+}
+
+/// Persist a raw frame at `path`, which callers derive from the sink's
+/// [`FrameSink::opt_in`] setting or flag and pass on as it is.
+///
+/// With `None` nothing touches the filesystem and an error is returned
+/// (or the process panics in assert mode).
+pub fn write_frame(sink: FrameSink, path: Option<&Path>, img: &DynamicImage) -> Result<()> {
+    let path = check(sink, path)?;
+    img.save(path)
+        .io(format!("writing frame to {}", path.display()))
+}
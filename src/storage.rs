@@ -1,13 +1,626 @@
-use crate::config::FACE_STORE_PREFIX;
-use anyhow::{Context, Result};
+use crate::config::{self, Config, StoreConfig, FACE_STORE_PREFIX};
+use crate::identity;
+use crate::error::{Context, Error, Result, ResultExt};
+use crate::matcher::{self, PruneStrategy};
+use howrs_vision::video::{DeviceInfo, SensorKind};
 use serde::{Deserialize, Serialize};
-use std::path::PathBuf;
-use std::os::unix::fs::PermissionsExt;
+use sha2::{Digest, Sha256};
+use std::collections::BTreeMap;
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+use std::os::unix::fs::{MetadataExt, OpenOptionsExt, PermissionsExt};
+use zeroize::{Zeroize, Zeroizing};
 
-#[derive(Debug, Serialize, Deserialize)]
+/// Magic bytes at the start of a versioned `faces.bin`
+const STORE_MAGIC: &[u8; 4] = b"HWRS";
+/// Current on-disk store format version
+pub const STORE_VERSION: u8 = 7;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct FaceRecord {
     pub id: String,
-    pub embedding: Vec<f32>,
+    /// Several samples of the same face, e.g. consecutive good frames
+    pub embeddings: Vec<Vec<f32>>,
+    pub meta: RecordMeta,
+}
+
+/// Extra information kept alongside each record
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct RecordMeta {
+    /// Unix timestamp of enrollment, 0 if unknown
+    pub created_at: u64,
+    /// Free-form label, e.g. the pose captured during guided enrollment
+    pub label: Option<String>,
+    pub source: RecordSource,
+    /// Camera the face was captured with, if known
+    pub capture: Option<CaptureDevice>,
+    /// Detector confidence of the enrolled face in `[0, 1]`, if known
+    pub quality: Option<f32>,
+}
+
+/// Camera device and pixel format a record was captured from
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CaptureDevice {
+    pub path: String,
+    pub fourcc: String,
+    pub sensor: Sensor,
+}
+
+/// Kind of sensor behind a camera, see [`SensorKind`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Sensor {
+    Rgb,
+    Ir,
+}
+
+impl From<SensorKind> for Sensor {
+    fn from(kind: SensorKind) -> Self {
+        match kind {
+            SensorKind::Rgb => Sensor::Rgb,
+            SensorKind::Ir => Sensor::Ir,
+        }
+    }
+}
+
+impl From<DeviceInfo> for CaptureDevice {
+    fn from(info: DeviceInfo) -> Self {
+        Self {
+            path: info.path,
+            fourcc: info.fourcc,
+            sensor: info.sensor.into(),
+        }
+    }
+}
+
+/// Where the face of a record was captured
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum RecordSource {
+    /// Live from the camera, or a recording passed as `--input`
+    #[default]
+    Camera,
+    /// A still image, which proves nobody was in front of the camera
+    Image,
+}
+
+impl Drop for FaceRecord {
+    fn drop(&mut self) {
+        self.embeddings.zeroize();
+    }
+}
+
+/// Seconds since the Unix epoch, 0 if the clock is before it
+pub fn unix_now() -> u64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+impl FaceRecord {
+    pub fn new(embeddings: Vec<Vec<f32>>, label: Option<String>) -> Self {
+        Self {
+            id: uuid::Uuid::new_v4().to_string(),
+            embeddings,
+            meta: RecordMeta {
+                created_at: unix_now(),
+                label,
+                source: RecordSource::Camera,
+                capture: None,
+                quality: None,
+            },
+        }
+    }
+
+    /// L2-normalized mean of the record's embeddings
+    pub fn centroid(&self) -> Vec<f32> {
+        let dim = self.embeddings.first().map_or(0, Vec::len);
+        let mut centroid = vec![0.0; dim];
+        for embedding in &self.embeddings {
+            for (c, x) in centroid.iter_mut().zip(embedding) {
+                *c += x;
+            }
+        }
+        let norm = centroid.iter().map(|x| x * x).sum::<f32>().sqrt();
+        if norm > 0.0 {
+            centroid.iter_mut().for_each(|c| *c /= norm);
+        }
+        centroid
+    }
+}
+
+/// Record layout of the unversioned format written before `STORE_VERSION` 2
+#[derive(Deserialize)]
+struct RecordV1 {
+    id: String,
+    embedding: Vec<f32>,
+}
+
+impl From<RecordV1> for FaceRecord {
+    fn from(r: RecordV1) -> Self {
+        Self {
+            id: r.id,
+            embeddings: vec![r.embedding],
+            meta: RecordMeta::default(),
+        }
+    }
+}
+
+/// Metadata layout before `STORE_VERSION` 4, without the source
+#[derive(Serialize, Deserialize)]
+struct MetaV3 {
+    created_at: u64,
+    label: Option<String>,
+}
+
+impl From<MetaV3> for RecordMeta {
+    fn from(m: MetaV3) -> Self {
+        Self {
+            created_at: m.created_at,
+            label: m.label,
+            source: RecordSource::Camera,
+            capture: None,
+            quality: None,
+        }
+    }
+}
+
+/// Metadata layout of `STORE_VERSION` 4, without the capture device
+#[derive(Serialize, Deserialize)]
+struct MetaV4 {
+    created_at: u64,
+    label: Option<String>,
+    source: RecordSource,
+}
+
+impl From<MetaV4> for RecordMeta {
+    fn from(m: MetaV4) -> Self {
+        Self {
+            created_at: m.created_at,
+            label: m.label,
+            source: m.source,
+            capture: None,
+            quality: None,
+        }
+    }
+}
+
+/// Metadata layout of `STORE_VERSION` 5 and 6, without the quality
+#[derive(Serialize, Deserialize)]
+struct MetaV6 {
+    created_at: u64,
+    label: Option<String>,
+    source: RecordSource,
+    capture: Option<CaptureDevice>,
+}
+
+impl From<MetaV6> for RecordMeta {
+    fn from(m: MetaV6) -> Self {
+        Self {
+            created_at: m.created_at,
+            label: m.label,
+            source: m.source,
+            capture: m.capture,
+            quality: None,
+        }
+    }
+}
+
+/// Record layout of `STORE_VERSION` 2, with a single embedding
+#[derive(Deserialize)]
+struct RecordV2 {
+    id: String,
+    embedding: Vec<f32>,
+    meta: MetaV3,
+}
+
+impl From<RecordV2> for FaceRecord {
+    fn from(r: RecordV2) -> Self {
+        Self {
+            id: r.id,
+            embeddings: vec![r.embedding],
+            meta: r.meta.into(),
+        }
+    }
+}
+
+/// Record layout of `STORE_VERSION` 3 to 6, whose metadata is `M`
+#[derive(Deserialize)]
+struct RecordV3<M> {
+    id: String,
+    embeddings: Vec<Vec<f32>>,
+    meta: M,
+}
+
+impl<M: Into<RecordMeta>> From<RecordV3<M>> for FaceRecord {
+    fn from(r: RecordV3<M>) -> Self {
+        Self {
+            id: r.id,
+            embeddings: r.embeddings,
+            meta: r.meta.into(),
+        }
+    }
+}
+
+/// HMAC-SHA256 of a record under the [`StoreKey`]
+pub type Tag = [u8; 32];
+
+/// Record layout since `STORE_VERSION` 6: the postcard encoding of a
+/// [`FaceRecord`] and, in the system store, its [`Tag`]
+#[derive(Serialize, Deserialize)]
+struct SignedRecord {
+    record: Vec<u8>,
+    tag: Option<Tag>,
+}
+
+impl Drop for SignedRecord {
+    fn drop(&mut self) {
+        self.record.zeroize();
+    }
+}
+
+/// Encode `records` unsigned, as in the staging store
+pub fn encode_records(records: &[FaceRecord]) -> Result<Vec<u8>> {
+    encode_with(records, |_| None)
+}
+
+fn encode_with(records: &[FaceRecord], sign: impl Fn(&[u8]) -> Option<Tag>) -> Result<Vec<u8>> {
+    let signed = records
+        .iter()
+        .map(|r| {
+            let record = postcard::to_allocvec(r)?;
+            let tag = sign(&record);
+            Ok(SignedRecord { record, tag })
+        })
+        .collect::<Result<Vec<_>>>()?;
+    let mut data = STORE_MAGIC.to_vec();
+    data.push(STORE_VERSION);
+    Ok(postcard::to_extend(&signed, data)?)
+}
+
+/// Decode a store read with [`std::fs::read`] or the like, ignoring tags.
+/// Callers should wipe `data` afterwards, e.g. by holding it in
+/// [`Zeroizing`].
+pub fn decode_records(data: &[u8]) -> Result<Vec<FaceRecord>> {
+    Ok(decode_store(data)?.into_iter().map(|(r, _)| r).collect())
+}
+
+/// Records of a store, each with what it was stored as from `STORE_VERSION`
+/// 6 on, to check its tag against
+fn decode_store(data: &[u8]) -> Result<Vec<(FaceRecord, Option<SignedRecord>)>> {
+    fn unsigned<R: Into<FaceRecord>>(records: Vec<R>) -> Vec<(FaceRecord, Option<SignedRecord>)> {
+        records.into_iter().map(|r| (r.into(), None)).collect()
+    }
+
+    let Some(rest) = data.strip_prefix(STORE_MAGIC) else {
+        // Legacy store without header
+        let records: Vec<RecordV1> = postcard::from_bytes(data)?;
+        return Ok(unsigned(records));
+    };
+
+    match rest.split_first() {
+        Some((&STORE_VERSION, payload)) => {
+            let signed: Vec<SignedRecord> = postcard::from_bytes(payload)?;
+            signed
+                .into_iter()
+                .map(|s| Ok((postcard::from_bytes(&s.record)?, Some(s))))
+                .collect()
+        }
+        // Signed like the current version, tags cover the old encoding
+        Some((6, payload)) => {
+            let signed: Vec<SignedRecord> = postcard::from_bytes(payload)?;
+            signed
+                .into_iter()
+                .map(|s| {
+                    let record: RecordV3<MetaV6> = postcard::from_bytes(&s.record)?;
+                    Ok((record.into(), Some(s)))
+                })
+                .collect()
+        }
+        Some((5, payload)) => {
+            let records: Vec<RecordV3<MetaV6>> = postcard::from_bytes(payload)?;
+            Ok(unsigned(records))
+        }
+        Some((4, payload)) => {
+            let records: Vec<RecordV3<MetaV4>> = postcard::from_bytes(payload)?;
+            Ok(unsigned(records))
+        }
+        Some((3, payload)) => {
+            let records: Vec<RecordV3<MetaV3>> = postcard::from_bytes(payload)?;
+            Ok(unsigned(records))
+        }
+        Some((2, payload)) => {
+            let records: Vec<RecordV2> = postcard::from_bytes(payload)?;
+            Ok(unsigned(records))
+        }
+        Some((version, _)) => Err(Error::storage(format!(
+            "unsupported face store version {}",
+            version
+        ))),
+        None => Err(Error::storage("truncated face store header")),
+    }
+}
+
+/// Secret key the records of the system store are signed with, so records
+/// modified or planted by someone who could write to the store but not read
+/// the key are rejected. It lives in `store.key` next to the user stores,
+/// readable by root and the `[store]` group.
+///
+/// Without it nothing can be verified, so records are never loaded or
+/// signed without it: a key is only created for an empty store, or by
+/// [`sign_store`] on an administrator's say-so.
+pub struct StoreKey(Zeroizing<Vec<u8>>);
+
+impl StoreKey {
+    pub fn path() -> PathBuf {
+        FACE_STORE_PREFIX.join("store.key")
+    }
+
+    /// The key, or `None` if there is none. Failing to read it, e.g. in a
+    /// greeter outside the `[store]` group, is an error.
+    pub fn load() -> Result<Option<Self>> {
+        Self::load_from(&Self::path())
+    }
+
+    fn load_from(path: &Path) -> Result<Option<Self>> {
+        match std::fs::read(path) {
+            Ok(key) => Ok(Some(Self(Zeroizing::new(key)))),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e).storage(format!(
+                "reading {}, without which face records can't be verified",
+                path.display()
+            )),
+        }
+    }
+
+    /// Generate a key; fails if there already is one
+    fn create(perms: &StorePermissions) -> Result<Self> {
+        let path = Self::path();
+        let mut key = Zeroizing::new(vec![0; 32]);
+        std::fs::File::open("/dev/urandom")
+            .and_then(|mut f| f.read_exact(&mut key))
+            .storage("reading /dev/urandom")?;
+        std::fs::create_dir_all(*FACE_STORE_PREFIX)
+            .storage(format!("creating {}", FACE_STORE_PREFIX.display()))?;
+        std::fs::OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .mode(0o600)
+            .open(&path)
+            .and_then(|mut f| f.write_all(&key))
+            .storage(format!("writing {}", path.display()))?;
+        set_owner(&path, perms.gid, perms.key_mode())?;
+        Ok(Self(key))
+    }
+
+    /// Tag of the encoded `record` of `user_id`. The user is covered too,
+    /// so records can't be copied between users' stores.
+    pub fn tag(&self, user_id: &str, record: &[u8]) -> Tag {
+        hmac_sha256(&self.0, &[user_id.as_bytes(), &[0], record])
+    }
+
+    fn verify(&self, user_id: &str, signed: &SignedRecord) -> bool {
+        signed.tag.is_some_and(|tag| {
+            let expected = self.tag(user_id, &signed.record);
+            // Constant time, not to leak how much of a forged tag was right
+            tag.iter().zip(expected).fold(0, |acc, (a, b)| acc | (a ^ b)) == 0
+        })
+    }
+}
+
+/// Create a [`StoreKey`] and sign every record already in the store with it,
+/// for a store from before records were signed or whose key was lost.
+///
+/// The records are taken as they are, so whatever is in the store becomes
+/// trusted: only for a store the administrator trusts. Fails if there is a
+/// key already. Returns the users whose records were signed.
+pub fn sign_store() -> Result<Vec<String>> {
+    if StoreKey::load()?.is_some() {
+        return Err(Error::storage(format!(
+            "{} exists; the store is signed already",
+            StoreKey::path().display()
+        )));
+    }
+    let mut stores = Vec::new();
+    for user_id in enrolled_users()? {
+        let file = user_store_path(&user_id).join("faces.bin");
+        let data =
+            Zeroizing::new(std::fs::read(&file).storage(format!("reading {}", file.display()))?);
+        let records = decode_records(&data).storage(format!("decoding {}", file.display()))?;
+        stores.push((user_id, records));
+    }
+
+    let perms = StorePermissions::system();
+    let key = StoreKey::create(&perms).context("creating the store key")?;
+    for (user_id, records) in &stores {
+        write_store(user_id, records, &key, &perms)?;
+    }
+    Ok(stores.into_iter().map(|(user_id, _)| user_id).collect())
+}
+
+/// HMAC (RFC 2104) with SHA-256 over the concatenated `parts`
+fn hmac_sha256(key: &[u8], parts: &[&[u8]]) -> Tag {
+    const BLOCK: usize = 64;
+    let mut block = Zeroizing::new([0u8; BLOCK]);
+    if key.len() > BLOCK {
+        block[..32].copy_from_slice(&Sha256::digest(key));
+    } else {
+        block[..key.len()].copy_from_slice(key);
+    }
+
+    let mut inner = Sha256::new();
+    inner.update(block.map(|b| b ^ 0x36));
+    for part in parts {
+        inner.update(part);
+    }
+    let mut outer = Sha256::new();
+    outer.update(block.map(|b| b ^ 0x5c));
+    outer.update(inner.finalize());
+    outer.finalize().into()
+}
+
+/// Bookkeeping about which records actually produce matches
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct MatchStats {
+    /// Probe embedding from the last successful authentication
+    pub last_probe: Option<Vec<f32>>,
+    /// Number of successful authentications each record won, by record id
+    pub hits: BTreeMap<String, u32>,
+}
+
+impl Drop for MatchStats {
+    fn drop(&mut self) {
+        self.last_probe.zeroize();
+    }
+}
+
+/// Keeps the embeddings of some records out of swap with `mlock(2)` for as
+/// long as it lives, see [`lock_in_memory`]
+pub struct MemoryLock<'a> {
+    records: &'a [FaceRecord],
+}
+
+/// Lock the embeddings of `records` in RAM, so a long-running process such
+/// as `howrs daemon` never writes them to swap. Failing to lock, e.g. past
+/// `RLIMIT_MEMLOCK`, is only logged.
+pub fn lock_in_memory(records: &[FaceRecord]) -> MemoryLock<'_> {
+    for embedding in records.iter().flat_map(|r| &r.embeddings) {
+        let len = std::mem::size_of_val(embedding.as_slice());
+        // SAFETY: the range is the live allocation of `embedding`
+        if len > 0 && unsafe { libc::mlock(embedding.as_ptr().cast(), len) } != 0 {
+            tracing::warn!("mlock failed: {}", std::io::Error::last_os_error());
+            break;
+        }
+    }
+    MemoryLock { records }
+}
+
+impl Drop for MemoryLock<'_> {
+    fn drop(&mut self) {
+        for embedding in self.records.iter().flat_map(|r| &r.embeddings) {
+            let len = std::mem::size_of_val(embedding.as_slice());
+            // SAFETY: same range as locked; unlocking an unlocked page is harmless
+            unsafe { libc::munlock(embedding.as_ptr().cast(), len) };
+        }
+    }
+}
+
+/// Ownership and modes of the store's paths under `[store]`; root always
+/// owns them
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct StorePermissions {
+    pub gid: u32,
+    pub dir_mode: u32,
+    pub file_mode: u32,
+}
+
+impl StorePermissions {
+    /// Resolve `config`. The group, if it exists, is granted read access on
+    /// top of the configured modes; otherwise root's group is used.
+    pub fn new(config: &StoreConfig) -> Self {
+        match config.group.as_deref().and_then(identity::group_id) {
+            Some(gid) => Self {
+                gid,
+                dir_mode: config.dir_mode | 0o050,
+                file_mode: config.file_mode | 0o040,
+            },
+            None => Self {
+                gid: 0,
+                dir_mode: config.dir_mode,
+                file_mode: config.file_mode,
+            },
+        }
+    }
+
+    /// From the system config, which governs the store whoever writes it
+    pub fn system() -> Self {
+        let config = config::load_config(None).map(|c| c.store);
+        Self::new(&config.unwrap_or_default())
+    }
+
+    /// Mode of the [`StoreKey`]: read by whoever may read the records, so
+    /// they can verify them, and written by root only
+    pub fn key_mode(&self) -> u32 {
+        0o600 | (self.file_mode & 0o040)
+    }
+}
+
+/// Make `path` owned by root and the store's group, with `mode`
+fn set_owner(path: &Path, gid: u32, mode: u32) -> Result<()> {
+    let meta = std::fs::metadata(path).storage(format!("reading {}", path.display()))?;
+    if meta.uid() != 0 || meta.gid() != gid {
+        std::os::unix::fs::chown(path, Some(0), Some(gid))
+            .storage(format!("changing the owner of {}", path.display()))?;
+    }
+    std::fs::set_permissions(path, std::fs::Permissions::from_mode(mode))
+        .storage(format!("changing the mode of {}", path.display()))
+}
+
+/// A path of the store whose owner or mode differs from `[store]`
+#[derive(Debug, Clone)]
+pub struct PermissionIssue {
+    pub path: PathBuf,
+    pub uid: u32,
+    pub gid: u32,
+    pub mode: u32,
+    pub expected_gid: u32,
+    pub expected_mode: u32,
+}
+
+impl std::fmt::Display for PermissionIssue {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{}: owner {}:{} mode {:04o}, expected 0:{} mode {:04o}",
+            self.path.display(),
+            self.uid,
+            self.gid,
+            self.mode,
+            self.expected_gid,
+            self.expected_mode
+        )
+    }
+}
+
+/// Check the owner and mode of the store key and of every user's directory,
+/// face and match files against `perms`, correcting them if `fix`. Returns
+/// what differed.
+pub fn audit_permissions(perms: &StorePermissions, fix: bool) -> Result<Vec<PermissionIssue>> {
+    let mut expected = Vec::new();
+    let key = StoreKey::path();
+    if key.exists() {
+        expected.push((key, perms.gid, perms.key_mode()));
+    }
+    for user_id in enrolled_users()? {
+        let dir = user_store_path(&user_id);
+        for name in ["faces.bin", "matches.bin"] {
+            let file = dir.join(name);
+            if file.exists() {
+                expected.push((file, perms.gid, perms.file_mode));
+            }
+        }
+        expected.push((dir, perms.gid, perms.dir_mode));
+    }
+
+    let mut issues = Vec::new();
+    for (path, expected_gid, expected_mode) in expected {
+        let meta = std::fs::metadata(&path).storage(format!("reading {}", path.display()))?;
+        let mode = meta.mode() & 0o7777;
+        if meta.uid() == 0 && meta.gid() == expected_gid && mode == expected_mode {
+            continue;
+        }
+        if fix {
+            set_owner(&path, expected_gid, expected_mode)?;
+        }
+        issues.push(PermissionIssue {
+            path,
+            uid: meta.uid(),
+            gid: meta.gid(),
+            mode,
+            expected_gid,
+            expected_mode,
+        });
+    }
+    Ok(issues)
 }
 
 fn user_store_path(user_id: &str) -> PathBuf {
@@ -16,41 +629,606 @@ fn user_store_path(user_id: &str) -> PathBuf {
     p
 }
 
+/// Load the records of `user_id`. Records whose tag under the
+/// [`StoreKey`] is missing or wrong are left out and logged; without the
+/// key, any record at all is an error, see [`load_verified`].
 pub fn load_records(user_id: &str) -> Result<Vec<FaceRecord>> {
-    let path = user_store_path(user_id);
-    let file = path.join("faces.bin");
-    
+    let file = user_store_path(user_id).join("faces.bin");
+    load_verified(&file, &StoreKey::path(), user_id)
+}
+
+/// Records of `user_id` in `file` that verify under the key in `key_path`.
+///
+/// A missing key fails rather than letting the records through: it may have
+/// been deleted to plant records, or the store predates signing and must be
+/// signed with [`sign_store`] first.
+fn load_verified(file: &Path, key_path: &Path, user_id: &str) -> Result<Vec<FaceRecord>> {
     if !file.exists() {
         return Ok(vec![]);
     }
-    
-    let data = std::fs::read(&file)
-        .with_context(|| format!("reading {}", file.display()))?;
-    Ok(postcard::from_bytes(&data)?)
+
+    let data =
+        Zeroizing::new(std::fs::read(file).storage(format!("reading {}", file.display()))?);
+    let records = decode_store(&data).storage(format!("decoding {}", file.display()))?;
+    if records.is_empty() {
+        return Ok(vec![]);
+    }
+    match StoreKey::load_from(key_path)? {
+        Some(key) => Ok(verify_records(&key, user_id, records)),
+        None => Err(Error::storage(format!(
+            "{} is missing, so the face records in {} can't be verified; if the \
+             store predates signed records, sign it with `sudo howrs doctor --sign-store`",
+            key_path.display(),
+            file.display()
+        ))),
+    }
+}
+
+fn verify_records(
+    key: &StoreKey,
+    user_id: &str,
+    records: Vec<(FaceRecord, Option<SignedRecord>)>,
+) -> Vec<FaceRecord> {
+    records
+        .into_iter()
+        .filter_map(|(record, signed)| {
+            if signed.is_some_and(|s| key.verify(user_id, &s)) {
+                return Some(record);
+            }
+            tracing::warn!(
+                "rejecting face record {} of {}: missing or invalid signature, the store was modified",
+                record.id,
+                user_id
+            );
+            None
+        })
+        .collect()
+}
+
+/// Users with a face store, sorted by name
+pub fn enrolled_users() -> Result<Vec<String>> {
+    let entries = match std::fs::read_dir(*FACE_STORE_PREFIX) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(vec![]),
+        Err(e) => return Err(e).storage(format!("reading {}", FACE_STORE_PREFIX.display())),
+    };
+
+    let mut users = Vec::new();
+    for entry in entries {
+        let entry = entry.storage(format!("reading {}", FACE_STORE_PREFIX.display()))?;
+        if !entry.path().join("faces.bin").is_file() {
+            continue;
+        }
+        if let Ok(name) = entry.file_name().into_string() {
+            users.push(name);
+        }
+    }
+    users.sort();
+    Ok(users)
 }
 
 pub fn save_record(user_id: &str, record: FaceRecord) -> Result<()> {
+    let mut records = load_records(user_id)?;
+    records.push(record);
+    save_records(user_id, &records)
+}
+
+/// Replace the user's whole store with `records`
+pub fn save_records(user_id: &str, records: &[FaceRecord]) -> Result<()> {
+    let path = user_store_path(user_id);
+    std::fs::create_dir_all(&path).storage(format!("creating {}", path.display()))?;
+    // Private to root, and to the `[store]` group display managers use to
+    // read faces without root
+    let perms = StorePermissions::system();
+    set_owner(&path, perms.gid, perms.dir_mode)?;
+
+    let key = match StoreKey::load()? {
+        Some(key) => key,
+        // Signing records already in the store would vouch for whatever was
+        // planted there
+        None if enrolled_users()?.is_empty() => {
+            StoreKey::create(&perms).context("creating the store key")?
+        }
+        None => {
+            return Err(Error::storage(format!(
+                "{} is missing but the store holds face records; sign them with \
+                 `sudo howrs doctor --sign-store` if they are trusted",
+                StoreKey::path().display()
+            )))
+        }
+    };
+    write_store(user_id, records, &key, &perms)
+}
+
+fn write_store(
+    user_id: &str,
+    records: &[FaceRecord],
+    key: &StoreKey,
+    perms: &StorePermissions,
+) -> Result<()> {
+    let file = user_store_path(user_id).join("faces.bin");
+    let data = Zeroizing::new(encode_with(records, |r| Some(key.tag(user_id, r)))?);
+    std::fs::write(&file, &data).storage(format!("writing {}", file.display()))?;
+    set_owner(&file, perms.gid, perms.file_mode)
+}
+
+pub fn load_match_stats(user_id: &str) -> Result<MatchStats> {
+    let file = user_store_path(user_id).join("matches.bin");
+    if !file.exists() {
+        return Ok(MatchStats::default());
+    }
+
+    let data =
+        Zeroizing::new(std::fs::read(&file).storage(format!("reading {}", file.display()))?);
+    Ok(postcard::from_bytes(&data)?)
+}
+
+/// Remember that `record_id` won a successful authentication against `probe`
+pub fn record_match(user_id: &str, record_id: &str, probe: &[f32]) -> Result<()> {
     let path = user_store_path(user_id);
-    std::fs::create_dir_all(&path)?;
-    // Set directory permissions to 755 (readable by all users, writable by root only)
-    // This allows SDDM and other non-root display managers to read face data
-    std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o755))?;
-    
+    if !path.exists() {
+        return Ok(());
+    }
+
+    let mut stats = load_match_stats(user_id)?;
+    stats.last_probe = Some(probe.to_vec());
+    *stats.hits.entry(record_id.to_string()).or_default() += 1;
+    save_match_stats(user_id, &stats)
+}
+
+fn save_match_stats(user_id: &str, stats: &MatchStats) -> Result<()> {
+    let file = user_store_path(user_id).join("matches.bin");
+    let data = Zeroizing::new(postcard::to_allocvec(stats)?);
+    std::fs::write(&file, &data).storage(format!("writing {}", file.display()))?;
+    let perms = StorePermissions::system();
+    set_owner(&file, perms.gid, perms.file_mode)
+}
+
+/// Index of the record whose ID is `id`, or else the only one starting with
+/// it, so the short prefix `howrs list` shows is enough
+pub fn find_record(records: &[FaceRecord], id: &str) -> Result<usize> {
+    if let Some(index) = records.iter().position(|r| r.id == id) {
+        return Ok(index);
+    }
+    let mut matches = records
+        .iter()
+        .enumerate()
+        .filter(|(_, r)| !id.is_empty() && r.id.starts_with(id))
+        .map(|(i, _)| i);
+    match (matches.next(), matches.next()) {
+        (Some(index), None) => Ok(index),
+        (None, _) => Err(Error::storage(format!("no face record {}", id))),
+        (Some(_), Some(_)) => Err(Error::storage(format!(
+            "{} matches several face records; give more of the ID",
+            id
+        ))),
+    }
+}
+
+/// Set the label of record `id` (see [`find_record`]), or clear it with
+/// `None`. Returns the record's full ID.
+pub fn set_label(user_id: &str, id: &str, label: Option<String>) -> Result<String> {
+    let mut records = load_records(user_id)?;
+    let index = find_record(&records, id)?;
+    records[index].meta.label = label;
+    save_records(user_id, &records)?;
+    Ok(records[index].id.clone())
+}
+
+/// Remove record `id` (see [`find_record`]) and its match count. Returns
+/// the removed record.
+pub fn remove_record(user_id: &str, id: &str) -> Result<FaceRecord> {
     let mut records = load_records(user_id)?;
+    let removed = records.remove(find_record(&records, id)?);
+    save_records(user_id, &records)?;
+
+    let mut stats = load_match_stats(user_id)?;
+    if stats.hits.remove(&removed.id).is_some() {
+        save_match_stats(user_id, &stats)?;
+    }
+    Ok(removed)
+}
+
+/// Remove records until at most `keep` remain, chosen by `strategy` (see
+/// [`matcher::select_prune`]). Returns the removed records.
+pub fn prune(user_id: &str, keep: usize, strategy: PruneStrategy) -> Result<Vec<FaceRecord>> {
+    let mut records = load_records(user_id)?;
+    let mut remove = matcher::select_prune(&records, keep, strategy);
+    if remove.is_empty() {
+        return Ok(vec![]);
+    }
+
+    // Back to front so earlier indices stay valid
+    remove.sort_unstable();
+    let removed: Vec<FaceRecord> = remove.iter().rev().map(|&i| records.remove(i)).collect();
+    save_records(user_id, &records)?;
+
+    let mut stats = load_match_stats(user_id)?;
+    let tracked = stats.hits.len();
+    for record in &removed {
+        stats.hits.remove(&record.id);
+    }
+    if stats.hits.len() < tracked {
+        save_match_stats(user_id, &stats)?;
+    }
+    Ok(removed)
+}
+
+/// Apply `max_records_per_user` after records were added. Returns the
+/// removed records.
+pub fn enforce_cap(user_id: &str, config: &Config) -> Result<Vec<FaceRecord>> {
+    match config.max_records_per_user {
+        0 => Ok(vec![]),
+        max => prune(user_id, max, config.prune_strategy),
+    }
+}
+
+/// Per-user staging store for enrolling without root, committed into the
+/// system store later with `howrs commit`
+pub fn staging_file(home: &Path) -> PathBuf {
+    home.join(".local/share/howrs/faces.bin")
+}
+
+pub fn load_staged(home: &Path) -> Result<Vec<FaceRecord>> {
+    let file = staging_file(home);
+    if !file.exists() {
+        return Ok(vec![]);
+    }
+    let data = std::fs::read(&file).storage(format!("reading {}", file.display()))?;
+    decode_records(&data).storage(format!("decoding {}", file.display()))
+}
+
+/// Append `record` to the staging store in `home`
+pub fn stage_record(home: &Path, record: FaceRecord) -> Result<()> {
+    let mut records = load_staged(home)?;
     records.push(record);
-    let file = path.join("faces.bin");
-    let data = postcard::to_allocvec(&records)?;
-    std::fs::write(&file, data)?;
-    
-    // Set file permissions to 644 (readable by all users, writable by root only)
-    std::fs::set_permissions(&file, std::fs::Permissions::from_mode(0o644))?;
+
+    let file = staging_file(home);
+    let dir = file.parent().expect("staging file has a parent");
+    std::fs::create_dir_all(dir).storage(format!("creating {}", dir.display()))?;
+    // Private until committed: nobody else needs to read staged faces
+    std::fs::set_permissions(dir, std::fs::Permissions::from_mode(0o700))
+        .storage(format!("changing the mode of {}", dir.display()))?;
+    std::fs::write(&file, encode_records(&records)?)
+        .storage(format!("writing {}", file.display()))?;
+    std::fs::set_permissions(&file, std::fs::Permissions::from_mode(0o600))
+        .storage(format!("changing the mode of {}", file.display()))?;
     Ok(())
 }
 
+/// Move staged records owned by `uid` into the system store of `user_id`.
+///
+/// Returns the number of records committed. The staging file must belong to
+/// `uid` so root never imports a store planted by another user.
+pub fn commit_staged(user_id: &str, home: &Path, uid: u32) -> Result<usize> {
+    let file = staging_file(home);
+    if !file.exists() {
+        return Ok(0);
+    }
+    let owner = std::fs::symlink_metadata(&file)
+        .storage(format!("reading {}", file.display()))?
+        .uid();
+    if owner != uid {
+        return Err(Error::storage(format!(
+            "{} is owned by uid {}, not {} ({})",
+            file.display(),
+            owner,
+            user_id,
+            uid
+        )));
+    }
+
+    let staged = load_staged(home)?;
+    let mut records = load_records(user_id)?;
+    records.extend(staged.iter().cloned());
+    save_records(user_id, &records)?;
+    std::fs::remove_file(&file).storage(format!("removing {}", file.display()))?;
+    Ok(staged.len())
+}
+
 pub fn purge(user_id: &str) -> Result<()> {
     let path = user_store_path(user_id);
     if path.exists() {
-        std::fs::remove_dir_all(&path).with_context(|| format!("removing {}", path.display()))?;
+        std::fs::remove_dir_all(&path).storage(format!("removing {}", path.display()))?;
     }
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_roundtrip() {
+        let mut records = vec![FaceRecord::new(vec![vec![0.5; 128]], Some("front".into()))];
+        records[0].meta.source = RecordSource::Image;
+        records[0].meta.quality = Some(0.9);
+        records[0].meta.capture = Some(CaptureDevice {
+            path: "/dev/video2".into(),
+            fourcc: "GREY".into(),
+            sensor: Sensor::Ir,
+        });
+        let decoded = decode_records(&encode_records(&records).unwrap()).unwrap();
+        assert_eq!(decoded.len(), 1);
+        assert_eq!(decoded[0].id, records[0].id);
+        assert_eq!(decoded[0].meta.label.as_deref(), Some("front"));
+        assert_eq!(decoded[0].meta.source, RecordSource::Image);
+        assert_eq!(decoded[0].meta.capture, records[0].meta.capture);
+        assert_eq!(decoded[0].meta.quality, Some(0.9));
+    }
+
+    #[test]
+    fn test_lock_in_memory() {
+        let records = vec![
+            FaceRecord::new(vec![vec![0.5; 128]], None),
+            FaceRecord::new(vec![vec![0.25; 128], vec![0.75; 128]], None),
+        ];
+        // mlock may be refused by RLIMIT_MEMLOCK; either way the records
+        // stay readable and unlock cleanly
+        let locked = lock_in_memory(&records);
+        assert_eq!(records[1].embeddings[1][0], 0.75);
+        drop(locked);
+    }
+
+    #[test]
+    fn test_hmac_sha256() {
+        // RFC 4231, test case 2
+        let tag = hmac_sha256(b"Jefe", &[b"what do ya want ", b"for nothing?"]);
+        let hex: String = tag.iter().map(|b| format!("{:02x}", b)).collect();
+        assert_eq!(
+            hex,
+            "5bdcc146bf60754e6a042426089575c75a003f089d2739839dec58b964ec3843"
+        );
+    }
+
+    #[test]
+    fn test_verify_records() {
+        let key = StoreKey(Zeroizing::new(vec![7; 32]));
+        let records = vec![
+            FaceRecord::new(vec![vec![0.5; 4]], Some("signed".into())),
+            FaceRecord::new(vec![vec![0.5; 4]], Some("tampered".into())),
+            FaceRecord::new(vec![vec![0.5; 4]], Some("other user".into())),
+        ];
+        let signed = encode_with(&records, |r| Some(key.tag("alice", r))).unwrap();
+        let mut stored = decode_store(&signed).unwrap();
+        stored[1].1.as_mut().unwrap().record[2] ^= 1;
+        stored[2].1.as_mut().unwrap().tag = Some(key.tag("mallory", &[]));
+        let injected = decode_store(&encode_records(&records[..1]).unwrap()).unwrap();
+        stored.extend(injected);
+
+        let verified = verify_records(&key, "alice", stored);
+        assert_eq!(verified.len(), 1);
+        assert_eq!(verified[0].meta.label.as_deref(), Some("signed"));
+        // The same store doesn't verify as someone else's
+        let stored = decode_store(&signed).unwrap();
+        assert!(verify_records(&key, "bob", stored).is_empty());
+    }
+
+    #[test]
+    fn test_load_without_key() {
+        let dir = std::env::temp_dir().join("howrs-test-store-key");
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        let (file, key_path) = (dir.join("faces.bin"), dir.join("store.key"));
+
+        let key = StoreKey(Zeroizing::new(vec![7; 32]));
+        std::fs::write(&key_path, &*key.0).unwrap();
+        let records = vec![FaceRecord::new(vec![vec![0.5; 4]], Some("enrolled".into()))];
+        let signed = encode_with(&records, |r| Some(key.tag("alice", r))).unwrap();
+        std::fs::write(&file, signed).unwrap();
+        assert_eq!(load_verified(&file, &key_path, "alice").unwrap().len(), 1);
+
+        // Someone able to write the store deletes the key and plants a face
+        std::fs::remove_file(&key_path).unwrap();
+        let planted = vec![FaceRecord::new(vec![vec![0.5; 4]], Some("planted".into()))];
+        std::fs::write(&file, encode_records(&planted).unwrap()).unwrap();
+        assert!(load_verified(&file, &key_path, "alice").is_err());
+
+        // Nor does a store in an unsigned older format get through
+        let mut v5 = STORE_MAGIC.to_vec();
+        v5.push(5);
+        let v5 = postcard::to_extend(&vec![v6_record("planted")], v5).unwrap();
+        std::fs::write(&file, v5).unwrap();
+        assert!(load_verified(&file, &key_path, "alice").is_err());
+
+        // An empty store needs no key
+        std::fs::write(&file, encode_records(&[]).unwrap()).unwrap();
+        assert!(load_verified(&file, &key_path, "alice").unwrap().is_empty());
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[derive(Serialize)]
+    struct V6 {
+        id: String,
+        embeddings: Vec<Vec<f32>>,
+        meta: MetaV6,
+    }
+
+    fn v6_record(label: &str) -> V6 {
+        V6 {
+            id: label.into(),
+            embeddings: vec![vec![1.0, 0.0]],
+            meta: MetaV6 {
+                created_at: 7,
+                label: Some(label.into()),
+                source: RecordSource::Camera,
+                capture: None,
+            },
+        }
+    }
+
+    #[test]
+    fn test_decode_v5() {
+        let mut data = STORE_MAGIC.to_vec();
+        data.push(5);
+        let data = postcard::to_extend(&vec![v6_record("v5")], data).unwrap();
+
+        let stored = decode_store(&data).unwrap();
+        assert_eq!(stored[0].0.meta.label.as_deref(), Some("v5"));
+        assert!(stored[0].1.is_none());
+    }
+
+    #[test]
+    fn test_decode_v6() {
+        let key = StoreKey(Zeroizing::new(vec![7; 32]));
+        let record = postcard::to_allocvec(&v6_record("v6")).unwrap();
+        let tag = Some(key.tag("alice", &record));
+        let mut data = STORE_MAGIC.to_vec();
+        data.push(6);
+        let data = postcard::to_extend(&vec![SignedRecord { record, tag }], data).unwrap();
+
+        // Tags written by version 6 still verify
+        let verified = verify_records(&key, "alice", decode_store(&data).unwrap());
+        assert_eq!(verified[0].meta.label.as_deref(), Some("v6"));
+        assert_eq!(verified[0].meta.quality, None);
+    }
+
+    #[test]
+    fn test_store_permissions() {
+        let config = StoreConfig {
+            dir_mode: 0o700,
+            file_mode: 0o600,
+            group: Some("root".into()),
+        };
+        let perms = StorePermissions::new(&config);
+        assert_eq!((perms.gid, perms.dir_mode, perms.file_mode), (0, 0o750, 0o640));
+
+        let config = StoreConfig {
+            group: Some("howrs-no-such-group".into()),
+            ..config
+        };
+        let perms = StorePermissions::new(&config);
+        assert_eq!((perms.gid, perms.dir_mode, perms.file_mode), (0, 0o700, 0o600));
+    }
+
+    #[test]
+    fn test_decode_legacy() {
+        #[derive(Serialize)]
+        struct Legacy {
+            id: String,
+            embedding: Vec<f32>,
+        }
+        let data = postcard::to_allocvec(&vec![Legacy {
+            id: "old".into(),
+            embedding: vec![1.0, 0.0],
+        }])
+        .unwrap();
+
+        let decoded = decode_records(&data).unwrap();
+        assert_eq!(decoded[0].id, "old");
+        assert_eq!(decoded[0].embeddings, vec![vec![1.0, 0.0]]);
+        assert_eq!(decoded[0].meta.created_at, 0);
+    }
+
+    #[test]
+    fn test_decode_v2() {
+        #[derive(Serialize)]
+        struct V2 {
+            id: String,
+            embedding: Vec<f32>,
+            meta: MetaV3,
+        }
+        let mut data = STORE_MAGIC.to_vec();
+        data.push(2);
+        let records = vec![V2 {
+            id: "single".into(),
+            embedding: vec![0.0, 1.0],
+            meta: MetaV3 {
+                created_at: 42,
+                label: None,
+            },
+        }];
+        let data = postcard::to_extend(&records, data).unwrap();
+
+        let decoded = decode_records(&data).unwrap();
+        assert_eq!(decoded[0].embeddings, vec![vec![0.0, 1.0]]);
+        assert_eq!(decoded[0].meta.created_at, 42);
+    }
+
+    #[test]
+    fn test_decode_v3() {
+        #[derive(Serialize)]
+        struct V3 {
+            id: String,
+            embeddings: Vec<Vec<f32>>,
+            meta: MetaV3,
+        }
+        let mut data = STORE_MAGIC.to_vec();
+        data.push(3);
+        let records = vec![V3 {
+            id: "pose".into(),
+            embeddings: vec![vec![0.0, 1.0], vec![1.0, 0.0]],
+            meta: MetaV3 {
+                created_at: 7,
+                label: Some("left".into()),
+            },
+        }];
+        let data = postcard::to_extend(&records, data).unwrap();
+
+        let decoded = decode_records(&data).unwrap();
+        assert_eq!(decoded[0].embeddings.len(), 2);
+        assert_eq!(decoded[0].meta.label.as_deref(), Some("left"));
+        assert_eq!(decoded[0].meta.source, RecordSource::Camera);
+    }
+
+    #[test]
+    fn test_centroid() {
+        let record = FaceRecord::new(vec![vec![1.0, 0.0], vec![0.0, 1.0]], None);
+        let half = std::f32::consts::FRAC_1_SQRT_2;
+        for c in record.centroid() {
+            assert!((c - half).abs() < 1e-6);
+        }
+    }
+
+    #[test]
+    fn test_decode_unknown_version() {
+        let mut data = STORE_MAGIC.to_vec();
+        data.push(STORE_VERSION + 1);
+        assert!(decode_records(&data).is_err());
+    }
+
+    #[test]
+    fn test_find_record() {
+        let mut records = vec![
+            FaceRecord::new(vec![vec![0.5; 4]], None),
+            FaceRecord::new(vec![vec![0.5; 4]], None),
+        ];
+        records[0].id = "abc123".into();
+        records[1].id = "abd456".into();
+        assert_eq!(find_record(&records, "abd456").unwrap(), 1);
+        assert_eq!(find_record(&records, "abc").unwrap(), 0);
+        assert!(find_record(&records, "ab").is_err());
+        assert!(find_record(&records, "x").is_err());
+        assert!(find_record(&records, "").is_err());
+    }
+
+    fn temp_home(name: &str) -> PathBuf {
+        let home = std::env::temp_dir().join(format!("howrs-test-{}", name));
+        let _ = std::fs::remove_dir_all(&home);
+        home
+    }
+
+    #[test]
+    fn test_staging_roundtrip() {
+        let home = temp_home("staging");
+        assert!(load_staged(&home).unwrap().is_empty());
+
+        stage_record(&home, FaceRecord::new(vec![vec![0.5; 128]], None)).unwrap();
+        stage_record(&home, FaceRecord::new(vec![vec![0.25; 128]], None)).unwrap();
+        assert_eq!(load_staged(&home).unwrap().len(), 2);
+
+        let mode = std::fs::metadata(staging_file(&home)).unwrap().mode();
+        assert_eq!(mode & 0o777, 0o600);
+    }
+
+    #[test]
+    fn test_commit_rejects_foreign_owner() {
+        let home = temp_home("staging-owner");
+        stage_record(&home, FaceRecord::new(vec![vec![0.5; 128]], None)).unwrap();
+
+        let owner = std::fs::metadata(staging_file(&home)).unwrap().uid();
+        assert!(commit_staged("nobody", &home, owner + 1).is_err());
+        // Nothing was consumed
+        assert_eq!(load_staged(&home).unwrap().len(), 1);
+    }
+}
@@ -0,0 +1,42 @@
+use howrs::privacy::{write_frame, FrameSink};
+use image::DynamicImage;
+use std::path::PathBuf;
+
+fn scratch_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("howrs-privacy-{}-{}", name, std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+fn frame() -> DynamicImage {
+    DynamicImage::new_rgb8(8, 8)
+}
+
+#[test]
+fn test_no_frames_written_without_opt_in() {
+    let dir = scratch_dir("refuse");
+
+    for sink in FrameSink::ALL {
+        let path = dir.join(format!("{:?}.png", sink));
+        let result = write_frame(sink, None, &frame());
+        assert!(result.is_err(), "{:?} wrote without opt-in", sink);
+        assert!(!path.exists(), "{:?} left a file behind", sink);
+    }
+
+    assert_eq!(std::fs::read_dir(&dir).unwrap().count(), 0);
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_frames_written_with_opt_in() {
+    let dir = scratch_dir("allow");
+
+    for sink in FrameSink::ALL {
+        let path = dir.join(format!("{:?}.png", sink));
+        write_frame(sink, Some(&path), &frame()).unwrap();
+        assert!(path.exists(), "{:?} did not write with opt-in", sink);
+    }
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
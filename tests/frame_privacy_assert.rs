@@ -0,0 +1,110 @@
+// Kept in its own test binary since assert mode is driven by a process-wide
+// environment variable.
+use howrs::debug_dump::DebugBundle;
+use howrs::preview::Preview;
+use howrs::privacy::{check, write_frame, FrameSink, ASSERT_ENV};
+use howrs::stream::StreamEvent;
+use howrs::AuthFailure;
+use howrs_vision::ring::FrameRing;
+use image::DynamicImage;
+use std::panic::AssertUnwindSafe;
+use std::path::PathBuf;
+use std::time::{Duration, Instant};
+
+fn scratch_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!(
+        "howrs-privacy-assert-{}-{}",
+        name,
+        std::process::id()
+    ));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+/// A bundle that has seen one frame, and the camera history holding it
+fn failed_scan() -> (DebugBundle, FrameRing) {
+    let frame = DynamicImage::new_rgb8(8, 8);
+    let mut history = FrameRing::new(2);
+    history.push(frame.as_rgb8().unwrap(), Instant::now());
+    let mut bundle = DebugBundle::default();
+    bundle.observe(&StreamEvent::FrameCaptured {
+        frame: &frame,
+        capture: Duration::from_millis(3),
+    });
+    (bundle, history)
+}
+
+#[test]
+fn test_assert_mode_panics_on_refused_write() {
+    std::env::set_var(ASSERT_ENV, "1");
+    let path =
+        std::env::temp_dir().join(format!("howrs-privacy-assert-{}.png", std::process::id()));
+
+    for sink in FrameSink::ALL {
+        let result = std::panic::catch_unwind(|| {
+            let _ = write_frame(sink, None, &DynamicImage::new_rgb8(8, 8));
+        });
+        assert!(result.is_err(), "{:?} did not panic in assert mode", sink);
+        assert!(!path.exists());
+    }
+}
+
+#[test]
+fn test_snapshot_needs_out() {
+    std::env::set_var(ASSERT_ENV, "1");
+    let dir = scratch_dir("snapshot");
+    let out = dir.join("frame.png");
+
+    // `howrs snapshot` checks its --out before opening the camera
+    assert!(std::panic::catch_unwind(|| check(FrameSink::Snapshot, None)).is_err());
+    check(FrameSink::Snapshot, Some(&out)).unwrap();
+    write_frame(
+        FrameSink::Snapshot,
+        Some(&out),
+        &DynamicImage::new_rgb8(8, 8),
+    )
+    .unwrap();
+    assert!(out.is_file());
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_debug_dump_needs_setting() {
+    std::env::set_var(ASSERT_ENV, "1");
+    let dir = scratch_dir("debug-dump");
+    let failure = AuthFailure::NoFaceDetected;
+
+    // Without `debug_dump` nothing is written, not even the bundle directory
+    let (bundle, history) = failed_scan();
+    let result = std::panic::catch_unwind(AssertUnwindSafe(|| {
+        bundle.write(None, "alice", 0.6, &failure, &history)
+    }));
+    assert!(result.is_err(), "debug dump wrote without debug_dump");
+    assert_eq!(std::fs::read_dir(&dir).unwrap().count(), 0);
+
+    let (bundle, history) = failed_scan();
+    let path = bundle
+        .write(Some(&dir), "alice", 0.6, &failure, &history)
+        .unwrap();
+    assert!(path.join("frame.png").is_file());
+    assert!(path.join("annotated.png").is_file());
+    assert!(path.join("recent-00.png").is_file());
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_preview_writes_to_debug_out() {
+    std::env::set_var(ASSERT_ENV, "1");
+    let dir = scratch_dir("preview");
+
+    let mut preview = Preview::open(&dir).unwrap();
+    preview
+        .write(&DynamicImage::new_rgb8(16, 16), None, None, 0.6)
+        .unwrap();
+    assert!(dir.join("frame-00001.png").is_file());
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}